@@ -0,0 +1,156 @@
+use std::prelude::v1::*;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::slice;
+use std::str;
+
+use std::borrow::Cow;
+
+use super::json::Json;
+
+/// A JSON value whose arrays and objects live in an `Arena`.
+/// Parsing into an arena replaces one allocation per container with a few large
+/// chunks which are freed together when the arena is dropped. Numbers are only `f64`, which
+/// rounds large integers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArenaJson<'a> {
+    JNumber(f64),
+    JString(&'a str),
+    JBool(bool),
+    JNull,
+    JArray(&'a [ArenaJson<'a>]),
+    JObject(&'a [(&'a str, ArenaJson<'a>)])
+}
+
+impl <'a> ArenaJson<'a> {
+    /// Copies the value out of the arena.
+    pub fn to_json(&self) -> Json<'a> {
+        match *self {
+            ArenaJson::JNumber(v) => Json::JNumber(v.into()),
+            ArenaJson::JString(s) => Json::JString(Cow::Borrowed(s)),
+            ArenaJson::JBool(b) => Json::JBool(b),
+            ArenaJson::JNull => Json::JNull,
+            ArenaJson::JArray(jsons) => Json::JArray(jsons.iter().map(ArenaJson::to_json).collect()),
+            ArenaJson::JObject(obj) => Json::JObject(obj.iter().map(|&(k, ref v)| (Cow::Borrowed(k), v.to_json())).collect())
+        }
+    }
+}
+
+/// Storage of the containers of `ArenaJson`.
+///
+/// ```
+/// # use toyjq::Json;
+/// # use toyjq::arena::*;
+/// let arena = Arena::new();
+/// let json = Json::parse_in(&arena, r#"{"a": [1, null]}"#).unwrap();
+/// assert_eq!(json, ArenaJson::JObject(&[("a", ArenaJson::JArray(&[ArenaJson::JNumber(1f64), ArenaJson::JNull]))]));
+/// assert_eq!(json.to_json(), Json::from_str(r#"{"a": [1, null]}"#).unwrap());
+/// ```
+#[derive(Default)]
+pub struct Arena<'a> {
+    values: Chunks<ArenaJson<'a>>,
+    members: Chunks<(&'a str, ArenaJson<'a>)>,
+    /// Strings which are not in the input, i.e. the ones with escape sequences
+    strings: Chunks<u8>
+}
+
+impl <'a> Arena<'a> {
+    pub fn new() -> Arena<'a> {
+        Arena::default()
+    }
+
+    pub fn alloc_values<I>(&self, v: I) -> &[ArenaJson<'a>]
+        where I: IntoIterator<Item = ArenaJson<'a>>,
+              I::IntoIter: ExactSizeIterator
+    {
+        self.values.alloc(v)
+    }
+
+    pub fn alloc_members<I>(&self, v: I) -> &[(&'a str, ArenaJson<'a>)]
+        where I: IntoIterator<Item = (&'a str, ArenaJson<'a>)>,
+              I::IntoIter: ExactSizeIterator
+    {
+        self.members.alloc(v)
+    }
+
+    /// Moves `s` into the arena unless it is borrowed from the input already.
+    pub fn alloc_str(&'a self, s: Cow<'a, str>) -> &'a str {
+        match s {
+            Cow::Borrowed(s) => s,
+            // The bytes are copied from a `str` as they are.
+            Cow::Owned(s) => unsafe { str::from_utf8_unchecked(self.strings.alloc(s.into_bytes())) }
+        }
+    }
+}
+
+const FIRST_CHUNK_LEN: usize = 64;
+
+/// A list of buffers which are never reallocated, so slices into them stay valid
+/// as long as the `Chunks` lives.
+struct Chunks<T> {
+    chunks: RefCell<Vec<Vec<T>>>
+}
+
+impl <T> Default for Chunks<T> {
+    fn default() -> Chunks<T> {
+        Chunks {chunks: RefCell::new(vec![])}
+    }
+}
+
+impl <T> Chunks<T> {
+    /// Moves the items of `v` one by one to the end of the last chunk, or of a new one if they
+    /// do not fit, without collecting them anywhere else.
+    fn alloc<I>(&self, v: I) -> &[T]
+        where I: IntoIterator<Item = T>,
+              I::IntoIter: ExactSizeIterator
+    {
+        let v = v.into_iter();
+        let len = v.len();
+        if len == 0 {
+            return &[]
+        }
+        let mut chunks = self.chunks.borrow_mut();
+        let has_room = chunks.last().is_some_and(|c| c.capacity() - c.len() >= len);
+        if !has_room {
+            let prev = chunks.last().map_or(FIRST_CHUNK_LEN / 2, |c| c.capacity());
+            chunks.push(Vec::with_capacity(max(prev * 2, len)));
+        }
+        let chunk = chunks.last_mut().unwrap();
+        let start = chunk.len();
+        // At most `len` items are taken even if the iterator tells a wrong length, so the chunk
+        // has enough capacity and `extend` does not move its buffer.
+        chunk.extend(v.take(len));
+        // Buffers are only freed when `self` is dropped.
+        unsafe { slice::from_raw_parts(chunk.as_ptr().add(start), chunk.len() - start) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::json::print_json_compact;
+
+    #[test]
+    fn test_chunks() {
+        let chunks = Chunks::default();
+        let slices: Vec<&[usize]> = (0..100).map(|i| chunks.alloc(0..i)).collect();
+        for (i, s) in slices.iter().enumerate() {
+            assert_eq!(s.to_vec(), (0..i).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_parse_escaped() {
+        let arena = Arena::new();
+        let json = Json::parse_in(&arena, r#"{"a\nb": ["c\"d", "e"]}"#).unwrap();
+        assert_eq!(json, ArenaJson::JObject(&[("a\nb", ArenaJson::JArray(&[ArenaJson::JString("c\"d"), ArenaJson::JString("e")]))]));
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let arena = Arena::new();
+        let text = r#"[[1, [2, 3]], {"a": [4], "b": {"c": 9007199254740993}}, []]"#;
+        let json = Json::parse_in(&arena, text).unwrap();
+        assert_eq!(print_json_compact(&json.to_json()), "[[1,[2,3]],{\"a\":[4],\"b\":{\"c\":9007199254740992}},[]]");
+    }
+}
@@ -0,0 +1,96 @@
+use std::prelude::v1::*;
+use super::prettyprinter::Tag;
+
+/// An ANSI SGR parameter list such as `"1;30"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Color(pub String);
+
+impl Color {
+    pub fn new(sgr: &str) -> Color {Color(sgr.to_string())}
+
+    /// The escape sequence which switches the terminal to this color.
+    pub fn escape(&self) -> String {
+        format!("\x1b[{}m", self.0)
+    }
+}
+
+/// The escape sequence which resets every attribute.
+pub const RESET: &str = "\x1b[0m";
+
+/// Colors for each kind of token in JSON output.
+/// `array` and `object` are used for the brackets and separators of each container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub null: Color,
+    pub false_value: Color,
+    pub true_value: Color,
+    pub number: Color,
+    pub string: Color,
+    pub array: Color,
+    pub object: Color,
+    pub key: Color
+}
+
+impl Theme {
+    /// Reads a specification in the format of `JQ_COLORS`: colors separated by `:` for
+    /// null, false, true, numbers, strings, arrays, objects and object keys in this order.
+    /// The colors which are not given are the defaults.
+    ///
+    /// ```
+    /// # use toyjq::color::*;
+    /// let theme = Theme::from_spec("4;31::0;33").unwrap();
+    /// assert_eq!(theme.null, Color::new("4;31"));
+    /// assert_eq!(theme.false_value, Theme::default().false_value);
+    /// assert_eq!(theme.true_value, Color::new("0;33"));
+    /// assert!(Theme::from_spec("red").is_err());
+    /// ```
+    pub fn from_spec(spec: &str) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+        let mut colors = [
+            &mut theme.null, &mut theme.false_value, &mut theme.true_value, &mut theme.number,
+            &mut theme.string, &mut theme.array, &mut theme.object, &mut theme.key
+        ];
+        let sgrs: Vec<&str> = spec.split(':').collect();
+        if sgrs.len() > colors.len() {
+            return Err(format!("Too many colors: {}", spec));
+        }
+        for (color, sgr) in colors.iter_mut().zip(sgrs) {
+            if sgr.chars().any(|c| !c.is_ascii_digit() && c != ';') {
+                return Err(format!("Invalid color: {}", sgr));
+            }
+            if !sgr.is_empty() {
+                **color = Color::new(sgr);
+            }
+        }
+        Ok(theme)
+    }
+
+    pub fn color(&self, tag: Tag) -> &Color {
+        match tag {
+            Tag::NullValue => &self.null,
+            Tag::FalseValue => &self.false_value,
+            Tag::TrueValue => &self.true_value,
+            Tag::NumberValue => &self.number,
+            Tag::StringValue => &self.string,
+            Tag::ObjectKey => &self.key,
+            Tag::ArrayPunctuation => &self.array,
+            Tag::ObjectPunctuation => &self.object
+        }
+    }
+}
+
+impl Default for Theme {
+    /// The same palette as jq.
+    fn default() -> Theme {
+        Theme {
+            null: Color::new("1;30"),
+            false_value: Color::new("0;39"),
+            true_value: Color::new("0;39"),
+            number: Color::new("0;39"),
+            string: Color::new("0;32"),
+            array: Color::new("1;39"),
+            object: Color::new("1;39"),
+            key: Color::new("34;1")
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Dates and times in UTC for the date builtins of the filters, such as `strftime`.
+//!
+//! ```
+//! # use toyjq::datetime::*;
+//! let tm = Tm::from_timestamp(1425599621f64);
+//! assert_eq!(tm.format("%Y-%m-%dT%H:%M:%SZ"), "2015-03-05T23:53:41Z");
+//! assert_eq!(Tm::parse("2015-03-05T23:53:41Z", "%Y-%m-%dT%H:%M:%SZ").unwrap().timestamp(), 1425599621f64);
+//! ```
+
+use std::prelude::v1::*;
+
+/// A broken down time like `struct tm` of C. `month` counts from 0 and `day` from 1.
+/// `weekday` counts from Sunday and `yearday` from 0 for January 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tm {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: f64,
+    pub weekday: i64,
+    pub yearday: i64
+}
+
+const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+const MONTHS: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July",
+    "August", "September", "October", "November", "December"];
+
+/// `floor`, which is not in `core`
+fn floor(x: f64) -> f64 {
+    let r = x % 1f64;
+    if r < 0f64 { x - r - 1f64 } else { x - r }
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar, where `month` counts from 1.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, the month from 1 and the day of `days` since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+impl Tm {
+    /// The time of seconds since the epoch, like `gmtime` of C but keeping the fraction of the seconds.
+    pub fn from_timestamp(t: f64) -> Tm {
+        let whole = floor(t);
+        let secs = whole as i64;
+        let days = secs.div_euclid(86400);
+        let rest = secs.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        Tm {
+            year,
+            month: month - 1,
+            day,
+            hour: rest / 3600,
+            minute: rest % 3600 / 60,
+            second: (rest % 60) as f64 + (t - whole),
+            weekday: (days + 4).rem_euclid(7),
+            yearday: days - days_from_civil(year, 1, 1)
+        }
+    }
+
+    /// Seconds since the epoch, like `timegm` of C. Fields out of their ranges carry over,
+    /// and `weekday` and `yearday` are ignored.
+    pub fn timestamp(&self) -> f64 {
+        let year = self.year + self.month.div_euclid(12);
+        let month = self.month.rem_euclid(12) + 1;
+        let days = days_from_civil(year, month, 1) + self.day - 1;
+        (days * 86400 + self.hour * 3600 + self.minute * 60) as f64 + self.second
+    }
+
+    /// Fills `weekday` and `yearday`, and brings the other fields into their ranges.
+    pub fn normalize(&self) -> Tm {
+        Tm::from_timestamp(self.timestamp())
+    }
+
+    /// Formats the time like `strftime` of C in the C locale.
+    /// An unknown conversion such as `%Q` is left as it is.
+    pub fn format(&self, format: &str) -> String {
+        let mut ret = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                ret.push(c);
+                continue;
+            }
+            let hour12 = if self.hour % 12 == 0 { 12 } else { self.hour % 12 };
+            let second = floor(self.second) as i64;
+            match chars.next() {
+                Some('a') => ret.push_str(&DAYS[self.weekday as usize][..3]),
+                Some('A') => ret.push_str(DAYS[self.weekday as usize]),
+                Some('b') | Some('h') => ret.push_str(&MONTHS[self.month as usize][..3]),
+                Some('B') => ret.push_str(MONTHS[self.month as usize]),
+                Some('c') => ret.push_str(&self.format("%a %b %e %H:%M:%S %Y")),
+                Some('C') => ret.push_str(&format!("{:02}", self.year.div_euclid(100))),
+                Some('d') => ret.push_str(&format!("{:02}", self.day)),
+                Some('D') => ret.push_str(&self.format("%m/%d/%y")),
+                Some('e') => ret.push_str(&format!("{:2}", self.day)),
+                Some('F') => ret.push_str(&self.format("%Y-%m-%d")),
+                Some('H') => ret.push_str(&format!("{:02}", self.hour)),
+                Some('I') => ret.push_str(&format!("{:02}", hour12)),
+                Some('j') => ret.push_str(&format!("{:03}", self.yearday + 1)),
+                Some('k') => ret.push_str(&format!("{:2}", self.hour)),
+                Some('l') => ret.push_str(&format!("{:2}", hour12)),
+                Some('m') => ret.push_str(&format!("{:02}", self.month + 1)),
+                Some('M') => ret.push_str(&format!("{:02}", self.minute)),
+                Some('n') => ret.push('\n'),
+                Some('p') => ret.push_str(if self.hour < 12 { "AM" } else { "PM" }),
+                Some('R') => ret.push_str(&self.format("%H:%M")),
+                Some('s') => ret.push_str(&format!("{}", floor(self.timestamp()) as i64)),
+                Some('S') => ret.push_str(&format!("{:02}", second)),
+                Some('t') => ret.push('\t'),
+                Some('T') => ret.push_str(&self.format("%H:%M:%S")),
+                Some('u') => ret.push_str(&format!("{}", if self.weekday == 0 { 7 } else { self.weekday })),
+                Some('U') => ret.push_str(&format!("{:02}", (self.yearday + 7 - self.weekday) / 7)),
+                Some('w') => ret.push_str(&format!("{}", self.weekday)),
+                Some('W') => ret.push_str(&format!("{:02}", (self.yearday + 7 - (self.weekday + 6) % 7) / 7)),
+                Some('y') => ret.push_str(&format!("{:02}", self.year.rem_euclid(100))),
+                Some('Y') => ret.push_str(&format!("{}", self.year)),
+                Some('z') => ret.push_str("+0000"),
+                Some('Z') => ret.push_str("UTC"),
+                Some('%') => ret.push('%'),
+                Some(c) => {
+                    ret.push('%');
+                    ret.push(c);
+                },
+                None => ret.push('%')
+            }
+        }
+        ret
+    }
+
+    /// Parses `s` in `format` like `strptime` of C in the C locale, and fills `weekday` and `yearday`.
+    /// A time zone by `%z` or `%Z` is read but ignored, and whitespace in `format` matches
+    /// any amount of whitespace.
+    pub fn parse(s: &str, format: &str) -> Option<Tm> {
+        let mut tm = Tm {year: 1900, month: 0, day: 1, hour: 0, minute: 0, second: 0f64, weekday: 0, yearday: 0};
+        let mut parser = TimeParser {chars: s.chars().collect(), pos: 0, pm: None, yearday: None};
+        parser.parse(format, &mut tm)?;
+        if parser.pos < parser.chars.len() {
+            return None;
+        }
+        if let Some(pm) = parser.pm {
+            tm.hour = tm.hour % 12 + if pm { 12 } else { 0 };
+        }
+        if let Some(yearday) = parser.yearday {
+            tm.month = 0;
+            tm.day = yearday + 1;
+        }
+        Some(tm.normalize())
+    }
+}
+
+struct TimeParser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Whether `%p` reads PM
+    pm: Option<bool>,
+    yearday: Option<i64>
+}
+
+impl TimeParser {
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// A number of at most `width` digits, which may have a sign if `signed`, within `min..=max`.
+    fn number(&mut self, width: usize, signed: bool, min: i64, max: i64) -> Option<i64> {
+        self.skip_spaces();
+        let negative = signed && self.chars.get(self.pos) == Some(&'-');
+        if negative || (signed && self.chars.get(self.pos) == Some(&'+')) {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.pos - start < width && self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let n: i64 = self.chars[start..self.pos].iter().collect::<String>().parse().ok()?;
+        let n = if negative { -n } else { n };
+        if n < min || n > max { None } else { Some(n) }
+    }
+
+    /// One of `names` in full or abbreviated to 3 letters, ignoring the case, and its index.
+    fn name(&mut self, names: &[&str]) -> Option<i64> {
+        let rest: String = self.chars[self.pos..].iter().collect::<String>().to_lowercase();
+        for (i, name) in names.iter().enumerate() {
+            let name = name.to_lowercase();
+            for len in &[name.len(), name.len().min(3)] {
+                if rest.starts_with(&name[..*len]) {
+                    self.pos += len;
+                    return Some(i as i64);
+                }
+            }
+        }
+        None
+    }
+
+    fn parse(&mut self, format: &str, tm: &mut Tm) -> Option<()> {
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                self.skip_spaces();
+                continue;
+            }
+            if c != '%' {
+                if self.chars.get(self.pos) != Some(&c) {
+                    return None;
+                }
+                self.pos += 1;
+                continue;
+            }
+            match chars.next()? {
+                'a' | 'A' => {
+                    self.name(&DAYS)?;
+                },
+                'b' | 'B' | 'h' => tm.month = self.name(&MONTHS)?,
+                'c' => self.parse("%a %b %e %H:%M:%S %Y", tm)?,
+                'C' => tm.year = self.number(2, false, 0, 99)? * 100 + tm.year.rem_euclid(100),
+                'd' | 'e' => tm.day = self.number(2, false, 1, 31)?,
+                'D' => self.parse("%m/%d/%y", tm)?,
+                'F' => self.parse("%Y-%m-%d", tm)?,
+                'H' | 'k' => tm.hour = self.number(2, false, 0, 23)?,
+                'I' | 'l' => tm.hour = self.number(2, false, 1, 12)?,
+                'j' => self.yearday = Some(self.number(3, false, 1, 366)? - 1),
+                'm' => tm.month = self.number(2, false, 1, 12)? - 1,
+                'M' => tm.minute = self.number(2, false, 0, 59)?,
+                'n' | 't' => self.skip_spaces(),
+                'p' => {
+                    self.skip_spaces();
+                    self.pm = Some(self.name(&["AM", "PM"]).map(|i| i == 1)?);
+                },
+                'R' => self.parse("%H:%M", tm)?,
+                's' => {
+                    let t = self.number(20, true, i64::MIN, i64::MAX)?;
+                    *tm = Tm::from_timestamp(t as f64);
+                },
+                'S' => tm.second = self.number(2, false, 0, 60)? as f64,
+                'T' => self.parse("%H:%M:%S", tm)?,
+                'y' => {
+                    let y = self.number(2, false, 0, 99)?;
+                    tm.year = if y < 69 { 2000 + y } else { 1900 + y };
+                },
+                'Y' => tm.year = self.number(4, true, -9999, 9999)?,
+                'z' => {
+                    self.skip_spaces();
+                    if self.chars.get(self.pos) == Some(&'Z') {
+                        self.pos += 1;
+                    } else {
+                        self.number(2, true, -99, 99)?;
+                        if self.chars.get(self.pos) == Some(&':') {
+                            self.pos += 1;
+                        }
+                        self.number(2, false, 0, 59)?;
+                    }
+                },
+                'Z' => {
+                    let start = self.pos;
+                    while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_alphabetic()) {
+                        self.pos += 1;
+                    }
+                    if self.pos == start {
+                        return None;
+                    }
+                },
+                '%' => {
+                    if self.chars.get(self.pos) != Some(&'%') {
+                        return None;
+                    }
+                    self.pos += 1;
+                },
+                _ => return None
+            }
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        let tm = Tm::from_timestamp(1425599621.25);
+        assert_eq!(tm, Tm {year: 2015, month: 2, day: 5, hour: 23, minute: 53, second: 41.25, weekday: 4, yearday: 63});
+        assert_eq!(tm.timestamp(), 1425599621.25);
+        assert_eq!(Tm::from_timestamp(-1f64), Tm {year: 1969, month: 11, day: 31, hour: 23, minute: 59, second: 59f64, weekday: 3, yearday: 364});
+        assert_eq!(Tm::from_timestamp(951782400f64).format("%F %j %a"), "2000-02-29 060 Tue");
+        let overflow = Tm {year: 2015, month: 13, day: 32, hour: 0, minute: 0, second: 0f64, weekday: 0, yearday: 0};
+        assert_eq!(overflow.normalize().format("%F"), "2016-03-03");
+    }
+
+    #[test]
+    fn test_format() {
+        let tm = Tm::from_timestamp(1425599621f64);
+        assert_eq!(tm.format("%A, %B %e %Y %I:%M %p %Z %z %%"), "Thursday, March  5 2015 11:53 PM UTC +0000 %");
+        assert_eq!(tm.format("%c|%D|%T|%s|%u|%w|%U|%W|%y|%C|%Q"), "Thu Mar  5 23:53:41 2015|03/05/15|23:53:41|1425599621|4|4|09|09|15|20|%Q");
+    }
+
+    #[test]
+    fn test_parse() {
+        let parse = |s, format| Tm::parse(s, format).map(|tm| tm.format("%F %T %a %j"));
+        assert_eq!(parse("2015-03-05T23:51:47Z", "%Y-%m-%dT%H:%M:%SZ"), Some("2015-03-05 23:51:47 Thu 064".to_string()));
+        assert_eq!(parse("thu, 5 mar 15  11:51pm", "%a, %d %b %y %I:%M%p"), Some("2015-03-05 23:51:00 Thu 064".to_string()));
+        assert_eq!(parse("2015 064 +09:00 JST", "%Y %j %z %Z"), Some("2015-03-05 00:00:00 Thu 064".to_string()));
+        assert_eq!(parse("1425599507", "%s"), Some("2015-03-05 23:51:47 Thu 064".to_string()));
+        assert_eq!(parse("2015-03-05", "%Y-%m-%dT"), None);
+        assert_eq!(parse("2015-13-05", "%F"), None);
+        assert_eq!(parse("2015-03-05x", "%F"), None);
+    }
+}
@@ -0,0 +1,293 @@
+use std::prelude::v1::*;
+use std::cmp::Reverse;
+use super::json::*;
+use super::prettyprinter::PrintStyle;
+use super::color::*;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DiffLine<'s> {
+    Same(&'s str),
+    Removed(&'s str),
+    Added(&'s str)
+}
+
+/// Compares two texts line by line and lists the lines of the shortest edit
+/// (a longest common subsequence of lines is kept as `Same`).
+/// The common lines at both ends are matched first, and the rest is split in halves as in
+/// Hirschberg's algorithm, so that only a row of lengths per split is held.
+///
+/// ```
+/// # use toyjq::diff::*;
+/// assert_eq! {
+///     diff_lines("a\nb\nc", "a\nc\nd"),
+///     vec![DiffLine::Same("a"), DiffLine::Removed("b"), DiffLine::Same("c"), DiffLine::Added("d")]
+/// }
+/// ```
+pub fn diff_lines<'s>(old: &'s str, new: &'s str) -> Vec<DiffLine<'s>> {
+    let olds: Vec<&str> = old.lines().collect();
+    let news: Vec<&str> = new.lines().collect();
+    let prefix = olds.iter().zip(&news).take_while(|(a, b)| a == b).count();
+    let suffix = olds[prefix..].iter().rev().zip(news[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let mut ret: Vec<DiffLine> = olds[..prefix].iter().map(|l| DiffLine::Same(l)).collect();
+    diff_middle(&olds[prefix..olds.len() - suffix], &news[prefix..news.len() - suffix], &mut ret);
+    ret.extend(olds[olds.len() - suffix..].iter().map(|l| DiffLine::Same(l)));
+    ret
+}
+
+/// Pushes the lines of the shortest edit from `olds` to `news`.
+fn diff_middle<'s>(olds: &[&'s str], news: &[&'s str], ret: &mut Vec<DiffLine<'s>>) {
+    match (olds.len(), news.len()) {
+        (0, _) => ret.extend(news.iter().map(|l| DiffLine::Added(l))),
+        (_, 0) => ret.extend(olds.iter().map(|l| DiffLine::Removed(l))),
+        (1, _) => match news.iter().position(|l| *l == olds[0]) {
+            Some(j) => {
+                ret.extend(news[..j].iter().map(|l| DiffLine::Added(l)));
+                ret.push(DiffLine::Same(olds[0]));
+                ret.extend(news[j + 1..].iter().map(|l| DiffLine::Added(l)));
+            },
+            None => {
+                ret.push(DiffLine::Removed(olds[0]));
+                ret.extend(news.iter().map(|l| DiffLine::Added(l)));
+            }
+        },
+        (n, m) => {
+            // Splits `news` where the LCS of the first half of `olds` and that of the second
+            // half meet, which the LCS of the whole goes through. The first such place puts
+            // removed lines before added ones.
+            let mid = n / 2;
+            let forward = lcs_lengths(olds[..mid].iter(), news.iter());
+            let backward = lcs_lengths(olds[mid..].iter().rev(), news.iter().rev());
+            let split = (0..=m).max_by_key(|&j| (forward[j] + backward[m - j], Reverse(j))).unwrap();
+            diff_middle(&olds[..mid], &news[..split], ret);
+            diff_middle(&olds[mid..], &news[split..], ret);
+        }
+    }
+}
+
+/// The lengths of the LCS of `olds` and each prefix of `news`, computed a row at a time.
+fn lcs_lengths<'a, 's: 'a, I, J>(olds: I, news: J) -> Vec<usize>
+    where I: Iterator<Item = &'a &'s str>,
+          J: Iterator<Item = &'a &'s str> + Clone
+{
+    let mut row = vec![0; news.clone().count() + 1];
+    for old in olds {
+        // The value of the previous row at `j - 1`
+        let mut diagonal = 0;
+        for (j, new) in news.clone().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if old == new { diagonal + 1 } else { above.max(row[j]) };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+/// Pretty prints both documents and renders their differences as a unified diff:
+/// every line is prefixed by `  `, `- ` or `+ `.
+/// With `colored`, removed lines are red and added lines are green.
+pub fn render_json_diff(old: &Json, new: &Json, width: i32, style: &PrintStyle, colored: bool) -> String {
+    let style = PrintStyle {trailing_newline: false, ..style.clone()};
+    let old_text = print_json(old, width, &style);
+    let new_text = print_json(new, width, &style);
+    let removed = Color::new("31");
+    let added = Color::new("32");
+    let mut ret = String::new();
+    for line in diff_lines(&old_text, &new_text) {
+        let (mark, color, l) = match line {
+            DiffLine::Same(l) => ("  ", None, l),
+            DiffLine::Removed(l) => ("- ", Some(&removed), l),
+            DiffLine::Added(l) => ("+ ", Some(&added), l)
+        };
+        match color {
+            Some(c) if colored => ret.push_str(&format!("{}{}{}{}", c.escape(), mark, l, RESET)),
+            _ => ret.push_str(&format!("{}{}", mark, l))
+        }
+        ret.push_str(style.line_ending.as_str());
+    }
+    ret
+}
+
+/// A step of a path in a document, the key of an object or the index of an array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathElem<'j> {
+    Key(&'j str),
+    Index(usize)
+}
+
+/// A difference between two documents at the path from the root.
+#[derive(Debug, PartialEq)]
+pub enum Change<'j> {
+    Added(Vec<PathElem<'j>>, &'j Json<'j>),
+    Removed(Vec<PathElem<'j>>, &'j Json<'j>),
+    Changed(Vec<PathElem<'j>>, &'j Json<'j>, &'j Json<'j>)
+}
+
+/// Compares two documents value by value and lists the paths which are only in `old`, only in
+/// `new`, or have different values. The order of the keys of objects does not matter, while
+/// the elements of arrays are compared index by index.
+///
+/// ```
+/// # use toyjq::Json;
+/// # use toyjq::diff::*;
+/// let old = Json::from_str(r#"{"a": 1, "b": [true]}"#).unwrap();
+/// let new = Json::from_str(r#"{"b": [true, null], "a": 2}"#).unwrap();
+/// assert_eq! {
+///     diff_json(&old, &new),
+///     vec![
+///         Change::Changed(vec![PathElem::Key("a")], &Json::JNumber(1.0.into()), &Json::JNumber(2.0.into())),
+///         Change::Added(vec![PathElem::Key("b"), PathElem::Index(1)], &Json::JNull)
+///     ]
+/// }
+/// ```
+pub fn diff_json<'j>(old: &'j Json<'j>, new: &'j Json<'j>) -> Vec<Change<'j>> {
+    fn walk<'j>(old: &'j Json<'j>, new: &'j Json<'j>, path: &mut Vec<PathElem<'j>>, ret: &mut Vec<Change<'j>>) {
+        match (old, new) {
+            (Json::JObject(olds), Json::JObject(news)) => {
+                for (k, v) in olds {
+                    path.push(PathElem::Key(k));
+                    match news.iter().find(|(k2, _)| k2 == k) {
+                        Some((_, v2)) => walk(v, v2, path, ret),
+                        None => ret.push(Change::Removed(path.clone(), v))
+                    }
+                    path.pop();
+                }
+                for (k, v) in news.iter().filter(|(k, _)| olds.iter().all(|(k2, _)| k2 != k)) {
+                    path.push(PathElem::Key(k));
+                    ret.push(Change::Added(path.clone(), v));
+                    path.pop();
+                }
+            },
+            (Json::JArray(olds), Json::JArray(news)) => {
+                for i in 0..olds.len().max(news.len()) {
+                    path.push(PathElem::Index(i));
+                    match (olds.get(i), news.get(i)) {
+                        (Some(v), Some(v2)) => walk(v, v2, path, ret),
+                        (Some(v), None) => ret.push(Change::Removed(path.clone(), v)),
+                        (None, Some(v2)) => ret.push(Change::Added(path.clone(), v2)),
+                        (None, None) => ()
+                    }
+                    path.pop();
+                }
+            },
+            _ if old == new => (),
+            _ => ret.push(Change::Changed(path.clone(), old, new))
+        }
+    }
+    let mut ret = vec![];
+    walk(old, new, &mut vec![], &mut ret);
+    ret
+}
+
+/// Writes `path` as a jq path expression such as `.a[0]["b c"]`, or `.` for the root.
+pub fn path_to_string(path: &[PathElem]) -> String {
+    if path.is_empty() {
+        return ".".to_string();
+    }
+    let mut ret = String::new();
+    for elem in path {
+        match *elem {
+            PathElem::Key(k) if is_identifier(k) => ret.push_str(&format!(".{}", k)),
+            PathElem::Key(k) => ret.push_str(&format!("[{}]", print_json_compact(&Json::JString(k.into())))),
+            PathElem::Index(i) => ret.push_str(&format!("[{}]", i))
+        }
+    }
+    ret
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false
+    }
+}
+
+/// Renders `changes` one per line: `+ path: value` for added values, `- path: value` for
+/// removed ones and `~ path: old -> new` for changed ones, where values are printed with `opts`.
+/// The marks are colored as well as the values when `opts` has a theme.
+pub fn render_changes(changes: &[Change], opts: &PrinterOptions) -> String {
+    let opts = opts.clone().trailing_newline(false);
+    let print = |json: &Json| json.pretty_print_with(&opts);
+    let mut ret = String::new();
+    for change in changes {
+        let (mark, color, line) = match *change {
+            Change::Added(ref path, v) => ("+", "32", format!("{}: {}", path_to_string(path), print(v))),
+            Change::Removed(ref path, v) => ("-", "31", format!("{}: {}", path_to_string(path), print(v))),
+            Change::Changed(ref path, v, v2) => ("~", "33", format!("{}: {} -> {}", path_to_string(path), print(v), print(v2)))
+        };
+        match opts.theme {
+            Some(_) => ret.push_str(&format!("{}{}{} {}", Color::new(color).escape(), mark, RESET, line)),
+            None => ret.push_str(&format!("{} {}", mark, line))
+        }
+        ret.push_str(opts.style.line_ending.as_str());
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_json_diff() {
+        use json::Json::*;
+        let old = JObject(vec![("a".into(), JNumber(1f64.into())), ("b".into(), JArray(vec![JBool(true), JNull]))]);
+        let new = JObject(vec![("a".into(), JNumber(2f64.into())), ("b".into(), JArray(vec![JBool(true), JNull])), ("c".into(), JNull)]);
+        assert_eq! {
+            render_json_diff(&old, &new, 0, &PrintStyle::default(), false),
+            r#"  {
+-   "a": 1,
++   "a": 2,
+    "b": [
+      true,
+      null
+-   ]
++   ],
++   "c": null
+  }
+"#
+        }
+        assert_eq! {
+            render_json_diff(&JNumber(1f64.into()), &JNumber(2f64.into()), 80, &PrintStyle::default(), true),
+            "\x1b[31m- 1\x1b[0m\n\x1b[32m+ 2\x1b[0m\n"
+        }
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        // Every pair of texts of up to 4 lines of `a` and `b`.
+        let texts: Vec<String> = (0..31).map(|i: u32| {
+            let len = 32 - (i + 1).leading_zeros() - 1;
+            (0..len).map(|bit| if (i + 1) >> bit & 1 == 1 { "a\n" } else { "b\n" }).collect()
+        }).collect();
+        for old in &texts {
+            for new in &texts {
+                let lines = diff_lines(old, new);
+                let side = |f: fn(&DiffLine) -> Option<String>| lines.iter().filter_map(f).collect::<String>();
+                assert_eq!(&side(|l| match *l { DiffLine::Same(l) | DiffLine::Removed(l) => Some(format!("{}\n", l)), _ => None }), old);
+                assert_eq!(&side(|l| match *l { DiffLine::Same(l) | DiffLine::Added(l) => Some(format!("{}\n", l)), _ => None }), new);
+                let olds: Vec<&str> = old.lines().collect();
+                let news: Vec<&str> = new.lines().collect();
+                let same = lines.iter().filter(|l| matches!(**l, DiffLine::Same(_))).count();
+                assert_eq!(same, lcs_lengths(olds.iter(), news.iter())[news.len()], "{:?} {:?}", old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let old = Json::from_str(r#"{"a": {"x": [1, 2, 3]}, "b": "s", "d": null}"#).unwrap();
+        let new = Json::from_str(r#"{"c": {"y z": false}, "a": {"x": [1, 5]}, "b": "s"}"#).unwrap();
+        assert_eq! {
+            render_changes(&diff_json(&old, &new), &PrinterOptions::new()),
+            "~ .a.x[1]: 2 -> 5\n- .a.x[2]: 3\n- .d: null\n+ .c: { \"y z\": false }\n"
+        }
+        let old = Json::from_str(r#"{"y z": [1]}"#).unwrap();
+        assert_eq!(render_changes(&diff_json(&old, &new), &PrinterOptions::new().compact(true)).lines().next(), Some(r#"- ["y z"]: [1]"#));
+        assert_eq!(render_changes(&diff_json(&Json::JNull, &Json::JBool(true)), &PrinterOptions::new()), "~ .: null -> true\n");
+        assert_eq!(diff_json(&new, &new), vec![]);
+        let colored = PrinterOptions::new().color(Some(Theme::default()));
+        assert_eq!(render_changes(&diff_json(&Json::JNull, &Json::JNull), &colored), "");
+        assert!(render_changes(&diff_json(&old, &new), &colored).starts_with("\x1b[31m-\x1b[0m "));
+    }
+}
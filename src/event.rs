@@ -0,0 +1,451 @@
+use std::prelude::v1::*;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use super::json::*;
+use super::parsercombinator::*;
+
+/// A parse event, the unit of streaming JSON processing.
+/// A document is a sequence of events such as
+/// `StartObject, Key("a"), StartArray, Number(1.0), EndArray, EndObject`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<'a> {
+    StartArray,
+    EndArray,
+    StartObject,
+    EndObject,
+    Key(Cow<'a, str>),
+    Number(Number<'a>),
+    String(Cow<'a, str>),
+    Bool(bool),
+    Null
+}
+
+impl <'a> Event<'a> {
+    /// Copies every borrowed string so that the event outlives the input.
+    pub fn into_owned(self) -> Event<'static> {
+        match self {
+            Event::StartArray => Event::StartArray,
+            Event::EndArray => Event::EndArray,
+            Event::StartObject => Event::StartObject,
+            Event::EndObject => Event::EndObject,
+            Event::Key(k) => Event::Key(Cow::Owned(k.into_owned())),
+            Event::Number(n) => Event::Number(n.into_owned()),
+            Event::String(s) => Event::String(Cow::Owned(s.into_owned())),
+            Event::Bool(b) => Event::Bool(b),
+            Event::Null => Event::Null
+        }
+    }
+}
+
+/// Lists the events which describe `json`.
+///
+/// ```
+/// # use toyjq::Json;
+/// # use toyjq::event::*;
+/// let json = Json::JArray(vec![Json::JNull]);
+/// assert_eq!(json_events(&json), vec![Event::StartArray, Event::Null, Event::EndArray]);
+/// ```
+pub fn json_events<'j>(json: &'j Json) -> Vec<Event<'j>> {
+    fn walk<'j>(json: &'j Json, ret: &mut Vec<Event<'j>>) {
+        match *json {
+            Json::JNumber(ref n) => ret.push(Event::Number(n.clone())),
+            Json::JString(ref s) => ret.push(Event::String(Cow::Borrowed(s))),
+            Json::JBool(b) => ret.push(Event::Bool(b)),
+            Json::JNull => ret.push(Event::Null),
+            Json::JArray(ref jsons) => {
+                ret.push(Event::StartArray);
+                for j in jsons {
+                    walk(j, ret);
+                }
+                ret.push(Event::EndArray);
+            },
+            Json::JObject(ref obj) => {
+                ret.push(Event::StartObject);
+                for (k, v) in obj {
+                    ret.push(Event::Key(Cow::Borrowed(k)));
+                    walk(v, ret);
+                }
+                ret.push(Event::EndObject);
+            }
+        }
+    }
+    let mut ret = vec![];
+    walk(json, &mut ret);
+    ret
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Array,
+    Object
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// A value is expected, or the end of the array if it is the first
+    Value {first: bool},
+    /// A key is expected, or the end of the object if it is the first
+    Key {first: bool},
+    /// A value has ended
+    AfterValue
+}
+
+/// Parses a document into events one by one without building a `Json`, holding only the
+/// kinds of the arrays and objects it is in. Strings are borrowed from the input unless they
+/// have escape sequences. It gives the first error and then ends.
+///
+/// With `DuplicateKeys::FirstWins`, the events of a member whose key has appeared in the object
+/// are left out. The events of the first member have been given by then, so
+/// `DuplicateKeys::LastWins` fails at the second key as `DuplicateKeys::Error` does.
+///
+/// ```
+/// # use toyjq::event::*;
+/// let events: Vec<Event> = EventParser::new(r#"{"a": [1, null]}"#).map(Result::unwrap).collect();
+/// assert_eq!(events, vec![
+///     Event::StartObject, Event::Key("a".into()), Event::StartArray, Event::Number(1.0.into()), Event::Null,
+///     Event::EndArray, Event::EndObject
+/// ]);
+/// ```
+pub struct EventParser<'a> {
+    input: &'a str,
+    machine: Machine,
+    done: bool
+}
+
+impl <'a> EventParser<'a> {
+    pub fn new(input: &'a str) -> EventParser<'a> {
+        EventParser::with_options(input, &ParserOptions::default())
+    }
+
+    /// Like `new`, but parses as `opts` tells.
+    ///
+    /// ```
+    /// # use toyjq::ParserOptions;
+    /// # use toyjq::event::*;
+    /// let events: Vec<Event> = EventParser::with_options("[NaN, /* two */ 2,]", &ParserOptions::lenient()).map(Result::unwrap).collect();
+    /// assert_eq!(events.len(), 4);
+    /// ```
+    pub fn with_options(input: &'a str, opts: &ParserOptions) -> EventParser<'a> {
+        EventParser {input, machine: Machine::new(opts, false), done: false}
+    }
+
+    /// Like `with_options`, but parses the documents one after another, with whitespace or
+    /// nothing between them, as `Json::from_str_many_with` does. There may be none.
+    ///
+    /// ```
+    /// # use toyjq::ParserOptions;
+    /// # use toyjq::event::*;
+    /// let events: Vec<Event> = EventParser::many_with_options("1 [] ", &ParserOptions::default()).map(Result::unwrap).collect();
+    /// assert_eq!(events, vec![Event::Number(1.0.into()), Event::StartArray, Event::EndArray]);
+    /// assert_eq!(EventParser::many_with_options(" ", &ParserOptions::default()).count(), 0);
+    /// ```
+    pub fn many_with_options(input: &'a str, opts: &ParserOptions) -> EventParser<'a> {
+        EventParser {input, machine: Machine::new(opts, true), done: false}
+    }
+}
+
+impl <'a> Iterator for EventParser<'a> {
+    type Item = Result<Event<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        loop {
+            match self.machine.step(self.input) {
+                Ok(Some((_, true))) => continue,
+                Ok(Some((event, false))) => return Some(Ok(event)),
+                Ok(None) => {
+                    self.done = true;
+                    return None
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.locate(self.input)))
+                }
+            }
+        }
+    }
+}
+
+/// Where parsing is in an input, which is given to every `step`, so that the input may be the
+/// text read so far as `reader::EventReader` has it as well as a whole text.
+pub(crate) struct Machine {
+    opts: ParserOptions,
+    /// The byte offset of the input where the next step starts
+    pub(crate) pos: usize,
+    stack: Vec<Container>,
+    /// The keys so far of each object in `stack`, which are kept unless `DuplicateKeys::Keep`
+    keys: Vec<BTreeSet<String>>,
+    state: State,
+    /// Parses any number of documents instead of one.
+    many: bool,
+    /// The input has been looked at for a byte order mark.
+    started: bool,
+    /// The depth of the member being left out with `DuplicateKeys::FirstWins`
+    skip: Option<usize>
+}
+
+impl Machine {
+    pub(crate) fn new(opts: &ParserOptions, many: bool) -> Machine {
+        Machine {opts: *opts, pos: 0, stack: vec![], keys: vec![], state: State::Value {first: false}, many, started: false, skip: None}
+    }
+
+    /// Runs `p` at the current position, which moves past it and the whitespace after it.
+    fn run<'a, T: 'a>(&mut self, input: &'a str, p: Parser<'a, T>) -> Result<T, ParseError> {
+        let (pos, v) = p.parse_at(input, self.pos).map_err(|e| error_at(e.message, e.pos))?;
+        self.pos = pos;
+        self.skip_spaces(input)?;
+        Ok(v)
+    }
+
+    /// Parses the string at the current position as `run` does with `parse_string`, which
+    /// is not needed for one without escape sequences.
+    fn string<'a>(&mut self, input: &'a str) -> Result<Cow<'a, str>, ParseError> {
+        let start = self.pos + 1;
+        match find_string_end(&input.as_bytes()[start..]) {
+            Some(len) if input.as_bytes()[start + len] == b'"' => {
+                self.pos = start + len + 1;
+                self.skip_spaces(input)?;
+                Ok(Cow::Borrowed(&input[start..start + len]))
+            },
+            _ => self.run(input, parse_string())
+        }
+    }
+
+    fn skip_spaces(&mut self, input: &str) -> Result<(), ParseError> {
+        // Most tokens are followed by none, for which the parser is not worth making.
+        match input.as_bytes().get(self.pos) {
+            Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') => (),
+            Some(b'/') if self.opts.comments => (),
+            _ => return Ok(())
+        }
+        self.pos = spaces(self.opts).parse_at(input, self.pos).map_err(|e| error_at(e.message, e.pos))?.0;
+        Ok(())
+    }
+
+    fn unexpected(&self, input: &str, expected: &str) -> ParseError {
+        let message = match input[self.pos..].chars().next() {
+            Some(c) => format!("Expected {} but actual is `{}`.", expected, c),
+            None => "Reaches end.".to_string()
+        };
+        error_at(message, self.pos)
+    }
+
+    fn close<'a>(&mut self, input: &'a str) -> Result<Event<'a>, ParseError> {
+        self.pos += 1;
+        self.skip_spaces(input)?;
+        self.state = State::AfterValue;
+        match self.stack.pop() {
+            Some(Container::Array) => Ok(Event::EndArray),
+            _ => {
+                self.keys.pop();
+                Ok(Event::EndObject)
+            }
+        }
+    }
+
+    /// Moves into an array or an object after its opening bracket.
+    fn open(&mut self, container: Container) -> Result<(), ParseError> {
+        if self.stack.len() >= self.opts.max_depth {
+            return Err(error_at("Exceeds depth limit for parsing".to_string(), self.pos))
+        }
+        self.stack.push(container);
+        if container == Container::Object {
+            self.keys.push(BTreeSet::new());
+        }
+        Ok(())
+    }
+
+    /// Whether the member of `key` is to be given, which is not if it is not the first of the
+    /// key with `DuplicateKeys::FirstWins`.
+    fn is_new_key(&mut self, key: &str, at: usize) -> Result<bool, ParseError> {
+        if self.opts.duplicate_keys == DuplicateKeys::Keep || self.keys.last_mut().unwrap().insert(key.to_string()) {
+            return Ok(true)
+        }
+        match self.opts.duplicate_keys {
+            DuplicateKeys::FirstWins => Ok(false),
+            _ => Err(error_at(format!("Duplicate key \"{}\"", key), at))
+        }
+    }
+
+    fn value<'a>(&mut self, input: &'a str) -> Result<Event<'a>, ParseError> {
+        self.state = State::AfterValue;
+        match input[self.pos..].chars().next() {
+            Some('[') => {
+                self.pos += 1;
+                self.skip_spaces(input)?;
+                self.open(Container::Array)?;
+                self.state = State::Value {first: true};
+                Ok(Event::StartArray)
+            },
+            Some('{') => {
+                self.pos += 1;
+                self.skip_spaces(input)?;
+                self.open(Container::Object)?;
+                self.state = State::Key {first: true};
+                Ok(Event::StartObject)
+            },
+            Some('"') => self.string(input).map(Event::String),
+            None => Err(self.unexpected(input, "a value")),
+            _ => {
+                let words = [("null", Event::Null), ("true", Event::Bool(true)), ("false", Event::Bool(false))];
+                if let Some(&(word, ref event)) = words.iter().find(|&&(word, _)| input[self.pos..].starts_with(word)) {
+                    self.pos += word.len();
+                    self.skip_spaces(input)?;
+                    return Ok(event.clone())
+                }
+                if let Some((len, integer)) = number_len(&input.as_bytes()[self.pos..]) {
+                    let text = &input[self.pos..self.pos + len];
+                    self.pos += len;
+                    self.skip_spaces(input)?;
+                    return Ok(Event::Number(number_of(Cow::Borrowed(text), integer, self.opts.number_text)))
+                }
+                let number = parse_jnumber(self.opts.number_text);
+                let number = if self.opts.non_finite { parse_non_finite().or(number) } else { number };
+                self.run(input, parse_jnull().or(parse_jbool()).or(number))
+            }.map(|json| match json {
+                Json::JNumber(n) => Event::Number(n),
+                Json::JBool(b) => Event::Bool(b),
+                _ => Event::Null
+            })
+        }
+    }
+
+    /// Parses the next event of `input`, which is given with whether it is of a member left
+    /// out, or `None` at the end. The position of an error is not located in `input` yet.
+    pub(crate) fn step<'a>(&mut self, input: &'a str) -> Result<Option<(Event<'a>, bool)>, ParseError> {
+        if !self.started {
+            self.started = true;
+            let mark = byte_order_mark_len(&input[self.pos..]);
+            if mark > 0 && !self.opts.bom {
+                return Err(error_at("Byte order mark is not allowed".to_string(), self.pos))
+            }
+            self.pos += mark;
+        }
+        self.skip_spaces(input)?;
+        loop {
+            let event = match (self.state, self.stack.last(), input[self.pos..].chars().next()) {
+                (State::Value {first: false}, None, None) if self.many => return Ok(None),
+                (State::Value {first: true}, _, Some(']')) => self.close(input)?,
+                (State::Value {..}, _, _) => self.value(input)?,
+                (State::Key {first: true}, _, Some('}')) => self.close(input)?,
+                (State::Key {..}, _, Some('"')) => {
+                    let at = self.pos;
+                    let key = self.string(input)?;
+                    if !input[self.pos..].starts_with(':') {
+                        return Err(self.unexpected(input, "`:`"))
+                    }
+                    self.pos += 1;
+                    self.skip_spaces(input)?;
+                    self.state = State::Value {first: false};
+                    if !self.is_new_key(&key, at)? && self.skip.is_none() {
+                        self.skip = Some(self.stack.len());
+                    }
+                    Event::Key(key)
+                },
+                (State::Key {..}, _, _) => return Err(self.unexpected(input, "a key")),
+                (State::AfterValue, None, None) => return Ok(None),
+                (State::AfterValue, None, _) if self.many => {
+                    self.state = State::Value {first: false};
+                    continue
+                },
+                (State::AfterValue, None, _) => return Err(self.unexpected(input, "end of input")),
+                (State::AfterValue, Some(&container), Some(',')) => {
+                    self.pos += 1;
+                    self.skip_spaces(input)?;
+                    // A trailing comma may be followed by the end as the first element may.
+                    let first = self.opts.trailing_commas;
+                    self.state = match container {
+                        Container::Array => State::Value {first},
+                        Container::Object => State::Key {first}
+                    };
+                    continue
+                },
+                (State::AfterValue, Some(Container::Array), Some(']')) => self.close(input)?,
+                (State::AfterValue, Some(Container::Object), Some('}')) => self.close(input)?,
+                (State::AfterValue, Some(Container::Array), _) => return Err(self.unexpected(input, "`,` or `]`")),
+                (State::AfterValue, Some(Container::Object), _) => return Err(self.unexpected(input, "`,` or `}`"))
+            };
+            let skipped = self.skip.is_some();
+            // The member left out ends with its value.
+            if self.skip == Some(self.stack.len()) && self.state == State::AfterValue {
+                self.skip = None;
+            }
+            return Ok(Some((event, skipped)))
+        }
+    }
+}
+
+/// What `reader::EventReader` looks at to read as much as a step takes.
+#[cfg(feature = "std")]
+impl Machine {
+    pub(crate) fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// How many tokens the next step takes at most, besides whitespace and comments: a `,`
+    /// and the key and the `:` after it in an object, for example. A step ends earlier at `]`
+    /// or `}`.
+    pub(crate) fn tokens_ahead(&self) -> usize {
+        match (self.state, self.stack.last()) {
+            (State::Key {..}, _) => 2,
+            (State::AfterValue, Some(Container::Object)) => 3,
+            (State::AfterValue, Some(Container::Array)) => 2,
+            _ => 1
+        }
+    }
+}
+
+fn error_at(message: String, pos: usize) -> ParseError {
+    ParseError {retry: false, message, pos, line: 0, column: 0}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_parser() {
+        for text in &["1", "\u{FEFF}[1]", " \"a\\nb\" ", "[]", "{}", "[[], {}, [1, [true]]]", "{\"a\": {\"b\": [null, -1.5e3]}, \"c\": \"\\u00e9\"}"] {
+            let events: Vec<Event> = EventParser::new(text).map(Result::unwrap).collect();
+            assert_eq!(events, json_events(&Json::from_str(text).unwrap()), "{}", text);
+        }
+        let errors: Vec<String> = ["[1 2]", "{\"a\" 1}", "{1: 2}", "[1,]", "{\"a\": 1,}", "[1] 2", "[", "nul", "{\"a\": [}"].iter()
+            .map(|text| {
+                let e = EventParser::new(text).find_map(Result::err).unwrap();
+                format!("{}:{}", e.column, e.message)
+            })
+            .collect();
+        assert_eq!(errors, vec![
+            "4:Expected `,` or `]` but actual is `2`.",
+            "6:Expected `:` but actual is `1`.",
+            "2:Expected a key but actual is `1`.",
+            "4:Expected a digit",
+            "9:Expected a key but actual is `}`.",
+            "5:Expected end of input but actual is `2`.",
+            "2:Reaches end.",
+            "1:Expected a digit",
+            "8:Expected a digit"
+        ]);
+        let events = |text, opts: &ParserOptions| EventParser::with_options(text, opts).collect::<Result<Vec<Event>, ParseError>>();
+        let text = "// c\n{\"a\": [Infinity, 1.10,], /* ] */ \"b\": -Infinity,}";
+        assert_eq!(events(text, &ParserOptions::lenient().number_text(true)), Ok(json_events(&Json::from_str_with(text, &ParserOptions::lenient().number_text(true)).unwrap())));
+        assert!(events(text, &ParserOptions::new()).is_err());
+        let text = "{\"a\": 1, \"b\": {\"c\": 2}, \"a\": [3, {\"a\": 4}], \"b\": {}}";
+        let with = |policy| events(text, &ParserOptions::new().duplicate_keys(policy));
+        assert_eq!(with(DuplicateKeys::FirstWins), Ok(json_events(&Json::from_str("{\"a\": 1, \"b\": {\"c\": 2}}").unwrap())));
+        let e = with(DuplicateKeys::Error).unwrap_err();
+        assert_eq!((e.message.as_str(), e.column), ("Duplicate key \"a\"", 25));
+        assert_eq!(with(DuplicateKeys::LastWins).unwrap_err().column, 25);
+        assert_eq!(with(DuplicateKeys::Keep), Ok(json_events(&Json::from_str(text).unwrap())));
+        let e = events("[{\"a\": [1]}]", &ParserOptions::new().max_depth(2)).unwrap_err();
+        assert_eq!((e.message.as_str(), e.column), ("Exceeds depth limit for parsing", 9));
+        assert_eq!(events("\u{FEFF}1", &ParserOptions::strict()).unwrap_err().message, "Byte order mark is not allowed");
+        assert_eq!(events("\u{FEFF} 1", &ParserOptions::new()), Ok(vec![Event::Number(1.0.into())]));
+        let mut events = EventParser::new("[1, x]");
+        assert_eq!(events.by_ref().take(2).collect::<Vec<_>>(), vec![Ok(Event::StartArray), Ok(Event::Number(1.0.into()))]);
+        assert!(events.next().unwrap().is_err());
+        assert_eq!(events.next(), None);
+    }
+}
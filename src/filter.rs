@@ -0,0 +1,2113 @@
+use std::prelude::v1::*;
+use super::parsercombinator::*;
+pub use super::parsercombinator::Span;
+use super::json::*;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+mod builtin;
+
+/// The variable for `input_line_number`, which no program can refer to by this name.
+const INPUT_LINE_NUMBER: &str = "input line number";
+
+/// A jq program.
+///
+/// ```
+/// # use toyjq::*;
+/// # use toyjq::filter::*;
+/// let filter = Filter::parse(".foo.bar").unwrap();
+/// let json = Json::from_str(r#"{"foo": {"bar": [1, 2]}}"#).unwrap();
+/// assert_eq!(filter.apply(&json), Ok(vec![Json::from_str("[1, 2]").unwrap()]));
+/// let filter = Filter::parse(".foo.bar[]").unwrap();
+/// assert_eq!(filter.apply(&json), Ok(vec![Json::JNumber(1f64.into()), Json::JNumber(2f64.into())]));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `.`
+    Identity,
+    /// A constant
+    Literal(Json<'static>),
+    /// `$name`
+    Var(String),
+    /// `@name` turns the input into a string in the format, such as `@csv` for a row of CSV
+    Format(String),
+    /// `"a\(f)b"` or `@name "a\(f)b"` outputs a string for every combination of the outputs of
+    /// the interpolated filters, which are turned into strings in the format, `text` by default
+    Interpolate(String, Vec<StringPart>),
+    /// `..` outputs the input and every value in it, depth first
+    Recurse,
+    /// `f.name`, where `f` is `Identity` for `.name`
+    Field(Box<Filter>, String),
+    /// `f[g]` outputs the element of `f` at each output of `g` on the input, a number for an
+    /// array counting from the end when it is negative, or a string for an object
+    Index(Box<Filter>, Box<Filter>),
+    /// `f[from:to]` of an array or a string, where both ends are optional
+    Slice(Box<Filter>, Option<isize>, Option<isize>),
+    /// `f[]` outputs every element of an array or every value of an object
+    Iterate(Box<Filter>),
+    /// `try f catch g`, or `f?` without `g`, stops at the first error of `f` after
+    /// the outputs preceding it, and then runs `g` on the error value
+    Try(Box<Filter>, Option<Box<Filter>>),
+    /// `[f]` collects the outputs of `f` into an array
+    Array(Box<Filter>),
+    /// `{k: v, ...}` outputs an object for every combination of the outputs of the keys and the values
+    Object(Vec<(Filter, Filter)>),
+    /// `if c then f else g end` runs `f` or `g` for each output of `c`
+    If(Box<Filter>, Box<Filter>, Box<Filter>),
+    /// `f as $x | g` runs `g` on the input once for each output of `f`, with `$x` bound to it.
+    /// `f as p0 ?// p1 | g` has more than one pattern, and tries the next one on an error.
+    Bind(Box<Filter>, Vec<Pattern>, Box<Filter>),
+    /// `reduce f as $x (init; update)` folds the outputs of `f` into each output of `init`,
+    /// running `update` on the accumulator with `$x` bound to each of them
+    Reduce(Box<Filter>, Pattern, Box<Filter>, Box<Filter>),
+    /// `label $name | f` runs `f`, which stops without an error at `break $name`
+    Label(String, Box<Filter>),
+    /// `break $name` stops the filter up to the innermost `label $name`
+    Break(String),
+    /// `def name(params): body; f` defines a function for `f`
+    Def(Box<FuncDef>, Box<Filter>),
+    /// `name` or `name(f; g)` runs a function defined by `def`, a filter parameter or a builtin
+    Call(String, Vec<Filter>),
+    /// `f // g` outputs the outputs of `f` except `false` and `null`, or those of `g` if there are none.
+    /// Errors of `f` are ignored.
+    Alternative(Box<Filter>, Box<Filter>),
+    /// `f = g`, `f |= g`, `f += g` and the like update the values at the paths of `f`
+    Assign(AssignOp, Box<Filter>, Box<Filter>),
+    /// `-f`
+    Neg(Box<Filter>),
+    /// `f op g` for every output of `f` and then every output of `g`
+    Binary(BinaryOp, Box<Filter>, Box<Filter>),
+    /// `f, g` outputs the outputs of `f` and then those of `g`
+    Comma(Box<Filter>, Box<Filter>),
+    /// `f | g` feeds the output of `f` to `g`
+    Pipe(Box<Filter>, Box<Filter>),
+    /// `f` with where it is in the program, which the runtime errors raised in `f` carry.
+    /// Only `Filter::parse_with_spans` keeps them.
+    Located(Box<Filter>, Span)
+}
+
+/// A piece of an interpolated string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Text(String),
+    /// `\(f)`
+    Filter(Filter)
+}
+
+/// What `as` binds variables with, such as `$x` in `f as $x | g`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `$name` binds the whole value
+    Var(String),
+    /// `[p0, p1, ...]` matches the elements of an array from the first
+    Array(Vec<Pattern>),
+    /// `{key: p, "key": p, (f): p, $name, $name: p}` matches the values of an object by the keys.
+    /// Each entry is the key, the variable bound to the value by `$name`, and the pattern of the value.
+    Object(Vec<(Filter, Option<String>, Option<Pattern>)>)
+}
+
+impl Pattern {
+    /// Pushes the names of the variables, each once, in the order they appear.
+    fn names<'p>(&'p self, names: &mut Vec<&'p str>) {
+        match *self {
+            Pattern::Var(ref name) => if !names.contains(&name.as_str()) {
+                names.push(name);
+            },
+            Pattern::Array(ref patterns) => for p in patterns {
+                p.names(names);
+            },
+            Pattern::Object(ref entries) => for (_, name, pattern) in entries {
+                if let Some(ref name) = *name {
+                    if !names.contains(&name.as_str()) {
+                        names.push(name);
+                    }
+                }
+                if let Some(ref pattern) = *pattern {
+                    pattern.names(names);
+                }
+            }
+        }
+    }
+
+    /// The keys of the object patterns.
+    fn filters(&self) -> Vec<&Filter> {
+        match *self {
+            Pattern::Var(_) => vec![],
+            Pattern::Array(ref patterns) => patterns.iter().flat_map(Pattern::filters).collect(),
+            Pattern::Object(ref entries) => entries.iter().flat_map(|(key, _, pattern)| {
+                let mut filters = vec![key];
+                filters.extend(pattern.iter().flat_map(Pattern::filters));
+                filters
+            }).collect()
+        }
+    }
+
+    fn filters_mut(&mut self) -> Vec<&mut Filter> {
+        match *self {
+            Pattern::Var(_) => vec![],
+            Pattern::Array(ref mut patterns) => patterns.iter_mut().flat_map(Pattern::filters_mut).collect(),
+            Pattern::Object(ref mut entries) => entries.iter_mut().flat_map(|(key, _, pattern)| {
+                let mut filters = vec![key];
+                filters.extend(pattern.iter_mut().flat_map(Pattern::filters_mut));
+                filters
+            }).collect()
+        }
+    }
+}
+
+/// `def name(params): body;`.
+/// Each parameter is a filter, which the body runs by its name like a function without arguments.
+/// A `$x` parameter is the parameter `x` with `x as $x | body` as the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Filter
+}
+
+impl FuncDef {
+    /// Pushes the functions which the body calls without defining them, like `Filter::free_calls`.
+    fn free_calls<'f>(&'f self, bound: &mut Vec<(&'f str, usize)>, calls: &mut Vec<(&'f str, usize)>) {
+        let len = bound.len();
+        bound.push((&self.name, self.params.len()));
+        bound.extend(self.params.iter().map(|p| (p.as_str(), 0)));
+        self.body.free_calls(bound, calls);
+        bound.truncate(len);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignOp {
+    /// `f = g` sets the values to each output of `g` on the input
+    Set,
+    /// `f |= g` replaces each value with the first output of `g` on it, or deletes it if there is none
+    Update,
+    /// `f += g` and the like update the values by the operator with each output of `g` on the input
+    Arithmetic(BinaryOp),
+    /// `f //= g` replaces `false` and `null` with each output of `g` on the input
+    Alternative
+}
+
+/// An error raised while running a filter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterError {
+    /// An error such as indexing a number, which `try` and `?` catch.
+    /// The value, usually a message, is the input of the `catch` handler.
+    /// The span is where in the program it was raised, if the filter keeps the spans.
+    Runtime(Json<'static>, Option<Span>),
+    /// Stops the filter up to where the label with the id is given, such as `break $name` or the end of `any(f)`
+    /// once `f` outputs `true`. Nothing but the label catches it, so it never escapes `apply`.
+    Break(usize),
+    /// Stops the whole program with the exit status by `halt` or `halt_error`,
+    /// the latter with the value to print to the standard error. Nothing catches it.
+    Halt(i32, Option<Json<'static>>)
+}
+
+impl FilterError {
+    pub fn message(message: String) -> FilterError {
+        FilterError::Runtime(Json::JString(Cow::Owned(message)), None)
+    }
+
+    /// Where in the program the error was raised, if known.
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            FilterError::Runtime(_, span) => span,
+            _ => None
+        }
+    }
+
+    /// Sets where the error was raised, unless it is already known.
+    fn at(self, span: Span) -> FilterError {
+        match self {
+            FilterError::Runtime(value, None) => FilterError::Runtime(value, Some(span)),
+            e => e
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FilterError::Runtime(Json::JString(ref s), _) => write!(f, "{}", s),
+            FilterError::Runtime(ref j, _) => write!(f, "{} (not a string)", print_json_compact(j)),
+            FilterError::Break(_) => write!(f, "break"),
+            FilterError::Halt(_, Some(Json::JString(ref s))) => write!(f, "{}", s),
+            FilterError::Halt(_, Some(ref j)) => write!(f, "{}", print_json_compact(j)),
+            FilterError::Halt(code, None) => write!(f, "halt with status {}", code)
+        }
+    }
+}
+
+pub type FilterResult<T> = Result<T, FilterError>;
+
+/// Where a filter sends its outputs, one at a time. An error stops the filter.
+type Output<'o, 'a> = dyn FnMut(Json<'a>) -> FilterResult<()> + 'o;
+
+/// Like `Output`, but for the outputs of a path expression.
+type PathOutput<'o, 'a> = dyn FnMut(PathValue<'a>) -> FilterResult<()> + 'o;
+
+/// Gives an id for `FilterError::Break`, which differs from all those given before.
+fn new_label() -> usize {
+    static LABELS: AtomicUsize = AtomicUsize::new(0);
+    LABELS.fetch_add(1, AtomicOrdering::Relaxed)
+}
+
+/// Runs `f` with a new label, and stops it without an error when it breaks to the label.
+fn with_label(f: impl FnOnce(usize) -> FilterResult<()>) -> FilterResult<()> {
+    let label = new_label();
+    match f(label) {
+        Err(FilterError::Break(l)) if l == label => Ok(()),
+        r => r
+    }
+}
+
+/// Runs `run` with its outputs sent to `out`, and keeps apart the errors of `out` as the outer
+/// error, so that `try` and `//` see only those of `run` itself.
+fn guard<T>(out: &mut dyn FnMut(T) -> FilterResult<()>, run: impl FnOnce(&mut dyn FnMut(T) -> FilterResult<()>) -> FilterResult<()>) -> FilterResult<FilterResult<()>> {
+    let mut stopped = None;
+    let label = new_label();
+    let result = run(&mut |j| out(j).map_err(|e| {
+        stopped = Some(e);
+        FilterError::Break(label)
+    }));
+    match stopped {
+        Some(e) => Err(e),
+        None => Ok(result)
+    }
+}
+
+impl Filter {
+    /// Parses `program` and defines the builtins written in jq which it calls.
+    pub fn parse(program: &str) -> Result<Filter, ParseError> {
+        let mut filter = Filter::parse_with_spans(program)?;
+        filter.strip_spans();
+        Ok(filter)
+    }
+
+    /// Like `parse`, but keeps the spans of the parts of `program` which may raise errors,
+    /// such as `.a.b` and `. + 1`, so that the runtime errors tell where they are raised.
+    pub fn parse_with_spans(program: &str) -> Result<Filter, ParseError> {
+        let filter = parse_filter().skip(eof()).parse(program)?;
+        link_builtins(filter)
+    }
+
+    /// Runs the filter on `json` and collects its outputs.
+    /// Accessing a field of `null`, or a missing field, gives `null`.
+    /// Likewise indexing `null`, or out of the range of an array, gives `null`.
+    pub fn apply<'a>(&self, json: &Json<'a>) -> FilterResult<Vec<Json<'a>>> {
+        self.apply_at(json, 0)
+    }
+
+    /// Like `apply`, where `input_line_number` gives `line`, the number of the lines read
+    /// till the end of `json`.
+    pub fn apply_at<'a>(&self, json: &Json<'a>, line: usize) -> FilterResult<Vec<Json<'a>>> {
+        self.apply_with_vars(json, line, &[])
+    }
+
+    /// Like `apply_at`, where the program may refer to the variables in `vars` such as
+    /// `$name` given by `--arg name value`. The later of the same names wins.
+    pub fn apply_with_vars<'a>(&self, json: &Json<'a>, line: usize, vars: &[(&str, Json<'a>)]) -> FilterResult<Vec<Json<'a>>> {
+        let mut outputs = vec![];
+        with_vars(vars, &Env::Var(INPUT_LINE_NUMBER, Json::JNumber((line as f64).into()), &Env::Empty), &mut |env| {
+            outputs = self.collect(json, env)?;
+            Ok(())
+        })?;
+        Ok(outputs)
+    }
+
+    fn collect<'a>(&self, json: &Json<'a>, env: &Env<'_, 'a>) -> FilterResult<Vec<Json<'a>>> {
+        let mut outputs = vec![];
+        self.eval(json, env, &mut |j| {
+            outputs.push(j);
+            Ok(())
+        })?;
+        Ok(outputs)
+    }
+
+    /// Sends the outputs to `out` one by one, until an error of the filter or of `out` stops it.
+    fn eval<'a>(&self, json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+        match *self {
+            Filter::Identity => out(json.clone()),
+            Filter::Literal(ref j) => out(j.clone()),
+            Filter::Var(ref name) => {
+                let value = env.lookup_var(name).ok_or_else(|| FilterError::message(format!("${} is not defined", name)))?;
+                out(value.clone())
+            },
+            Filter::Recurse => recurse(json, out),
+            Filter::Format(ref name) => out(Json::JString(Cow::Owned(find_format(name)?(json)?))),
+            Filter::Interpolate(ref name, ref parts) => interpolate(find_format(name)?, parts, json, env)?
+                .into_iter()
+                .try_for_each(|s| out(Json::JString(Cow::Owned(s)))),
+            Filter::Field(ref f, ref name) => f.eval(json, env, &mut |j| out(field(j, name)?)),
+            Filter::Index(ref f, ref g) => g.eval(json, env, &mut |key| f.eval(json, env, &mut |j| out(builtin::index_by(j, &key)?))),
+            Filter::Slice(ref f, from, to) => f.eval(json, env, &mut |j| out(slice(j, from, to)?)),
+            Filter::Iterate(ref f) => f.eval(json, env, &mut |j| iterate(j)?.into_iter().try_for_each(&mut *out)),
+            Filter::Try(ref f, ref handler) => match guard(out, |out| f.eval(json, env, out))? {
+                Err(FilterError::Runtime(e, _)) => match *handler {
+                    Some(ref g) => g.eval(&e, env, out),
+                    None => Ok(())
+                },
+                r => r
+            },
+            Filter::Array(ref f) => out(Json::JArray(f.collect(json, env)?)),
+            Filter::Object(ref entries) => construct_object(entries, json, env)?.into_iter().try_for_each(out),
+            Filter::If(ref c, ref f, ref g) => c.eval(json, env, &mut |cond| {
+                if is_truthy(&cond) { f.eval(json, env, out) } else { g.eval(json, env, out) }
+            }),
+            Filter::Bind(ref source, ref patterns, ref body) => source.eval(json, env, &mut |v| {
+                bind(patterns, &v, json, env, out, &|env, out| body.eval(json, env, out))
+            }),
+            Filter::Reduce(ref source, ref pattern, ref init, ref update) => {
+                let values = source.collect(json, env)?;
+                init.eval(json, env, &mut |mut acc| {
+                    for v in &values {
+                        destructure(pattern, v.clone(), json, env, &mut vec![], &mut |vars| with_vars(vars, env, &mut |env| {
+                            // Like jq, the last output of `update` is the next accumulator, or `null` if none.
+                            acc = update.collect(&acc, env)?.pop().unwrap_or(Json::JNull);
+                            Ok(())
+                        }))?;
+                    }
+                    out(acc)
+                })
+            },
+            Filter::Label(ref name, ref f) => with_label(|label| f.eval(json, &Env::Label(name, label, env), out)),
+            Filter::Break(ref name) => Err(FilterError::Break(env.lookup_label(name)?)),
+            Filter::Def(ref def, ref f) => f.eval(json, &Env::Func(def, env), out),
+            Filter::Call(ref name, ref args) => match env.lookup_func(name, args.len()) {
+                Some(Callee::Func(def, scope)) => call(def, args, scope, env, &mut |scope| def.body.eval(json, scope, out)),
+                Some(Callee::Arg(f, caller)) => f.eval(json, caller, out),
+                None => match builtin::native(name, args.len()) {
+                    Some(f) => f(args, json, env, out),
+                    None => Err(FilterError::message(format!("{}/{} is not defined", name, args.len())))
+                }
+            },
+            Filter::Alternative(ref f, ref g) => {
+                let mut found = false;
+                // The errors of `f` count as `false`, unlike a break or a halt.
+                let result = guard(out, |out| f.eval(json, env, &mut |j| {
+                    if !is_truthy(&j) {
+                        return Ok(());
+                    }
+                    found = true;
+                    out(j)
+                }))?;
+                if let Err(FilterError::Break(_)) | Err(FilterError::Halt(..)) = result {
+                    return result;
+                }
+                if found { Ok(()) } else { g.eval(json, env, out) }
+            },
+            Filter::Assign(AssignOp::Update, ref f, ref g) => {
+                out(update_paths(f, json, env, |old| Ok(g.collect(old, env)?.into_iter().next()))?)
+            },
+            Filter::Assign(op, ref f, ref g) => g.eval(json, env, &mut |v| {
+                out(update_paths(f, json, env, |old| Ok(Some(match op {
+                    AssignOp::Arithmetic(op) => binary(op, old.clone(), v.clone())?,
+                    AssignOp::Alternative if is_truthy(old) => old.clone(),
+                    _ => v.clone()
+                })))?)
+            }),
+            Filter::Neg(ref f) => f.eval(json, env, &mut |j| match j {
+                Json::JNumber(n) => out(Json::JNumber((-n.value()).into())),
+                j => Err(FilterError::message(format!("{} ({}) cannot be negated", type_name(&j), print_json_compact(&j))))
+            }),
+            Filter::Binary(op, ref f, ref g) => {
+                // Like jq, the right operand is the outer loop: `(1, 2) + (10, 20)` is `11, 12, 21, 22`.
+                let rhs = g.collect(json, env)?;
+                let lhs = f.collect(json, env)?;
+                for r in &rhs {
+                    for l in &lhs {
+                        out(binary(op, l.clone(), r.clone())?)?;
+                    }
+                }
+                Ok(())
+            },
+            Filter::Comma(ref f, ref g) => {
+                f.eval(json, env, out)?;
+                g.eval(json, env, out)
+            },
+            Filter::Pipe(ref f, ref g) => f.eval(json, env, &mut |j| g.eval(&j, env, out)),
+            // The errors sent back by `out` are not of `f`, and have their own spans if any.
+            Filter::Located(ref f, span) => guard(out, |out| f.eval(json, env, out))?.map_err(|e| e.at(span))
+        }
+    }
+
+    /// Runs the filter as a path expression on `json`, the value at the path in the input of the
+    /// expression, and sends the outputs with their paths.
+    /// Only filters which output parts of their inputs, such as `.a[]`, are path expressions.
+    fn eval_paths<'a>(&self, json: &PathValue<'a>, env: &Env<'_, 'a>, out: &mut PathOutput<'_, 'a>) -> FilterResult<()> {
+        let value = &json.1;
+        match *self {
+            Filter::Identity => out(json.clone()),
+            Filter::Recurse => recurse_paths(json.clone(), out),
+            Filter::Field(ref f, ref name) => f.eval_paths(json, env, &mut |(path, j)| {
+                out((append(path, Json::JString(Cow::Owned(name.clone()))), field(j, name)?))
+            }),
+            Filter::Index(ref f, ref g) => g.eval(value, env, &mut |key| f.eval_paths(json, env, &mut |(path, j)| {
+                let j = builtin::index_by(j, &key)?;
+                out((append(path, key.clone()), j))
+            })),
+            Filter::Slice(ref f, from, to) => f.eval_paths(json, env, &mut |(path, j)| {
+                let end = |i: Option<isize>| i.map_or(Json::JNull, |i| Json::JNumber((i as f64).into()));
+                let key = Json::JObject(vec![("start".into(), end(from)), ("end".into(), end(to))]);
+                out((append(path, key), slice(j, from, to)?))
+            }),
+            Filter::Iterate(ref f) => f.eval_paths(json, env, &mut |(path, j)| match j {
+                Json::JArray(items) => items.into_iter().enumerate().try_for_each(|(i, item)| out((append(path.clone(), Json::JNumber((i as f64).into())), item))),
+                Json::JObject(entries) => entries.into_iter().try_for_each(|(k, v)| out((append(path.clone(), Json::JString(k)), v))),
+                j => Err(cannot_iterate(&j))
+            }),
+            Filter::Try(ref f, ref handler) => match guard(out, |out| f.eval_paths(json, env, out))? {
+                Err(FilterError::Runtime(e, _)) => match *handler {
+                    Some(ref g) => no_paths(g, &e, env),
+                    None => Ok(())
+                },
+                r => r
+            },
+            Filter::If(ref c, ref f, ref g) => c.eval(value, env, &mut |cond| {
+                if is_truthy(&cond) { f.eval_paths(json, env, out) } else { g.eval_paths(json, env, out) }
+            }),
+            Filter::Bind(ref source, ref patterns, ref body) => source.eval(value, env, &mut |v| {
+                bind(patterns, &v, value, env, out, &|env, out| body.eval_paths(json, env, out))
+            }),
+            Filter::Label(ref name, ref f) => with_label(|label| f.eval_paths(json, &Env::Label(name, label, env), out)),
+            Filter::Break(ref name) => Err(FilterError::Break(env.lookup_label(name)?)),
+            Filter::Def(ref def, ref f) => f.eval_paths(json, &Env::Func(def, env), out),
+            Filter::Call(ref name, ref args) => match env.lookup_func(name, args.len()) {
+                Some(Callee::Func(def, scope)) => call(def, args, scope, env, &mut |scope| def.body.eval_paths(json, scope, out)),
+                Some(Callee::Arg(f, caller)) => f.eval_paths(json, caller, out),
+                None => match builtin::native_paths(name, args.len()) {
+                    Some(f) => f(args, json, env, out),
+                    None => no_paths(self, value, env)
+                }
+            },
+            Filter::Alternative(ref f, ref g) => {
+                let mut found = false;
+                let result = guard(out, |out| f.eval_paths(json, env, &mut |j| {
+                    if !is_truthy(&j.1) {
+                        return Ok(());
+                    }
+                    found = true;
+                    out(j)
+                }))?;
+                if let Err(FilterError::Break(_)) | Err(FilterError::Halt(..)) = result {
+                    return result;
+                }
+                if found { Ok(()) } else { g.eval_paths(json, env, out) }
+            },
+            Filter::Comma(ref f, ref g) => {
+                f.eval_paths(json, env, out)?;
+                g.eval_paths(json, env, out)
+            },
+            Filter::Pipe(ref f, ref g) => f.eval_paths(json, env, &mut |j| g.eval_paths(&j, env, out)),
+            Filter::Located(ref f, span) => guard(out, |out| f.eval_paths(json, env, out))?.map_err(|e| e.at(span)),
+            _ => no_paths(self, value, env)
+        }
+    }
+
+    /// Pushes the functions which the filter calls without defining them.
+    /// `bound` are the functions in scope, as pairs of the name and the number of the parameters.
+    fn free_calls<'f>(&'f self, bound: &mut Vec<(&'f str, usize)>, calls: &mut Vec<(&'f str, usize)>) {
+        match *self {
+            Filter::Def(ref def, ref f) => {
+                def.free_calls(bound, calls);
+                bound.push((&def.name, def.params.len()));
+                f.free_calls(bound, calls);
+                bound.pop();
+            },
+            Filter::Call(ref name, ref args) => {
+                if !bound.contains(&(name.as_str(), args.len())) {
+                    calls.push((name, args.len()));
+                }
+                for f in args {
+                    f.free_calls(bound, calls);
+                }
+            },
+            _ => for f in self.children() {
+                f.free_calls(bound, calls);
+            }
+        }
+    }
+
+    fn children(&self) -> Vec<&Filter> {
+        match *self {
+            Filter::Identity | Filter::Literal(_) | Filter::Var(_) | Filter::Recurse | Filter::Format(_) | Filter::Break(_) => vec![],
+            Filter::Interpolate(_, ref parts) => parts.iter().filter_map(|part| match *part {
+                StringPart::Filter(ref f) => Some(f),
+                StringPart::Text(_) => None
+            }).collect(),
+            Filter::Field(ref f, _) | Filter::Slice(ref f, _, _) | Filter::Iterate(ref f) |
+            Filter::Array(ref f) | Filter::Neg(ref f) | Filter::Label(_, ref f) | Filter::Located(ref f, _) => vec![&**f],
+            Filter::Try(ref f, ref handler) => {
+                let mut children = vec![&**f];
+                children.extend(handler.as_deref());
+                children
+            },
+            Filter::Object(ref entries) => entries.iter().flat_map(|(k, v)| vec![k, v]).collect(),
+            Filter::If(ref c, ref f, ref g) => vec![&**c, &**f, &**g],
+            Filter::Bind(ref f, ref patterns, ref g) => {
+                let mut children = vec![&**f, &**g];
+                children.extend(patterns.iter().flat_map(Pattern::filters));
+                children
+            },
+            Filter::Reduce(ref source, ref pattern, ref init, ref update) => {
+                let mut children = vec![&**source, &**init, &**update];
+                children.extend(pattern.filters());
+                children
+            },
+            Filter::Def(ref def, ref f) => vec![&def.body, &**f],
+            Filter::Call(_, ref args) => args.iter().collect(),
+            Filter::Index(ref f, ref g) | Filter::Alternative(ref f, ref g) | Filter::Assign(_, ref f, ref g) | Filter::Binary(_, ref f, ref g) |
+            Filter::Comma(ref f, ref g) | Filter::Pipe(ref f, ref g) => vec![&**f, &**g]
+        }
+    }
+
+    /// Like `children`, but mutable.
+    fn children_mut(&mut self) -> Vec<&mut Filter> {
+        match *self {
+            Filter::Identity | Filter::Literal(_) | Filter::Var(_) | Filter::Recurse | Filter::Format(_) | Filter::Break(_) => vec![],
+            Filter::Interpolate(_, ref mut parts) => parts.iter_mut().filter_map(|part| match *part {
+                StringPart::Filter(ref mut f) => Some(f),
+                StringPart::Text(_) => None
+            }).collect(),
+            Filter::Field(ref mut f, _) | Filter::Slice(ref mut f, _, _) | Filter::Iterate(ref mut f) |
+            Filter::Array(ref mut f) | Filter::Neg(ref mut f) | Filter::Label(_, ref mut f) | Filter::Located(ref mut f, _) => vec![&mut **f],
+            Filter::Try(ref mut f, ref mut handler) => {
+                let mut children = vec![&mut **f];
+                children.extend(handler.as_deref_mut());
+                children
+            },
+            Filter::Object(ref mut entries) => entries.iter_mut().flat_map(|(k, v)| vec![k, v]).collect(),
+            Filter::If(ref mut c, ref mut f, ref mut g) => vec![&mut **c, &mut **f, &mut **g],
+            Filter::Bind(ref mut f, ref mut patterns, ref mut g) => {
+                let mut children = vec![&mut **f, &mut **g];
+                children.extend(patterns.iter_mut().flat_map(Pattern::filters_mut));
+                children
+            },
+            Filter::Reduce(ref mut source, ref mut pattern, ref mut init, ref mut update) => {
+                let mut children = vec![&mut **source, &mut **init, &mut **update];
+                children.extend(pattern.filters_mut());
+                children
+            },
+            Filter::Def(ref mut def, ref mut f) => vec![&mut def.body, &mut **f],
+            Filter::Call(_, ref mut args) => args.iter_mut().collect(),
+            Filter::Index(ref mut f, ref mut g) | Filter::Alternative(ref mut f, ref mut g) | Filter::Assign(_, ref mut f, ref mut g) | Filter::Binary(_, ref mut f, ref mut g) |
+            Filter::Comma(ref mut f, ref mut g) | Filter::Pipe(ref mut f, ref mut g) => vec![&mut **f, &mut **g]
+        }
+    }
+
+    /// Removes every `Located` in the filter.
+    fn strip_spans(&mut self) {
+        while let Filter::Located(ref mut f, _) = *self {
+            *self = mem::replace(&mut **f, Filter::Identity);
+        }
+        for f in self.children_mut() {
+            f.strip_spans();
+        }
+    }
+}
+
+/// Puts the definitions of the builtins in `PRELUDE` which `filter` calls in front of it,
+/// and checks that every function called is defined.
+fn link_builtins(filter: Filter) -> Result<Filter, ParseError> {
+    fn not_native(calls: Vec<(&str, usize)>) -> Vec<(String, usize)> {
+        calls.into_iter().filter(|&(name, arity)| builtin::native(name, arity).is_none()).map(|(name, arity)| (name.to_string(), arity)).collect()
+    }
+    let mut calls = vec![];
+    filter.free_calls(&mut vec![], &mut calls);
+    let mut calls = not_native(calls);
+    if calls.is_empty() {
+        return Ok(filter);
+    }
+    // The prelude has a definition on each line, which is parsed only when it is called.
+    let lines: Vec<&str> = builtin::PRELUDE.lines().filter(|line| !line.is_empty()).collect();
+    let mut defs: Vec<Option<FuncDef>> = lines.iter().map(|_| None).collect();
+    let mut used = vec![false; lines.len()];
+    while let Some((name, arity)) = calls.pop() {
+        let head = format!("def {}", name);
+        let candidates = lines.iter().enumerate()
+            .filter(|(_, line)| line.strip_prefix(head.as_str()).is_some_and(|rest| rest.starts_with([':', '('])))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let found = candidates.into_iter().find(|&i| {
+            let def = defs[i].get_or_insert_with(|| {
+                let mut def = keyword("def").then(parse_def()).skip(eof()).parse(lines[i]).expect("the prelude is broken");
+                // The errors in the builtins are told at the calls in the program instead.
+                def.body.strip_spans();
+                def
+            });
+            def.params.len() == arity
+        });
+        match found {
+            Some(i) if !used[i] => {
+                used[i] = true;
+                let mut def_calls = vec![];
+                defs[i].as_ref().unwrap().free_calls(&mut vec![], &mut def_calls);
+                calls.extend(not_native(def_calls));
+            },
+            Some(_) => (),
+            None => return Err(ParseError {retry: false, message: format!("{}/{} is not defined", name, arity), pos: 0, line: 1, column: 1})
+        }
+    }
+    // A definition in the prelude calls only those above it, so they are put outside of it.
+    let defs = defs.into_iter().zip(used).filter_map(|(def, used)| if used { def } else { None }).collect::<Vec<_>>();
+    Ok(defs.into_iter().rev().fold(filter, |f, def| Filter::Def(Box::new(def), Box::new(f))))
+}
+
+/// Binds the last parameters of `def` to `args` and runs `body` in the scope, which
+/// has the definition and the parameters bound already.
+fn call<'a>(def: &FuncDef, args: &[Filter], scope: &Env<'_, 'a>, caller: &Env<'_, 'a>, body: &mut dyn FnMut(&Env<'_, 'a>) -> FilterResult<()>) -> FilterResult<()> {
+    match args.split_first() {
+        None => body(scope),
+        Some((arg, rest)) => {
+            let param = &def.params[def.params.len() - args.len()];
+            call(def, rest, &Env::Arg(param, arg, caller, scope), caller, body)
+        }
+    }
+}
+
+/// A value and its path, the keys and the indices which lead to it from the input of a path expression.
+type PathValue<'a> = (Vec<Json<'a>>, Json<'a>);
+
+fn append<'a>(mut path: Vec<Json<'a>>, key: Json<'a>) -> Vec<Json<'a>> {
+    path.push(key);
+    path
+}
+
+/// Replaces each value at the paths of `f` in `json` with `update` on it, in order.
+/// `update` gives `None` to delete the value, which is done after all the replacements.
+fn update_paths<'a, U>(f: &Filter, json: &Json<'a>, env: &Env<'_, 'a>, mut update: U) -> FilterResult<Json<'a>>
+    where U: FnMut(&Json<'a>) -> FilterResult<Option<Json<'a>>>
+{
+    let mut paths = vec![];
+    f.eval_paths(&(vec![], json.clone()), env, &mut |p| {
+        paths.push(p);
+        Ok(())
+    })?;
+    let mut result = json.clone();
+    let mut deleted = vec![];
+    for (path, _) in paths {
+        let mut delete = false;
+        result = builtin::update_path(result, &path, &mut |old| match update(&old)? {
+            Some(new) => Ok(new),
+            None => {
+                delete = true;
+                Ok(old)
+            }
+        })?;
+        if delete {
+            deleted.push(Json::JArray(path));
+        }
+    }
+    builtin::delete_paths(result, deleted)
+}
+
+/// Runs `f`, which is not a path expression, to raise an error if it outputs something.
+fn no_paths<'a>(f: &Filter, json: &Json<'a>, env: &Env<'_, 'a>) -> FilterResult<()> {
+    f.eval(json, env, &mut |j| Err(FilterError::message(format!("Invalid path expression with result {}", print_json_compact(&j)))))
+}
+
+/// What runs with the variables bound by `bind`, sending its outputs to the second argument.
+type Body<'f, 'a, T> = dyn Fn(&Env<'_, 'a>, &mut dyn FnMut(T) -> FilterResult<()>) -> FilterResult<()> + 'f;
+
+/// Variables bound by a pattern, in the order they appear.
+type Vars<'p, 'a> = Vec<(&'p str, Json<'a>)>;
+
+/// Where `destructure` sends the variables, for every way of binding them.
+type VarsOutput<'o, 'p, 'a> = dyn FnMut(&mut Vars<'p, 'a>) -> FilterResult<()> + 'o;
+
+/// Binds the variables of the first of `patterns` which `value` matches, and runs `run` with them.
+/// An error in `run` moves on to the next pattern as well, but not one of the last pattern.
+/// Every variable of the patterns is bound, to `null` if not in the pattern being tried.
+/// The keys of the object patterns run on `json`.
+fn bind<'a, T>(patterns: &[Pattern], value: &Json<'a>, json: &Json<'a>, env: &Env<'_, 'a>, out: &mut dyn FnMut(T) -> FilterResult<()>,
+        run: &Body<'_, 'a, T>) -> FilterResult<()> {
+    let mut names = vec![];
+    for pattern in patterns {
+        pattern.names(&mut names);
+    }
+    for (i, pattern) in patterns.iter().enumerate() {
+        let result = guard(out, |out| destructure(pattern, value.clone(), json, env, &mut vec![], &mut |vars| {
+            let vars: Vec<_> = names.iter().map(|&name| {
+                let value = vars.iter().rev().find(|v| v.0 == name).map_or(Json::JNull, |v| v.1.clone());
+                (name, value)
+            }).collect();
+            with_vars(&vars, env, &mut |env| run(env, out))
+        }))?;
+        match result {
+            Err(FilterError::Runtime(..)) if i + 1 < patterns.len() => (),
+            r => return r
+        }
+    }
+    Ok(())
+}
+
+/// Sends the variables bound by `pattern` to `value`, after those in `vars`, for every output of
+/// the keys of the object patterns.
+fn destructure<'p, 'a>(pattern: &'p Pattern, value: Json<'a>, json: &Json<'a>, env: &Env<'_, 'a>, vars: &mut Vars<'p, 'a>,
+        out: &mut VarsOutput<'_, 'p, 'a>) -> FilterResult<()> {
+    match *pattern {
+        Pattern::Var(ref name) => {
+            vars.push((name, value));
+            let result = out(vars);
+            vars.pop();
+            result
+        },
+        Pattern::Array(ref patterns) => destructure_array(patterns, 0, &value, json, env, vars, out),
+        Pattern::Object(ref entries) => destructure_object(entries, &value, json, env, vars, out)
+    }
+}
+
+/// Destructures the elements from the index `i` of `value` with `patterns`.
+fn destructure_array<'p, 'a>(patterns: &'p [Pattern], i: usize, value: &Json<'a>, json: &Json<'a>, env: &Env<'_, 'a>,
+        vars: &mut Vars<'p, 'a>, out: &mut VarsOutput<'_, 'p, 'a>) -> FilterResult<()> {
+    match patterns.split_first() {
+        None => out(vars),
+        Some((pattern, rest)) => destructure(pattern, index(value.clone(), i as isize)?, json, env, vars, &mut |vars| {
+            destructure_array(rest, i + 1, value, json, env, vars, out)
+        })
+    }
+}
+
+fn destructure_object<'p, 'a>(entries: &'p [(Filter, Option<String>, Option<Pattern>)], value: &Json<'a>, json: &Json<'a>, env: &Env<'_, 'a>,
+        vars: &mut Vars<'p, 'a>, out: &mut VarsOutput<'_, 'p, 'a>) -> FilterResult<()> {
+    let ((key, name, pattern), rest) = match entries.split_first() {
+        None => return out(vars),
+        Some(entry) => entry
+    };
+    key.eval(json, env, &mut |key| {
+        let v = match key {
+            Json::JString(ref k) => field(value.clone(), k)?,
+            ref k => return Err(FilterError::message(format!("Cannot index {} with {}", type_name(value), type_name(k))))
+        };
+        let len = vars.len();
+        vars.extend(name.as_ref().map(|name| (name.as_str(), v.clone())));
+        let result = match *pattern {
+            Some(ref pattern) => destructure(pattern, v, json, env, vars, &mut |vars| destructure_object(rest, value, json, env, vars, out)),
+            None => destructure_object(rest, value, json, env, vars, out)
+        };
+        vars.truncate(len);
+        result
+    })
+}
+
+/// Runs `f` with `vars` bound after `env`.
+fn with_vars<'a>(vars: &[(&str, Json<'a>)], env: &Env<'_, 'a>, f: &mut dyn FnMut(&Env<'_, 'a>) -> FilterResult<()>) -> FilterResult<()> {
+    match vars.split_first() {
+        None => f(env),
+        Some(((name, value), rest)) => with_vars(rest, &Env::Var(name, value.clone(), env), f)
+    }
+}
+
+/// Variables and functions in scope, the innermost first.
+enum Env<'e, 'a: 'e> {
+    Empty,
+    Var(&'e str, Json<'a>, &'e Env<'e, 'a>),
+    Func(&'e FuncDef, &'e Env<'e, 'a>),
+    /// `label $name` with the id of the label
+    Label(&'e str, usize, &'e Env<'e, 'a>),
+    /// A filter parameter, which runs the argument in the environment of the caller
+    Arg(&'e str, &'e Filter, &'e Env<'e, 'a>, &'e Env<'e, 'a>)
+}
+
+/// What a function call runs.
+enum Callee<'e, 'a: 'e> {
+    /// A definition and the environment where it is defined, including itself for recursion
+    Func(&'e FuncDef, &'e Env<'e, 'a>),
+    /// An argument and the environment of the caller
+    Arg(&'e Filter, &'e Env<'e, 'a>)
+}
+
+impl <'e, 'a> Env<'e, 'a> {
+    fn lookup_var(&self, name: &str) -> Option<&Json<'a>> {
+        match *self {
+            Env::Empty => None,
+            Env::Var(n, ref value, _) if n == name => Some(value),
+            Env::Var(_, _, parent) | Env::Func(_, parent) | Env::Label(_, _, parent) | Env::Arg(_, _, _, parent) => parent.lookup_var(name)
+        }
+    }
+
+    fn lookup_label(&self, name: &str) -> FilterResult<usize> {
+        match *self {
+            Env::Empty => Err(FilterError::message(format!("$*label-{} is not defined", name))),
+            Env::Label(n, label, _) if n == name => Ok(label),
+            Env::Var(_, _, parent) | Env::Func(_, parent) | Env::Label(_, _, parent) | Env::Arg(_, _, _, parent) => parent.lookup_label(name)
+        }
+    }
+
+    fn lookup_func(&'e self, name: &str, arity: usize) -> Option<Callee<'e, 'a>> {
+        match *self {
+            Env::Empty => None,
+            Env::Func(def, _) if def.name == name && def.params.len() == arity => Some(Callee::Func(def, self)),
+            Env::Arg(n, f, caller, _) if n == name && arity == 0 => Some(Callee::Arg(f, caller)),
+            Env::Var(_, _, parent) | Env::Func(_, parent) | Env::Label(_, _, parent) | Env::Arg(_, _, _, parent) => parent.lookup_func(name, arity)
+        }
+    }
+}
+
+/// Anything but `false` and `null` is true.
+fn is_truthy(json: &Json) -> bool {
+    !matches!(*json, Json::JNull | Json::JBool(false))
+}
+
+/// The name of the type of `json` used in error messages.
+fn type_name(json: &Json) -> &'static str {
+    match *json {
+        Json::JNull => "null",
+        Json::JBool(_) => "boolean",
+        Json::JNumber(_) => "number",
+        Json::JString(_) => "string",
+        Json::JArray(_) => "array",
+        Json::JObject(_) => "object"
+    }
+}
+
+fn binary<'a>(op: BinaryOp, lhs: Json<'a>, rhs: Json<'a>) -> FilterResult<Json<'a>> {
+    use self::BinaryOp::*;
+    use json::Json::*;
+    let error = |lhs: &Json, rhs: &Json, verb: &str| {
+        Err(FilterError::message(format!("{} ({}) and {} ({}) cannot be {}",
+            type_name(lhs), print_json_compact(lhs), type_name(rhs), print_json_compact(rhs), verb)))
+    };
+    match (op, lhs, rhs) {
+        (Add, JNull, j) | (Add, j, JNull) => Ok(j),
+        (Add, JNumber(a), JNumber(b)) => Ok(JNumber((a.value() + b.value()).into())),
+        (Add, JString(a), JString(b)) => Ok(JString(Cow::Owned(a.into_owned() + &b))),
+        (Add, JArray(mut a), JArray(b)) => {
+            a.extend(b);
+            Ok(JArray(a))
+        },
+        (Add, JObject(mut a), JObject(b)) => {
+            for (k, v) in b {
+                insert(&mut a, k, v);
+            }
+            Ok(JObject(a))
+        },
+        (Add, l, r) => error(&l, &r, "added"),
+        (Sub, JNumber(a), JNumber(b)) => Ok(JNumber((a.value() - b.value()).into())),
+        (Sub, JArray(a), JArray(b)) => {
+            Ok(JArray(a.into_iter().filter(|x| b.iter().all(|y| compare(x, y) != Ordering::Equal)).collect()))
+        },
+        (Sub, l, r) => error(&l, &r, "subtracted"),
+        (Mul, JNumber(a), JNumber(b)) => Ok(JNumber((a.value() * b.value()).into())),
+        (Mul, JString(s), JNumber(n)) | (Mul, JNumber(n), JString(s)) => {
+            Ok(if n.value() >= 1f64 { JString(Cow::Owned(s.repeat(n.value() as usize))) } else { JNull })
+        },
+        (Mul, JObject(a), JObject(b)) => Ok(JObject(deep_merge(a, b))),
+        (Mul, l, r) => error(&l, &r, "multiplied"),
+        (Div, JNumber(a), JNumber(b)) if b.value() == 0f64 => error(&JNumber(a), &JNumber(b), "divided because the divisor is zero"),
+        (Div, JNumber(a), JNumber(b)) => Ok(JNumber((a.value() / b.value()).into())),
+        (Div, JString(a), JString(b)) => {
+            if a.is_empty() {
+                return Ok(JArray(vec![]))
+            }
+            Ok(JArray(a.split(&*b).map(|s| JString(Cow::Owned(s.to_string()))).collect()))
+        },
+        (Div, l, r) => error(&l, &r, "divided"),
+        // Like jq, the remainder is of the operands truncated to integers.
+        (Mod, JNumber(a), JNumber(b)) if b.value() as i64 == 0 => error(&JNumber(a), &JNumber(b), "divided because the divisor is zero"),
+        (Mod, JNumber(a), JNumber(b)) => Ok(JNumber(((a.value() as i64).wrapping_rem(b.value() as i64) as f64).into())),
+        (Mod, l, r) => error(&l, &r, "divided"),
+        (Eq, l, r) => Ok(JBool(compare_operands(&l, &r) == Ordering::Equal)),
+        (Ne, l, r) => Ok(JBool(compare_operands(&l, &r) != Ordering::Equal)),
+        (Lt, l, r) => Ok(JBool(compare_operands(&l, &r) == Ordering::Less)),
+        (Le, l, r) => Ok(JBool(compare_operands(&l, &r) != Ordering::Greater)),
+        (Gt, l, r) => Ok(JBool(compare_operands(&l, &r) == Ordering::Greater)),
+        (Ge, l, r) => Ok(JBool(compare_operands(&l, &r) != Ordering::Less))
+    }
+}
+
+/// jq's total order of values: `null < false < true < numbers < strings < arrays < objects`.
+/// Arrays are ordered lexicographically. Objects are ordered by their sorted keys first
+/// and then by the values under those keys.
+/// `compare` for the comparison operators, where NaN is less than any number including itself
+/// like jq, so that `nan == nan` is `false`. `compare` keeps NaN equal to itself for sorting.
+fn compare_operands(a: &Json, b: &Json) -> Ordering {
+    match (a, b) {
+        (Json::JNumber(x), Json::JNumber(_)) if x.value().is_nan() => Ordering::Less,
+        (Json::JNumber(_), Json::JNumber(y)) if y.value().is_nan() => Ordering::Greater,
+        _ => compare(a, b)
+    }
+}
+
+fn compare(a: &Json, b: &Json) -> Ordering {
+    fn rank(json: &Json) -> u8 {
+        match *json {
+            Json::JNull => 0,
+            Json::JBool(false) => 1,
+            Json::JBool(true) => 2,
+            Json::JNumber(_) => 3,
+            Json::JString(_) => 4,
+            Json::JArray(_) => 5,
+            Json::JObject(_) => 6
+        }
+    }
+    match (a, b) {
+        // NaN is less than any number, as in jq.
+        (Json::JNumber(x), Json::JNumber(y)) => {
+            let (x, y) = (x.value(), y.value());
+            x.partial_cmp(&y).unwrap_or_else(|| y.is_nan().cmp(&x.is_nan()))
+        },
+        (Json::JString(x), Json::JString(y)) => x.cmp(y),
+        (Json::JArray(xs), Json::JArray(ys)) => {
+            xs.iter().zip(ys).map(|(x, y)| compare(x, y)).find(|&o| o != Ordering::Equal)
+                .unwrap_or_else(|| xs.len().cmp(&ys.len()))
+        },
+        (Json::JObject(xs), Json::JObject(ys)) => {
+            let (xs, ys) = (sorted_entries(xs), sorted_entries(ys));
+            xs.iter().map(|kv| kv.0).cmp(ys.iter().map(|kv| kv.0)).then_with(|| {
+                xs.iter().zip(&ys).map(|(x, y)| compare(x.1, y.1)).find(|&o| o != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            })
+        },
+        _ => rank(a).cmp(&rank(b))
+    }
+}
+
+/// Sets `key` of `obj` to `value`. A repeated key overwrites the former value in place.
+fn insert<'a>(obj: &mut Vec<(Cow<'a, str>, Json<'a>)>, key: Cow<'a, str>, value: Json<'a>) {
+    match obj.iter().position(|kv| kv.0 == key) {
+        Some(i) => obj[i].1 = value,
+        None => obj.push((key, value))
+    }
+}
+
+/// Merges `b` into `a`, recursively where both have an object under the same key.
+fn deep_merge<'a>(mut a: Vec<(Cow<'a, str>, Json<'a>)>, b: Vec<(Cow<'a, str>, Json<'a>)>) -> Vec<(Cow<'a, str>, Json<'a>)> {
+    for (k, v) in b {
+        match a.iter().position(|kv| kv.0 == k) {
+            Some(i) => {
+                let old = mem::replace(&mut a[i].1, Json::JNull);
+                a[i].1 = match (old, v) {
+                    (Json::JObject(x), Json::JObject(y)) => Json::JObject(deep_merge(x, y)),
+                    (_, v) => v
+                };
+            },
+            None => a.push((k, v))
+        }
+    }
+    a
+}
+
+fn sorted_entries<'j>(obj: &'j [(Cow<str>, Json)]) -> Vec<(&'j str, &'j Json<'j>)> {
+    let mut kvs: Vec<(&str, &Json)> = obj.iter().map(|kv| (&*kv.0, &kv.1)).collect();
+    kvs.sort_by_key(|kv| kv.0);
+    kvs
+}
+
+fn find_format(name: &str) -> FilterResult<builtin::Format> {
+    builtin::format(name).ok_or_else(|| FilterError::message(format!("@{} is not a valid format", name)))
+}
+
+/// The strings for every combination of the outputs of the interpolated filters,
+/// where those of the last one vary the slowest like jq.
+fn interpolate(format: builtin::Format, parts: &[StringPart], json: &Json, env: &Env) -> FilterResult<Vec<String>> {
+    let mut strings = vec![String::new()];
+    for part in parts {
+        strings = match *part {
+            StringPart::Text(ref text) => strings.into_iter().map(|s| s + text).collect(),
+            StringPart::Filter(ref f) => {
+                let mut next = vec![];
+                for j in f.collect(json, env)? {
+                    let text = format(&j)?;
+                    next.extend(strings.iter().map(|s| format!("{}{}", s, text)));
+                }
+                next
+            }
+        };
+    }
+    Ok(strings)
+}
+
+/// Builds an object for every combination of the outputs of the keys and the values.
+/// The combinations of the first entry change the slowest.
+fn construct_object<'a>(entries: &[(Filter, Filter)], json: &Json<'a>, env: &Env<'_, 'a>) -> FilterResult<Vec<Json<'a>>> {
+    let mut objects = vec![vec![]];
+    for (k, v) in entries {
+        let keys = k.collect(json, env)?;
+        let values = v.collect(json, env)?;
+        let mut next = vec![];
+        for obj in &objects {
+            for key in &keys {
+                let key = match *key {
+                    Json::JString(ref s) => s,
+                    ref j => return Err(FilterError::message(format!("Object keys must be strings, not {}", type_name(j))))
+                };
+                for value in &values {
+                    let mut obj: Vec<(Cow<str>, Json)> = obj.clone();
+                    insert(&mut obj, key.clone(), value.clone());
+                    next.push(obj);
+                }
+            }
+        }
+        objects = next;
+    }
+    Ok(objects.into_iter().map(Json::JObject).collect())
+}
+
+fn recurse<'a>(json: &Json<'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(json.clone())?;
+    match *json {
+        Json::JArray(ref jsons) => jsons.iter().try_for_each(|j| recurse(j, out)),
+        Json::JObject(ref obj) => obj.iter().try_for_each(|kv| recurse(&kv.1, out)),
+        _ => Ok(())
+    }
+}
+
+fn recurse_paths<'a>(json: PathValue<'a>, out: &mut PathOutput<'_, 'a>) -> FilterResult<()> {
+    out(json.clone())?;
+    let (path, value) = json;
+    match value {
+        Json::JArray(jsons) => jsons.into_iter().enumerate().try_for_each(|(i, j)| {
+            recurse_paths((append(path.clone(), Json::JNumber((i as f64).into())), j), out)
+        }),
+        Json::JObject(obj) => obj.into_iter().try_for_each(|(k, j)| {
+            recurse_paths((append(path.clone(), Json::JString(k)), j), out)
+        }),
+        _ => Ok(())
+    }
+}
+
+fn field<'a>(json: Json<'a>, name: &str) -> FilterResult<Json<'a>> {
+    match json {
+        Json::JObject(obj) => Ok(obj.into_iter().find(|kv| kv.0 == name).map_or(Json::JNull, |kv| kv.1)),
+        Json::JNull => Ok(Json::JNull),
+        j => Err(FilterError::message(format!("Cannot index {} with \"{}\"", type_name(&j), name)))
+    }
+}
+
+fn index(json: Json, i: isize) -> FilterResult<Json> {
+    match json {
+        Json::JArray(mut jsons) => {
+            let i = if i < 0 { i + jsons.len() as isize } else { i };
+            Ok(if 0 <= i && (i as usize) < jsons.len() { jsons.swap_remove(i as usize) } else { Json::JNull })
+        },
+        Json::JNull => Ok(Json::JNull),
+        j => Err(FilterError::message(format!("Cannot index {} with number", type_name(&j))))
+    }
+}
+
+fn slice(json: Json, from: Option<isize>, to: Option<isize>) -> FilterResult<Json> {
+    match json {
+        Json::JArray(jsons) => {
+            let (start, end) = slice_range(jsons.len(), from, to);
+            Ok(Json::JArray(jsons.into_iter().skip(start).take(end - start).collect()))
+        },
+        Json::JString(s) => {
+            let (start, end) = slice_range(s.chars().count(), from, to);
+            let offset = |i| s.char_indices().nth(i).map_or(s.len(), |(b, _)| b);
+            let (start, end) = (offset(start), offset(end));
+            Ok(Json::JString(match s {
+                Cow::Borrowed(s) => Cow::Borrowed(&s[start..end]),
+                Cow::Owned(s) => Cow::Owned(s[start..end].to_string())
+            }))
+        },
+        Json::JNull => Ok(Json::JNull),
+        j => Err(FilterError::message(format!("Cannot index {} with object", type_name(&j))))
+    }
+}
+
+fn iterate(json: Json) -> FilterResult<Vec<Json>> {
+    match json {
+        Json::JArray(jsons) => Ok(jsons),
+        Json::JObject(obj) => Ok(obj.into_iter().map(|kv| kv.1).collect()),
+        j => Err(cannot_iterate(&j))
+    }
+}
+
+fn cannot_iterate(json: &Json) -> FilterError {
+    FilterError::message(format!("Cannot iterate over {}", type_name(json)))
+}
+
+/// Resolves the ends of a slice of a sequence of `len` items like Python does:
+/// negative ends count from the end and both are clamped into the sequence.
+fn slice_range(len: usize, from: Option<isize>, to: Option<isize>) -> (usize, usize) {
+    let resolve = |i: isize| {
+        let i = if i < 0 { i + len as isize } else { i };
+        i.max(0).min(len as isize) as usize
+    };
+    let start = from.map_or(0, resolve);
+    let end = to.map_or(len, resolve);
+    (start, end.max(start))
+}
+
+const IDENT_START: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+const IDENT_REST: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_0123456789";
+
+fn parse_filter<'a>() -> Parser<'a, Filter> {
+    parse_pipe(parse_comma)
+}
+
+/// `operand`s separated by `|`, the loosest operator.
+/// The pipe is right associative: `f | g | h` is `f | (g | h)`.
+fn parse_pipe<'a>(operand: fn() -> Parser<'a, Filter>) -> Parser<'a, Filter> {
+    operand()
+        .and(chr('|').with_spaces().then_lazy(operand).many())
+        .map(|(head, rest)| {
+            let mut terms = vec![head];
+            terms.extend(rest);
+            let last = terms.pop().unwrap();
+            terms.into_iter().rev().fold(last, |g, f| Filter::Pipe(Box::new(f), Box::new(g)))
+        })
+}
+
+/// Alternatives separated by `,`.
+fn parse_comma<'a>() -> Parser<'a, Filter> {
+    parse_alternative()
+        .and(chr(',').with_spaces().then_lazy(parse_alternative).many())
+        .map(|(head, rest)| rest.into_iter().fold(head, |f, g| Filter::Comma(Box::new(f), Box::new(g))))
+}
+
+/// Assignments separated by `//`, which is right associative.
+fn parse_alternative<'a>() -> Parser<'a, Filter> {
+    parse_assign()
+        .and(operator("//").with_spaces().then_lazy(parse_alternative).or_not())
+        .map(|(f, g)| match g {
+            Some(g) => Filter::Alternative(Box::new(f), Box::new(g)),
+            None => f
+        })
+}
+
+/// A comparison, or two comparisons with one of `=`, `|=`, `+=`, `-=`, `*=`, `/=`, `%=` or `//=`.
+fn parse_assign<'a>() -> Parser<'a, Filter> {
+    use self::AssignOp::*;
+    use self::BinaryOp::*;
+    let ops = [("|=", Update), ("+=", Arithmetic(Add)), ("-=", Arithmetic(Sub)), ("*=", Arithmetic(Mul)),
+        ("//=", Alternative), ("/=", Arithmetic(Div)), ("%=", Arithmetic(Mod)), ("=", Set)];
+    let op = or_from(ops.iter().map(|&(s, op)| operator(s).map_(op))).with_spaces();
+    start().and(parse_comparison())
+        .and(op.and_lazy(parse_comparison).and(position()).or_not())
+        .map(|((start, f), rest)| match rest {
+            Some(((op, g), end)) => located(Filter::Assign(op, Box::new(f), Box::new(g)), start, end),
+            None => f
+        })
+}
+
+/// The position after the whitespace at the current one, where the next filter starts.
+fn start<'a>() -> Parser<'a, Position> {
+    or_from(" \n\t".chars().map(chr)).many().then(position())
+}
+
+/// `f` which keeps the span from `start` to `end` for its runtime errors.
+/// Identities and literals never raise errors and are left as they are.
+fn located(f: Filter, start: Position, end: Position) -> Filter {
+    match f {
+        Filter::Identity | Filter::Literal(_) => f,
+        f => Filter::Located(Box::new(f), Span {start, end})
+    }
+}
+
+/// The operator `s`, which is not the head of a longer one such as `+=` or `//`.
+fn operator<'a>(s: &'static str) -> Parser<'a, &'static str> {
+    string(s)
+        .and(or_from("=/".chars().map(chr)).or_not())
+        .flat_map(move |(s, next)| match next {
+            Some(_) => failure(format!("Expected `{}`", s)).map_(s),
+            None => unit(s)
+        })
+        .try()
+}
+
+fn parse_binary_op<'a>(ops: &[(&'static str, BinaryOp)]) -> Parser<'a, BinaryOp> {
+    or_from(ops.iter().map(|&(s, op)| operator(s).map_(op))).with_spaces()
+}
+
+/// Two arithmetic expressions compared by one of `==`, `!=`, `<`, `<=`, `>` or `>=`.
+/// Comparisons do not chain: `a < b < c` is an error.
+fn parse_comparison<'a>() -> Parser<'a, Filter> {
+    use self::BinaryOp::*;
+    // Longer operators first so that `<` does not match the head of `<=`.
+    let op = parse_binary_op(&[("==", Eq), ("!=", Ne), ("<=", Le), (">=", Ge), ("<", Lt), (">", Gt)]);
+    start().and(parse_additive())
+        .and(op.and_lazy(parse_additive).and(position()).or_not())
+        .map(|((start, f), rest)| match rest {
+            Some(((op, g), end)) => located(Filter::Binary(op, Box::new(f), Box::new(g)), start, end),
+            None => f
+        })
+}
+
+/// `operand`s separated by the binary operators in `ops`, which are left associative.
+fn parse_binary<'a>(operand: fn() -> Parser<'a, Filter>, ops: &[(&'static str, BinaryOp)]) -> Parser<'a, Filter> {
+    let op = parse_binary_op(ops);
+    start().and(operand())
+        .and(op.and_lazy(operand).and(position()).many())
+        .map(|((start, head), rest)| {
+            rest.into_iter().fold(head, |f, ((op, g), end)| located(Filter::Binary(op, Box::new(f), Box::new(g)), start, end))
+        })
+}
+
+fn parse_additive<'a>() -> Parser<'a, Filter> {
+    parse_binary(parse_multiplicative, &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)])
+}
+
+fn parse_multiplicative<'a>() -> Parser<'a, Filter> {
+    parse_binary(parse_unary, &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Mod)])
+}
+
+fn parse_unary<'a>() -> Parser<'a, Filter> {
+    start().skip(chr('-').with_spaces()).and_lazy(parse_term).and(position())
+        .map(|((start, f), end)| located(Filter::Neg(Box::new(f)), start, end))
+        .or(parse_bind()).with_spaces()
+}
+
+/// A term, optionally followed by `as $x | f`, or by patterns separated by `?//` in place of `$x`.
+/// As in jq, the binding takes the rest of the pipe: `1 + . as $x | $x` is `1 + (. as $x | $x)`.
+fn parse_bind<'a>() -> Parser<'a, Filter> {
+    let binding = keyword("as")
+        .then(parse_pattern().with_spaces())
+        .and(string("?//").with_spaces().then_lazy(|| parse_pattern().with_spaces()).many())
+        .skip(chr('|').with_spaces())
+        .and_lazy(parse_filter);
+    parse_term().with_spaces()
+        .and(binding.or_not())
+        .map(|(f, binding)| match binding {
+            Some(((pattern, alternatives), body)) => {
+                let mut patterns = vec![pattern];
+                patterns.extend(alternatives);
+                Filter::Bind(Box::new(f), patterns, Box::new(body))
+            },
+            None => f
+        })
+}
+
+/// `$name`, `[p0, p1, ...]` or `{key: p, $name, ...}`.
+fn parse_pattern<'a>() -> Parser<'a, Pattern> {
+    let var = parse_var().map(Pattern::Var);
+    let array = chr('[').with_spaces()
+        .then_lazy(|| parse_pattern().with_spaces().sep_by(chr(',').with_spaces()))
+        .skip(chr(']'))
+        .map(Pattern::Array);
+    let object = chr('{').with_spaces()
+        .then_lazy(|| parse_pattern_entry().with_spaces().sep_by(chr(',').with_spaces()))
+        .skip(chr('}'))
+        .map(Pattern::Object);
+    var.or(array).or(object)
+}
+
+/// `$name`, `$name: p`, or a key followed by `: p`, where the key is a name, a string or `(f)`.
+fn parse_pattern_entry<'a>() -> Parser<'a, (Filter, Option<String>, Option<Pattern>)> {
+    let value = || chr(':').with_spaces().then_lazy(parse_pattern);
+    let var = parse_var().with_spaces().and(value().or_not())
+        .map(|(name, pattern)| (Filter::Literal(Json::JString(Cow::Owned(name.clone()))), Some(name), pattern));
+    let key = parse_ident().map(|name| Filter::Literal(Json::JString(Cow::Owned(name))))
+        .or(parse_string().map(|parts| string_filter("text".to_string(), parts)))
+        .or(chr('(').with_spaces().then_lazy(parse_filter).skip(chr(')')));
+    var.or(key.with_spaces().and(value()).map(|(key, pattern)| (key, None, Some(pattern))))
+}
+
+fn parse_ident<'a>() -> Parser<'a, String> {
+    or_from(IDENT_START.chars().map(chr))
+        .and(or_from(IDENT_REST.chars().map(chr)).many())
+        .map(|(head, tail)| {
+            let mut s = String::new();
+            s.push(head);
+            s.extend(tail);
+            s
+        })
+}
+
+fn parse_digits<'a>() -> Parser<'a, String> {
+    or_from("0123456789".chars().map(chr))
+        .and(or_from("0123456789".chars().map(chr)).many())
+        .map(|(head, tail)| {
+            let mut s = String::new();
+            s.push(head);
+            s.extend(tail);
+            s
+        })
+}
+
+/// `12`, `1.5`, `1e-3` and so on. The sign is an operator.
+fn parse_number_literal<'a>() -> Parser<'a, f64> {
+    let exponent = or_from("eE".chars().map(chr)).then(or_from("+-".chars().map(chr)).or_not()).and(parse_digits());
+    parse_digits()
+        .and(chr('.').then(parse_digits().or_not()).or_not())
+        .and(exponent.or_not())
+        .map(|((int, frac), exp)| {
+            let mut s = int;
+            if let Some(frac) = frac {
+                s.push('.');
+                s.push_str(&frac.unwrap_or_else(|| "0".to_string()));
+            }
+            if let Some((sign, digits)) = exp {
+                s.push('e');
+                s.extend(sign);
+                s.push_str(&digits);
+            }
+            s.parse().unwrap()
+        })
+}
+
+/// `null`, `true`, `false` or an expression beginning with a keyword such as `if`
+fn parse_keyword<'a>() -> Parser<'a, Filter> {
+    parse_ident().flat_map(|name| match name.as_str() {
+        "null" => unit(Filter::Literal(Json::JNull)),
+        "true" => unit(Filter::Literal(Json::JBool(true))),
+        "false" => unit(Filter::Literal(Json::JBool(false))),
+        "if" => parse_if(),
+        "try" => parse_try(),
+        "reduce" => parse_reduce(),
+        "label" => parse_var().with_spaces().skip(chr('|').with_spaces()).and_lazy(parse_filter)
+            .map(|(name, f)| Filter::Label(name, Box::new(f))),
+        "break" => parse_var().with_spaces().map(Filter::Break),
+        "def" => parse_def().and_lazy(parse_filter).map(|(def, f)| Filter::Def(Box::new(def), Box::new(f))),
+        _ if KEYWORDS.contains(&name.as_str()) => failure(format!("Unknown name: {}", name)).map(|_| Filter::Identity),
+        _ => parse_args().map(move |args| Filter::Call(name.clone(), args))
+    })
+}
+
+/// Words which can follow a filter and hence are not function names.
+const KEYWORDS: &[&str] = &["then", "elif", "else", "end", "as", "catch"];
+
+/// `(f; g)` after a function name, or nothing for no arguments.
+fn parse_args<'a>() -> Parser<'a, Vec<Filter>> {
+    chr('(').with_spaces()
+        .then_lazy(|| parse_filter().sep_by(chr(';').with_spaces()))
+        .skip(chr(')'))
+        .or_not()
+        .map(|args| args.unwrap_or_default())
+}
+
+/// `name(params): body;` after `def`, where the parameters are optional.
+fn parse_def<'a>() -> Parser<'a, FuncDef> {
+    let param = parse_var().map(|name| (name, true)).or(parse_ident().map(|name| (name, false))).with_spaces();
+    let params = chr('(').with_spaces().then(param.sep_by(chr(';').with_spaces())).skip(chr(')')).or_not();
+    parse_ident().with_spaces()
+        .and(params.with_spaces())
+        .skip(chr(':').with_spaces())
+        .and_lazy(parse_filter)
+        .skip(chr(';').with_spaces())
+        .map(|((name, params), body)| {
+            let params = params.unwrap_or_default();
+            let body = params.iter().rev().filter(|p| p.1).fold(body, |body, p| {
+                Filter::Bind(Box::new(Filter::Call(p.0.clone(), vec![])), vec![Pattern::Var(p.0.clone())], Box::new(body))
+            });
+            FuncDef {name, params: params.into_iter().map(|p| p.0).collect(), body}
+        })
+}
+
+/// The keyword `name`, which must not be followed by a character of identifiers.
+fn keyword<'a>(name: &'static str) -> Parser<'a, ()> {
+    parse_ident().flat_map(move |s| {
+        if s == name { unit(()) } else { failure(format!("Expected `{}` but actual is `{}`.", name, s)) }
+    }).with_spaces()
+}
+
+fn parse_var<'a>() -> Parser<'a, String> {
+    chr('$').then(parse_ident())
+}
+
+/// `$name`, or `$__loc__`, which is `{"file": "<top-level>", "line": n}` for the line where it is written, as jq names the program.
+fn parse_var_or_loc<'a>() -> Parser<'a, Filter> {
+    position().and(parse_var()).map(|(pos, name)| {
+        if name != "__loc__" {
+            return Filter::Var(name);
+        }
+        Filter::Literal(Json::JObject(vec![
+            (Cow::Borrowed("file"), Json::JString(Cow::Borrowed("<top-level>"))),
+            (Cow::Borrowed("line"), Json::JNumber((pos.line as f64).into()))
+        ]))
+    })
+}
+
+/// The rest of `reduce f as $x (init; update)` after `reduce`, where `f` is a term and `$x` may be a pattern.
+fn parse_reduce<'a>() -> Parser<'a, Filter> {
+    parse_term().with_spaces()
+        .skip(keyword("as"))
+        .and(parse_pattern().with_spaces())
+        .skip(chr('(').with_spaces())
+        .and_lazy(parse_filter)
+        .skip(chr(';').with_spaces())
+        .and_lazy(parse_filter)
+        .skip(chr(')'))
+        .map(|(((source, pattern), init), update)| Filter::Reduce(Box::new(source), pattern, Box::new(init), Box::new(update)))
+}
+
+/// The rest of `try f` or `try f catch g` after `try`. Both `f` and `g` are terms.
+fn parse_try<'a>() -> Parser<'a, Filter> {
+    parse_term().with_spaces()
+        .and_lazy(|| keyword("catch").then_lazy(parse_term).or_not())
+        .map(|(f, handler)| Filter::Try(Box::new(f), handler.map(Box::new)))
+}
+
+/// The rest of `if c then f (elif c then f)* (else f)? end` after `if`.
+/// Without `else`, the input is output as is.
+fn parse_if<'a>() -> Parser<'a, Filter> {
+    parse_filter()
+        .skip(keyword("then")).and_lazy(parse_filter)
+        .and_lazy(|| keyword("elif").then_lazy(parse_filter).skip(keyword("then")).and_lazy(parse_filter).many())
+        .and_lazy(|| keyword("else").then_lazy(parse_filter).or_not())
+        .skip(keyword("end"))
+        .map(|(((cond, then), elifs), otherwise)| {
+            let otherwise = otherwise.unwrap_or(Filter::Identity);
+            let otherwise = elifs.into_iter().rev().fold(otherwise, |f, (c, t)| Filter::If(Box::new(c), Box::new(t), Box::new(f)));
+            Filter::If(Box::new(cond), Box::new(then), Box::new(otherwise))
+        })
+}
+
+/// A character of a string literal, or a UTF-16 code unit of `\uXXXX`, which may be a half of a surrogate pair
+enum StringPiece {
+    Char(char),
+    Unit(u16),
+    Filter(Filter)
+}
+
+/// A string literal with the escape sequences of JSON, and interpolations `\(f)`.
+fn parse_string<'a>() -> Parser<'a, Vec<StringPart>> {
+    let hex = || satisfy(|c| c.is_ascii_hexdigit());
+    let unicode = string("\\u").then(hex().and(hex()).and(hex()).and(hex()))
+        .map(|(((a, b), c), d)| StringPiece::Unit(u16::from_str_radix(&[a, b, c, d].iter().collect::<String>(), 16).unwrap()));
+    let interpolation = string("\\(").then_lazy(parse_filter).skip(chr(')')).map(StringPiece::Filter);
+    let escape = chr('\\').then(or_from("\"\\/bfnrt".chars().map(chr))).map(|c| StringPiece::Char(match c {
+        'b' => '\u{8}',
+        'f' => '\u{c}',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        c => c
+    }));
+    let plain = satisfy(|c| c != '"' && c != '\\').map(StringPiece::Char);
+    chr('"').then(unicode.try().or(interpolation.try()).or(escape.try()).or(plain).many()).skip(chr('"'))
+        .map(|pieces| {
+            let mut parts = vec![];
+            let mut text = String::new();
+            let mut units = vec![];
+            for piece in pieces {
+                if let StringPiece::Unit(u) = piece {
+                    units.push(u);
+                    continue;
+                }
+                // A lone surrogate becomes U+FFFD like jq.
+                text.extend(char::decode_utf16(units.drain(..)).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+                match piece {
+                    StringPiece::Char(c) => text.push(c),
+                    StringPiece::Filter(f) => {
+                        if !text.is_empty() {
+                            parts.push(StringPart::Text(mem::take(&mut text)));
+                        }
+                        parts.push(StringPart::Filter(f));
+                    },
+                    StringPiece::Unit(_) => unreachable!()
+                }
+            }
+            text.extend(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+            if !text.is_empty() || parts.is_empty() {
+                parts.push(StringPart::Text(text));
+            }
+            parts
+        })
+}
+
+/// A literal for a string without interpolations, or `Interpolate` in the format `name`
+fn string_filter(name: String, mut parts: Vec<StringPart>) -> Filter {
+    match parts.as_mut_slice() {
+        [StringPart::Text(text)] => Filter::Literal(Json::JString(Cow::Owned(mem::take(text)))),
+        _ => Filter::Interpolate(name, parts)
+    }
+}
+
+/// `@name`, or `@name "a\(f)b"` to interpolate in the format.
+fn parse_format<'a>() -> Parser<'a, Filter> {
+    chr('@').then(parse_ident())
+        .flat_map(|name| match builtin::format(&name) {
+            Some(_) => unit(name),
+            None => failure(format!("@{} is not a valid format", name)).map(|_| String::new())
+        })
+        .and(parse_string().with_spaces().or_not())
+        .map(|(name, parts)| match parts {
+            Some(parts) => string_filter(name, parts),
+            None => Filter::Format(name)
+        })
+}
+
+fn parse_int<'a>() -> Parser<'a, isize> {
+    chr('-').or_not()
+        .and(parse_digits())
+        .flat_map(|(minus, digits)| {
+            let mut s: String = minus.into_iter().collect();
+            s.push_str(&digits);
+            match s.parse::<isize>() {
+                Ok(i) => unit(i),
+                Err(_) => failure(format!("Index out of range: {}", s)).map_(0)
+            }
+        })
+}
+
+#[derive(Clone)]
+enum Suffix {
+    Field(String),
+    Index(Filter),
+    Iterate,
+    Try,
+    Slice(Option<isize>, Option<isize>)
+}
+
+/// `name` or `"name"` after `.`, where a name with interpolations is an index
+fn parse_field_name<'a>() -> Parser<'a, Suffix> {
+    parse_ident().map(Suffix::Field)
+        .or(parse_string().map(|parts| match string_filter("text".to_string(), parts) {
+            Filter::Literal(Json::JString(name)) => Suffix::Field(name.into_owned()),
+            f => Suffix::Index(f)
+        }))
+}
+
+/// `.name`, `."name"`, `[f]`, `[]`, `[from:to]` or `?`
+fn parse_suffix<'a>() -> Parser<'a, Suffix> {
+    let optional = chr('?').map(|_| Suffix::Try);
+    let field = chr('.').then(parse_field_name());
+    let slice = parse_int().with_spaces().or_not()
+        .and(chr(':').with_spaces().then(parse_int().with_spaces().or_not()))
+        .skip(chr(']'))
+        .map(|(from, to)| Suffix::Slice(from, to));
+    let iterate = chr(']').map(|_| Suffix::Iterate);
+    let bracket = chr('[').with_spaces()
+        .then(slice.try().or(iterate).or_lazy(|| parse_filter().skip(chr(']')).map(Suffix::Index)));
+    field.try().or(optional).or(bracket)
+}
+
+/// `[f]`, or `[]` for an empty array
+fn parse_array<'a>() -> Parser<'a, Filter> {
+    chr('[').with_spaces().then_lazy(|| parse_filter().or_not()).skip(chr(']'))
+        .map(|f| match f {
+            Some(f) => Filter::Array(Box::new(f)),
+            None => Filter::Literal(Json::JArray(vec![]))
+        })
+}
+
+/// `{a: f, "b": g, (h): i, c, "d"}`, where `c` is short for `c: .c`.
+fn parse_object<'a>() -> Parser<'a, Filter> {
+    chr('{').with_spaces().then_lazy(||
+        parse_object_entry().sep_by(chr(',').with_spaces())
+    ).skip(chr('}')).map(Filter::Object)
+}
+
+fn parse_object_entry<'a>() -> Parser<'a, (Filter, Filter)> {
+    let key = parse_ident().try().map(|name| Filter::Literal(Json::JString(Cow::Owned(name))))
+        .or(parse_string().map(|parts| string_filter("text".to_string(), parts)));
+    let named = key.with_spaces()
+        .and_lazy(|| chr(':').with_spaces().then_lazy(parse_object_value).or_not())
+        .flat_map(|(key, value)| match (key, value) {
+            (key, Some(value)) => unit((key, value)),
+            (Filter::Literal(Json::JString(name)), None) => {
+                let value = Filter::Field(Box::new(Filter::Identity), name.to_string());
+                unit((Filter::Literal(Json::JString(name)), value))
+            },
+            _ => failure("An interpolated key needs a value".to_string()).map(|_| (Filter::Identity, Filter::Identity))
+        });
+    let computed = chr('(').with_spaces().then_lazy(parse_filter).skip(chr(')').with_spaces())
+        .skip(chr(':').with_spaces())
+        .and_lazy(parse_object_value);
+    named.or(computed)
+}
+
+/// Values of objects may be piped but cannot contain `,`, which separates entries.
+fn parse_object_value<'a>() -> Parser<'a, Filter> {
+    parse_pipe(|| parse_term().with_spaces())
+}
+
+/// `..`, `.`, `.foo`, a literal, `$name`, `(f)`, `[...]` or `{...}` followed by any number of suffixes, e.g. `.foo.bar[0][1:]?`
+fn parse_term<'a>() -> Parser<'a, Filter> {
+    let path = chr('.').then(parse_field_name().or_not())
+        .map(|name| match name {
+            Some(Suffix::Index(f)) => Filter::Index(Box::new(Filter::Identity), Box::new(f)),
+            Some(Suffix::Field(name)) => Filter::Field(Box::new(Filter::Identity), name),
+            _ => Filter::Identity
+        });
+    let paren = chr('(').then_lazy(parse_filter).skip(chr(')'));
+    let recurse = string("..").map(|_| Filter::Recurse);
+    let number = parse_number_literal().map(|v| Filter::Literal(Json::JNumber(v.into())));
+    let text = parse_string().map(|parts| string_filter("text".to_string(), parts));
+    let array = parse_array();
+    let object = parse_object();
+    let head = recurse.try().or(path).or(number).or(text).or(parse_format()).or(parse_keyword()).or(parse_var_or_loc()).or(paren).or(array).or(object);
+    // Each suffix has its own span, which an error of the step points at, e.g. `.b` of `.a.b`.
+    position().and(head).and(position()).and(position().and(parse_suffix()).and(position()).many())
+        .map(|(((start, head), end), suffixes)| {
+            suffixes.into_iter().fold(located(head, start, end), |f, ((start, suffix), end)| located(match suffix {
+                Suffix::Field(name) => Filter::Field(Box::new(f), name),
+                Suffix::Index(g) => Filter::Index(Box::new(f), Box::new(g)),
+                Suffix::Iterate => Filter::Iterate(Box::new(f)),
+                Suffix::Try => Filter::Try(Box::new(f), None),
+                Suffix::Slice(from, to) => Filter::Slice(Box::new(f), from, to)
+            }, start, end))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &str, json: &Json) -> FilterResult<String> {
+        let outputs = Filter::parse(program).unwrap().apply(json)?;
+        Ok(outputs.iter().map(print_json_compact).collect::<Vec<_>>().join(" "))
+    }
+
+    fn error(message: &str) -> FilterResult<String> {
+        Err(FilterError::message(message.to_string()))
+    }
+
+    fn field(f: Filter, name: &str) -> Filter {
+        Filter::Field(Box::new(f), name.to_string())
+    }
+
+    fn pipe(f: Filter, g: Filter) -> Filter {
+        Filter::Pipe(Box::new(f), Box::new(g))
+    }
+
+    fn index(f: Filter, i: f64) -> Filter {
+        Filter::Index(Box::new(f), Box::new(Filter::Literal(Json::JNumber(i.into()))))
+    }
+
+    fn comma(f: Filter, g: Filter) -> Filter {
+        Filter::Comma(Box::new(f), Box::new(g))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Filter::parse(".").unwrap(), Filter::Identity);
+        assert_eq!(Filter::parse(" .foo ").unwrap(), field(Filter::Identity, "foo"));
+        assert_eq!(Filter::parse(".foo.bar_2").unwrap(), field(field(Filter::Identity, "foo"), "bar_2"));
+        assert!(Filter::parse("foo").is_err());
+        assert!(Filter::parse(".foo.").is_err());
+        assert!(Filter::parse(".foo bar").is_err());
+        assert_eq! {
+            Filter::parse(".a | .b|.").unwrap(),
+            pipe(field(Filter::Identity, "a"), pipe(field(Filter::Identity, "b"), Filter::Identity))
+        }
+        assert!(Filter::parse(".a |").is_err());
+        assert_eq! {
+            Filter::parse(".a[0][-1: ][:2].b").unwrap(),
+            field(Filter::Slice(Box::new(Filter::Slice(Box::new(index(field(Filter::Identity, "a"), 0.0)), Some(-1), None)), None, Some(2)), "b")
+        }
+        assert_eq!(Filter::parse(r#"."a b"."c""#).unwrap(), field(field(Filter::Identity, "a b"), "c"));
+        assert_eq! {
+            Filter::parse(r#".["a"][.i]"#).unwrap(),
+            Filter::Index(Box::new(Filter::Index(Box::new(Filter::Identity), Box::new(Filter::Literal(Json::JString("a".into()))))), Box::new(field(Filter::Identity, "i")))
+        }
+        assert_eq!(Filter::parse(".[][ ]").unwrap(), Filter::Iterate(Box::new(Filter::Iterate(Box::new(Filter::Identity)))));
+        assert_eq! {
+            Filter::parse("( .a | .b )?.c").unwrap(),
+            field(Filter::Try(Box::new(pipe(field(Filter::Identity, "a"), field(Filter::Identity, "b"))), None), "c")
+        }
+        assert!(Filter::parse("(.a").is_err());
+        assert_eq!(Filter::parse("..|.a?").unwrap(), pipe(Filter::Recurse, Filter::Try(Box::new(field(Filter::Identity, "a")), None)));
+        assert!(Filter::parse("...").is_err());
+        assert_eq! {
+            Filter::parse(".a, .b | .c, (.d, .e)").unwrap(),
+            pipe(
+                comma(field(Filter::Identity, "a"), field(Filter::Identity, "b")),
+                comma(field(Filter::Identity, "c"), comma(field(Filter::Identity, "d"), field(Filter::Identity, "e"))))
+        }
+        assert!(Filter::parse(".a,").is_err());
+        assert!(Filter::parse(".[1").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        use json::Json::*;
+        let json = JObject(vec![("a".into(), JObject(vec![("b".into(), JNumber(1f64.into()))])), ("c".into(), JNull)]);
+        let run = |program| {
+            let mut outputs = Filter::parse(program).unwrap().apply(&json).unwrap();
+            assert_eq!(outputs.len(), 1);
+            outputs.pop().unwrap()
+        };
+        assert_eq!(run("."), json);
+        assert_eq!(run(".a.b"), JNumber(1f64.into()));
+        assert_eq!(run(".x"), JNull);
+        assert_eq!(run(".a.x.c"), JNull);
+        assert_eq!(run(".a | .b"), JNumber(1f64.into()));
+        assert_eq!(run(". | .a | . | .b"), JNumber(1f64.into()));
+    }
+
+    #[test]
+    fn test_apply_index() {
+        use json::Json::*;
+        let json = JObject(vec![("xs".into(), JArray((0..5).map(|i| JNumber((i as f64).into())).collect())), ("s".into(), JString("a\u{3042}bc".into()))]);
+        let run = |program| {
+            let mut outputs = Filter::parse(program).unwrap().apply(&json).unwrap();
+            assert_eq!(outputs.len(), 1);
+            outputs.pop().unwrap()
+        };
+        let numbers = |v: &[i32]| JArray(v.iter().map(|&i| JNumber((i as f64).into())).collect());
+        assert_eq!(run(".xs[0]"), JNumber(0f64.into()));
+        assert_eq!(run(".xs[-1]"), JNumber(4f64.into()));
+        assert_eq!(run(".xs[5]"), JNull);
+        assert_eq!(run(".xs[-6]"), JNull);
+        assert_eq!(run(".xs[1:3]"), numbers(&[1, 2]));
+        assert_eq!(run(".xs[-2:]"), numbers(&[3, 4]));
+        assert_eq!(run(".xs[:-3]"), numbers(&[0, 1]));
+        assert_eq!(run(".xs[3:1]"), numbers(&[]));
+        assert_eq!(run(".xs[-10:10]"), run(".xs"));
+        assert_eq!(run(".s[1:]"), JString("\u{3042}bc".into()));
+        assert_eq!(run(".xs[.xs[2]]"), JNumber(2f64.into()));
+        assert_eq!(run(".xs as $i | .xs[$i[3]]"), JNumber(3f64.into()));
+        assert_eq!(run(r#".["xs"][1]"#), JNumber(1f64.into()));
+        assert_eq!(run(r#"[.xs[0, -1]]"#), numbers(&[0, 4]));
+        assert_eq!(run(r#"."\("s")""#), run(".s"));
+        assert_eq!(Filter::parse(".xs[.s]").unwrap().apply(&json), Err(FilterError::message("Cannot index array with \"a\u{3042}bc\"".to_string())));
+        assert_eq!(Filter::parse(".s[0]").unwrap().apply(&json), Err(FilterError::message("Cannot index string with number".to_string())));
+    }
+
+    #[test]
+    fn test_apply_iterate() {
+        use json::Json::*;
+        let json = JObject(vec![("a".into(), JArray(vec![JObject(vec![("b".into(), JNumber(1f64.into()))]), JObject(vec![("b".into(), JNull)])])), ("c".into(), JBool(true))]);
+        let run = |program| Filter::parse(program).unwrap().apply(&json).unwrap();
+        assert_eq!(run(".a[].b"), vec![JNumber(1f64.into()), JNull]);
+        assert_eq!(run(".a[] | .b"), vec![JNumber(1f64.into()), JNull]);
+        assert_eq!(run(".[] | .[0]?"), vec![run(".a[0]")[0].clone()]);
+        assert_eq!(run(".a[1:][]"), vec![JObject(vec![("b".into(), JNull)])]);
+    }
+
+    #[test]
+    fn test_apply_comma() {
+        use json::Json::*;
+        let json = JObject(vec![("a".into(), JArray(vec![JNumber(1f64.into()), JNumber(2f64.into())])), ("b".into(), JNull)]);
+        let run = |program| Filter::parse(program).unwrap().apply(&json);
+        assert_eq!(run(".b, .a[], .b"), Ok(vec![JNull, JNumber(1f64.into()), JNumber(2f64.into()), JNull]));
+        assert_eq!(run("(.a, .b) | .[0]?"), Ok(vec![JNumber(1f64.into()), JNull]));
+        assert_eq!(run(".a[1], .a.x, .b"), Err(FilterError::message("Cannot index array with \"x\"".to_string())));
+        assert_eq!(run("(.a[1], .a.x, .b)?"), Ok(vec![JNumber(2f64.into())]));
+    }
+
+    #[test]
+    fn test_apply_array() {
+        let json = Json::from_str(r#"{"items": [{"id": 1}, {"id": 2}], "s": "x"}"#).unwrap();
+        assert_eq!(run("[]", &json), Ok("[]".to_string()));
+        assert_eq!(run("[ .items[].id ]", &json), Ok("[1,2]".to_string()));
+        assert_eq!(run("[.s, [.items[0]], {s}][1:]", &json), Ok(r#"[[{"id":1}],{"s":"x"}]"#.to_string()));
+        assert_eq!(run("[.s[]]", &json), error("Cannot iterate over string"));
+        assert_eq!(run("[.s[]?]", &json), Ok("[]".to_string()));
+        assert!(Filter::parse("[.a").is_err());
+    }
+
+    #[test]
+    fn test_apply_object() {
+        let json = Json::from_str(r#"{"user": "u", "titles": ["a", "b"], "k": "key", "n": 1}"#).unwrap();
+        assert_eq!(run("{}", &json), Ok("{}".to_string()));
+        assert_eq!(run("{user, title: .titles[]}", &json), Ok(r#"{"user":"u","title":"a"} {"user":"u","title":"b"}"#.to_string()));
+        assert_eq!(run(r#"{ "user" , (.k): .n, "a b": .titles | .[0] }"#, &json), Ok(r#"{"user":"u","key":1,"a b":"a"}"#.to_string()));
+        assert_eq!(run("{(.titles[]): (.n, .k)}", &json), Ok(r#"{"a":1} {"a":"key"} {"b":1} {"b":"key"}"#.to_string()));
+        assert_eq! {
+            run("{a: .titles[], b: .titles[]}", &json),
+            Ok(r#"{"a":"a","b":"a"} {"a":"a","b":"b"} {"a":"b","b":"a"} {"a":"b","b":"b"}"#.to_string())
+        }
+        assert_eq!(run("{n: .n, n: .k}", &json), Ok(r#"{"n":"key"}"#.to_string()));
+        assert_eq!(run("{(.n): .n}", &json), error("Object keys must be strings, not number"));
+        assert!(Filter::parse("{(.k)}").is_err());
+        assert!(Filter::parse("{a: .b, }").is_err());
+    }
+
+    #[test]
+    fn test_apply_arithmetic() {
+        let json = Json::from_str(r#"{"a": [1, 2, 1], "o": {"x": {"y": 1}, "z": 2}, "s": "a,b", "n": 7}"#).unwrap();
+        assert_eq!(run("1 + 2 * 3 - -4", &json), Ok("11".to_string()));
+        assert_eq!(run("(1 + 2) * 3, 2.5e1 / 10, .n % 3, -.n % 3, 5 % 2.7", &json), Ok("9 2.5 1 -1 1".to_string()));
+        assert_eq!(run("(1, 2) + (10, 20)", &json), Ok("11 12 21 22".to_string()));
+        assert_eq!(run("null + 1, .a + null, null + null", &json), Ok("1 [1,2,1] null".to_string()));
+        assert_eq!(run(r#""ab" + "c", .a + [3], .a - [1]"#, &json), Ok(r#""abc" [1,2,1,3] [2]"#.to_string()));
+        assert_eq!(run(r#".o + {"x": 1, "w": 3}"#, &json), Ok(r#"{"x":1,"z":2,"w":3}"#.to_string()));
+        assert_eq!(run(r#".o * {"x": {"w": 3}}"#, &json), Ok(r#"{"x":{"y":1,"w":3},"z":2}"#.to_string()));
+        assert_eq!(run(r#""ab" * 3, 0 * "ab", .s / ",", "" / ",""#, &json), Ok(r#""ababab" null ["a","b"] []"#.to_string()));
+        assert_eq!(run("true, false, null", &json), Ok("true false null".to_string()));
+        assert_eq!(run(r#"1 + "a""#, &json), error(r#"number (1) and string ("a") cannot be added"#));
+        assert_eq!(run("{} - 1", &json), error("object ({}) and number (1) cannot be subtracted"));
+        assert_eq!(run("1 / 0", &json), error("number (1) and number (0) cannot be divided because the divisor is zero"));
+        assert_eq!(run("1 % 0.5", &json), error("number (1) and number (0.5) cannot be divided because the divisor is zero"));
+        assert_eq!(run(r#"-"a""#, &json), error(r#"string ("a") cannot be negated"#));
+        assert!(Filter::parse("1 +").is_err());
+        assert!(Filter::parse("nul").is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let values: Vec<Json> = [
+            "null", "false", "true", "-1", "0", "1e3", r#""""#, r#""a""#, r#""ab""#, r#""b""#,
+            "[]", "[0]", "[0, 1]", "[1]", "{}", r#"{"a": 2}"#, r#"{"a": 3}"#, r#"{"a": 1, "b": 0}"#, r#"{"b": 0}"#
+        ].iter().map(|s| Json::from_str(s).unwrap()).collect();
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(compare(a, b), i.cmp(&j), "{:?} {:?}", a, b);
+            }
+        }
+        let x = Json::from_str(r#"{"a": 1, "b": [2]}"#).unwrap();
+        let y = Json::from_str(r#"{"b": [2], "a": 1}"#).unwrap();
+        assert_eq!(compare(&x, &y), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_apply_comparison() {
+        let json = Json::from_str(r#"{"a": [1, 2], "o": {"x": 1, "y": 2}}"#).unwrap();
+        assert_eq!(run("1 == 1.0, 1 != 1, 1 < 2, 2 <= 2, 1 > 2, 1 >= 2", &json).unwrap(), "true false true true false false");
+        assert_eq!(run(r#"null < false, true < 0, 1 < "a", "a" < [], [] < {}"#, &json).unwrap(), "true true true true true");
+        assert_eq!(run(r#".o == {"y": 2, "x": 1}, .a[] > 1"#, &json).unwrap(), "true false true");
+        assert_eq!(run("1 + 1 == 2, (1 < 2) == true", &json).unwrap(), "true true");
+        assert_eq!(run(r#"[.a[] | . == 1] - [true]"#, &json).unwrap(), "[false]");
+        assert!(Filter::parse("1 < 2 < 3").is_err());
+    }
+
+    #[test]
+    fn test_apply_if() {
+        let json = Json::from_str(r#"[0, 1, 2, null, false]"#).unwrap();
+        assert_eq!(run(r#".[] | if . then "t" else "f" end"#, &json).unwrap(), r#""t" "t" "t" "f" "f""#);
+        assert_eq!(run(r#".[:3][] | if . == 0 then "zero" elif . == 1 then "one" else "many" end"#, &json).unwrap(), r#""zero" "one" "many""#);
+        assert_eq!(run(".[:3][] | if . > 0 then . * 10 end", &json).unwrap(), "0 10 20");
+        assert_eq!(run(r#"if .[] then "t" else "f" end"#, &json).unwrap(), r#""t" "t" "t" "f" "f""#);
+        assert_eq!(run("if true then .[1], .[2] else null end", &json).unwrap(), "1 2");
+        assert_eq!(run("[if (true, false) then 1 else 2 end] | .[0] + .[1]", &json).unwrap(), "3");
+        assert!(Filter::parse("if . then 1").is_err());
+        assert!(Filter::parse("if . else 1 end").is_err());
+        assert!(Filter::parse("if . then 1 else 2 end3").is_err());
+    }
+
+    #[test]
+    fn test_apply_try() {
+        let json = Json::from_str(r#"{"a": 1, "b": [1, "x", 2]}"#).unwrap();
+        assert_eq!(run(r#"try .a.b catch "oops""#, &json), Ok(r#""oops""#.to_string()));
+        assert_eq!(run("try .a.b catch .", &json), Ok(r#""Cannot index number with \"b\"""#.to_string()));
+        assert_eq!(run("try .a.b", &json), Ok("".to_string()));
+        assert_eq!(run("try .a catch 0", &json), Ok("1".to_string()));
+        assert_eq!(run(r#"[.b[] | try (. + 1) catch "bad"]"#, &json), Ok(r#"[2,"bad",3]"#.to_string()));
+        assert_eq!(run(r#"[try (.b[] | . + 1) catch "bad"]"#, &json), Ok(r#"[2,"bad"]"#.to_string()));
+        assert_eq!(run("try .a.b catch .x", &json), Err(FilterError::message(r#"Cannot index string with "x""#.to_string())));
+        assert_eq!(run("(try .a.b catch .x)?, 0", &json), Ok("0".to_string()));
+        // The errors after the outputs of `try` are not its own.
+        assert_eq!(run("try ((try .b[]) | error) catch .", &json), Ok("1".to_string()));
+        assert_eq!(run(r#"[.b[] // 0 | try error("x") catch .]"#, &json), Ok(r#"["x","x","x"]"#.to_string()));
+    }
+
+    #[test]
+    fn test_apply_bind() {
+        let json = Json::from_str(r#"{"a": [1, 2], "b": "x"}"#).unwrap();
+        assert_eq!(run(".b as $x | .a | [$x, .]", &json), Ok(r#"["x",[1,2]]"#.to_string()));
+        assert_eq!(run(".a[] as $x | $x * 10", &json), Ok("10 20".to_string()));
+        assert_eq!(run(".a[] as $x | .a[] as $y | [$x, $y]", &json), Ok("[1,1] [1,2] [2,1] [2,2]".to_string()));
+        assert_eq!(run("1 as $x | 2 as $x | $x", &json), Ok("2".to_string()));
+        assert_eq!(run("1 as $x | (2 as $x | $x), $x", &json), Ok("2 1".to_string()));
+        assert_eq!(run("10 + .a[0] as $x | $x", &json), Ok("11".to_string()));
+        assert_eq!(run("[.a[] as $x | $x + 1]", &json), Ok("[2,3]".to_string()));
+        assert_eq!(run("(.b as $x | $x) | $x", &json), Err(FilterError::message("$x is not defined".to_string())));
+        assert!(Filter::parse(".a as $x").is_err());
+        assert!(Filter::parse(".a, .b as $x | $x").is_ok());
+        assert_eq!(run("1 as $x |\n  $__loc__", &json), Ok(r#"{"file":"<top-level>","line":2}"#.to_string()));
+        assert_eq!(run("$__loc__x", &json), Err(FilterError::message("$__loc__x is not defined".to_string())));
+        let vars = [("x", Json::JString("a".into())), ("y", Json::JNumber(1f64.into())), ("x", Json::JString("b".into()))];
+        let filter = Filter::parse("[$x, $y, (.b as $x | $x)]").unwrap();
+        assert_eq!(filter.apply_with_vars(&json, 0, &vars), Ok(vec![Json::from_str(r#"["b",1,"x"]"#).unwrap()]));
+    }
+
+    #[test]
+    fn test_apply_destructure() {
+        let json = Json::from_str(r#"{"a": [1, [2, 3]], "b": {"c": 4, "d": [5]}, "k": "c"}"#).unwrap();
+        assert_eq!(run(".a as [$x, [$y, $z]] | [$x, $y, $z]", &json), Ok("[1,2,3]".to_string()));
+        assert_eq!(run(".a as [$x, $y, $z] | [$x, $z]", &json), Ok("[1,null]".to_string()));
+        assert_eq!(run(". as {a: [$x], $k} | [$x, $k]", &json), Ok(r#"[1,"c"]"#.to_string()));
+        assert_eq!(run(".b as {\"c\": $c, $d: [$e]} | [$c, $d, $e]", &json), Ok("[4,[5],5]".to_string()));
+        assert_eq!(run(". as {k: $k, b: {($k): $v}} | $v", &json), Err(FilterError::message("$k is not defined".to_string())));
+        assert_eq!(run(". as {(.k, \"d\"): $v} | $v", &json), Ok("null null".to_string()));
+        assert_eq!(run(".b as {(.k): $v} | $v", &json), Ok("4".to_string()));
+        assert_eq!(run(". as {b: {(.k): $v}} | $v", &json), Ok("4".to_string()));
+        assert_eq!(run(".a as {$x} | $x", &json), Err(FilterError::message(r#"Cannot index array with "x""#.to_string())));
+        assert_eq!(run(".b as [$x] | $x", &json), Err(FilterError::message("Cannot index object with number".to_string())));
+        assert_eq!(run(". as {(1): $x} | $x", &json), Err(FilterError::message("Cannot index object with number".to_string())));
+        assert_eq!(run("reduce .a[1:][] as [$x, $y] (0; . + $x + $y), reduce (.b | to_entries[]) as {$key} (\"\"; . + $key)", &json), Ok(r#"5 "cd""#.to_string()));
+        assert_eq!(run("[.a[] as [$x] ?// $x | $x]", &json), Ok("[1,2]".to_string()));
+        assert_eq!(run("[.a[] as [$x] ?// $y | [$x, $y]]", &json), Ok("[[null,1],[2,null]]".to_string()));
+        assert_eq!(run("[[3]] | .[] as [$a] ?// [$b] | if $a != null then error(\"err\") else [$a, $b] end", &json), Ok("[null,3]".to_string()));
+        assert_eq!(run(".b as [$x] ?// [$y] | $y", &json), Err(FilterError::message("Cannot index object with number".to_string())));
+        assert_eq!(run("[.a as [$x] ?// $y | $x, error(\"e\")]", &json), Err(FilterError::message("e".to_string())));
+        assert_eq!(run("[path(.a as [$x, $y] | .b)]", &json), Ok(r#"[["b"]]"#.to_string()));
+        assert!(Filter::parse(". as [$x | $x").is_err());
+        assert!(Filter::parse(". as {a} | .").is_err());
+    }
+
+    #[test]
+    fn test_apply_def() {
+        let json = Json::from_str(r#"{"a": [1, 2, 3], "o": {"x": 1, "y": "z"}}"#).unwrap();
+        assert_eq!(run("def f: .a; f", &json), Ok("[1,2,3]".to_string()));
+        assert_eq!(run("def inc(f): f + 1; .a | inc(.[0], .[1])", &json), Ok("2 3".to_string()));
+        assert_eq!(run("def f(g; h): [g, h]; f(.a[0]; .o.x)", &json), Ok("[1,1]".to_string()));
+        assert_eq!(run("def f($x; g): [$x, g, x]; f(.a[0], .a[1]; 0)", &json), Ok("[1,0,1,2] [2,0,1,2]".to_string()));
+        assert_eq!(run("def fac: if . <= 1 then 1 else . * (. - 1 | fac) end; 5 | fac", &json), Ok("120".to_string()));
+        // Arguments run in the environment of the caller.
+        assert_eq!(run("1 as $x | def f(g): 2 as $x | [g, $x]; f($x)", &json), Ok("[1,2]".to_string()));
+        assert_eq!(run("def f: 1; def g: f; def f: 2; g, f", &json), Ok("1 2".to_string()));
+        assert_eq!(run("def f: 1; def f(g): g + f; f(10)", &json), Ok("11".to_string()));
+        assert_eq!(run("[.a[] | empty], [1, empty, 2]", &json), Ok("[] [1,2]".to_string()));
+        assert_eq!(run(".a | map(. * 2)", &json), Ok("[2,4,6]".to_string()));
+        assert_eq!(run(".a | map(., 0)", &json), Ok("[1,0,2,0,3,0]".to_string()));
+        assert_eq!(run(".o | map(.)", &json), Ok(r#"[1,"z"]"#.to_string()));
+        assert_eq!(run("def map(f): 0; .a | map(. * 2)", &json), Ok("0".to_string()));
+        assert_eq!(run(".a | map_values(. * 2)", &json), Ok("[2,4,6]".to_string()));
+        assert_eq!(run(".a | map_values(empty, 0)", &json), Ok("[0,0,0]".to_string()));
+        assert_eq!(run(".o | map_values(.)", &json), Ok(r#"{"x":1,"y":"z"}"#.to_string()));
+        assert_eq!(run(".a | map_values(if . == 2 then empty else . end)", &json), Ok("[1,3]".to_string()));
+        assert_eq!(run(".o.x | map(.)", &json), Err(FilterError::message("Cannot iterate over number".to_string())));
+        assert_eq!(run(".o.x | map_values(.)", &json), Err(FilterError::message("Cannot iterate over number".to_string())));
+        assert!(Filter::parse("f").is_err());
+        assert!(Filter::parse("def f: 1; f(1)").is_err());
+        assert!(Filter::parse("def f(g): g; g").is_err());
+        assert!(Filter::parse("def f: 1;").is_err());
+        assert!(Filter::parse("[then]").is_err());
+    }
+
+    #[test]
+    fn test_apply_string() {
+        let json = Json::from_str(r#"{"a": 1, "b": "x"}"#).unwrap();
+        assert_eq!(run(r#""a\"b\\c\/\né😀\ud83d""#, &json), Ok("\"a\\\"b\\\\c/\\n\u{e9}\u{1f600}\u{fffd}\"".to_string()));
+        assert_eq!(run(r#""a=\(.a), b=\(.b)!""#, &json), Ok(r#""a=1, b=x!""#.to_string()));
+        assert_eq!(run(r#""\(1, 2)-\(3, 4)""#, &json), Ok(r#""1-3" "2-3" "1-4" "2-4""#.to_string()));
+        assert_eq!(run(r#""\("\(.a)")" | length"#, &json), Ok("1".to_string()));
+        assert_eq!(run(r#"{"k\(.a)": .b, "b"}"#, &json), Ok(r#"{"k1":"x","b":"x"}"#.to_string()));
+        assert_eq!(run(r#""\(empty)""#, &json), Ok("".to_string()));
+        assert!(Filter::parse(r#""\d""#).is_err());
+        assert!(Filter::parse(r#"{"\(.a)"}"#).is_err());
+        assert!(Filter::parse("@foo").is_err());
+    }
+
+    #[test]
+    fn test_apply_alternative() {
+        let json = Json::from_str(r#"{"a": [null, false, 1, 2], "b": 3}"#).unwrap();
+        assert_eq!(run(".x // .b", &json), Ok("3".to_string()));
+        assert_eq!(run(".a[] // .b", &json), Ok("1 2".to_string()));
+        assert_eq!(run("(.a[0], .a[1]) // .b, 4", &json), Ok("3 4".to_string()));
+        assert_eq!(run("(.b | .x) // 5", &json), Ok("5".to_string()));
+        assert_eq!(run("null // false // 6", &json), Ok("6".to_string()));
+        assert_eq!(run("null // false", &json), Ok("false".to_string()));
+        assert_eq!(run("[.x // .a[]]", &json), Ok("[null,false,1,2]".to_string()));
+        assert_eq!(run("1 // (.b | .x)", &json), Ok("1".to_string()));
+        assert_eq!(run("null // (.b | .x)", &json), Err(FilterError::message(r#"Cannot index number with "x""#.to_string())));
+        assert_eq!(run("6 / 3", &json), Ok("2".to_string()));
+    }
+
+    #[test]
+    fn test_apply_assign() {
+        let json = Json::from_str(r#"{"a": [1, 2, 3], "o": {"x": 1, "y": null}, "n": 10}"#).unwrap();
+        assert_eq!(run(".o.x = 5 | .o", &json), Ok(r#"{"x":5,"y":null}"#.to_string()));
+        assert_eq!(run(".o.z = .n | .o", &json), Ok(r#"{"x":1,"y":null,"z":10}"#.to_string()));
+        assert_eq!(run(".a[] = 0 | .a", &json), Ok("[0,0,0]".to_string()));
+        assert_eq!(run("(.o.x, .n) = (1, 2) | [.o.x, .n]", &json), Ok("[1,1] [2,2]".to_string()));
+        assert_eq!(run(".a |= map(. * 2) | .a", &json), Ok("[2,4,6]".to_string()));
+        assert_eq!(run(".a[] |= (. + 1, 0) | .a", &json), Ok("[2,3,4]".to_string()));
+        assert_eq!(run(".a[] |= if . == 2 then empty else . end | .a", &json), Ok("[1,3]".to_string()));
+        assert_eq!(run(".o[] |= empty | .o", &json), Ok("{}".to_string()));
+        assert_eq!(run(".new.list[1] |= 7 | .new", &json), Ok(r#"{"list":[null,7]}"#.to_string()));
+        assert_eq!(run(".n += 1 | .n", &json), Ok("11".to_string()));
+        assert_eq!(run(".a[] -= 1 | .a", &json), Ok("[0,1,2]".to_string()));
+        assert_eq!(run(".a[] *= .n | .a", &json), Ok("[10,20,30]".to_string()));
+        assert_eq!(run(".n /= 4 | .n", &json), Ok("2.5".to_string()));
+        assert_eq!(run(".n %= 3 | .n", &json), Ok("1".to_string()));
+        assert_eq!(run(".n += (1, 2) | .n", &json), Ok("11 12".to_string()));
+        assert_eq!(run(".o[] //= 9 | .o", &json), Ok(r#"{"x":1,"y":9}"#.to_string()));
+        assert_eq!(run(".a[1:] = [] | .a", &json), Ok("[1]".to_string()));
+        assert_eq!(run(r#".a[.a[0]] = 0 | .["a"]"#, &json), Ok("[1,0,3]".to_string()));
+        assert_eq!(run(r#"[path(.["o"][.a[0,1] | tostring])]"#, &json), Ok(r#"[["o","1"],["o","2"]]"#.to_string()));
+        assert_eq!(run("del(.a[0, 1]) | .a", &json), Ok("[3]".to_string()));
+        assert_eq!(run(".n |= . * 2 | .n", &json), Ok("20".to_string()));
+        assert_eq!(run(".a | map_values(. + 1)", &json), Ok("[2,3,4]".to_string()));
+        let users = Json::from_str(r#"{"users": [{"active": true}, {"active": false}]}"#).unwrap();
+        let outputs = Filter::parse(".users[].active |= not").unwrap().apply(&users).unwrap();
+        assert_eq!(print_json_compact(&outputs[0]), r#"{"users":[{"active":false},{"active":true}]}"#);
+        assert_eq!(run(".o.x += \"s\"", &json), Err(FilterError::message(r#"number (1) and string ("s") cannot be added"#.to_string())));
+        assert_eq!(run(".a[0].b = 1", &json), Err(FilterError::message(r#"Cannot index number with "b""#.to_string())));
+        assert_eq!(run("1 = 2", &json), Err(FilterError::message("Invalid path expression with result 1".to_string())));
+        assert!(Filter::parse(".a = .b = 1").is_err());
+        assert!(Filter::parse(".a + = 1").is_err());
+    }
+
+    #[test]
+    fn test_apply_label() {
+        let json = Json::from_str(r#"{"a": [1, 2, 3]}"#).unwrap();
+        assert_eq!(run("label $out | .a[] | if . == 2 then break $out else . end", &json), Ok("1".to_string()));
+        assert_eq!(run("[label $out | .a[] | ., break $out], 0", &json), Ok("[1] 0".to_string()));
+        assert_eq!(run("label $a | label $b | .a[] | if . == 2 then break $b else . end, 9", &json), Ok("1 9".to_string()));
+        assert_eq!(run("[.a[] as $x | label $next | if $x == 2 then break $next else $x end]", &json), Ok("[1,3]".to_string()));
+        assert_eq!(run("label $a | (label $b | .a[] | ., break $a), 9", &json), Ok("1".to_string()));
+        assert_eq!(run("[label $out | def f: .a[] | ., break $out; f]", &json), Ok("[1]".to_string()));
+        assert_eq!(run("try (label $out | break $out, error(\"x\")) catch .", &json), Ok("".to_string()));
+        assert_eq!(run("path(label $out | .a[] | select(. > 1) | ., break $out)", &json), Ok(r#"["a",1]"#.to_string()));
+        assert_eq!(run("break $out", &json), Err(FilterError::message("$*label-out is not defined".to_string())));
+        assert!(Filter::parse("label $out").is_err());
+    }
+
+    #[test]
+    fn test_apply_reduce() {
+        let json = Json::from_str(r#"{"a": [1, 2, 3], "o": {"x": 1, "y": 2}}"#).unwrap();
+        assert_eq!(run("reduce .a[] as $n (0; . + $n)", &json), Ok("6".to_string()));
+        assert_eq!(run("reduce .a[] as $n ([]; [$n] + .)", &json), Ok("[3,2,1]".to_string()));
+        assert_eq!(run("reduce .o[] as $v (.a; . + [$v])", &json), Ok("[1,2,3,1,2]".to_string()));
+        assert_eq!(run("reduce .a[] as $n (0, 10; . + $n)", &json), Ok("6 16".to_string()));
+        assert_eq!(run("reduce .a[] as $n (0; ., . * 10 + $n)", &json), Ok("123".to_string()));
+        assert_eq!(run("reduce .a[]?[]? as $n (0; . + 1)", &json), Ok("0".to_string()));
+        assert_eq!(run("reduce .a[] as $a (0; reduce (1, 2) as $b (.; . + $a * $b))", &json), Ok("18".to_string()));
+        assert_eq!(run("$x", &json), Err(FilterError::message("$x is not defined".to_string())));
+        assert!(Filter::parse("reduce .a[] as n (0; .)").is_err());
+        assert!(Filter::parse("reduce .a[] as $n (0)").is_err());
+    }
+
+    #[test]
+    fn test_apply_recurse() {
+        use json::Json::*;
+        let json = JObject(vec![("name".into(), JString("a".into())), ("children".into(), JArray(vec![JObject(vec![("name".into(), JString("b".into()))]), JNumber(1f64.into())]))]);
+        let run = |program| Filter::parse(program).unwrap().apply(&json).unwrap();
+        assert_eq!(run(".."), vec![json.clone(), JString("a".into()), run(".children")[0].clone(), run(".children[0]")[0].clone(), JString("b".into()), JNumber(1f64.into())]);
+        assert_eq!(run(".. | .name?"), vec![JString("a".into()), JString("b".into())]);
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        use json::Json::*;
+        let json = JArray(vec![JNumber(1f64.into()), JObject(vec![("a".into(), JBool(true))]), JString("s".into())]);
+        assert_eq!(run(".[0].a", &json), error("Cannot index number with \"a\""));
+        assert_eq!(run(".[1][0]", &json), error("Cannot index object with number"));
+        assert_eq!(run(".[0][1:]", &json), error("Cannot index number with object"));
+        assert_eq!(run(".[2][]", &json), error("Cannot iterate over string"));
+        assert_eq!(run(".[0].a?", &json), Ok(String::new()));
+        assert_eq!(run(".[].a?", &json), Ok(String::new()));
+        assert_eq!(run("(.[] | .a)?", &json), Ok(String::new()));
+        assert_eq!(run("(.[1:][] | .a)?", &json), Ok("true".to_string()));
+        assert_eq!(run(".[2][0]", &json), error("Cannot index string with number"));
+        assert_eq!(run(".[] | .a?", &json), Ok("true".to_string()));
+        assert_eq!(run(".[3].a[]", &json), error("Cannot iterate over null"));
+    }
+
+    #[test]
+    fn test_apply_spans() {
+        let json = Json::from_str(r#"{"a": [1, "x"], "b": {"c": 2}}"#).unwrap();
+        let span = |program: &str| {
+            let e = Filter::parse_with_spans(program).unwrap().apply(&json).unwrap_err();
+            assert_eq!(Filter::parse(program).unwrap().apply(&json).map_err(|e| e.to_string()), Err(e.to_string()));
+            e.span().map(|span| (span.start.line, span.start.column, span.fragment(program).to_string()))
+        };
+        assert_eq!(span(".a[0].c"), Some((1, 6, ".c".to_string())));
+        assert_eq!(span("{a: 1} | .a.b"), Some((1, 12, ".b".to_string())));
+        assert_eq!(span(".b.c[]"), Some((1, 5, "[]".to_string())));
+        assert_eq!(span(".a[.a]"), Some((1, 3, "[.a]".to_string())));
+        assert_eq!(span(".b | .c[]"), Some((1, 8, "[]".to_string())));
+        assert_eq!(span(".a[] | . + 1"), Some((1, 8, ". + 1".to_string())));
+        assert_eq!(span("1 +\n  (.b - 1) * 2"), Some((2, 4, ".b - 1".to_string())));
+        assert_eq!(span(".a | -.[1]"), Some((1, 6, "-.[1]".to_string())));
+        assert_eq!(span(".b.c |= . + \"s\""), Some((1, 9, ". + \"s\"".to_string())));
+        assert_eq!(span(".a[1] | error"), Some((1, 9, "error".to_string())));
+        assert_eq!(span("[.a[] | tonumber]"), Some((1, 9, "tonumber".to_string())));
+        assert_eq!(span("try error catch (. - 1)"), Some((1, 18, ". - 1".to_string())));
+        assert_eq!(Filter::parse_with_spans(r#"[.a[] | select(type == "number") | . + 1]"#).unwrap().apply(&json), Ok(vec![Json::from_str("[2]").unwrap()]));
+    }
+}
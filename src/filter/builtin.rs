@@ -0,0 +1,1498 @@
+use std::prelude::v1::*;
+use json::*;
+use parsercombinator::*;
+use datetime::Tm;
+use regex::{Flags, Match, Regex, RetryLimitExceeded};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::mem;
+use super::{BinaryOp, Filter, FilterError, FilterResult, Env, Output, PathOutput, PathValue, INPUT_LINE_NUMBER};
+use super::{binary, compare, field, index, insert, is_truthy, iterate, slice, slice_range, type_name, with_label};
+use super::parse_number_literal;
+
+/// Builtins written in jq, one definition on each line. A definition may call only those above it.
+pub const PRELUDE: &str = r#"
+def not: if . then false else true end;
+def map(f): [.[] | f];
+def map_values(f): .[] |= f;
+def select(f): if f then . else empty end;
+def values: select(. != null);
+def in(xs): . as $x | xs | has($x);
+def inside(xs): . as $x | xs | contains($x);
+def sort: sort_by(.);
+def unique: unique_by(.);
+def with_entries(f): to_entries | map(f) | from_entries;
+def error(msg): msg | error;
+def halt_error: halt_error(5);
+def del(f): delpaths([path(f)]);
+def paths: path(..) | select(length > 0);
+def paths(node_filter): . as $dot | paths | select(. as $p | $dot | getpath($p) | node_filter);
+def scalars: select([type] - ["array", "object"] | length > 0);
+def leaf_paths: paths(scalars);
+def any: any(.[]; .);
+def any(f): any(.[]; f);
+def all: all(.[]; .);
+def all(f): all(.[]; f);
+def first(f): label $out | (f | ., break $out);
+def first: .[0];
+def last(f): reduce f as $x (null; $x);
+def last: .[-1];
+def nth($n; f): if $n < 0 then error("Out of bounds negative array index") else last(limit($n + 1; f)) end;
+def until(cond; update): def _until: if cond then . else update | _until end; _until;
+def while(cond; update): def _while: if cond then ., (update | _while) else empty end; _while;
+def repeat(f): def _repeat: ., (f | _repeat); _repeat;
+def match($re): if ($re | type) == "array" then match($re[0]; $re[1]) else match($re; null) end;
+def test($re): if ($re | type) == "array" then test($re[0]; $re[1]) else test($re; null) end;
+def capture(re; flags): match(re; flags) | [.captures[] | select(.name != null) | {key: .name, value: .string}] | from_entries;
+def capture($re): if ($re | type) == "array" then capture($re[0]; $re[1]) else capture($re; null) end;
+def sub(re; str): sub(re; str; "");
+def gsub(re; str; flags): sub(re; str; flags + "g");
+def gsub(re; str): sub(re; str; "g");
+def todateiso8601: strftime("%Y-%m-%dT%H:%M:%SZ");
+def fromdateiso8601: strptime("%Y-%m-%dT%H:%M:%SZ") | mktime;
+def todate: todateiso8601;
+def fromdate: fromdateiso8601;
+def date: todate;
+def dateadd(u; n): . + n;
+def datesub(u; n): . - n;
+"#;
+
+/// A builtin written in Rust, which takes the arguments as they are.
+pub type Native = for<'a> fn(&[Filter], &Json<'a>, &Env<'_, 'a>, &mut Output<'_, 'a>) -> FilterResult<()>;
+
+pub fn native(name: &str, arity: usize) -> Option<Native> {
+    match (name, arity) {
+        ("empty", 0) => Some(empty),
+        ("error", 0) => Some(error),
+        ("halt", 0) => Some(halt),
+        ("halt_error", 1) => Some(halt_error),
+        ("keys", 0) => Some(keys),
+        ("keys_unsorted", 0) => Some(keys_unsorted),
+        ("has", 1) => Some(has),
+        ("contains", 1) => Some(contains),
+        ("length", 0) => Some(length),
+        ("utf8bytelength", 0) => Some(utf8bytelength),
+        ("sort_by", 1) => Some(sort_by),
+        ("group_by", 1) => Some(group_by),
+        ("unique_by", 1) => Some(unique_by),
+        ("to_entries", 0) => Some(to_entries),
+        ("from_entries", 0) => Some(from_entries),
+        ("type", 0) => Some(type_),
+        ("tostring", 0) => Some(tostring),
+        ("tonumber", 0) => Some(tonumber),
+        ("ascii_downcase", 0) => Some(ascii_downcase),
+        ("ascii_upcase", 0) => Some(ascii_upcase),
+        ("startswith", 1) => Some(startswith),
+        ("endswith", 1) => Some(endswith),
+        ("ltrimstr", 1) => Some(ltrimstr),
+        ("rtrimstr", 1) => Some(rtrimstr),
+        ("trim", 0) => Some(trim),
+        ("ltrim", 0) => Some(ltrim),
+        ("rtrim", 0) => Some(rtrim),
+        ("flatten", 0) => Some(flatten),
+        ("flatten", 1) => Some(flatten),
+        ("path", 1) => Some(path),
+        ("getpath", 1) => Some(getpath),
+        ("setpath", 2) => Some(setpath),
+        ("delpaths", 1) => Some(delpaths),
+        ("tostream", 0) => Some(tostream),
+        ("fromstream", 1) => Some(fromstream),
+        ("truncate_stream", 1) => Some(truncate_stream),
+        ("any", 2) => Some(any),
+        #[cfg(feature = "std")]
+        ("floor", 0) => Some(floor),
+        #[cfg(feature = "std")]
+        ("ceil", 0) => Some(ceil),
+        #[cfg(feature = "std")]
+        ("round", 0) => Some(round),
+        #[cfg(feature = "std")]
+        ("sqrt", 0) => Some(sqrt),
+        #[cfg(feature = "std")]
+        ("fabs", 0) => Some(fabs),
+        #[cfg(feature = "std")]
+        ("log", 0) => Some(log),
+        #[cfg(feature = "std")]
+        ("exp", 0) => Some(exp),
+        #[cfg(feature = "std")]
+        ("pow", 2) => Some(pow),
+        ("input_line_number", 0) => Some(input_line_number),
+        ("gmtime", 0) => Some(gmtime),
+        ("mktime", 0) => Some(mktime),
+        ("strftime", 1) => Some(strftime),
+        ("strptime", 1) => Some(strptime),
+        #[cfg(feature = "std")]
+        ("now", 0) => Some(now),
+        ("nan", 0) => Some(nan),
+        ("infinite", 0) => Some(infinite),
+        ("isnan", 0) => Some(isnan),
+        ("isinfinite", 0) => Some(isinfinite),
+        ("isnormal", 0) => Some(isnormal),
+        ("limit", 2) => Some(limit),
+        ("nth", 1) => Some(nth),
+        ("match", 2) => Some(match_),
+        ("test", 2) => Some(test),
+        ("sub", 3) => Some(sub),
+        ("all", 2) => Some(all),
+        _ => None
+    }
+}
+
+/// A format of `@name`, which turns a value into a string.
+pub type Format = fn(&Json) -> FilterResult<String>;
+
+pub fn format(name: &str) -> Option<Format> {
+    match name {
+        "text" => Some(format_text),
+        "json" => Some(format_json),
+        "csv" => Some(format_csv),
+        "tsv" => Some(format_tsv),
+        "html" => Some(format_html),
+        "uri" => Some(format_uri),
+        "base64" => Some(format_base64),
+        "base64d" => Some(format_base64d),
+        _ => None
+    }
+}
+
+/// A builtin which is a path expression, run by `path(f)` and the like.
+pub type NativePaths = for<'a> fn(&[Filter], &PathValue<'a>, &Env<'_, 'a>, &mut PathOutput<'_, 'a>) -> FilterResult<()>;
+
+pub fn native_paths(name: &str, arity: usize) -> Option<NativePaths> {
+    match (name, arity) {
+        ("getpath", 1) => Some(getpath_paths),
+        _ => None
+    }
+}
+
+fn empty<'a>(_: &[Filter], _: &Json<'a>, _: &Env<'_, 'a>, _: &mut Output<'_, 'a>) -> FilterResult<()> {
+    Ok(())
+}
+
+/// `error` raises the input as an error, which `try` catches.
+fn error<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, _: &mut Output<'_, 'a>) -> FilterResult<()> {
+    Err(FilterError::Runtime(json.clone().into_owned(), None))
+}
+
+/// `halt` stops the program with the exit status 0.
+fn halt<'a>(_: &[Filter], _: &Json<'a>, _: &Env<'_, 'a>, _: &mut Output<'_, 'a>) -> FilterResult<()> {
+    Err(FilterError::Halt(0, None))
+}
+
+/// `halt_error(code)` stops the program with the exit status `code`, printing the input to the standard error.
+fn halt_error<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, _: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |code| match code {
+        Json::JNumber(ref code) => Err(FilterError::Halt(code.value() as i32, Some(json.clone().into_owned()))),
+        _ => Err(FilterError::message("halt_error/1: number required".to_string()))
+    })
+}
+
+/// `keys` lists the keys of an object in order of the code points, or the indices of an array.
+fn keys<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let keys = match *json {
+        Json::JObject(ref entries) => {
+            let mut keys: Vec<_> = entries.iter().map(|(k, _)| k.clone()).collect();
+            keys.sort();
+            keys.into_iter().map(Json::JString).collect()
+        },
+        _ => list_keys(json)?
+    };
+    out(Json::JArray(keys))
+}
+
+/// `keys_unsorted` lists the keys of an object in the order of the object.
+fn keys_unsorted<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JArray(list_keys(json)?))
+}
+
+fn list_keys<'a>(json: &Json<'a>) -> FilterResult<Vec<Json<'a>>> {
+    match *json {
+        Json::JObject(ref entries) => Ok(entries.iter().map(|(k, _)| Json::JString(k.clone())).collect()),
+        Json::JArray(ref items) => Ok((0..items.len()).map(|i| Json::JNumber((i as f64).into())).collect()),
+        ref j => Err(FilterError::message(format!("{} ({}) has no keys", type_name(j), print_json_compact(j))))
+    }
+}
+
+/// `has(k)` tells whether an object has the key `k` or an array has the index `k`, for each output of `k`.
+fn has<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |key| {
+        let found = match (json, &key) {
+            (Json::JObject(entries), Json::JString(k)) => entries.iter().any(|(k2, _)| k2 == k),
+            // Like jq, the index is truncated into an integer.
+            (Json::JArray(items), Json::JNumber(i)) => i.value() > -1f64 && (i.value() as usize) < items.len(),
+            (j, k) => return Err(FilterError::message(format!("Cannot check whether {} has a {} key", type_name(j), type_name(k))))
+        };
+        out(Json::JBool(found))
+    })
+}
+
+/// `contains(b)` tells whether the input contains `b`: a string contains its substrings,
+/// an array contains the arrays whose every element is contained by one of its elements,
+/// and an object contains the objects whose every value is contained by its value of the same key.
+/// The other values contain only those equal to them.
+fn contains<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |b| {
+        if !same_kind(json, &b) {
+            return Err(FilterError::message(format!("{} ({}) and {} ({}) cannot have their containment checked",
+                type_name(json), print_json_compact(json), type_name(&b), print_json_compact(&b))));
+        }
+        out(Json::JBool(contains_json(json, &b)))
+    })
+}
+
+/// Whether `a` and `b` are of the same type, where `true` and `false` are of different ones like jq.
+fn same_kind(a: &Json, b: &Json) -> bool {
+    match (a, b) {
+        (&Json::JBool(x), &Json::JBool(y)) => x == y,
+        _ => type_name(a) == type_name(b)
+    }
+}
+
+fn contains_json(a: &Json, b: &Json) -> bool {
+    match (a, b) {
+        (Json::JString(a), Json::JString(b)) => a.contains(&**b),
+        (Json::JArray(a), Json::JArray(b)) => b.iter().all(|y| a.iter().any(|x| contains_json(x, y))),
+        (Json::JObject(a), Json::JObject(b)) =>
+            b.iter().all(|(key, y)| a.iter().any(|(k, x)| k == key && contains_json(x, y))),
+        _ => same_kind(a, b) && compare(a, b) == Ordering::Equal
+    }
+}
+
+/// `length` counts the code points of a string, the elements of an array or the entries of an object.
+/// The length of a number is its absolute value, and that of `null` is 0.
+fn length<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let len = match *json {
+        Json::JString(ref s) => s.chars().count() as f64,
+        Json::JArray(ref items) => items.len() as f64,
+        Json::JObject(ref entries) => entries.len() as f64,
+        Json::JNumber(ref n) => n.value().abs(),
+        Json::JNull => 0f64,
+        ref j => return Err(FilterError::message(format!("{} ({}) has no length", type_name(j), print_json_compact(j))))
+    };
+    out(Json::JNumber(len.into()))
+}
+
+fn utf8bytelength<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    match *json {
+        Json::JString(ref s) => out(Json::JNumber((s.len() as f64).into())),
+        ref j => Err(FilterError::message(format!("{} ({}) only strings have UTF-8 byte length", type_name(j), print_json_compact(j))))
+    }
+}
+
+/// `sort_by(f)` sorts an array stably by the outputs of `f` on each element.
+fn sort_by<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let sorted = sort_by_key(&args[0], json, env)?;
+    out(Json::JArray(sorted.into_iter().map(|(_, item)| item).collect()))
+}
+
+/// `group_by(f)` sorts an array by `f` like `sort_by(f)` and splits it into arrays of the elements
+/// for which `f` outputs the same.
+fn group_by<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let groups = group_by_key(sort_by_key(&args[0], json, env)?);
+    out(Json::JArray(groups.into_iter().map(Json::JArray).collect()))
+}
+
+/// `unique_by(f)` takes the first element of each group of `group_by(f)`.
+fn unique_by<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let groups = group_by_key(sort_by_key(&args[0], json, env)?);
+    out(Json::JArray(groups.into_iter().filter_map(|group| group.into_iter().next()).collect()))
+}
+
+/// Pairs each element of an array with `[f]` on it, and sorts them stably by the former.
+fn sort_by_key<'a>(f: &Filter, json: &Json<'a>, env: &Env<'_, 'a>) -> FilterResult<Vec<(Json<'a>, Json<'a>)>> {
+    let items = match *json {
+        Json::JArray(ref items) => items,
+        ref j => return Err(FilterError::message(format!("{} ({}) cannot be sorted, as it is not an array", type_name(j), print_json_compact(j))))
+    };
+    let mut pairs = vec![];
+    for item in items {
+        pairs.push((Json::JArray(f.collect(item, env)?), item.clone()));
+    }
+    pairs.sort_by(|a, b| compare(&a.0, &b.0));
+    Ok(pairs)
+}
+
+fn group_by_key<'a>(sorted: Vec<(Json<'a>, Json<'a>)>) -> Vec<Vec<Json<'a>>> {
+    let mut groups: Vec<(Json, Vec<Json>)> = vec![];
+    for (key, item) in sorted {
+        match groups.last_mut() {
+            Some(&mut (ref k, ref mut group)) if compare(k, &key) == Ordering::Equal => group.push(item),
+            _ => groups.push((key, vec![item]))
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// `to_entries` turns an object into an array of `{"key": k, "value": v}`.
+/// Arrays give their indices as the keys.
+fn to_entries<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let keys = list_keys(json)?;
+    let values = iterate(json.clone())?;
+    let entries = keys.into_iter().zip(values).map(|(k, v)| Json::JObject(vec![("key".into(), k), ("value".into(), v)]));
+    out(Json::JArray(entries.collect()))
+}
+
+/// `from_entries` builds an object from entries like those of `to_entries`.
+/// As in jq, the key may be under `k`, `name`, `Name`, `K` or `Key` instead, and the value under `v`.
+/// A key which is not a string is converted into its JSON text.
+fn from_entries<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let mut obj = vec![];
+    for entry in iterate(json.clone())? {
+        let mut key = field(entry.clone(), "key")?;
+        if key == Json::JNull {
+            // `.k // .name // .Name // .K // .Key`
+            for name in &["k", "name", "Name", "K", "Key"] {
+                key = field(entry.clone(), name)?;
+                if is_truthy(&key) {
+                    break;
+                }
+            }
+        }
+        let key = match key {
+            Json::JString(k) => k,
+            k => Cow::Owned(print_json_compact(&k))
+        };
+        let has_value = match entry {
+            Json::JObject(ref entries) => entries.iter().any(|(k, _)| k == "value"),
+            _ => false
+        };
+        let value = field(entry, if has_value { "value" } else { "v" })?;
+        insert(&mut obj, key, value);
+    }
+    out(Json::JObject(obj))
+}
+
+/// `flatten(depth)` splices the arrays in an array into it, recursively down to `depth` levels.
+/// `flatten` has no limit of the depth.
+fn flatten<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    fn splice<'a>(items: Vec<Json<'a>>, depth: f64, out: &mut Vec<Json<'a>>) {
+        for item in items {
+            match item {
+                Json::JArray(items) if depth > 0f64 => splice(items, depth - 1f64, out),
+                item => out.push(item)
+            }
+        }
+    }
+    let depths = match args.first() {
+        Some(f) => f.collect(json, env)?,
+        None => vec![Json::JNumber(f64::INFINITY.into())]
+    };
+    for depth in depths {
+        let depth = match depth {
+            Json::JNumber(ref d) if d.value() >= 0f64 => d.value(),
+            Json::JNumber(_) => return Err(FilterError::message("flatten depth must not be negative".to_string())),
+            d => return Err(FilterError::message(format!("{} ({}) cannot be a depth", type_name(&d), print_json_compact(&d))))
+        };
+        let mut flat = vec![];
+        splice(iterate(json.clone())?, depth, &mut flat);
+        out(Json::JArray(flat))?;
+    }
+    Ok(())
+}
+
+fn type_<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JString(type_name(json).into()))
+}
+
+/// `tostring` gives a string as it is, and any other value as its compact JSON text.
+fn tostring<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    match *json {
+        Json::JString(_) => out(json.clone()),
+        ref j => out(Json::JString(Cow::Owned(print_json_compact(j))))
+    }
+}
+
+/// `@text`, which is `tostring`
+fn format_text(json: &Json) -> FilterResult<String> {
+    Ok(match *json {
+        Json::JString(ref s) => s.to_string(),
+        ref j => print_json_compact(j)
+    })
+}
+
+fn format_json(json: &Json) -> FilterResult<String> {
+    Ok(print_json_compact(json))
+}
+
+/// Joins the elements of an array into a row, quoting the strings by `quote`.
+fn format_row(json: &Json, name: &str, separator: &str, quote: fn(&str) -> String) -> FilterResult<String> {
+    let items = match *json {
+        Json::JArray(ref items) => items,
+        ref j => return Err(FilterError::message(format!("{} ({}) cannot be {}-formatted, only an array can be", type_name(j), print_json_compact(j), name)))
+    };
+    let fields = items.iter().map(|item| match *item {
+        Json::JString(ref s) => Ok(quote(s)),
+        Json::JNumber(_) | Json::JBool(_) => Ok(print_json_compact(item)),
+        Json::JNull => Ok(String::new()),
+        ref j => Err(FilterError::message(format!("{} ({}) is not valid in a {} row", type_name(j), print_json_compact(j), name)))
+    });
+    Ok(fields.collect::<FilterResult<Vec<_>>>()?.join(separator))
+}
+
+/// `@csv` of an array, where strings are quoted with `"` and `"` in them is doubled.
+fn format_csv(json: &Json) -> FilterResult<String> {
+    format_row(json, "csv", ",", |s| format!("\"{}\"", s.replace('"', "\"\"")))
+}
+
+/// `@tsv` of an array, where tabs, newlines, carriage returns and backslashes in strings are escaped.
+fn format_tsv(json: &Json) -> FilterResult<String> {
+    format_row(json, "tsv", "\t", |s| s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"))
+}
+
+/// `@html` of the text, where `<>&'"` are escaped.
+fn format_html(json: &Json) -> FilterResult<String> {
+    let mut ret = String::new();
+    for c in format_text(json)?.chars() {
+        match c {
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '&' => ret.push_str("&amp;"),
+            '\'' => ret.push_str("&#39;"),
+            '"' => ret.push_str("&quot;"),
+            c => ret.push(c)
+        }
+    }
+    Ok(ret)
+}
+
+/// `@uri` of the text, where every byte but the unreserved characters is percent-encoded.
+fn format_uri(json: &Json) -> FilterResult<String> {
+    let mut ret = String::new();
+    for b in format_text(json)?.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => ret.push(b as char),
+            b => ret.push_str(&format!("%{:02X}", b))
+        }
+    }
+    Ok(ret)
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// `@base64` of the UTF-8 bytes of the text, with the padding.
+fn format_base64(json: &Json) -> FilterResult<String> {
+    let mut ret = String::new();
+    for chunk in format_text(json)?.as_bytes().chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// `@base64d` decodes the text from Base64, where the padding is optional.
+/// The bytes which are not UTF-8 become U+FFFD.
+fn format_base64d(json: &Json) -> FilterResult<String> {
+    let text = format_text(json)?;
+    let invalid = || FilterError::message(format!("{} ({}) is not valid base64 data", type_name(json), print_json_compact(json)));
+    let digits = text.trim_end_matches('=').bytes()
+        .map(|b| BASE64.iter().position(|&d| d == b).map(|d| d as u32))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    if digits.len() % 4 == 1 {
+        return Err(invalid());
+    }
+    let mut bytes = vec![];
+    for chunk in digits.chunks(4) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &d)| n | d << (18 - 6 * i));
+        bytes.extend((0..chunk.len() - 1).map(|i| (n >> (16 - 8 * i)) as u8));
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// `tonumber` parses a string such as `"-1.5e3"` into a number, and gives a number as it is.
+fn tonumber<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    match *json {
+        Json::JNumber(_) => out(json.clone()),
+        Json::JString(ref s) => match chr('-').or_not().and(parse_number_literal()).skip(eof()).parse(s) {
+            Ok((sign, v)) => out(Json::JNumber(if sign.is_some() { (-v).into() } else { v.into() })),
+            _ => Err(FilterError::message(format!("Cannot parse '{}' as a number", s)))
+        },
+        ref j => Err(FilterError::message(format!("{} ({}) cannot be parsed as a number", type_name(j), print_json_compact(j))))
+    }
+}
+
+/// The input of a builtin which takes only strings, such as `ascii_downcase`.
+fn string_input<'j>(json: &'j Json, name: &str) -> FilterResult<&'j str> {
+    match *json {
+        Json::JString(ref s) => Ok(s),
+        ref j => Err(FilterError::message(format!("{} input must be a string, not {} ({})", name, type_name(j), print_json_compact(j))))
+    }
+}
+
+/// `ascii_downcase` turns the ASCII letters of a string into the lower case, leaving the others as they are.
+fn ascii_downcase<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JString(Cow::Owned(string_input(json, "ascii_downcase")?.to_ascii_lowercase())))
+}
+
+fn ascii_upcase<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JString(Cow::Owned(string_input(json, "ascii_upcase")?.to_ascii_uppercase())))
+}
+
+/// `ltrimstr(s)` removes the prefix `s` from a string. Like jq, it gives the input as it is
+/// when it does not start with `s`, or either is not a string.
+fn ltrimstr<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |prefix| match (json, &prefix) {
+        (Json::JString(s), Json::JString(p)) if s.starts_with(&**p) => out(Json::JString(Cow::Owned(s[p.len()..].to_string()))),
+        _ => out(json.clone())
+    })
+}
+
+/// `startswith(s)` tells whether a string starts with `s`.
+fn startswith<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |prefix| match (json, &prefix) {
+        (Json::JString(s), Json::JString(p)) => out(Json::JBool(s.starts_with(&**p))),
+        _ => Err(FilterError::message("startswith() requires string inputs".to_string()))
+    })
+}
+
+/// `endswith(s)` tells whether a string ends with `s`.
+fn endswith<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |suffix| match (json, &suffix) {
+        (Json::JString(s), Json::JString(p)) => out(Json::JBool(s.ends_with(&**p))),
+        _ => Err(FilterError::message("endswith() requires string inputs".to_string()))
+    })
+}
+
+/// `rtrimstr(s)` removes the suffix `s` from a string like `ltrimstr(s)`.
+fn rtrimstr<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |suffix| match (json, &suffix) {
+        (Json::JString(s), Json::JString(p)) if s.ends_with(&**p) => out(Json::JString(Cow::Owned(s[..s.len() - p.len()].to_string()))),
+        _ => out(json.clone())
+    })
+}
+
+/// `trim` removes the whitespace at both the ends of a string.
+fn trim<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JString(Cow::Owned(string_input(json, "trim")?.trim().to_string())))
+}
+
+fn ltrim<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JString(Cow::Owned(string_input(json, "ltrim")?.trim_start().to_string())))
+}
+
+fn rtrim<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JString(Cow::Owned(string_input(json, "rtrim")?.trim_end().to_string())))
+}
+
+/// `path(f)` outputs the path of each output of `f` in the input, such as `["a", 0]` for `.a[0]`.
+fn path<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval_paths(&(vec![], json.clone()), env, &mut |(path, _)| out(Json::JArray(path)))
+}
+
+fn getpath<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |path| out(get_path(json.clone(), &path_keys(path)?)?))
+}
+
+/// `getpath(p)` as a path expression, whose path is `p` after that of the input.
+fn getpath_paths<'a>(args: &[Filter], json: &PathValue<'a>, env: &Env<'_, 'a>, out: &mut PathOutput<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(&json.1, env, &mut |path| {
+        let keys = path_keys(path)?;
+        let value = get_path(json.1.clone(), &keys)?;
+        let mut path = json.0.clone();
+        path.extend(keys);
+        out((path, value))
+    })
+}
+
+/// `setpath(p; v)` replaces the value at the path `p` with `v`, creating the objects and
+/// the arrays on the way where there is `null`.
+fn setpath<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |path| {
+        let keys = path_keys(path)?;
+        args[1].eval(json, env, &mut |value| out(update_path(json.clone(), &keys, &mut |_| Ok(value.clone()))?))
+    })
+}
+
+/// `delpaths(ps)` deletes the values at every path in the array `ps`.
+fn delpaths<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |paths| match paths {
+        Json::JArray(paths) => out(delete_paths(json.clone(), paths)?),
+        _ => Err(FilterError::message("Paths must be specified as an array".to_string()))
+    })
+}
+
+/// `tostream` outputs the events of the input: `[path, leaf]` for every scalar or empty array or object,
+/// and `[path]` after the last element of every other array or object, where `path` is that of the last element.
+fn tostream<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    stream_events(&mut vec![], json, out)
+}
+
+fn stream_events<'a>(path: &mut Vec<Json<'a>>, json: &Json<'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let children: Vec<(Json, &Json)> = match *json {
+        Json::JArray(ref items) => items.iter().enumerate().map(|(i, v)| (Json::JNumber((i as f64).into()), v)).collect(),
+        Json::JObject(ref entries) => entries.iter().map(|(k, v)| (Json::JString(k.clone()), v)).collect(),
+        _ => vec![]
+    };
+    if children.is_empty() {
+        return out(Json::JArray(vec![Json::JArray(path.clone()), json.clone()]));
+    }
+    for (key, value) in children {
+        path.push(key);
+        stream_events(path, value, out)?;
+        path.pop();
+    }
+    let mut last = path.clone();
+    last.push(match *json {
+        Json::JArray(ref items) => Json::JNumber(((items.len() - 1) as f64).into()),
+        Json::JObject(ref entries) => Json::JString(entries[entries.len() - 1].0.clone()),
+        _ => unreachable!()
+    });
+    out(Json::JArray(vec![Json::JArray(last)]))
+}
+
+/// Splits an event of `tostream` into its path and its leaf, if any.
+fn stream_event(event: Json) -> FilterResult<(Vec<Json>, Option<Json>)> {
+    if let Json::JArray(mut items) = event {
+        if items.len() == 1 || items.len() == 2 {
+            let leaf = if items.len() == 2 { items.pop() } else { None };
+            if let Some(Json::JArray(path)) = items.pop() {
+                return Ok((path, leaf));
+            }
+        }
+    }
+    Err(FilterError::message("Invalid stream event".to_string()))
+}
+
+/// `fromstream(f)` puts the events of `f` back together, and outputs each value at the top level.
+fn fromstream<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let mut value = Json::JNull;
+    args[0].eval(json, env, &mut |event| match stream_event(event)? {
+        (path, Some(leaf)) => {
+            if path.is_empty() {
+                return out(leaf);
+            }
+            value = update_path(mem::replace(&mut value, Json::JNull), &path, &mut |_| Ok(leaf.clone()))?;
+            Ok(())
+        },
+        (path, None) if path.len() == 1 => out(mem::replace(&mut value, Json::JNull)),
+        _ => Ok(())
+    })
+}
+
+/// `depth | truncate_stream(f)` removes the first `depth` keys from the paths of the events of `f`,
+/// dropping those not deeper than that. `f` runs on `null`.
+fn truncate_stream<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let depth = match *json {
+        Json::JNumber(ref n) if n.value() >= 0f64 => n.value() as usize,
+        ref j => return Err(FilterError::message(format!("{} ({}) cannot be a depth", type_name(j), print_json_compact(j))))
+    };
+    args[0].eval(&Json::JNull, env, &mut |event| {
+        let (path, leaf) = stream_event(event)?;
+        if path.len() <= depth {
+            return Ok(());
+        }
+        let mut event = vec![Json::JArray(path[depth..].to_vec())];
+        event.extend(leaf);
+        out(Json::JArray(event))
+    })
+}
+
+fn number_required(json: &Json) -> FilterError {
+    FilterError::message(format!("{} ({}) number required", type_name(json), print_json_compact(json)))
+}
+
+/// Runs `f` on the input, which must be a number.
+#[cfg(feature = "std")]
+fn math<'a>(json: &Json<'a>, out: &mut Output<'_, 'a>, f: fn(f64) -> f64) -> FilterResult<()> {
+    match *json {
+        Json::JNumber(ref n) => out(Json::JNumber(f(n.value()).into())),
+        ref j => Err(number_required(j))
+    }
+}
+
+#[cfg(feature = "std")]
+fn floor<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::floor)
+}
+
+#[cfg(feature = "std")]
+fn ceil<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::ceil)
+}
+
+/// `round` rounds half away from zero.
+#[cfg(feature = "std")]
+fn round<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::round)
+}
+
+#[cfg(feature = "std")]
+fn sqrt<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::sqrt)
+}
+
+#[cfg(feature = "std")]
+fn fabs<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::abs)
+}
+
+/// `log` is the natural logarithm.
+#[cfg(feature = "std")]
+fn log<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::ln)
+}
+
+#[cfg(feature = "std")]
+fn exp<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    math(json, out, f64::exp)
+}
+
+/// `pow(x; y)` for each output of `y` and then each output of `x`, like the operators.
+#[cfg(feature = "std")]
+fn pow<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[1].eval(json, env, &mut |y| args[0].eval(json, env, &mut |x| match (&x, &y) {
+        (Json::JNumber(x), Json::JNumber(y)) => out(Json::JNumber(x.value().powf(y.value()).into())),
+        (Json::JNumber(_), y) => Err(number_required(y)),
+        (x, _) => Err(number_required(x))
+    }))
+}
+
+/// `input_line_number` is the number of the lines read till the end of the input, with the rest
+/// of the line where it ends.
+fn input_line_number<'a>(_: &[Filter], _: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(env.lookup_var(INPUT_LINE_NUMBER).cloned().unwrap_or(Json::JNumber(0f64.into())))
+}
+
+/// The broken down time `[year, month, day, hours, minutes, seconds, weekday, yearday]` of `gmtime`.
+fn tm_to_json<'a>(tm: &Tm) -> Json<'a> {
+    let fields = [tm.year as f64, tm.month as f64, tm.day as f64, tm.hour as f64, tm.minute as f64,
+        tm.second, tm.weekday as f64, tm.yearday as f64];
+    Json::JArray(fields.iter().map(|&v| Json::JNumber(v.into())).collect())
+}
+
+/// Reads a broken down time, of which only the first 6 numbers are used.
+fn tm_from_json(json: &Json) -> Option<Tm> {
+    let fields = match *json {
+        Json::JArray(ref fields) if fields.len() >= 6 => fields,
+        _ => return None
+    };
+    let mut v = [0f64; 6];
+    for (v, field) in v.iter_mut().zip(fields) {
+        match *field {
+            Json::JNumber(ref n) => *v = n.value(),
+            _ => return None
+        }
+    }
+    Some(Tm {year: v[0] as i64, month: v[1] as i64, day: v[2] as i64, hour: v[3] as i64, minute: v[4] as i64,
+        second: v[5], weekday: 0, yearday: 0})
+}
+
+/// `gmtime` turns seconds since the epoch into a broken down time in UTC.
+fn gmtime<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    match *json {
+        Json::JNumber(ref t) => out(tm_to_json(&Tm::from_timestamp(t.value()))),
+        _ => Err(FilterError::message("gmtime() requires a number".to_string()))
+    }
+}
+
+/// `mktime` turns a broken down time in UTC into whole seconds since the epoch.
+fn mktime<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    match tm_from_json(json) {
+        Some(tm) => out(Json::JNumber((Tm {second: tm.second as i64 as f64, ..tm}.timestamp()).into())),
+        None => Err(FilterError::message("mktime requires array of 6 numbers".to_string()))
+    }
+}
+
+/// `strftime(fmt)` formats a broken down time, or seconds since the epoch.
+fn strftime<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let tm = match *json {
+        Json::JNumber(ref t) => Tm::from_timestamp(t.value()),
+        ref j => tm_from_json(j).ok_or_else(|| FilterError::message("strftime/1 requires parsed datetime inputs".to_string()))?.normalize()
+    };
+    args[0].eval(json, env, &mut |format| match format {
+        Json::JString(ref format) => out(Json::JString(Cow::Owned(tm.format(format)))),
+        _ => Err(FilterError::message("strftime/1 requires a string format".to_string()))
+    })
+}
+
+/// `strptime(fmt)` parses a string into a broken down time.
+fn strptime<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |format| match (json, &format) {
+        (Json::JString(s), Json::JString(format)) => match Tm::parse(s, format) {
+            Some(tm) => out(tm_to_json(&tm)),
+            None => Err(FilterError::message(format!("date \"{}\" does not match format \"{}\"", s, format)))
+        },
+        _ => Err(FilterError::message("strptime/1 requires string inputs and arguments".to_string()))
+    })
+}
+
+/// `now` is the current time in seconds since the epoch.
+#[cfg(feature = "std")]
+fn now<'a>(_: &[Filter], _: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    let elapsed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| FilterError::message(e.to_string()))?;
+    out(Json::JNumber(elapsed.as_secs_f64().into()))
+}
+
+fn nan<'a>(_: &[Filter], _: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JNumber(f64::NAN.into()))
+}
+
+fn infinite<'a>(_: &[Filter], _: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JNumber(f64::INFINITY.into()))
+}
+
+/// Tests the input, which must be a number, with `f`.
+fn number_test<'a>(json: &Json<'a>, out: &mut Output<'_, 'a>, f: fn(f64) -> bool) -> FilterResult<()> {
+    match *json {
+        Json::JNumber(ref n) => out(Json::JBool(f(n.value()))),
+        ref j => Err(number_required(j))
+    }
+}
+
+fn isnan<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    number_test(json, out, f64::is_nan)
+}
+
+fn isinfinite<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    number_test(json, out, f64::is_infinite)
+}
+
+/// `isnormal` is `false` for zero, subnormal numbers, the infinities and NaN.
+fn isnormal<'a>(_: &[Filter], json: &Json<'a>, _: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    number_test(json, out, f64::is_normal)
+}
+
+/// `limit(n; f)` outputs the first `n` outputs of `f`, and stops `f` then.
+fn limit<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |n| {
+        let n = match n {
+            Json::JNumber(n) => n.value(),
+            n => return Err(FilterError::message(format!("{} ({}) cannot be a limit", type_name(&n), print_json_compact(&n))))
+        };
+        let mut count = 0f64;
+        with_label(|label| {
+            if count >= n {
+                return Ok(());
+            }
+            args[1].eval(json, env, &mut |j| {
+                count += 1f64;
+                out(j)?;
+                if count >= n { Err(FilterError::Break(label)) } else { Ok(()) }
+            })
+        })
+    })
+}
+
+/// `nth(n)` is `.[n]` for each output of `n`.
+fn nth<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    args[0].eval(json, env, &mut |n| match n {
+        Json::JNumber(n) => out(index(json.clone(), n.value() as isize)?),
+        n => Err(cannot_index(json, &n))
+    })
+}
+
+/// `any(gen; cond)` tells whether `cond` outputs `true` for some output of `gen`,
+/// and stops `gen` at the first one.
+fn any<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JBool(find_truthiness(args, json, env, true)?))
+}
+
+/// `all(gen; cond)` tells whether `cond` outputs only `true` for every output of `gen`,
+/// and stops `gen` at the first `false`.
+fn all<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    out(Json::JBool(!find_truthiness(args, json, env, false)?))
+}
+
+/// Tells whether `args[1]` outputs a value of the truthiness `truthy` on an output of `args[0]`.
+fn find_truthiness<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, truthy: bool) -> FilterResult<bool> {
+    let mut found = false;
+    with_label(|label| args[0].eval(json, env, &mut |j| args[1].eval(&j, env, &mut |cond| {
+        if is_truthy(&cond) != truthy {
+            return Ok(());
+        }
+        found = true;
+        Err(FilterError::Break(label))
+    })))?;
+    Ok(found)
+}
+
+/// Runs `then` with the input as code points, and the regex of each output of `re`
+/// with the modifiers of each output of `flags`, which may be `null` for none.
+fn with_regex<'a>(re: &Filter, flags: &Filter, json: &Json<'a>, env: &Env<'_, 'a>, then: &mut dyn FnMut(&[char], Regex) -> FilterResult<()>) -> FilterResult<()> {
+    let text: Vec<char> = match *json {
+        Json::JString(ref s) => s.chars().collect(),
+        ref j => return Err(FilterError::message(format!("{} ({}) cannot be matched, as it is not a string", type_name(j), print_json_compact(j))))
+    };
+    re.eval(json, env, &mut |re| {
+        let re = match re {
+            Json::JString(re) => re,
+            re => return Err(FilterError::message(format!("{} ({}) cannot be matched, as it is not a string", type_name(&re), print_json_compact(&re))))
+        };
+        flags.eval(json, env, &mut |flags| {
+            let flags = match flags {
+                Json::JString(flags) => flags,
+                Json::JNull => Cow::Borrowed(""),
+                flags => return Err(FilterError::message(format!("{} ({}) is not a string", type_name(&flags), print_json_compact(&flags))))
+            };
+            if Flags::parse(&flags).is_none() {
+                return Err(FilterError::message(format!("{} is not a valid modifier string", flags)));
+            }
+            match Regex::new(&re, &flags) {
+                Ok(regex) => then(&text, regex),
+                Err(e) => Err(FilterError::message(format!("{} (at offset {}) is not a valid regex: {}", re, e.pos, e.message)))
+            }
+        })
+    })
+}
+
+fn retry_limit_exceeded(_: RetryLimitExceeded) -> FilterError {
+    FilterError::message("Regex failure: retry-limit-in-match over".to_string())
+}
+
+fn substring<'a>(text: &[char], start: usize, end: usize) -> Json<'a> {
+    Json::JString(Cow::Owned(text[start..end].iter().collect()))
+}
+
+/// `{"offset": 1, "length": 2, "string": "bc", "captures": [...]}` for a match, where
+/// each capture has the same keys and `"name"`. A group without a match has the offset -1.
+fn match_object<'a>(text: &[char], m: &Match, names: &[Option<String>]) -> Json<'a> {
+    let span = |start: usize, end: usize| vec![
+        ("offset".into(), Json::JNumber((start as f64).into())),
+        ("length".into(), Json::JNumber(((end - start) as f64).into())),
+        ("string".into(), substring(text, start, end))
+    ];
+    let captures = m.groups.iter().zip(names).map(|(group, name)| {
+        let mut capture = match *group {
+            Some((start, end)) => span(start, end),
+            None => vec![("offset".into(), Json::JNumber((-1f64).into())), ("length".into(), Json::JNumber(0f64.into())), ("string".into(), Json::JNull)]
+        };
+        capture.push(("name".into(), name.as_ref().map_or(Json::JNull, |name| Json::JString(Cow::Owned(name.clone())))));
+        Json::JObject(capture)
+    });
+    let mut obj = span(m.start, m.end);
+    obj.push(("captures".into(), Json::JArray(captures.collect())));
+    Json::JObject(obj)
+}
+
+/// `match(re; flags)` outputs each match of the regex `re` in a string as an object like
+/// `{"offset": 0, "length": 1, "string": "a", "captures": []}`. Only the first one is output
+/// without the modifier `g`.
+fn match_<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    with_regex(&args[0], &args[1], json, env, &mut |text, regex| {
+        regex.matches(text).map_err(retry_limit_exceeded)?.iter().try_for_each(|m| out(match_object(text, m, regex.names())))
+    })
+}
+
+/// `test(re; flags)` tells whether the regex `re` matches a string.
+fn test<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    with_regex(&args[0], &args[1], json, env, &mut |text, regex| out(Json::JBool(regex.find_at(text, 0).map_err(retry_limit_exceeded)?.is_some())))
+}
+
+/// `sub(re; str; flags)` replaces the first match of the regex `re` in a string, or every one with
+/// the modifier `g`, with `str` run on the object of the named captures like that of `capture(re)`.
+/// Each combination of the outputs of `str` for the matches gives an output.
+fn sub<'a>(args: &[Filter], json: &Json<'a>, env: &Env<'_, 'a>, out: &mut Output<'_, 'a>) -> FilterResult<()> {
+    with_regex(&args[0], &args[2], json, env, &mut |text, regex| {
+        let mut results = vec![String::new()];
+        let mut previous = 0;
+        for m in regex.matches(text).map_err(retry_limit_exceeded)? {
+            let gap: String = text[previous..m.start].iter().collect();
+            let captures = m.groups.iter().zip(regex.names()).filter_map(|(group, name)| {
+                let value = group.map_or(Json::JNull, |(start, end)| substring(text, start, end));
+                name.as_ref().map(|name| (Cow::Owned(name.clone()), value))
+            });
+            let replacements = args[1].collect(&Json::JObject(captures.collect()), env)?;
+            let mut next = vec![];
+            for result in &results {
+                for replacement in &replacements {
+                    let prefix = Json::JString(Cow::Owned(format!("{}{}", result, gap)));
+                    match binary(BinaryOp::Add, prefix, replacement.clone())? {
+                        Json::JString(s) => next.push(s.into_owned()),
+                        j => return Err(FilterError::message(format!("{} ({}) cannot be a replacement", type_name(&j), print_json_compact(&j))))
+                    }
+                }
+            }
+            results = next;
+            previous = m.end;
+        }
+        let rest: String = text[previous..].iter().collect();
+        results.into_iter().try_for_each(|result| out(Json::JString(Cow::Owned(result + &rest))))
+    })
+}
+
+pub fn delete_paths<'a>(mut json: Json<'a>, mut paths: Vec<Json<'a>>) -> FilterResult<Json<'a>> {
+    // Deleting the greatest path first keeps the indices in the others valid.
+    paths.sort_by(|a, b| compare(b, a));
+    for path in paths {
+        json = delete_path(json, &path_keys(path)?)?;
+    }
+    Ok(json)
+}
+
+fn path_keys(path: Json) -> FilterResult<Vec<Json>> {
+    match path {
+        Json::JArray(keys) => Ok(keys),
+        _ => Err(FilterError::message("Path must be specified as an array".to_string()))
+    }
+}
+
+/// The ends of a slice in a path, such as `{"start": 1, "end": null}` for `.[1:]`.
+fn slice_ends(key: &Json) -> FilterResult<(Option<isize>, Option<isize>)> {
+    let end = |name| match field(key.clone(), name)? {
+        Json::JNumber(i) => Ok(Some(i.value() as isize)),
+        Json::JNull => Ok(None),
+        _ => Err(FilterError::message("Start and end indices of an array slice must be numbers".to_string()))
+    };
+    Ok((end("start")?, end("end")?))
+}
+
+fn cannot_index(json: &Json, key: &Json) -> FilterError {
+    let key = match *key {
+        Json::JString(ref k) => format!("\"{}\"", k),
+        ref k => type_name(k).to_string()
+    };
+    FilterError::message(format!("Cannot index {} with {}", type_name(json), key))
+}
+
+/// The value of `json` at `key`, which is a string for an object, a number for an array or
+/// a slice such as `{"start": 1, "end": null}`.
+pub(super) fn index_by<'a>(json: Json<'a>, key: &Json) -> FilterResult<Json<'a>> {
+    match *key {
+        Json::JString(ref k) => field(json, k),
+        Json::JNumber(ref i) => index(json, i.value() as isize),
+        Json::JObject(_) => {
+            let (from, to) = slice_ends(key)?;
+            slice(json, from, to)
+        },
+        ref k => Err(cannot_index(&json, k))
+    }
+}
+
+fn get_path<'a>(json: Json<'a>, path: &[Json<'a>]) -> FilterResult<Json<'a>> {
+    path.iter().try_fold(json, |json, key| index_by(json, key))
+}
+
+/// Replaces the value at `path` in `json` with `update` on it, where a missing value is `null`.
+/// The objects and the arrays on the way are created where there is `null`.
+pub fn update_path<'a>(json: Json<'a>, path: &[Json<'a>], update: &mut dyn FnMut(Json<'a>) -> FilterResult<Json<'a>>) -> FilterResult<Json<'a>> {
+    let (key, rest) = match path.split_first() {
+        Some(kv) => kv,
+        None => return update(json)
+    };
+    match (json, key) {
+        (Json::JNull, Json::JString(_)) => update_path(Json::JObject(vec![]), path, update),
+        (Json::JNull, Json::JNumber(_)) | (Json::JNull, Json::JObject(_)) => update_path(Json::JArray(vec![]), path, update),
+        (Json::JObject(mut entries), Json::JString(k)) => {
+            match entries.iter().position(|(k2, _)| k2 == k) {
+                Some(i) => {
+                    let child = mem::replace(&mut entries[i].1, Json::JNull);
+                    entries[i].1 = update_path(child, rest, update)?;
+                },
+                None => entries.push((k.clone(), update_path(Json::JNull, rest, update)?))
+            }
+            Ok(Json::JObject(entries))
+        },
+        (Json::JArray(mut items), Json::JNumber(i)) => {
+            let i = i.value() as isize;
+            let i = if i < 0 { i + items.len() as isize } else { i };
+            if i < 0 {
+                return Err(FilterError::message("Out of bounds negative array index".to_string()));
+            }
+            let i = i as usize;
+            if i >= items.len() {
+                items.resize(i + 1, Json::JNull);
+            }
+            let child = mem::replace(&mut items[i], Json::JNull);
+            items[i] = update_path(child, rest, update)?;
+            Ok(Json::JArray(items))
+        },
+        (Json::JArray(mut items), Json::JObject(_)) => {
+            let (from, to) = slice_ends(key)?;
+            let (start, end) = slice_range(items.len(), from, to);
+            let old = items.drain(start..end).collect();
+            match update_path(Json::JArray(old), rest, update)? {
+                Json::JArray(new) => {
+                    items.splice(start..start, new);
+                    Ok(Json::JArray(items))
+                },
+                _ => Err(FilterError::message("A slice of an array can only be assigned another array".to_string()))
+            }
+        },
+        (json, key) => Err(cannot_index(&json, key))
+    }
+}
+
+/// Deletes the value at `path` in `json` if there is one.
+fn delete_path<'a>(json: Json<'a>, path: &[Json<'a>]) -> FilterResult<Json<'a>> {
+    let (key, rest) = match path.split_first() {
+        Some(kv) => kv,
+        None => return Ok(Json::JNull)
+    };
+    match (json, key) {
+        (Json::JNull, _) => Ok(Json::JNull),
+        (Json::JObject(mut entries), Json::JString(k)) => {
+            if let Some(i) = entries.iter().position(|(k2, _)| k2 == k) {
+                if rest.is_empty() {
+                    entries.remove(i);
+                } else {
+                    let child = mem::replace(&mut entries[i].1, Json::JNull);
+                    entries[i].1 = delete_path(child, rest)?;
+                }
+            }
+            Ok(Json::JObject(entries))
+        },
+        (Json::JArray(mut items), Json::JNumber(i)) => {
+            let i = i.value() as isize;
+            let i = if i < 0 { i + items.len() as isize } else { i };
+            if 0 <= i && (i as usize) < items.len() {
+                let i = i as usize;
+                if rest.is_empty() {
+                    items.remove(i);
+                } else {
+                    let child = mem::replace(&mut items[i], Json::JNull);
+                    items[i] = delete_path(child, rest)?;
+                }
+            }
+            Ok(Json::JArray(items))
+        },
+        (Json::JArray(mut items), Json::JObject(_)) => {
+            let (from, to) = slice_ends(key)?;
+            let (start, end) = slice_range(items.len(), from, to);
+            let old: Vec<_> = items.drain(start..end).collect();
+            if !rest.is_empty() {
+                if let Json::JArray(new) = delete_path(Json::JArray(old), rest)? {
+                    items.splice(start..start, new);
+                }
+            }
+            Ok(Json::JArray(items))
+        },
+        (json, key) => Err(cannot_index(&json, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &str, input: &str) -> FilterResult<String> {
+        let json = Json::from_str(input).unwrap();
+        let outputs = Filter::parse(program).unwrap().apply(&json)?;
+        Ok(outputs.iter().map(print_json_compact).collect::<Vec<_>>().join(" "))
+    }
+
+    fn error(message: &str) -> FilterResult<String> {
+        Err(FilterError::message(message.to_string()))
+    }
+
+    #[test]
+    fn test_keys() {
+        let obj = "{\"b\": 1, \"a\": [null], \"\u{3042}\": 2, \"B\": null}";
+        assert_eq!(run("keys", obj), Ok("[\"B\",\"a\",\"b\",\"\u{3042}\"]".to_string()));
+        assert_eq!(run("keys_unsorted", obj), Ok("[\"b\",\"a\",\"\u{3042}\",\"B\"]".to_string()));
+        assert_eq!(run("keys, keys_unsorted", "[3, 2]"), Ok("[0,1] [0,1]".to_string()));
+        assert_eq!(run("keys", "{}"), Ok("[]".to_string()));
+        assert_eq!(run("keys", "1"), error("number (1) has no keys"));
+        assert_eq!(run("keys_unsorted", "null"), error("null (null) has no keys"));
+    }
+
+    #[test]
+    fn test_values() {
+        assert_eq!(run("[.[] | values]", "[1, null, false, {}]"), Ok("[1,false,{}]".to_string()));
+        assert_eq!(run("[.[] | select(. > 1)]", "[1, 2, 3]"), Ok("[2,3]".to_string()));
+        assert_eq!(run("[.[] | select(true, true)]", "[1]"), Ok("[1,1]".to_string()));
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(run("[.[] | length]", "[\"a\u{3042}\", [1, [2]], {\"a\": 1}, -1.5, 0, null, \"\"]"), Ok("[2,2,1,1.5,0,0,0]".to_string()));
+        assert_eq!(run("length", "true"), error("boolean (true) has no length"));
+        assert_eq!(run("[.[] | utf8bytelength]", "[\"a\u{3042}\", \"\"]"), Ok("[4,0]".to_string()));
+        assert_eq!(run("utf8bytelength", "[1]"), error("array ([1]) only strings have UTF-8 byte length"));
+    }
+
+    #[test]
+    fn test_sort() {
+        let input = r#"[3, "a", null, [1], {"a": 1}, true, 1, false, {}, [0, 2]]"#;
+        assert_eq!(run("sort", input), Ok(r#"[null,false,true,1,3,"a",[0,2],[1],{},{"a":1}]"#.to_string()));
+        assert_eq!(run("sort, unique", "[]"), Ok("[] []".to_string()));
+        assert_eq!(run("unique", "[2, 1, 2, [1], 1, [1]]"), Ok("[1,2,[1]]".to_string()));
+        let people = r#"[{"n": "a", "age": 30}, {"n": "b", "age": 20}, {"n": "c", "age": 30}, {"n": "d"}]"#;
+        assert_eq!(run("sort_by(.age) | map(.n)", people), Ok(r#"["d","b","a","c"]"#.to_string()));
+        assert_eq!(run("sort_by(.age, .n) | map(.n)", people), Ok(r#"["d","b","a","c"]"#.to_string()));
+        assert_eq!(run("sort_by(.age | if . then -. else 0 end) | map(.n)", people), Ok(r#"["a","c","b","d"]"#.to_string()));
+        assert_eq!(run("group_by(.age) | map(map(.n))", people), Ok(r#"[["d"],["b"],["a","c"]]"#.to_string()));
+        assert_eq!(run("unique_by(.age) | map(.n)", people), Ok(r#"["d","b","a"]"#.to_string()));
+        assert_eq!(run("unique_by(length)", r#"["ab", "c", "de", ""]"#), Ok(r#"["","c","ab"]"#.to_string()));
+        assert_eq!(run("sort", r#"{"a": 1}"#), error(r#"object ({"a":1}) cannot be sorted, as it is not an array"#));
+        assert_eq!(run("group_by(.)", "1"), error("number (1) cannot be sorted, as it is not an array"));
+        assert_eq!(run("sort_by(.a)", "[1]"), error("Cannot index number with \"a\""));
+    }
+
+    #[test]
+    fn test_entries() {
+        assert_eq!(run("to_entries", r#"{"a": 1, "b": [2]}"#), Ok(r#"[{"key":"a","value":1},{"key":"b","value":[2]}]"#.to_string()));
+        assert_eq!(run("to_entries", r#"["x"]"#), Ok(r#"[{"key":0,"value":"x"}]"#.to_string()));
+        assert_eq!(run("to_entries", "1"), error("number (1) has no keys"));
+        assert_eq!(run("to_entries | from_entries", r#"{"a": 1, "b": null}"#), Ok(r#"{"a":1,"b":null}"#.to_string()));
+        let entries = r#"[{"k": "a", "v": 1}, {"name": "b", "value": 2}, {"Name": "c", "value": null, "v": 3},
+            {"K": "d"}, {"Key": "e", "v": 5}, {"key": 1, "value": 6}, {"key": false, "k": "g"}, {"key": null, "Key": true}, {"k": "a", "value": 0}]"#;
+        assert_eq!(run("from_entries", entries), Ok(r#"{"a":0,"b":2,"c":null,"d":null,"e":5,"1":6,"false":null,"true":null}"#.to_string()));
+        assert_eq!(run("from_entries", "[]"), Ok("{}".to_string()));
+        assert_eq!(run("from_entries", "[1]"), error("Cannot index number with \"key\""));
+        assert_eq!(run("from_entries", "1"), error("Cannot iterate over number"));
+        assert_eq!(run(r#"with_entries({key: .value, value: .key})"#, r#"{"a": "x", "b": "y"}"#), Ok(r#"{"x":"a","y":"b"}"#.to_string()));
+        assert_eq!(run(r#"with_entries(select(.value > 1))"#, r#"{"a": 1, "b": 2}"#), Ok(r#"{"b":2}"#.to_string()));
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(run(r#"error("x")"#, "null"), error("x"));
+        assert_eq!(run("error", r#"{"a": 1}"#), Err(FilterError::Runtime(Json::from_str(r#"{"a": 1}"#).unwrap(), None)));
+        assert_eq!(run(r#"try error({"a": 1}) catch .a"#, "null"), Ok("1".to_string()));
+        assert_eq!(run("[.[] | try error catch .]", "[1, null]"), Ok("[1,null]".to_string()));
+        assert_eq!(run(r#"[.[] | try (if . > 1 then error("big") else . end) catch "caught"]"#, "[1, 2]"), Ok(r#"[1,"caught"]"#.to_string()));
+        assert_eq!(run(r#"[error("a", "b")?]"#, "null"), Ok("[]".to_string()));
+        assert_eq!(run(r#"try error("x") catch ., 0"#, "null"), Ok(r#""x" 0"#.to_string()));
+        assert_eq!(run(r#"try error(error("inner")) catch ."#, "null"), Ok(r#""inner""#.to_string()));
+        assert_eq!(FilterError::Runtime(Json::JNull, None).to_string(), "null (not a string)");
+    }
+
+    #[test]
+    fn test_paths() {
+        let json = r#"{"a": [1, {"b": 2}], "c": null}"#;
+        assert_eq!(run("[path(.a[1].b, .c, .a[-1], .x.y)]", json), Ok(r#"[["a",1,"b"],["c"],["a",-1],["x","y"]]"#.to_string()));
+        assert_eq!(run("[path(.a[], .a[1:])]", json), Ok(r#"[["a",0],["a",1],["a",{"start":1,"end":null}]]"#.to_string()));
+        assert_eq!(run("[path(..)]", "[[1]]"), Ok("[[],[0],[0,0]]".to_string()));
+        assert_eq!(run("[path(.a | if length > 1 then .[0] else empty end)]", json), Ok(r#"[["a",0]]"#.to_string()));
+        assert_eq!(run("[path(.a[] | select(type == \"object\") | .b)]", json), Ok(r#"[["a",1,"b"]]"#.to_string()));
+        assert_eq!(run("def f: .a; [path(f[0]), path(getpath([\"a\", 1]) | .b)]", json), Ok(r#"[["a",0],["a",1,"b"]]"#.to_string()));
+        assert_eq!(run("[path(.c[]?, empty)]", json), Ok("[]".to_string()));
+        assert_eq!(run("path(1)", json), error("Invalid path expression with result 1"));
+        assert_eq!(run("path(.a | map(.))", json), error(r#"Invalid path expression with result [1,{"b":2}]"#));
+        assert_eq!(run("[paths]", json), Ok(r#"[["a"],["a",0],["a",1],["a",1,"b"],["c"]]"#.to_string()));
+        assert_eq!(run("[paths(type == \"number\")]", json), Ok(r#"[["a",0],["a",1,"b"]]"#.to_string()));
+        assert_eq!(run("[leaf_paths]", json), Ok(r#"[["a",0],["a",1,"b"]]"#.to_string()));
+    }
+
+    #[test]
+    fn test_getpath_setpath() {
+        let json = r#"{"a": [1, {"b": 2}], "c": null}"#;
+        assert_eq!(run(r#"getpath(["a", 1, "b"], ["x", 0], ["a", -2], ["a", {"start": 1}])"#, json), Ok(r#"2 null 1 [{"b":2}]"#.to_string()));
+        assert_eq!(run(r#"getpath(["a", "b"])"#, json), error(r#"Cannot index array with "b""#));
+        assert_eq!(run(r#"getpath("a")"#, json), error("Path must be specified as an array"));
+        assert_eq!(run(r#"setpath(["a", 1, "b"]; 3)"#, json), Ok(r#"{"a":[1,{"b":3}],"c":null}"#.to_string()));
+        assert_eq!(run(r#"setpath(["c", "d", 2]; 1)"#, json), Ok(r#"{"a":[1,{"b":2}],"c":{"d":[null,null,1]}}"#.to_string()));
+        assert_eq!(run(r#"setpath([]; 1), setpath(["a", -1]; 0)"#, json), Ok(r#"1 {"a":[1,0],"c":null}"#.to_string()));
+        assert_eq!(run(r#"setpath([{"start": 1, "end": 2}]; ["x", "y"])"#, "[1, 2, 3]"), Ok(r#"[1,"x","y",3]"#.to_string()));
+        assert_eq!(run(r#"setpath([{"start": 1}]; 0)"#, "[1, 2]"), error("A slice of an array can only be assigned another array"));
+        assert_eq!(run("[setpath([0], [1]; 5, 6)]", "[0, 0]"), Ok("[[5,0],[6,0],[0,5],[0,6]]".to_string()));
+        assert_eq!(run("setpath([-3]; 1)", "[0]"), error("Out of bounds negative array index"));
+        assert_eq!(run(r#"setpath(["a"]; 1)"#, "[]"), error(r#"Cannot index array with "a""#));
+        assert_eq!(run("setpath([0]; 1)", "{}"), error("Cannot index object with number"));
+    }
+
+    #[test]
+    fn test_delpaths() {
+        let json = r#"{"a": [1, {"b": 2}, 3], "c": null}"#;
+        assert_eq!(run(r#"delpaths([["a", 0], ["a", 2], ["c"], ["x", "y"]])"#, json), Ok(r#"{"a":[{"b":2}]}"#.to_string()));
+        assert_eq!(run(r#"delpaths([["a", 1, "b"], ["a", -1]]), delpaths([[]])"#, json), Ok(r#"{"a":[1,{}],"c":null} null"#.to_string()));
+        assert_eq!(run("del(.a[0], .a[2], .c)", json), Ok(r#"{"a":[{"b":2}]}"#.to_string()));
+        assert_eq!(run("del(.a[1:])", json), Ok(r#"{"a":[1],"c":null}"#.to_string()));
+        assert_eq!(run("del(.[] | select(. > 1))", "[1, 3, 2, 0]"), Ok("[1,0]".to_string()));
+        assert_eq!(run("del(.a[5], .c.d)", json), Ok(r#"{"a":[1,{"b":2},3],"c":null}"#.to_string()));
+        assert_eq!(run("del(.a.b)", json), error(r#"Cannot index array with "b""#));
+        assert_eq!(run("delpaths(1)", json), error("Paths must be specified as an array"));
+    }
+
+    #[test]
+    fn test_stream() {
+        let json = r#"{"a": [1, {"b": 2}], "c": [], "d": {}}"#;
+        assert_eq!(run("[tostream]", json), Ok(r#"[[["a",0],1],[["a",1,"b"],2],[["a",1,"b"]],[["a",1]],[["c"],[]],[["d"],{}],[["d"]]]"#.to_string()));
+        assert_eq!(run("[1, [], {}] | [tostream]", "null"), Ok("[[[0],1],[[1],[]],[[2],{}],[[2]]]".to_string()));
+        assert_eq!(run("tostream", "1"), Ok("[[],1]".to_string()));
+        assert_eq!(run("fromstream(tostream)", json), Ok(r#"{"a":[1,{"b":2}],"c":[],"d":{}}"#.to_string()));
+        assert_eq!(run("fromstream(.[] | tostream)", r#"[1, [2, 3], {"a": []}]"#), Ok(r#"1 [2,3] {"a":[]}"#.to_string()));
+        assert_eq!(run("[1 | truncate_stream([[0], 1], [[1, 0], 2], [[1, 0]], [[1]])]", "null"), Ok("[[[0],2],[[0]]]".to_string()));
+        assert_eq!(run("[fromstream(1 | truncate_stream([[0], 1], [[1, 0], 2], [[1, 0]], [[1]]))]", "null"), Ok("[[2]]".to_string()));
+        assert_eq!(run("fromstream(1)", "null"), error("Invalid stream event"));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let json = r#"[1, [2, [3, [4]]], {"a": [5]}, []]"#;
+        assert_eq!(run("flatten", json), Ok(r#"[1,2,3,4,{"a":[5]}]"#.to_string()));
+        assert_eq!(run("flatten(0), flatten(1)", json), Ok(r#"[1,[2,[3,[4]]],{"a":[5]},[]] [1,2,[3,[4]],{"a":[5]}]"#.to_string()));
+        assert_eq!(run("[flatten(2, 3)]", json), Ok(r#"[[1,2,3,[4],{"a":[5]}],[1,2,3,4,{"a":[5]}]]"#.to_string()));
+        assert_eq!(run("flatten", r#"{"a": [1, [2]], "b": 3}"#), Ok("[1,2,3]".to_string()));
+        assert_eq!(run("flatten(-1)", json), error("flatten depth must not be negative"));
+        assert_eq!(run("flatten", "1"), error("Cannot iterate over number"));
+    }
+
+    #[test]
+    fn test_has_in() {
+        let obj = r#"{"a": null, "b": 1}"#;
+        assert_eq!(run(r#"has("a"), has("b"), has("c")"#, obj), Ok("true true false".to_string()));
+        assert_eq!(run(r#"[has("a", "c")]"#, obj), Ok("[true,false]".to_string()));
+        assert_eq!(run("[has(0, 1, 2, -1, 1.5)]", "[0, 1]"), Ok("[true,true,false,false,true]".to_string()));
+        assert_eq!(run(r#"[.[] | in({"a": 1})]"#, r#"["a", "b"]"#), Ok("[true,false]".to_string()));
+        assert_eq!(run("[.[] | in([5, 6])]", "[0, 2]"), Ok("[true,false]".to_string()));
+        assert_eq!(run("has(0)", obj), error("Cannot check whether object has a number key"));
+        assert_eq!(run(r#"has("a")"#, "[]"), error("Cannot check whether array has a string key"));
+        assert_eq!(run(r#"has("a")"#, "1"), error("Cannot check whether number has a string key"));
+    }
+
+    #[test]
+    fn test_contains_inside() {
+        assert_eq!(run(r#"contains("bar"), contains("baz")"#, r#""foobar""#), Ok("true false".to_string()));
+        assert_eq!(run(r#"contains(["baz", "bar"]), contains(["bazzzz", "bar"])"#, r#"["foobar", "foobaz", "blarp"]"#), Ok("true false".to_string()));
+        let obj = r#"{"foo": 12, "bar": [1, 2, {"barp": 12, "blip": 13}]}"#;
+        assert_eq!(run(r#"contains({foo: 12, bar: [{barp: 12}]}), contains({foo: 12, bar: [{barp: 15}]})"#, obj), Ok("true false".to_string()));
+        assert_eq!(run("contains(1), contains(2), ([] | contains([])), ({} | contains({})), (null | contains(null))", "1"), Ok("true false true true true".to_string()));
+        assert_eq!(run(r#"inside("foobar"), ([.] | inside(["foobar", "baz"])), ([.] | inside(["baz"]))"#, r#""bar""#), Ok("true true false".to_string()));
+        assert_eq!(run(r#"contains("a")"#, "1"), error(r#"number (1) and string ("a") cannot have their containment checked"#));
+        assert_eq!(run("contains(false)", "true"), error("boolean (true) and boolean (false) cannot have their containment checked"));
+    }
+
+    #[test]
+    fn test_tostring_tonumber() {
+        assert_eq!(run("[.[] | tostring]", r#"["a", 1, null, {"b": [true]}]"#), Ok(r#"["a","1","null","{\"b\":[true]}"]"#.to_string()));
+        assert_eq!(run("[.[] | tonumber]", r#"["1", "-2.5e1", 3]"#), Ok("[1,-25,3]".to_string()));
+        assert_eq!(run("tonumber", r#""1x""#), error("Cannot parse '1x' as a number"));
+        assert_eq!(run("tonumber", r#"" 1""#), error("Cannot parse ' 1' as a number"));
+        assert_eq!(run("tonumber", r#""[1]""#), error("Cannot parse '[1]' as a number"));
+        assert_eq!(run("tonumber", "null"), error("null (null) cannot be parsed as a number"));
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(run("ascii_downcase, ascii_upcase", r#""aBc-É1""#), Ok(r#""abc-É1" "ABC-É1""#.to_string()));
+        assert_eq!(run("ascii_downcase", "1"), error("ascii_downcase input must be a string, not number (1)"));
+        assert_eq!(run(r#"[.[] | ltrimstr("ab")]"#, r#"["abc", "cab", "ab", 1]"#), Ok(r#"["c","cab","",1]"#.to_string()));
+        assert_eq!(run(r#"[.[] | rtrimstr("ab")]"#, r#"["abc", "cab", "ab", 1]"#), Ok(r#"["abc","c","",1]"#.to_string()));
+        assert_eq!(run(r#"ltrimstr(1, "a")"#, r#""ab""#), Ok(r#""ab" "b""#.to_string()));
+        assert_eq!(run(r#"[.[] | startswith("ab"), endswith("ab")]"#, r#"["abc", "cab", "ab", ""]"#),
+            Ok("[true,false,false,true,true,true,false,false]".to_string()));
+        assert_eq!(run(r#"startswith("a", "b")"#, r#""ab""#), Ok("true false".to_string()));
+        assert_eq!(run(r#"startswith(1)"#, r#""ab""#), error("startswith() requires string inputs"));
+        assert_eq!(run(r#"endswith("a")"#, "1"), error("endswith() requires string inputs"));
+        assert_eq!(run("[trim, ltrim, rtrim]", r#"" \t a b\n""#), Ok(r#"["a b","a b\n"," \t a b"]"#.to_string()));
+        assert_eq!(run("trim", "null"), error("trim input must be a string, not null (null)"));
+    }
+
+    #[test]
+    fn test_regex() {
+        assert_eq!(run(r#"test("b+"), test("B"), test("B"; "i"), test(["B", "ix"])"#, r#""abbc""#), Ok("true false true true".to_string()));
+        assert_eq!(run(r#"[match("b+"; "g") | [.offset, .length, .string]]"#, r#""abbcb""#), Ok(r#"[[1,2,"bb"],[4,1,"b"]]"#.to_string()));
+        assert_eq!(run(r#"match("(a)(x)?(?<n>é)")"#, r#""caé""#), Ok(concat!(
+            r#"{"offset":1,"length":2,"string":"aé","captures":["#,
+            r#"{"offset":1,"length":1,"string":"a","name":null},"#,
+            r#"{"offset":-1,"length":0,"string":null,"name":null},"#,
+            r#"{"offset":2,"length":1,"string":"é","name":"n"}]}"#).to_string()));
+        assert_eq!(run(r#"[match("x")]"#, r#""abc""#), Ok("[]".to_string()));
+        assert_eq!(run(r#"capture("(?<y>\\d+)-(?<m>\\d+)")"#, r#""on 2024-05""#), Ok(r#"{"y":"2024","m":"05"}"#.to_string()));
+        assert_eq!(run(r#"[capture("(?<d>\\d)"; "g")]"#, r#""a1b2""#), Ok(r#"[{"d":"1"},{"d":"2"}]"#.to_string()));
+        assert_eq!(run(r#"sub("b+"; "X"), gsub("b"; "X"), gsub("B"; "X"; "i")"#, r#""abbcb""#), Ok(r#""aXcb" "aXXcX" "aXXcX""#.to_string()));
+        assert_eq!(run(r#"gsub("(?<x>[a-z])"; .x + .x)"#, r#""a1b""#), Ok(r#""aa1bb""#.to_string()));
+        assert_eq!(run(r#"[sub("b"; "1", "2")]"#, r#""abc""#), Ok(r#"["a1c","a2c"]"#.to_string()));
+        assert_eq!(run(r#"gsub(""; "-")"#, r#""ab""#), Ok(r#""-a-b-""#.to_string()));
+        assert_eq!(run(r#"test("a")"#, "1"), error("number (1) cannot be matched, as it is not a string"));
+        assert_eq!(run(r#"test("(")"#, r#""a""#), error("( (at offset 1) is not a valid regex: end pattern with unmatched parenthesis"));
+        assert_eq!(run(r#"test("a"; "q")"#, r#""a""#), error("q is not a valid modifier string"));
+        assert_eq!(run(r#"test(1)"#, r#""a""#), error("number (1) cannot be matched, as it is not a string"));
+        assert_eq!(run(r#"test("a"; 1)"#, r#""a""#), error("number (1) is not a string"));
+        assert_eq!(run(r#"try test("^(a+)+$") catch ."#, &format!(r#""{}b""#, "a".repeat(30))), Ok(r#""Regex failure: retry-limit-in-match over""#.to_string()));
+        assert_eq!(run(r#"sub("a"; 1)"#, r#""a""#), error(r#"string ("") and number (1) cannot be added"#));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(run("@text, @json", r#"[1, "a"]"#), Ok(r#""[1,\"a\"]" "[1,\"a\"]""#.to_string()));
+        assert_eq!(run("@text, @json", r#""a""#), Ok(r#""a" "\"a\"""#.to_string()));
+        assert_eq!(run(r#"[1, "a,\"b", null, true] | @csv"#, "null"), Ok(r#""1,\"a,\"\"b\",,true""#.to_string()));
+        assert_eq!(run(r#"[1, "a\tb\\c", null] | @tsv"#, "null"), Ok(r#""1\ta\\tb\\\\c\t""#.to_string()));
+        assert_eq!(run("@csv", "[[1]]"), error("array ([1]) is not valid in a csv row"));
+        assert_eq!(run("@tsv", "1"), error("number (1) cannot be tsv-formatted, only an array can be"));
+        assert_eq!(run(r#""<a href='x'>&\"</a>" | @html"#, "null"), Ok(r#""&lt;a href=&#39;x&#39;&gt;&amp;&quot;&lt;/a&gt;""#.to_string()));
+        assert_eq!(run("@uri", r#""a b/é~""#), Ok(r#""a%20b%2F%C3%A9~""#.to_string()));
+        assert_eq!(run("[.[] | @base64]", r#"["", "f", "fo", "foo", "foob", "é"]"#), Ok(r#"["","Zg==","Zm8=","Zm9v","Zm9vYg==","w6k="]"#.to_string()));
+        assert_eq!(run("[.[] | @base64d]", r#"["", "Zg==", "Zm8", "Zm9v", "Zm9vYg", "w6k="]"#), Ok(r#"["","f","fo","foo","foob","é"]"#.to_string()));
+        assert_eq!(run("@base64d", r#""Z""#), error(r#"string ("Z") is not valid base64 data"#));
+        assert_eq!(run(r#"@base64 "token=\(.t)", @json "v: \(.t)", @csv "\(.a)""#, r#"{"t": "ab", "a": [1, "x"]}"#),
+            Ok(r#""token=YWI=" "v: \"ab\"" "1,\"x\"""#.to_string()));
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(run("[limit(5; repeat(. * 2))]", "1"), Ok("[1,2,4,8,16]".to_string()));
+        assert_eq!(run("[limit(2; .[])], [limit(0; .[])], [limit(5; .[])]", "[1, 2, 3]"), Ok("[1,2] [] [1,2,3]".to_string()));
+        assert_eq!(run("[limit(1, 2; .[])]", "[1, 2, 3]"), Ok("[1,1,2]".to_string()));
+        assert_eq!(run(r#"first(.[]), first(1, error("x")), [first(empty)], first, last"#, "[1, 2, 3]"), Ok("1 1 [] 1 3".to_string()));
+        assert_eq!(run("last(.[]), [last(empty)]", "[1, 2, 3]"), Ok("3 [null]".to_string()));
+        assert_eq!(run(r#"nth(1; .[]), nth(0; 5, error("x")), [nth(5; .[])], nth(1), nth(-1)"#, "[1, 2, 3]"), Ok("2 5 [3] 2 3".to_string()));
+        assert_eq!(run("nth(-1; .[])", "[1]"), error("Out of bounds negative array index"));
+        assert_eq!(run("until(. > 100; . * 2), [while(. < 20; . * 3)]", "1"), Ok("128 [1,3,9]".to_string()));
+        assert_eq!(run("[limit(3; repeat(1))], first(repeat(.))", "0"), Ok("[0,1,1] 0".to_string()));
+        assert_eq!(run(r#"limit("a"; 1)"#, "null"), error(r#"string ("a") cannot be a limit"#));
+    }
+
+    #[test]
+    fn test_math() {
+        assert_eq!(run("[.[] | floor], [.[] | ceil], [.[] | round], [.[] | fabs]", "[1.5, -1.5, 2]"), Ok("[1,-2,2] [2,-1,2] [2,-2,2] [1.5,1.5,2]".to_string()));
+        assert_eq!(run("sqrt, log, exp", "1"), Ok("1 0 2.718281828459045".to_string()));
+        assert_eq!(run("pow(2; 10), [pow(2, 3; 2, 3)]", "null"), Ok("1024 [4,9,8,27]".to_string()));
+        assert_eq!(run("floor", r#""a""#), error(r#"string ("a") number required"#));
+        assert_eq!(run(r#"pow(2; "a")"#, "null"), error(r#"string ("a") number required"#));
+    }
+
+    #[test]
+    fn test_nan_infinite() {
+        assert_eq!(run("nan, infinite, -infinite, [nan] | tostring", "null"), Ok(r#""null" "1.7976931348623157e308" "-1.7976931348623157e308" "[null]""#.to_string()));
+        assert_eq!(run("[nan, infinite, 1, 0] | map(isnan), map(isinfinite), map(isnormal)", "null"),
+            Ok("[true,false,false,false] [false,true,false,false] [false,false,true,false]".to_string()));
+        assert_eq!(run("nan == nan, nan < nan, nan > nan, nan < -infinite, 1 > nan, [nan] == [nan]", "null"), Ok("false true false true true true".to_string()));
+        assert_eq!(run("[1, nan, -1, nan] | sort | map(isnan)", "null"), Ok("[true,true,false,false]".to_string()));
+        assert_eq!(run("isnan", r#""a""#), error(r#"string ("a") number required"#));
+    }
+
+    #[test]
+    fn test_any_all() {
+        assert_eq!(run("any, all", "[true, false]"), Ok("true false".to_string()));
+        assert_eq!(run("any, all", "[]"), Ok("false true".to_string()));
+        assert_eq!(run("any(. > 2), all(. > 0)", "[1, 2, 3]"), Ok("true true".to_string()));
+        assert_eq!(run("any(.[]; . == null), all(.[]; .)", "[1, null]"), Ok("true false".to_string()));
+        assert_eq!(run(r#"any(1, error("x"); . == 1), all(1, error("x"); . == 2)"#, "null"), Ok("true false".to_string()));
+        assert_eq!(run(r#"any(2, error("x"); . == 1)"#, "null"), error("x"));
+        assert_eq!(run("[.[] | any(.[]; all(.[]; . > 0))]", "[[[1], [0]], [[0]]]"), Ok("[true,false]".to_string()));
+        assert_eq!(run("any(1, 2; try (. == 1) catch false)", "null"), Ok("true".to_string()));
+        assert_eq!(run("any(1; true, error)", "null"), Ok("true".to_string()));
+    }
+
+    #[test]
+    fn test_input_line_number() {
+        let json = Json::from_str("[1,\n2]\n").unwrap();
+        let filter = Filter::parse("input_line_number").unwrap();
+        assert_eq!(filter.apply_at(&json, 2), Ok(vec![Json::JNumber(2f64.into())]));
+        assert_eq!(filter.apply(&json), Ok(vec![Json::JNumber(0f64.into())]));
+        assert_eq!(run("input_line_number as $n | [$n]", "null"), Ok("[0]".to_string()));
+    }
+
+    #[test]
+    fn test_halt() {
+        assert_eq!(run("1, halt, 2", "null"), Err(FilterError::Halt(0, None)));
+        assert_eq!(run("try halt catch 1", "null"), Err(FilterError::Halt(0, None)));
+        assert_eq!(run("[.[] | halt_error(0)]", "[1, 2]"), Err(FilterError::Halt(0, Some(Json::JNumber(1f64.into())))));
+        assert_eq!(run("halt_error", "{}"), Err(FilterError::Halt(5, Some(Json::JObject(vec![])))));
+        assert_eq!(run(r#"(.a | halt_error(1)) // 2"#, r#"{"a": "x"}"#), Err(FilterError::Halt(1, Some(Json::JString(Cow::Borrowed("x"))))));
+        assert_eq!(run(r#"halt_error("1")"#, "null"), error("halt_error/1: number required"));
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(run("todate, gmtime", "1425599621.25"), Ok(r#""2015-03-05T23:53:41Z" [2015,2,5,23,53,41.25,4,63]"#.to_string()));
+        assert_eq!(run("fromdate, (fromdate | gmtime | mktime)", r#""2015-03-05T23:51:47Z""#), Ok("1425599507 1425599507".to_string()));
+        assert_eq!(run(r#"strptime("%d %b %Y") | ., strftime("%A, %B %d, %Y")"#, r#""5 Mar 2015""#),
+            Ok(r#"[2015,2,5,0,0,0,4,63] "Thursday, March 05, 2015""#.to_string()));
+        assert_eq!(run(r#"[2015, 13, 32, 0, 0, 0.5] | mktime, todate"#, "null"), Ok(r#"1456963200 "2016-03-03T00:00:00Z""#.to_string()));
+        assert_eq!(run("mktime", "[2015]"), error("mktime requires array of 6 numbers"));
+        assert_eq!(run(r#"strftime("%Y")"#, r#""a""#), error("strftime/1 requires parsed datetime inputs"));
+        assert_eq!(run(r#"strptime(1)"#, r#""a""#), error("strptime/1 requires string inputs and arguments"));
+        assert_eq!(run(r#"strptime("%Y")"#, r#""a""#), error(r#"date "a" does not match format "%Y""#));
+        assert_eq!(run("now | type", "null"), Ok(r#""number""#.to_string()));
+    }
+}
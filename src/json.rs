@@ -1,137 +1,984 @@
+use std::prelude::v1::*;
 use super::parsercombinator::*;
 use super::prettyprinter::*;
+use super::color::*;
+use super::arena::*;
+use std::borrow::Cow;
+use std::iter;
+use std::str;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Json<'a> {
-    JNumber(f64),
-    JString(&'a str),
+    JNumber(Number<'a>),
+    JString(Cow<'a, str>),
     JBool(bool),
     JNull,
     JArray(Vec<Json<'a>>),
-    JObject(Vec<(&'a str, Json<'a>)>) // To preserve input order, use Vec instead of HashMap
+    JObject(Vec<(Cow<'a, str>, Json<'a>)>) // To preserve input order, use Vec instead of HashMap
 }
 
 impl <'a> Json<'a> {
-    pub fn from_str(s: &str) -> Result<Json, ParseError> {
-        parse_json().parse(s)
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Result<Json<'_>, ParseError> {
+        Json::from_str_with(s, &ParserOptions::default())
+    }
+
+    /// Like `from_str`, but accepts what `opts` allows besides JSON.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// let opts = ParserOptions::new().comments(true);
+    /// let json = Json::from_str_with("{\n  // the answer\n  \"a\": /* not 43 */ 42\n}", &opts).unwrap();
+    /// assert_eq!(json, Json::JObject(vec![("a".into(), Json::JNumber(42.0.into()))]));
+    /// assert!(Json::from_str("[1 /* two */]").is_err());
+    /// let opts = ParserOptions::new().trailing_commas(true);
+    /// assert_eq!(Json::from_str_with("[1, 2,]", &opts).unwrap(), Json::from_str("[1, 2]").unwrap());
+    /// ```
+    pub fn from_str_with<'s>(s: &'s str, opts: &ParserOptions) -> Result<Json<'s>, ParseError> {
+        leading(*opts).then(parse_json(*opts)).parse(s).map_err(consumed)
+    }
+
+    /// Like `from_str`, but parses the UTF-8 text in `bytes`, failing at the first byte which
+    /// is not UTF-8. The text is borrowed rather than copied.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// assert_eq!(Json::from_slice(b"[\"a\"]").unwrap(), Json::JArray(vec![Json::JString("a".into())]));
+    /// let e = Json::from_slice(b"[1,\n \"\xff\"]").unwrap_err();
+    /// assert_eq!((e.message.as_str(), e.line, e.column), ("Invalid UTF-8", 2, 3));
+    /// ```
+    pub fn from_slice(bytes: &[u8]) -> Result<Json<'_>, ParseError> {
+        Json::from_slice_with(bytes, &ParserOptions::default())
+    }
+
+    /// Like `from_slice`, but accepts what `opts` allows besides JSON.
+    pub fn from_slice_with<'s>(bytes: &'s [u8], opts: &ParserOptions) -> Result<Json<'s>, ParseError> {
+        Json::from_str_with(utf8(bytes)?, opts)
+    }
+
+    /// Parses the values in `s` one after another, separated by whitespace if any, as jq reads
+    /// its input. An empty or blank `s` has no values.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// let jsons = Json::from_str_many("1 [2]\n\"a\"").unwrap();
+    /// assert_eq!(jsons, vec![Json::JNumber(1.0.into()), Json::JArray(vec![Json::JNumber(2.0.into())]), Json::JString("a".into())]);
+    /// assert_eq!(Json::from_str_many(" \n").unwrap(), vec![]);
+    /// assert!(Json::from_str_many("1 [").is_err());
+    /// ```
+    pub fn from_str_many(s: &str) -> Result<Vec<Json<'_>>, ParseError> {
+        Json::from_str_many_with(s, &ParserOptions::default())
+    }
+
+    /// Like `from_str_many`, but accepts what `opts` allows besides JSON.
+    pub fn from_str_many_with<'s>(s: &'s str, opts: &ParserOptions) -> Result<Vec<Json<'s>>, ParseError> {
+        leading(*opts).then(parse_json(*opts).many()).skip(eof()).parse(s).map_err(consumed)
+    }
+
+    /// Like `from_str_many`, but gives each value with the part of `s` where it is, as for
+    /// newline-delimited JSON such as logs.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// let docs = Json::parse_many("{\"a\": 1}\n[2]\n").unwrap();
+    /// assert_eq!(docs.len(), 2);
+    /// assert_eq!(docs[1].0, Json::JArray(vec![Json::JNumber(2.0.into())]));
+    /// assert_eq!((docs[1].1.start.line, docs[1].1.fragment("{\"a\": 1}\n[2]\n")), (2, "[2]"));
+    /// ```
+    pub fn parse_many(s: &'a str) -> Result<Vec<(Json<'a>, Span)>, ParseError> {
+        Json::parse_many_with(s, &ParserOptions::default())
+    }
+
+    /// Like `parse_many`, but accepts what `opts` allows besides JSON.
+    pub fn parse_many_with(s: &'a str, opts: &ParserOptions) -> Result<Vec<(Json<'a>, Span)>, ParseError> {
+        Json::documents_with(s, opts).collect()
+    }
+
+    /// Parses the values in `s` one at a time, which gives the first error and then ends.
+    pub fn documents(s: &'a str) -> Documents<'a> {
+        Json::documents_with(s, &ParserOptions::default())
+    }
+
+    /// Like `documents`, but accepts what `opts` allows besides JSON.
+    pub fn documents_with(s: &'a str, opts: &ParserOptions) -> Documents<'a> {
+        Documents {input: s, opts: *opts, next: Position::of(s, 0), done: false}
+    }
+
+    /// Parses `s` storing arrays and objects in `arena`. Numbers are kept as `f64` without their
+    /// text, so integers which it cannot hold exactly, such as `9007199254740993`, are rounded.
+    pub fn parse_in(arena: &'a Arena<'a>, s: &'a str) -> Result<ArenaJson<'a>, ParseError> {
+        Json::parse_in_with(arena, s, &ParserOptions::default())
+    }
+
+    /// Like `parse_in`, but accepts what `opts` allows besides JSON. `opts.number_text` has no
+    /// effect, as arena values keep no text of numbers.
+    pub fn parse_in_with(arena: &'a Arena<'a>, s: &'a str, opts: &ParserOptions) -> Result<ArenaJson<'a>, ParseError> {
+        leading(*opts).then(parse_json_in(arena, *opts)).parse(s).map_err(consumed)
+    }
+
+    /// Copies every borrowed string so that the value outlives the input.
+    pub fn into_owned(self) -> Json<'static> {
+        match self {
+            Json::JNumber(n) => Json::JNumber(n.into_owned()),
+            Json::JString(s) => Json::JString(Cow::Owned(s.into_owned())),
+            Json::JBool(b) => Json::JBool(b),
+            Json::JNull => Json::JNull,
+            Json::JArray(jsons) => Json::JArray(jsons.into_iter().map(Json::into_owned).collect()),
+            Json::JObject(obj) => Json::JObject(obj.into_iter().map(|(k, v)| (Cow::Owned(k.into_owned()), v.into_owned())).collect())
+        }
     }
 
     pub fn pretty_print(&self, width: i32) -> String {
-        Doc::new(vec![json_to_doc_elem(&self)]).pretty(width)
+        self.pretty_print_with(&PrinterOptions::new().width(width))
+    }
+
+    pub fn pretty_print_with(&self, opts: &PrinterOptions) -> String {
+        let ctx = DocContext {style: &opts.style, compact: opts.compact};
+        let doc = Doc::new(vec![json_to_doc_elem(self, &ctx, 0)]);
+        let width = if opts.compact { UNLIMITED_WIDTH } else { opts.width };
+        match opts.theme {
+            Some(ref theme) => doc.render_ansi(width, &opts.style, theme),
+            None => doc.pretty_with_style(width, &opts.style)
+        }
+    }
+}
+
+/// A JSON number. Besides its value, it keeps the text it is parsed from when the value
+/// cannot tell it, as for an integer beyond 2^53, or when `ParserOptions::number_text` asks
+/// for it, so that it is written back as it is.
+/// Numbers are equal if their values are.
+///
+/// ```
+/// # use toyjq::*;
+/// let json = Json::from_str("[9007199254740993, 1.5]").unwrap();
+/// assert_eq!(print_json_compact(&json), "[9007199254740993,1.5]");
+/// assert_eq!(json, Json::JArray(vec![Json::JNumber(9007199254740992.0.into()), Json::JNumber(1.5.into())]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Number<'a> {
+    value: f64,
+    text: Option<Cow<'a, str>>
+}
+
+impl <'a> Number<'a> {
+    /// A number which is written as `text` rather than as its value.
+    pub fn with_text(value: f64, text: Cow<'a, str>) -> Number<'a> {
+        Number {value, text: Some(text)}
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// The text which the number is written as, if it is not its value.
+    pub fn text(&self) -> Option<&str> {
+        self.text.as_ref().map(|t| t.as_ref())
+    }
+
+    pub fn into_owned(self) -> Number<'static> {
+        Number {value: self.value, text: self.text.map(|t| Cow::Owned(t.into_owned()))}
+    }
+
+    /// Writes the number in `format` unless it has its own text.
+    pub fn format(&self, format: &NumberFormat) -> String {
+        match self.text {
+            Some(ref t) => t.to_string(),
+            None => format.format(self.value)
+        }
     }
 }
 
-fn parse_json<'a>() -> Parser<'a, Json<'a>> {
-    parse_jarray()
-        .or_lazy(||parse_jobject())
-        .or_lazy(||parse_jstring())
-        .or_lazy(||parse_jnull())
-        .or_lazy(||parse_jbool())
-        .or_lazy(||parse_jnumber())
+impl <'a> From<f64> for Number<'a> {
+    fn from(value: f64) -> Number<'a> {
+        Number {value, text: None}
+    }
 }
 
-fn parse_jbool<'a>() -> Parser<'a, Json<'a>> {
+impl <'a> PartialEq for Number<'a> {
+    fn eq(&self, other: &Number) -> bool {
+        self.value == other.value
+    }
+}
+
+/// Every knob of the JSON parser: what it accepts besides JSON, which is nothing but a byte
+/// order mark by default, how it takes what it parses, and how deep values may be.
+///
+/// ```
+/// # use toyjq::*;
+/// let opts = ParserOptions::lenient().max_depth(8);
+/// assert!(Json::from_str_with("[1, NaN, /* three */ 3,]", &opts).is_ok());
+/// assert!(Json::from_str_with("{\"a\": 1, \"a\": 2}", &ParserOptions::strict()).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserOptions {
+    /// `// line` and `/* block */` comments wherever whitespace may be, as in JSONC.
+    pub comments: bool,
+    /// A comma after the last element of an array or the last member of an object.
+    pub trailing_commas: bool,
+    /// What to do with a key which appears twice in an object.
+    pub duplicate_keys: DuplicateKeys,
+    /// How many arrays and objects a value may be in, counting itself. Parsing takes no stack
+    /// for them, but printing and filtering do, so deep inputs fail early instead. 256 by
+    /// default as in jq.
+    pub max_depth: usize,
+    /// The text of every number as it is written, such as `1.10`, `1e3` or `-0`, so that it is
+    /// printed back the same. Otherwise only integers which `f64` cannot hold keep theirs.
+    pub number_text: bool,
+    /// A byte order mark (U+FEFF) at the start of the input, which is skipped. Allowed by
+    /// default, since Windows tools often write one.
+    pub bom: bool,
+    /// `NaN`, `Infinity` and `-Infinity` as numbers, as Python and JavaScript write them.
+    pub non_finite: bool
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {comments: false, trailing_commas: false, duplicate_keys: DuplicateKeys::Keep, max_depth: 256, number_text: false, bom: true, non_finite: false}
+    }
+}
+
+/// The ways to take an object with a key which appears more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Keeps every member as it is.
+    #[default]
+    Keep,
+    /// Fails at the second one.
+    Error,
+    /// Keeps the first value of the key.
+    FirstWins,
+    /// Keeps the last value of the key at the place of the first one, as jq does.
+    LastWins
+}
+
+
+impl ParserOptions {
+    pub fn new() -> ParserOptions {ParserOptions::default()}
+
+    /// Only JSON itself: no byte order mark and no key twice in an object.
+    pub fn strict() -> ParserOptions {
+        ParserOptions::new().bom(false).duplicate_keys(DuplicateKeys::Error)
+    }
+
+    /// Whatever the parser can take besides JSON: comments, trailing commas, NaN and the
+    /// infinities besides a byte order mark.
+    pub fn lenient() -> ParserOptions {
+        ParserOptions::new().comments(true).trailing_commas(true).non_finite(true)
+    }
+
+    pub fn comments(mut self, comments: bool) -> ParserOptions {
+        self.comments = comments;
+        self
+    }
+
+    pub fn trailing_commas(mut self, trailing_commas: bool) -> ParserOptions {
+        self.trailing_commas = trailing_commas;
+        self
+    }
+
+    pub fn duplicate_keys(mut self, duplicate_keys: DuplicateKeys) -> ParserOptions {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> ParserOptions {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn number_text(mut self, number_text: bool) -> ParserOptions {
+        self.number_text = number_text;
+        self
+    }
+
+    pub fn bom(mut self, bom: bool) -> ParserOptions {
+        self.bom = bom;
+        self
+    }
+
+    pub fn non_finite(mut self, non_finite: bool) -> ParserOptions {
+        self.non_finite = non_finite;
+        self
+    }
+}
+
+/// Every knob of the JSON printer.
+///
+/// ```
+/// # use toyjq::*;
+/// let json = Json::from_str(r#"{"b": [1, 2], "a": null}"#).unwrap();
+/// let opts = PrinterOptions::new().sort_keys(true).compact(true);
+/// assert_eq!(json.pretty_print_with(&opts), r#"{"a":null,"b":[1,2]}"#);
+/// let opts = PrinterOptions::new().width(20).indent(4);
+/// assert_eq!(json.pretty_print_with(&opts), "{\n    \"b\": [ 1, 2 ],\n    \"a\": null\n}");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrinterOptions {
+    pub width: i32,
+    pub style: PrintStyle,
+    /// Colors tokens with ANSI escape sequences when present.
+    pub theme: Option<Theme>,
+    /// Prints without any whitespace, ignoring `width` and the layout options of `style`.
+    pub compact: bool
+}
+
+impl Default for PrinterOptions {
+    fn default() -> PrinterOptions {
+        PrinterOptions {width: 80, style: PrintStyle::default(), theme: None, compact: false}
+    }
+}
+
+impl PrinterOptions {
+    pub fn new() -> PrinterOptions {PrinterOptions::default()}
+
+    pub fn width(mut self, width: i32) -> PrinterOptions {
+        self.width = width;
+        self
+    }
+
+    pub fn style(mut self, style: PrintStyle) -> PrinterOptions {
+        self.style = style;
+        self
+    }
+
+    pub fn indent(mut self, indent_width: i32) -> PrinterOptions {
+        self.style.indent_width = indent_width;
+        self
+    }
+
+    pub fn tabs(mut self, use_tabs: bool) -> PrinterOptions {
+        self.style.use_tabs = use_tabs;
+        self
+    }
+
+    pub fn color(mut self, theme: Option<Theme>) -> PrinterOptions {
+        self.theme = theme;
+        self
+    }
+
+    pub fn sort_keys(mut self, sort_keys: bool) -> PrinterOptions {
+        self.style.sort_keys = sort_keys;
+        self
+    }
+
+    pub fn ascii(mut self, ascii: bool) -> PrinterOptions {
+        self.style.escape.ascii = ascii;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> PrinterOptions {
+        self.style.max_depth = max_depth;
+        self
+    }
+
+    pub fn compact(mut self, compact: bool) -> PrinterOptions {
+        self.compact = compact;
+        self
+    }
+
+    pub fn number_format(mut self, number_format: NumberFormat) -> PrinterOptions {
+        self.style.number_format = number_format;
+        self
+    }
+
+    pub fn non_finite(mut self, non_finite: NonFinite) -> PrinterOptions {
+        self.style.number_format.non_finite = non_finite;
+        self
+    }
+
+    pub fn trailing_newline(mut self, trailing_newline: bool) -> PrinterOptions {
+        self.style.trailing_newline = trailing_newline;
+        self
+    }
+}
+
+/// Pretty prints `json` within `width` columns, indenting as described by `style`.
+pub fn print_json(json: &Json, width: i32, style: &PrintStyle) -> String {
+    json.pretty_print_with(&PrinterOptions::new().width(width).style(style.clone()))
+}
+
+/// Prints `json` on a single line without any whitespace, e.g. `{"a":[1,2]}`.
+pub fn print_json_compact(json: &Json) -> String {
+    json.pretty_print_with(&PrinterOptions::new().compact(true))
+}
+
+/// Like `print_json` but colors each token with ANSI escape sequences.
+/// The escape sequences do not count against `width`.
+pub fn print_json_colored(json: &Json, width: i32, style: &PrintStyle, theme: &Theme) -> String {
+    json.pretty_print_with(&PrinterOptions::new().width(width).style(style.clone()).color(Some(theme.clone())))
+}
+
+/// Pretty prints `json` as a `<pre class="toyjq">` element.
+/// Each token is wrapped by a `<span>` whose class tells its kind:
+/// `toyjq-null`, `toyjq-false`, `toyjq-true`, `toyjq-number`, `toyjq-string`, `toyjq-key`,
+/// `toyjq-array` (brackets and commas of arrays) or `toyjq-object` (braces, colons and commas of objects).
+pub fn json_to_html(json: &Json, width: i32) -> String {
+    let style = PrintStyle::default();
+    let ctx = DocContext {style: &style, compact: false};
+    let body = Doc::new(vec![json_to_doc_elem(json, &ctx, 0)]).render_html(width, &style);
+    format!("<pre class=\"toyjq\">{}</pre>", body)
+}
+
+/// The iterator of `Json::documents`.
+pub struct Documents<'a> {
+    input: &'a str,
+    opts: ParserOptions,
+    next: Position,
+    done: bool
+}
+
+impl <'a> Iterator for Documents<'a> {
+    type Item = Result<(Json<'a>, Span), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let rest = &self.input[self.next.offset..];
+        let opts = self.opts;
+        // Only the first value may follow a byte order mark.
+        let before = |at_start| if at_start { leading(opts) } else { spaces(opts) };
+        if before(self.next.offset == 0).skip(eof()).parse(rest).is_ok() {
+            self.done = true;
+            return None
+        }
+        let parser = before(self.next.offset == 0).then(offset()).and(parse_value(opts)).and(offset()).skip(spaces(opts)).and(offset());
+        match parser.parse(rest) {
+            Ok((((start, json), end), next)) => {
+                let start = self.next.advance(&rest[..start]);
+                let end = start.advance(&rest[start.offset - self.next.offset..end]);
+                self.next = end.advance(&rest[end.offset - self.next.offset..next]);
+                Some(Ok((json, Span {start, end})))
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(ParseError {pos: self.next.offset + e.pos, ..e}.locate(self.input)))
+            }
+        }
+    }
+}
+
+/// `bytes` as a string, or an error at the first byte which is not UTF-8.
+fn utf8(bytes: &[u8]) -> Result<&str, ParseError> {
+    str::from_utf8(bytes).map_err(|e| {
+        let valid = str::from_utf8(&bytes[..e.valid_up_to()]).unwrap();
+        ParseError {retry: false, message: "Invalid UTF-8".to_string(), pos: valid.len(), line: 0, column: 0}.locate(valid)
+    })
+}
+
+/// The length of the byte order mark which `s` starts with, if any.
+pub(crate) fn byte_order_mark_len(s: &str) -> usize {
+    if s.starts_with('\u{FEFF}') { '\u{FEFF}'.len_utf8() } else { 0 }
+}
+
+/// What may come before the first value: a byte order mark if `opts` allows it, and whitespace.
+fn leading<'a>(opts: ParserOptions) -> Parser<'a, ()> {
+    let mark = chr('\u{FEFF}');
+    let mark = if opts.bom {
+        mark.map(|_| ())
+    } else {
+        mark.try_map(|_| Err((0, "Byte order mark is not allowed".to_string())))
+    };
+    mark.or(unit(())).then(spaces(opts))
+}
+
+fn is_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+/// The whitespace of RFC 8259, which may be around any value and punctuation, and the
+/// comments if `opts` allows them.
+pub(crate) fn spaces<'a>(opts: ParserOptions) -> Parser<'a, ()> {
+    if !opts.comments {
+        return take_while(is_space).map_(())
+    }
+    let line = string("//").then(take_while(|c| c != '\n'));
+    let block = string("/*")
+        .then(until("*/").or(failure("Unterminated comment".to_string()).map(|_| "")))
+        .skip(string("*/"));
+    take_while(is_space).then(line.or(block).skip(take_while(is_space)).many()).map_(())
+}
+
+fn token<'a>(c: char, opts: ParserOptions) -> Parser<'a, char> {
+    chr(c).skip(spaces(opts))
+}
+
+/// A value and the whitespace after it.
+/// Marks an error as one after some input was taken, so that nothing else is tried instead.
+/// Only a value which fails at its very start may be tried for something else.
+fn consumed(e: ParseError) -> ParseError {
+    ParseError {retry: false, ..e}
+}
+
+fn parse_json<'a>(opts: ParserOptions) -> Parser<'a, Json<'a>> {
+    parse_value(opts).skip(spaces(opts))
+}
+
+fn parse_value<'a>(opts: ParserOptions) -> Parser<'a, Json<'a>> {
+    from_fn(move |input, pos| parse_nested(input, pos, opts, &BuildJson(opts.duplicate_keys)))
+}
+
+/// A member of an object with the offset of its key.
+type KeyedMember<'a, V> = ((usize, Cow<'a, str>), V);
+
+/// How `parse_nested` makes values of what it parses. The elements of a container are drained
+/// from a buffer which all of them share.
+trait Build<'a> {
+    type Value;
+    /// A string, a number, a boolean or null
+    fn scalar(&self, json: Json<'a>) -> Self::Value;
+    fn array<I: ExactSizeIterator<Item = Self::Value>>(&self, values: I) -> Self::Value;
+    /// Fails at an offset of the input as `Parser::try_map` does.
+    fn object<I: ExactSizeIterator<Item = KeyedMember<'a, Self::Value>>>(&self, members: I) -> Result<Self::Value, (usize, String)>;
+}
+
+struct BuildJson(DuplicateKeys);
+
+impl <'a> Build<'a> for BuildJson {
+    type Value = Json<'a>;
+
+    fn scalar(&self, json: Json<'a>) -> Json<'a> {
+        json
+    }
+
+    fn array<I: ExactSizeIterator<Item = Json<'a>>>(&self, values: I) -> Json<'a> {
+        Json::JArray(values.collect())
+    }
+
+    fn object<I: ExactSizeIterator<Item = KeyedMember<'a, Json<'a>>>>(&self, members: I) -> Result<Json<'a>, (usize, String)> {
+        dedup_keys(members.collect(), self.0).map(Json::JObject)
+    }
+}
+
+struct BuildInArena<'a>(&'a Arena<'a>);
+
+impl <'a> Build<'a> for BuildInArena<'a> {
+    type Value = ArenaJson<'a>;
+
+    fn scalar(&self, json: Json<'a>) -> ArenaJson<'a> {
+        match json {
+            Json::JString(s) => ArenaJson::JString(self.0.alloc_str(s)),
+            json => scalar_in_arena(json)
+        }
+    }
+
+    fn array<I: ExactSizeIterator<Item = ArenaJson<'a>>>(&self, values: I) -> ArenaJson<'a> {
+        ArenaJson::JArray(self.0.alloc_values(values))
+    }
+
+    fn object<I: ExactSizeIterator<Item = KeyedMember<'a, ArenaJson<'a>>>>(&self, members: I) -> Result<ArenaJson<'a>, (usize, String)> {
+        let members = members.map(|((_, k), v)| (self.0.alloc_str(k), v));
+        Ok(ArenaJson::JObject(self.0.alloc_members(members)))
+    }
+}
+
+/// An array or an object which `parse_nested` is in, with where its elements start in the
+/// buffer of values or members.
+enum Frame<'a> {
+    Array(usize),
+    /// With the key of the value being parsed
+    Object(usize, Option<(usize, Cow<'a, str>)>)
+}
+
+/// Parses the value at `start` of `input` as `Parser::parse_at` does, keeping the arrays and
+/// objects it is in on a stack of its own rather than recursing, so that only
+/// `opts.max_depth` limits how deep it may be.
+fn parse_nested<'a, B: Build<'a>>(input: &'a str, start: usize, opts: ParserOptions, build: &B) -> Result<(usize, B::Value), ParseError> {
+    let ws = spaces(opts);
+    let number = if opts.non_finite { parse_non_finite().or(parse_jnumber(opts.number_text)) } else { parse_jnumber(opts.number_text) };
+    let scalar = parse_jstring().or(parse_jnull()).or(parse_jbool()).or(number);
+    let key = offset().and(parse_string()).skip(spaces(opts)).skip(token(':', opts));
+    let comma = token(',', opts);
+    let unclosed = |pos: usize, close: char| match chr(close).parse_at(input, pos) {
+        Err(e) => consumed(e),
+        Ok(_) => unreachable!()
+    };
+    let mut stack: Vec<Frame<'a>> = vec![];
+    // The elements so far of every array and object in `stack`, those of the innermost last.
+    let mut values: Vec<B::Value> = vec![];
+    let mut members: Vec<KeyedMember<'a, B::Value>> = vec![];
+    let mut pos = start;
+    loop {
+        // Parses the value at `pos`, or opens an array or an object and goes on to what is in it.
+        let mut value = match input[pos..].chars().next() {
+            Some(open) if open == '[' || open == '{' => {
+                pos = ws.parse_at(input, pos + 1).map_err(consumed)?.0;
+                if stack.len() >= opts.max_depth {
+                    return Err(ParseError {retry: false, message: "Exceeds depth limit for parsing".to_string(), pos, line: 0, column: 0})
+                }
+                let close = if open == '[' { ']' } else { '}' };
+                if input[pos..].starts_with(close) {
+                    pos += 1;
+                    if open == '[' {
+                        build.array(iter::empty())
+                    } else {
+                        build.object(iter::empty()).map_err(|(pos, message)| ParseError {retry: false, message, pos, line: 0, column: 0})?
+                    }
+                } else if open == '[' {
+                    stack.push(Frame::Array(values.len()));
+                    continue
+                } else {
+                    let (p, k) = key.parse_at(input, pos).map_err(|e| if e.retry { unclosed(pos, '}') } else { e })?;
+                    pos = p;
+                    stack.push(Frame::Object(members.len(), Some(k)));
+                    continue
+                }
+            },
+            _ => match scalar.parse_at(input, pos) {
+                Ok((p, json)) => {
+                    pos = p;
+                    build.scalar(json)
+                },
+                Err(e) => return Err(match stack.last() {
+                    None => e,
+                    // An array just opened ends where nothing of an element is.
+                    Some(&Frame::Array(start)) if start == values.len() && e.retry => unclosed(pos, ']'),
+                    _ => consumed(e)
+                })
+            }
+        };
+        // Closes the arrays and objects which end after `value`, till another element is to be parsed.
+        loop {
+            let close = match stack.last_mut() {
+                None => return Ok((pos, value)),
+                Some(&mut Frame::Array(_)) => {
+                    values.push(value);
+                    ']'
+                },
+                Some(&mut Frame::Object(_, ref mut k)) => {
+                    members.push((k.take().unwrap(), value));
+                    '}'
+                }
+            };
+            pos = ws.parse_at(input, pos).map_err(consumed)?.0;
+            if let Ok((p, _)) = comma.parse_at(input, pos) {
+                pos = p;
+                if !(opts.trailing_commas && input[pos..].starts_with(close)) {
+                    if let Some(&mut Frame::Object(_, ref mut k)) = stack.last_mut() {
+                        let (p, next) = key.parse_at(input, pos).map_err(consumed)?;
+                        pos = p;
+                        *k = Some(next);
+                    }
+                    break
+                }
+            }
+            pos = chr(close).parse_at(input, pos).map_err(consumed)?.0;
+            value = match stack.pop().unwrap() {
+                Frame::Array(start) => build.array(values.drain(start..)),
+                Frame::Object(start, _) => build.object(members.drain(start..)).map_err(|(pos, message)| ParseError {retry: false, message, pos, line: 0, column: 0})?
+            };
+        }
+    }
+}
+
+pub(crate) fn parse_jbool<'a>() -> Parser<'a, Json<'a>> {
     string("true").map(|_|Json::JBool(true)).try()
         .or(string("false").map(|_|Json::JBool(false))).try()
 }
 
-fn parse_jnull<'a>() -> Parser<'a, Json<'a>> {
+pub(crate) fn parse_jnull<'a>() -> Parser<'a, Json<'a>> {
     string("null").map(|_|Json::JNull).try()
 }
 
-fn parse_jnumber<'a>() -> Parser<'a, Json<'a>> {
-    or_from("-0123456789.Ee+".chars().map(chr))
-        .many().try().flat_map(|v| {
-            let s: String = v.iter().collect();
-            if let Ok(d) = s.as_str().parse::<f64>() {
-                unit(d).map(Json::JNumber)
-            } else {
-                failure(format!("Unable to parse a number: {}", s)).map(|_| Json::JNull)
+/// A number in the grammar of RFC 8259: `-? (0 | [1-9][0-9]*) (\.[0-9]+)? ([eE][+-]?[0-9]+)?`,
+/// which keeps its text if `keep_text`, or if it is an integer which the value rounds.
+pub(crate) fn parse_jnumber<'a>(keep_text: bool) -> Parser<'a, Json<'a>> {
+    fn digits<'a>() -> Parser<'a, String> {
+        satisfy(|c| c.is_ascii_digit()).or(failure("Expected a digit".to_string()).map_('0'))
+            .and(take_while(|c| c.is_ascii_digit()))
+            .map(|(head, rest)| format!("{}{}", head, rest))
+    }
+    fn optional<'a>(p: Parser<'a, String>) -> Parser<'a, String> {
+        p.or(unit(String::new()))
+    }
+    let zero = chr('0').then(satisfy(|c| c.is_ascii_digit()).map(Some).or(unit(None)))
+        .flat_map(|digit| match digit {
+            Some(_) => failure("Leading zeros are not allowed".to_string()).map(|_| String::new()),
+            None => unit("0".to_string())
+        });
+    let frac = chr('.').then(digits()).map(|d| format!(".{}", d));
+    let exp = or_from("eE".chars().map(chr))
+        .and(optional(or_from("+-".chars().map(chr)).map(|c| c.to_string())))
+        .and(digits())
+        .map(|((e, sign), d)| format!("{}{}{}", e, sign, d));
+    optional(chr('-').map(|c| c.to_string()))
+        .and(zero.or(digits()))
+        .and(optional(frac))
+        .and(optional(exp))
+        .map(move |(((minus, int), frac), exp)| {
+            let text = format!("{}{}{}{}", minus, int, frac, exp);
+            Json::JNumber(number_of(Cow::Owned(text), frac.is_empty() && exp.is_empty(), keep_text))
+        })
+}
+
+/// The number written as `text`, which keeps the text as `parse_jnumber` does.
+pub(crate) fn number_of(text: Cow<str>, integer: bool, keep_text: bool) -> Number {
+    let value: f64 = text.parse().unwrap();
+    if keep_text || integer && value.to_string() != *text {
+        Number::with_text(value, text)
+    } else {
+        value.into()
+    }
+}
+
+/// The length of the number at the start of `s` in the grammar of `parse_jnumber`, and
+/// whether it is an integer, or `None` if there is none, which `parse_jnumber` tells why.
+pub(crate) fn number_len(s: &[u8]) -> Option<(usize, bool)> {
+    let digits = |from: usize| s[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let mut len = (s.first() == Some(&b'-')) as usize;
+    match s.get(len) {
+        Some(b'0') if !s.get(len + 1).is_some_and(u8::is_ascii_digit) => len += 1,
+        Some(b'1'..=b'9') => len += digits(len),
+        _ => return None
+    }
+    let integer = !matches!(s.get(len), Some(b'.') | Some(b'e') | Some(b'E'));
+    if s.get(len) == Some(&b'.') {
+        match digits(len + 1) {
+            0 => return None,
+            n => len += 1 + n
+        }
+    }
+    if let Some(b'e') | Some(b'E') = s.get(len) {
+        len += 1;
+        if let Some(b'+') | Some(b'-') = s.get(len) {
+            len += 1;
+        }
+        match digits(len) {
+            0 => return None,
+            n => len += n
+        }
+    }
+    Some((len, integer))
+}
+
+/// `NaN`, `Infinity` or `-Infinity`, which are not JSON.
+pub(crate) fn parse_non_finite<'a>() -> Parser<'a, Json<'a>> {
+    string("NaN").map(|_| f64::NAN)
+        .or(string("Infinity").map(|_| f64::INFINITY))
+        .or(string("-Infinity").map(|_| f64::NEG_INFINITY))
+        .map(|v| Json::JNumber(v.into()))
+}
+
+/// A string with its escape sequences decoded, which borrows the input unless it has any.
+/// Control characters below U+0020 must be escaped as RFC 8259 requires.
+pub(crate) fn parse_string<'a>() -> Parser<'a, Cow<'a, str>> {
+    let plain = || from_fn(|input: &'a str, pos| {
+        let rest = &input[pos..];
+        let len = find_string_end(rest.as_bytes()).unwrap_or(rest.len());
+        match rest.as_bytes().get(len) {
+            Some(&b) if b < 0x20 => Err(ParseError {retry: false, message: format!("Control character U+{:04X} must be escaped", b), pos: pos + len, line: 0, column: 0}),
+            _ => Ok((pos + len, &rest[..len]))
+        }
+    });
+    let simple = or_from("\"\\/bfnrt".chars().map(chr)).map(|c| match c {
+        'b' => '\u{8}',
+        'f' => '\u{c}',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        c => c
+    });
+    let escape = chr('\\').then(
+        simple.try()
+            .or(chr('u').then(parse_unicode_escape()))
+            .or(failure("Invalid escape sequence".to_string()).map(|_| ' '))
+    );
+    chr('"').then(plain().and(escape.and(plain()).many())).skip(chr('"'))
+        .map(|(head, rest)| {
+            if rest.is_empty() {
+                return Cow::Borrowed(head)
+            }
+            let mut s = head.to_string();
+            for (c, text) in rest {
+                s.push(c);
+                s.push_str(text);
             }
+            Cow::Owned(s)
         })
 }
 
-fn parse_string<'a>() -> Parser<'a, &'a str> {
-    chr('"').then_lazy(||until("\"")).skip(chr('"'))
+/// The rest of `\uXXXX` after `\u`, which is followed by another for the low half of a surrogate pair.
+fn parse_unicode_escape<'a>() -> Parser<'a, char> {
+    fn hex<'a>() -> Parser<'a, u32> {
+        let digit = || satisfy(|c| c.is_ascii_hexdigit()).map(|c| c.to_digit(16).unwrap());
+        digit().and(digit()).and(digit()).and(digit()).map(|(((a, b), c), d)| a << 12 | b << 8 | c << 4 | d).try()
+            .or(failure("Expected 4 hexadecimal digits after \\u".to_string()).map(|_| 0))
+    }
+    let lone = |u: u32| failure(format!("Lone surrogate \\u{:04X}", u)).map(|_| ' ');
+    hex().flat_map(move |high| match high {
+        0xD800..=0xDBFF => string("\\u").then(hex())
+            .flat_map(move |low| match low {
+                0xDC00..=0xDFFF => unit(char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).unwrap()),
+                _ => lone(high)
+            })
+            .or(lone(high)),
+        0xDC00..=0xDFFF => lone(high),
+        _ => unit(char::from_u32(high).unwrap())
+    })
 }
 
 fn parse_jstring<'a>() -> Parser<'a, Json<'a>> {
     parse_string().map(Json::JString)
 }
 
-fn parse_keyvalue<'a>() -> Parser<'a, (&'a str, Json<'a>)> {
-    parse_string().skip(chr(':').with_spaces()).and_lazy(||parse_json())
+type Member<'a> = (Cow<'a, str>, Json<'a>);
+
+/// Takes the members of an object as `policy` tells, failing at the offset of the second
+/// occurrence of a key for `DuplicateKeys::Error`.
+fn dedup_keys<'a>(members: Vec<KeyedMember<'a, Json<'a>>>, policy: DuplicateKeys) -> Result<Vec<Member<'a>>, (usize, String)> {
+    use std::collections::BTreeMap;
+    if policy == DuplicateKeys::Keep {
+        return Ok(members.into_iter().map(|((_, k), v)| (k, v)).collect())
+    }
+    let mut ret: Vec<Member> = vec![];
+    // The index in `ret` of every key so far
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+    for ((offset, k), v) in members {
+        match seen.get(k.as_ref()) {
+            None => {
+                seen.insert(k.to_string(), ret.len());
+                ret.push((k, v));
+            },
+            Some(&i) => match policy {
+                DuplicateKeys::Error => return Err((offset, format!("Duplicate key \"{}\"", k))),
+                DuplicateKeys::LastWins => ret[i].1 = v,
+                _ => ()
+            }
+        }
+    }
+    Ok(ret)
 }
 
-fn parse_jobject<'a>() -> Parser<'a, Json<'a>> {
-    chr('{').with_spaces().then_lazy(||
-        parse_keyvalue().sep_by(chr(',').with_spaces())
-    ).skip(chr('}').with_spaces()).map(|v|Json::JObject(v.into_iter().collect()))
+/// Like `parse_json`, a value and the whitespace after it.
+fn parse_json_in<'a>(arena: &'a Arena<'a>, opts: ParserOptions) -> Parser<'a, ArenaJson<'a>> {
+    from_fn(move |input, pos| parse_nested(input, pos, opts, &BuildInArena(arena))).skip(spaces(opts))
 }
 
-fn parse_jarray<'a>() -> Parser<'a, Json<'a>> {
-    chr('[').with_spaces().then_lazy(||
-        parse_json().sep_by(chr(',').with_spaces())
-    ).skip(chr(']').with_spaces()).map(Json::JArray)
+fn scalar_in_arena(json: Json) -> ArenaJson {
+    match json {
+        Json::JNumber(n) => ArenaJson::JNumber(n.value()),
+        Json::JString(Cow::Borrowed(s)) => ArenaJson::JString(s),
+        Json::JBool(b) => ArenaJson::JBool(b),
+        _ => ArenaJson::JNull
+    }
+}
+
+struct DocContext<'o> {
+    style: &'o PrintStyle,
+    compact: bool
 }
 
-const INDENT_DEPTH: i32 = 2;
+impl <'o> DocContext<'o> {
+    /// Pushes a line break, which never appears in compact output.
+    fn push_newline(&self, indent: i32, ret: &mut Vec<DocElem>) {
+        if !self.compact {
+            ret.push(newline(indent));
+        }
+    }
+
+    /// Pushes the line break just inside a bracket.
+    fn push_bracket_newline(&self, indent: i32, ret: &mut Vec<DocElem>) {
+        if !self.compact {
+            ret.push(if self.style.bracket_spacing { newline(indent) } else { softline(indent) });
+        }
+    }
+
+    fn is_too_deep(&self, depth: usize) -> bool {
+        self.style.max_depth.is_some_and(|max| depth >= max)
+    }
 
-fn json_to_doc_elem(json: &Json) -> DocElem {
+    fn colon(&self) -> &'static str {
+        if self.compact { ":" } else { ": " }
+    }
+}
+
+fn json_to_doc_elem(json: &Json, ctx: &DocContext, depth: usize) -> DocElem {
     match *json {
-        Json::JNumber(v) => text(format!("{}", v)),
-        Json::JString(s) => text(format!("\"{}\"", s)),
-        Json::JBool(true) => literal("true"),
-        Json::JBool(false) => literal("false"),
-        Json::JNull => literal("null"),
-        Json::JArray(ref jsons) => json_vec_to_flatable_doc_elem(jsons),
-        Json::JObject(ref obj) => json_object_to_flatable_doc_elem(obj)
+        Json::JNumber(ref n) => annotated(Tag::NumberValue, text(n.format(&ctx.style.number_format))),
+        Json::JString(ref s) => annotated(Tag::StringValue, text(ctx.style.escape.quote(s))),
+        Json::JBool(true) => annotated(Tag::TrueValue, literal("true")),
+        Json::JBool(false) => annotated(Tag::FalseValue, literal("false")),
+        Json::JNull => annotated(Tag::NullValue, literal("null")),
+        Json::JArray(ref jsons) => json_vec_to_flatable_doc_elem(jsons, ctx, depth),
+        Json::JObject(ref obj) => json_object_to_flatable_doc_elem(obj, ctx, depth)
     }
 }
 
-fn json_vec_to_flatable_doc_elem(jsons: &Vec<Json>) -> DocElem {
+fn json_vec_to_flatable_doc_elem(jsons: &[Json], ctx: &DocContext, depth: usize) -> DocElem {
+    let punct = |s| annotated(Tag::ArrayPunctuation, literal(s));
     if jsons.is_empty() {
-        literal("[]")
+        punct("[]")
+    } else if ctx.is_too_deep(depth) {
+        let items = if jsons.len() == 1 { "item" } else { "items" };
+        flatable(vec![punct("["), text(format!("... {} {}", jsons.len(), items)), punct("]")])
     } else {
         let mut it = jsons.iter();
-        let mut ret = vec![literal("["), newline(INDENT_DEPTH)];
-        ret.push(json_to_doc_elem(it.next().unwrap()));
-        while let Some(j) = it.next() {
-            ret.push(literal(","));
-            ret.push(newline(0));
-            ret.push(json_to_doc_elem(j));
-        }
-        ret.push(newline(-2));
-        ret.push(literal("]"));
+        let mut items = vec![json_to_doc_elem(it.next().unwrap(), ctx, depth + 1)];
+        for j in it {
+            items.push(punct(","));
+            ctx.push_newline(0, &mut items);
+            items.push(json_to_doc_elem(j, ctx, depth + 1));
+        }
+        let mut ret = vec![punct("[")];
+        ctx.push_bracket_newline(ctx.style.indent_width, &mut ret);
+        if ctx.style.fill_arrays && jsons.iter().all(is_scalar) {
+            ret.push(fill(items));
+        } else {
+            ret.append(&mut items);
+        }
+        ctx.push_bracket_newline(-ctx.style.indent_width, &mut ret);
+        ret.push(punct("]"));
         flatable(ret)
     }
 }
 
-fn json_object_to_flatable_doc_elem(obj: &Vec<(&str, Json)>) -> DocElem {
+fn is_scalar(json: &Json) -> bool {
+    !matches!(*json, Json::JArray(_) | Json::JObject(_))
+}
+
+fn json_object_to_flatable_doc_elem(obj: &[(Cow<str>, Json)], ctx: &DocContext, depth: usize) -> DocElem {
+    let punct = |s| annotated(Tag::ObjectPunctuation, literal(s));
     if obj.is_empty() {
-        literal("{}")
+        punct("{}")
+    } else if ctx.is_too_deep(depth) {
+        flatable(vec![punct("{"), literal("..."), punct("}")])
     } else {
-        let mut it = obj.iter();
-        let mut ret = vec![literal("{"), newline(INDENT_DEPTH)];
+        let mut kvs: Vec<&(Cow<str>, Json)> = obj.iter().collect();
+        if ctx.style.sort_keys {
+            kvs.sort_by_key(|kv| &kv.0);
+        }
+        let key_width = if ctx.style.align_values && !ctx.compact {
+            kvs.iter().map(|kv| ctx.style.escape.quote(&kv.0).chars().count()).max().unwrap_or(0)
+        } else {
+            0
+        };
+        let mut it = kvs.into_iter();
+        let mut ret = vec![punct("{")];
+        ctx.push_bracket_newline(ctx.style.indent_width, &mut ret);
         let kv0 = it.next().unwrap();
-        ret.append(&mut json_keyvalue_to_doc_elems(kv0));
-        while let Some(kv) = it.next() {
-            ret.push(literal(","));
-            ret.push(newline(0));
-            ret.append(&mut json_keyvalue_to_doc_elems(kv));
-        }
-        ret.push(newline(-2));
-        ret.push(literal("}"));
+        ret.append(&mut json_keyvalue_to_doc_elems(kv0, ctx, depth, key_width));
+        for kv in it {
+            ret.push(punct(","));
+            ctx.push_newline(0, &mut ret);
+            ret.append(&mut json_keyvalue_to_doc_elems(kv, ctx, depth, key_width));
+        }
+        ctx.push_bracket_newline(-ctx.style.indent_width, &mut ret);
+        ret.push(punct("}"));
         flatable(ret)
     }
 }
 
-fn json_keyvalue_to_doc_elems(keyvalue: &(&str, Json)) -> Vec<DocElem> {
+/// When the object is broken into lines, the value is padded so that it starts
+/// `key_width` columns after the beginning of the key.
+fn json_keyvalue_to_doc_elems(keyvalue: &(Cow<str>, Json), ctx: &DocContext, depth: usize, key_width: usize) -> Vec<DocElem> {
     let (ref k, ref v) = *keyvalue;
+    let key = ctx.style.escape.quote(k);
+    let pad = key_width.saturating_sub(key.chars().count());
     vec![
-        text(format!("\"{}\"", k)),
-        literal(": "),
-        json_to_doc_elem(v)
+        annotated(Tag::ObjectKey, text(key)),
+        annotated(Tag::ObjectPunctuation, literal(ctx.colon())),
+        pad_when_broken(pad as i32),
+        json_to_doc_elem(v, ctx, depth + 1)
     ]
 }
 
@@ -143,18 +990,18 @@ mod tests {
     fn test_json_pretty_print() {
         use self::Json::*;
         let json = JArray(vec![
-            JNumber(42f64),
-            JString("foo"),
+            JNumber(42f64.into()),
+            JString("foo".into()),
             JBool(true),
             JBool(false),
             JArray(vec![]),
             JArray(vec![JNull]),
             JObject(vec![]),
-            JObject(vec![("poem", JString("Lorem ipsum"))]),
+            JObject(vec![("poem".into(), JString("Lorem ipsum".into()))]),
             JObject(vec![
-                ("a", JNumber(1f64)),
-                ("foo-bar-baz", JString("1 2 Fizz 4 Buzz 6 7 8 Fizz Buzz")),
-                ("Numbers", JArray((1..20).map(|i: i32| JNumber(i as f64)).collect()))
+                ("a".into(), JNumber(1f64.into())),
+                ("foo-bar-baz".into(), JString("1 2 Fizz 4 Buzz 6 7 8 Fizz Buzz".into())),
+                ("Numbers".into(), JArray((1..20).map(|i: i32| JNumber((i as f64).into())).collect()))
             ])
         ]);
         assert_eq! {
@@ -223,22 +1070,222 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_print_json_style() {
+        use self::Json::*;
+        let json = JObject(vec![("a".into(), JArray(vec![JNumber(1f64.into()), JNull]))]);
+        assert_eq! {
+            print_json(&json, 0, &PrintStyle {indent_width: 4, use_tabs: false, ..PrintStyle::default()}),
+            "{\n    \"a\": [\n        1,\n        null\n    ]\n}"
+        }
+        assert_eq! {
+            print_json(&json, 0, &PrintStyle {indent_width: 4, use_tabs: true, ..PrintStyle::default()}),
+            "{\n\t\"a\": [\n\t\t1,\n\t\tnull\n\t]\n}"
+        }
+    }
+
+    #[test]
+    fn test_print_json_colored() {
+        use self::Json::*;
+        let json = JObject(vec![("a".into(), JArray(vec![JNumber(1f64.into()), JNull]))]);
+        let theme = Theme {key: Color::new("34"), null: Color::new("90"), ..Theme::default()};
+        assert_eq! {
+            print_json_colored(&json, 80, &PrintStyle::default(), &theme),
+            "\x1b[1;39m{\x1b[0m \x1b[34m\"a\"\x1b[0m\x1b[1;39m: \x1b[0m\x1b[1;39m[\x1b[0m \x1b[0;39m1\x1b[0m\x1b[1;39m,\x1b[0m \x1b[90mnull\x1b[0m \x1b[1;39m]\x1b[0m \x1b[1;39m}\x1b[0m"
+        }
+        assert_eq! {
+            print_json_colored(&json, 20, &PrintStyle::default(), &theme),
+            print_json_colored(&json, 80, &PrintStyle::default(), &theme)
+        }
+    }
+
+    #[test]
+    fn test_print_json_fill_arrays() {
+        use self::Json::*;
+        let json = JObject(vec![
+            ("n".into(), JArray((1..13).map(|i: i32| JNumber((i as f64).into())).collect())),
+            ("m".into(), JArray(vec![JArray(vec![JNull]), JBool(true)]))
+        ]);
+        let style = PrintStyle {fill_arrays: true, ..PrintStyle::default()};
+        assert_eq! {
+            print_json(&json, 20, &style),
+            r#"{
+  "n": [
+    1, 2, 3, 4, 5,
+    6, 7, 8, 9, 10,
+    11, 12
+  ],
+  "m": [
+    [ null ],
+    true
+  ]
+}"#
+        }
+        assert_eq! {
+            print_json(&json, 10, &style),
+            r#"{
+  "n": [
+    1, 2,
+    3, 4,
+    5, 6,
+    7, 8,
+    9, 10,
+    11, 12
+  ],
+  "m": [
+    [
+      null
+    ],
+    true
+  ]
+}"#
+        }
+    }
+
+    #[test]
+    fn test_print_json_max_depth() {
+        use self::Json::*;
+        let json = JObject(vec![
+            ("a".into(), JArray(vec![JNumber(1f64.into()), JArray(vec![JNull, JNull]), JObject(vec![("b".into(), JNull)])])),
+            ("c".into(), JArray(vec![JArray(vec![JNull])])),
+            ("d".into(), JArray(vec![]))
+        ]);
+        let style = |max_depth| PrintStyle {max_depth, ..PrintStyle::default()};
+        assert_eq! {
+            print_json(&json, 80, &style(Some(2))),
+            r#"{ "a": [ 1, [... 2 items], {...} ], "c": [ [... 1 item] ], "d": [] }"#
+        }
+        assert_eq!(print_json(&json, 80, &style(Some(1))), r#"{ "a": [... 3 items], "c": [... 1 item], "d": [] }"#);
+        assert_eq!(print_json(&json, 80, &style(Some(0))), "{...}");
+        assert_eq!(print_json(&json, 80, &style(None)), json.pretty_print(80));
+    }
+
+    #[test]
+    fn test_print_json_sort_keys() {
+        use self::Json::*;
+        let json = JObject(vec![
+            ("b".into(), JObject(vec![("y".into(), JNull), ("x".into(), JNull)])),
+            ("a".into(), JNumber(1f64.into())),
+            ("B".into(), JArray(vec![JObject(vec![("d".into(), JNull), ("c".into(), JNull)])]))
+        ]);
+        let style = PrintStyle {sort_keys: true, ..PrintStyle::default()};
+        assert_eq! {
+            print_json(&json, 80, &style),
+            r#"{ "B": [ { "c": null, "d": null } ], "a": 1, "b": { "x": null, "y": null } }"#
+        }
+        assert_eq! {
+            json.pretty_print(80),
+            r#"{ "b": { "y": null, "x": null }, "a": 1, "B": [ { "d": null, "c": null } ] }"#
+        }
+    }
+
+    #[test]
+    fn test_print_json_ascii_output() {
+        use self::Json::*;
+        let json = JObject(vec![("caf\u{e9}".into(), JString("\u{3042}\u{1f600}!".into()))]);
+        let style = PrintStyle {escape: EscapeOptions {ascii: true, ..EscapeOptions::default()}, ..PrintStyle::default()};
+        assert_eq!(print_json(&json, 80, &style), r#"{ "caf\u00e9": "\u3042\ud83d\ude00!" }"#);
+        assert_eq!(json.pretty_print(80), "{ \"caf\u{e9}\": \"\u{3042}\u{1f600}!\" }");
+    }
+
+    #[test]
+    fn test_print_json_number_format() {
+        use self::Json::*;
+        let json = JArray(vec![JNumber(30000f64.into()), JNumber(1.5f64.into()), JNumber(2e-7f64.into()), JNumber(1e20f64.into())]);
+        let style = |precision, notation| PrintStyle {
+            number_format: NumberFormat {precision, notation, ..NumberFormat::default()},
+            ..PrintStyle::default()
+        };
+        assert_eq!(json.pretty_print(80), "[ 30000, 1.5, 0.0000002, 100000000000000000000 ]");
+        assert_eq!(print_json(&json, 80, &style(None, NumberNotation::Exponent)), "[ 3e4, 1.5e0, 2e-7, 1e20 ]");
+        assert_eq!(print_json(&json, 80, &style(None, NumberNotation::Auto)), "[ 30000, 1.5, 2e-7, 1e20 ]");
+        assert_eq!(print_json(&json, 80, &style(Some(0), NumberNotation::Plain)), "[ 30000, 2, 0, 100000000000000000000 ]");
+    }
+
+    #[test]
+    fn test_print_json_escape() {
+        use self::Json::*;
+        let json = JObject(vec![("a\"b".into(), JString("back\\slash\nnew\tline\u{1}\u{7f}</>".into()))]);
+        assert_eq!(json.pretty_print(80), r#"{ "a\"b": "back\\slash\nnew\tline\u0001\u007f</>" }"#);
+        assert_eq!(print_json_compact(&json), r#"{"a\"b":"back\\slash\nnew\tline\u0001\u007f</>"}"#);
+        let style = PrintStyle {escape: EscapeOptions {slash: true, ..EscapeOptions::default()}, ..PrintStyle::default()};
+        assert_eq!(print_json(&json, 80, &style), r#"{ "a\"b": "back\\slash\nnew\tline\u0001\u007f<\/>" }"#);
+    }
+
+    #[test]
+    fn test_print_json_bracket_spacing() {
+        use self::Json::*;
+        let json = JObject(vec![("a".into(), JArray(vec![JNumber(1f64.into()), JNumber(2f64.into())])), ("b".into(), JObject(vec![]))]);
+        let style = PrintStyle {bracket_spacing: false, ..PrintStyle::default()};
+        assert_eq!(print_json(&json, 80, &style), r#"{"a": [1, 2], "b": {}}"#);
+        assert_eq!(print_json(&json, 14, &style), "{\n  \"a\": [1, 2],\n  \"b\": {}\n}");
+    }
+
+    #[test]
+    fn test_print_json_align_values() {
+        use self::Json::*;
+        let json = JObject(vec![
+            ("a".into(), JNumber(1f64.into())),
+            ("long key".into(), JObject(vec![("x".into(), JNull), ("yy".into(), JNull)])),
+            ("mid".into(), JString("s".into()))
+        ]);
+        let style = PrintStyle {align_values: true, ..PrintStyle::default()};
+        assert_eq! {
+            print_json(&json, 40, &style),
+            r#"{
+  "a":        1,
+  "long key": { "x": null, "yy": null },
+  "mid":      "s"
+}"#
+        }
+        assert_eq!(print_json(&json, 80, &style), json.pretty_print(80));
+    }
+
+    #[test]
+    fn test_print_json_compact() {
+        use self::Json::*;
+        let json = JObject(vec![
+            ("a".into(), JArray(vec![JNumber(1f64.into()), JArray(vec![]), JNull])),
+            ("b".into(), JObject(vec![("c".into(), JString("d e".into()))]))
+        ]);
+        assert_eq!(print_json_compact(&json), r#"{"a":[1,[],null],"b":{"c":"d e"}}"#);
+        assert_eq! {
+            print_json(&json, UNLIMITED_WIDTH, &PrintStyle::default()),
+            r#"{ "a": [ 1, [], null ], "b": { "c": "d e" } }"#
+        }
+    }
+
+    #[test]
+    fn test_json_to_html() {
+        use self::Json::*;
+        let json = JObject(vec![("<a>".into(), JArray(vec![JString("&".into()), JBool(true)]))]);
+        assert_eq! {
+            json_to_html(&json, 80),
+            concat!(
+                r#"<pre class="toyjq"><span class="toyjq-object">{</span> "#,
+                r#"<span class="toyjq-key">&quot;&lt;a&gt;&quot;</span><span class="toyjq-object">: </span>"#,
+                r#"<span class="toyjq-array">[</span> <span class="toyjq-string">&quot;&amp;&quot;</span><span class="toyjq-array">,</span> "#,
+                r#"<span class="toyjq-true">true</span> <span class="toyjq-array">]</span> <span class="toyjq-object">}</span></pre>"#
+            )
+        }
+    }
+
     #[test]
     fn test_parse_json() {
         assert_eq! {
             Json::from_str("123").unwrap(),
-            Json::JNumber(123f64)
+            Json::JNumber(123f64.into())
         }
         assert_eq! {
             Json::from_str("\"fooo\"").unwrap(),
-            Json::JString("fooo")
+            Json::JString("fooo".into())
         }
         assert_eq! {
             Json::from_str("[1, -2, 3.0E4, true, false, null]").unwrap(),
             Json::JArray(vec! {
-                Json::JNumber(1f64),
-                Json::JNumber(-2f64),
-                Json::JNumber(30000f64),
+                Json::JNumber(1f64.into()),
+                Json::JNumber((-2f64).into()),
+                Json::JNumber(30000f64.into()),
                 Json::JBool(true),
                 Json::JBool(false),
                 Json::JNull,
@@ -247,8 +1294,8 @@ mod tests {
         assert_eq! {
             Json::from_str("{\"key1\" : 123, \"key2\" : \"foo\"}").unwrap(),
             Json::JObject(vec! {
-                ("key1", Json::JNumber(123f64)),
-                ("key2", Json::JString("foo"))
+                ("key1".into(), Json::JNumber(123f64.into())),
+                ("key2".into(), Json::JString("foo".into()))
             })
         }
         assert_eq! {
@@ -264,22 +1311,200 @@ mod tests {
 "#).unwrap(),
             Json::JArray(vec! {
                 Json::JObject(vec! {
-                    ("key1", Json::JNumber(123f64)),
-                    ("key2", Json::JString("foo"))
+                    ("key1".into(), Json::JNumber(123f64.into())),
+                    ("key2".into(), Json::JString("foo".into()))
                 }),
-                Json::JNumber(123f64),
+                Json::JNumber(123f64.into()),
                 Json::JArray(vec! {
-                    Json::JString("foo"),
+                    Json::JString("foo".into()),
                     Json::JBool(true)
                 })
             })
         }
         assert_eq! {
             {
-                let ParseError {retry, message: _, pos} = Json::from_str("[[null, null ],[null ,null      null] , [ null ] ] ").unwrap_err();
-                (retry, pos)
+                let ParseError {retry, pos, line, column, ..} = Json::from_str("[[null, null ],[null ,null      null] , [ null ] ] ").unwrap_err();
+                (retry, pos, line, column)
+            },
+            (false, 32, 1, 33)
+        }
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        assert_eq! {
+            Json::from_str(r#"{"a\"b": "\\ \/\b\f\n\r\t", "c": "d"}"#).unwrap(),
+            Json::JObject(vec![("a\"b".into(), Json::JString("\\ /\u{8}\u{c}\n\r\t".into())), ("c".into(), Json::JString("d".into()))])
+        }
+        match Json::from_str(r#"["plain", "esc\"aped"]"#).unwrap() {
+            Json::JArray(ref v) => match (&v[0], &v[1]) {
+                (Json::JString(Cow::Borrowed(_)), Json::JString(Cow::Owned(_))) => (),
+                strings => panic!("{:?}", strings)
             },
-            (false, 26)
+            json => panic!("{:?}", json)
+        }
+        assert_eq!(Json::from_str(r#""a\x""#).unwrap_err().message, "Invalid escape sequence");
+        assert_eq!(Json::from_str(r#""\u00e9\u3042 \uD83D\uDE00\u0041""#).unwrap(), Json::JString("éあ 😀A".into()));
+        assert_eq!(Json::from_str(r#""\uD83Dx""#).unwrap_err().message, "Lone surrogate \\uD83D");
+        assert_eq!(Json::from_str(r#""\uD83D\u0041""#).unwrap_err().message, "Lone surrogate \\uD83D");
+        assert_eq!(Json::from_str(r#""\uDE00""#).unwrap_err().message, "Lone surrogate \\uDE00");
+        assert_eq!(Json::from_str(r#""\u12G4""#).unwrap_err().message, "Expected 4 hexadecimal digits after \\u");
+        assert!(Json::from_str(r#""a\""#).is_err());
+        let e = Json::from_str("[\"a\tb\"]").unwrap_err();
+        assert_eq!((e.message.as_str(), e.column), ("Control character U+0009 must be escaped", 4));
+        let long = format!("\"{}\\n{}\"", "x".repeat(20), "\u{3042}".repeat(5));
+        assert_eq!(Json::from_str(&long).unwrap(), Json::JString(format!("{}\n{}", "x".repeat(20), "\u{3042}".repeat(5)).into()));
+    }
+
+    #[test]
+    fn test_parse_numbers() {
+        for &(text, n) in &[("0", 0.0), ("-0", -0.0), ("10", 10.0), ("0.5", 0.5), ("1e3", 1000.0), ("1E+2", 100.0), ("-1.5e-3", -0.0015)] {
+            assert_eq!(Json::from_str(text).unwrap(), Json::JNumber(n.into()), "{}", text);
+        }
+        let big = Json::from_str("[9007199254740993, -12345678901234567890, 9007199254740992, 1e400]").unwrap();
+        assert_eq!(print_json_compact(&big.into_owned()), "[9007199254740993,-12345678901234567890,9007199254740992,1.7976931348623157e308]");
+        let text = "[1.10, 1e3, -0, 1E+2, 0.1e-0]";
+        assert_eq!(print_json_compact(&Json::from_str(text).unwrap()), "[1.1,1000,-0,100,0.1]");
+        let opts = ParserOptions::new().number_text(true);
+        assert_eq!(print_json_compact(&Json::from_str_with(text, &opts).unwrap()), "[1.10,1e3,-0,1E+2,0.1e-0]");
+        assert_eq!(Json::from_str_with(text, &opts).unwrap(), Json::from_str(text).unwrap());
+        for text in &["1..2", "--3", "+1", "1.", ".5", "1e", "1e+", "-", "[0x10]"] {
+            assert!(Json::from_str(text).is_err(), "{}", text);
+        }
+        assert_eq!(Json::from_str("01").unwrap_err().message, "Leading zeros are not allowed");
+        assert_eq!(Json::from_str("[-a]").unwrap_err().message, "Expected a digit");
+        // `number_len` takes what `parse_jnumber` does.
+        for text in &["0", "-0", "10,", "0.5]", "1e3", "1E+2x", "-1.5e-3", "9007199254740993", "1.10", "00", "01", "1..2", "--3", "1.", ".5", "1e", "1e+", "-", "0x10"] {
+            let by_parser = parse_jnumber(true).parse_at(text, 0).ok();
+            let by_len = number_len(text.as_bytes()).map(|(len, integer)| (len, Json::JNumber(number_of(Cow::Borrowed(&text[..len]), integer, true))));
+            assert_eq!(by_len, by_parser, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_whitespace() {
+        assert_eq! {
+            Json::from_str(" \r\n{\t\"a\"\r\n:\n[\t1\r,\r\n2\n]\t,\"b\"\t:\ttrue\r\n}\r\n").unwrap(),
+            Json::JObject(vec![("a".into(), Json::JArray(vec![Json::JNumber(1.0.into()), Json::JNumber(2.0.into())])), ("b".into(), Json::JBool(true))])
+        }
+        assert_eq!(Json::from_str_many("1\r\n2\t\"x\"\r\n").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_documents() {
+        let text = "1\r\n{\"a\":\n [true]} \"x\"\n\n[1, 2 3]\n";
+        let mut docs = Json::documents(text);
+        let spans: Vec<(usize, usize, usize, usize, &str)> = docs.by_ref().take(3).map(|doc| {
+            let span = doc.unwrap().1;
+            (span.start.line, span.start.column, span.end.line, span.end.column, span.fragment(text))
+        }).collect();
+        assert_eq!(spans, vec![(1, 1, 1, 2, "1"), (2, 1, 3, 9, "{\"a\":\n [true]}"), (3, 10, 3, 13, "\"x\"")]);
+        let e = docs.next().unwrap().unwrap_err();
+        assert_eq!((e.line, e.column), (5, 7));
+        assert!(docs.next().is_none());
+        assert_eq!(Json::parse_many(" \n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        let opts = ParserOptions::new().max_depth(2);
+        assert!(Json::from_str_with("[{\"a\": 1}, [2, []]]", &opts).is_err());
+        assert!(Json::from_str_with("[{\"a\": 1}, [2, 3]]", &opts).is_ok());
+        let e = Json::from_str_with("[{\"a\": [1]}]", &opts).unwrap_err();
+        assert_eq!((e.message.as_str(), e.column), ("Exceeds depth limit for parsing", 9));
+        assert!(Json::from_str_with("{\"a\": {\"b\": {}}}", &opts).is_err());
+        let deep = |n| format!("{}{}", "[".repeat(n), "]".repeat(n));
+        assert!(Json::from_str(&deep(256)).is_ok());
+        assert_eq!(Json::from_str(&deep(100_000)).unwrap_err().message, "Exceeds depth limit for parsing");
+        assert_eq!(Json::from_str_many(&deep(257)).unwrap_err().pos, 257);
+        let unlimited = ParserOptions::new().max_depth(usize::MAX);
+        let text = format!("{}1{}", "{\"a\": [".repeat(3_000), "]}".repeat(3_000));
+        let parsed = Json::from_str_with(&text, &unlimited).unwrap();
+        let mut json = &parsed;
+        let mut depth = 0;
+        while let Json::JObject(ref members) = *json {
+            match members[0].1 {
+                Json::JArray(ref values) => json = &values[0],
+                _ => panic!("{:?}", members)
+            }
+            depth += 1;
+        }
+        assert_eq!((depth, json), (3_000, &Json::JNumber(1.0.into())));
+    }
+
+    #[test]
+    fn test_parse_duplicate_keys() {
+        let text = "{\"a\": 1, \"b\": {\"c\": 2}, \"a\": 3}";
+        let with = |policy| Json::from_str_with(text, &ParserOptions::new().duplicate_keys(policy));
+        assert_eq!(with(DuplicateKeys::Keep).unwrap(), Json::from_str(text).unwrap());
+        assert_eq!(with(DuplicateKeys::FirstWins).unwrap(), Json::from_str("{\"a\": 1, \"b\": {\"c\": 2}}").unwrap());
+        assert_eq!(with(DuplicateKeys::LastWins).unwrap(), Json::from_str("{\"a\": 3, \"b\": {\"c\": 2}}").unwrap());
+        let e = with(DuplicateKeys::Error).unwrap_err();
+        assert_eq!((e.message.as_str(), e.line, e.column), ("Duplicate key \"a\"", 1, 25));
+        let opts = ParserOptions::new().duplicate_keys(DuplicateKeys::Error);
+        assert_eq!(print_json_compact(&Json::from_str_with("[{\"a\": 1}, {\"a\": {\"a\": 2}}]", &opts).unwrap()), "[{\"a\":1},{\"a\":{\"a\":2}}]");
+        assert_eq!(Json::from_str_with("[{\"x\": {\"\\u0061\": 1, \"a\": 2}}]", &opts).unwrap_err().column, 22);
+    }
+
+    #[test]
+    fn test_parse_trailing_commas() {
+        let opts = ParserOptions::new().trailing_commas(true);
+        assert_eq! {
+            Json::from_str_with("{\"a\": [1, [], {},], \"b\": {\"c\": null,},\n}", &opts).unwrap(),
+            Json::from_str("{\"a\": [1, [], {}], \"b\": {\"c\": null}}").unwrap()
         }
+        for text in &["[,]", "[1,,]", "{,}", "[1 ,, ]"] {
+            assert!(Json::from_str_with(text, &opts).is_err(), "{}", text);
+        }
+        assert!(Json::from_str("[1,]").is_err());
+        let opts = opts.comments(true);
+        assert_eq!(Json::from_str_with("[1, // one\n]", &opts).unwrap(), Json::JArray(vec![Json::JNumber(1.0.into())]));
+    }
+
+    #[test]
+    fn test_parse_bom() {
+        assert_eq!(Json::from_str_many("\u{FEFF} [1] 2").unwrap(), Json::from_str_many("[1] 2").unwrap());
+        let e = Json::from_str_with("\u{FEFF}[1]", &ParserOptions::new().bom(false)).unwrap_err();
+        assert_eq!((e.message.as_str(), e.column), ("Byte order mark is not allowed", 1));
+        assert!(Json::from_str("[\u{FEFF}1]").is_err());
+        assert_eq!(Json::parse_many("\u{FEFF}1").unwrap()[0].1.start.column, 2);
+    }
+
+    #[test]
+    fn test_parse_non_ascii_errors() {
+        let e = Json::from_str("{\n  \"あ\": 1,\n  \"b\" 2\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (3, 7));
+    }
+
+    #[test]
+    fn test_parse_non_finite() {
+        let text = "[NaN, Infinity, -Infinity, -1, 2]";
+        assert!(Json::from_str(text).is_err());
+        let json = Json::from_str_with(text, &ParserOptions::new().non_finite(true)).unwrap();
+        assert_eq!(print_json_compact(&json), "[null,1.7976931348623157e308,-1.7976931348623157e308,-1,2]");
+        let opts = PrinterOptions::new().compact(true);
+        assert_eq!(json.pretty_print_with(&opts.clone().non_finite(NonFinite::Null)), "[null,null,null,-1,2]");
+        assert_eq!(json.pretty_print_with(&opts.non_finite(NonFinite::Literal)), "[NaN,Infinity,-Infinity,-1,2]");
+        assert_eq!(Json::from_str_with("-Inf", &ParserOptions::new().non_finite(true)).unwrap_err().message, "Expected a digit");
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let text = "// first\n[1,] /* second */ {\"a\": NaN}\n// end\n";
+        let docs = Json::parse_many_with(text, &ParserOptions::lenient()).unwrap();
+        assert_eq!(docs.iter().map(|doc| doc.1.fragment(text)).collect::<Vec<_>>(), vec!["[1,]", "{\"a\": NaN}"]);
+        assert!(Json::parse_many(text).is_err());
+        let arena = Arena::new();
+        assert_eq!(Json::parse_in_with(&arena, "[1,]", &ParserOptions::lenient()).unwrap().to_json(), Json::from_str("[1]").unwrap());
+        assert_eq!(Json::parse_many_with("\u{FEFF}1", &ParserOptions::strict()).unwrap_err().message, "Byte order mark is not allowed");
+    }
+
+    #[test]
+    fn test_from_slice() {
+        assert_eq!(Json::from_slice(b"\xEF\xBB\xBF\"\xE3\x81\x82\"").unwrap(), Json::JString("\u{3042}".into()));
+        assert_eq!(Json::from_slice_with(b"[1,]", &ParserOptions::lenient()).unwrap(), Json::from_str("[1]").unwrap());
+        let e = Json::from_slice(b"\"\xE3\x81").unwrap_err();
+        assert_eq!((e.message.as_str(), e.pos, e.column), ("Invalid UTF-8", 1, 2));
+        assert_eq!(Json::from_slice(b"[1 2]").unwrap_err().message, "Expected `]` but actual is `2`.");
     }
 }
@@ -1,6 +1,58 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+
+// Lets modules refer to `std::` paths in the `alloc`-only build as well.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::{cell, cmp, convert, fmt, iter, mem, str, sync};
+    pub use alloc::{borrow, collections, slice};
+
+    pub mod prelude {
+        pub mod v1 {
+            pub use alloc::boxed::Box;
+            pub use alloc::string::{String, ToString};
+            pub use alloc::vec::Vec;
+        }
+    }
+}
+
 pub mod parsercombinator;
 
 pub mod prettyprinter;
 
+pub mod color;
+
 pub mod json;
 pub use json::*;
+
+pub mod filter;
+
+pub mod event;
+
+#[cfg(feature = "std")]
+pub mod streamprinter;
+
+pub mod diff;
+
+pub mod regex;
+
+pub mod datetime;
+
+pub mod arena;
+
+#[cfg(feature = "std")]
+pub mod mmap;
+
+#[cfg(feature = "std")]
+pub mod terminal;
+
+#[cfg(feature = "std")]
+pub mod reader;
+
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+#[cfg(feature = "wasm")]
+pub mod wasm;
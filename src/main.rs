@@ -1,35 +1,847 @@
 extern crate toyjq;
 
-use toyjq::Json;
+use toyjq::{Json, ParserOptions, PrinterOptions, print_json_compact};
+use toyjq::color::Theme;
+use toyjq::event::Event;
+use toyjq::reader::{EventReader, ReadError};
+use toyjq::streamprinter::{self, StreamPrinter};
+use toyjq::prettyprinter::{NumberFormat, NumberNotation, UNLIMITED_WIDTH};
+use toyjq::diff::{diff_json, render_changes, render_json_diff};
+use toyjq::filter::{Filter, FilterError};
+use toyjq::parsercombinator::{ParseError, Position};
 
+use std::borrow::Cow;
+use std::env;
+use std::fs::File;
 use std::io;
-use std::io::{Read};
+use std::io::{BufRead, BufWriter, IsTerminal, Read, Write};
+use std::process;
+use std::str;
+
+/// The record separator, which starts every text of `application/json-seq`.
+const RS: char = '\u{1e}';
+
+/// What `toyjq --help` prints.
+const USAGE: &str = "\
+Usage: toyjq [OPTIONS] [PROGRAM] [FILES...]
+       toyjq diff [OPTIONS] OLD NEW
+       toyjq --validate [FILES...]
+
+Runs the jq program PROGRAM, `.` by default, on the JSON values in FILES or the standard input.
+
+Options:
+  -C, --color-output        Color the output even if it is not a terminal
+  -M, --monochrome-output   Do not color the output
+      --tab                 Indent with tabs
+      --indent N            Indent by N spaces (0 to 7, 2 by default)
+  -c, --compact-output      Print every value on a line without any whitespace
+  -r, --raw-output          Print strings as they are instead of as JSON
+  -S, --sort-keys           Print the members of objects in the order of their keys
+  -a, --ascii-output        Escape every non-ASCII character in strings as \\uXXXX
+      --precision N         Print numbers with at most N digits after the point
+      --notation NOTATION   Print numbers in `plain`, `exponent` or `auto` notation (plain by default)
+      --width N             Fit the output in N columns (the terminal width or 80 by default), or
+                            never break lines with `inf`
+      --depth N             Collapse the containers nested N deep into `{...}` or `[... 3 items]`
+      --expand              Break every non-empty array and object into lines
+      --seq                 Read and write application/json-seq (RFC 7464)
+      --unbuffered          Flush the outputs of every input value at once
+      --arg NAME VALUE      Set $NAME to the string VALUE
+      --args                Take the words after PROGRAM as strings of $ARGS.positional
+      --jsonargs            Take the words after PROGRAM as JSON values of $ARGS.positional
+      --validate            Only check that the input is JSON
+      --unified             Make diff show the printed documents with - and + on changed lines
+      --jsonc               Allow // and /* */ comments and trailing commas in the input
+      --preserve-numbers    Print the numbers of the input as they are written, e.g. 1.10
+      --error-format FORMAT Report errors as `text` or `json`
+  -h, --help                Print this help
+  -V, --version             Print the version
+      --                    Take every word after this as an argument, not an option
+
+Short options may be combined, e.g. `-CV`.
+
+Exit status: 0 on success, 1 if `diff` finds differences, 2 for usage and I/O errors,
+3 for programs which do not compile, 4 for inputs which are not JSON and 5 for runtime errors.
+";
+
+/// What the command line asks for.
+struct Options {
+    /// `toyjq diff a.json b.json`, which compares the files at `paths` instead of running a program
+    diff: bool,
+    /// `--validate`, which only checks that the files at `paths` are JSON
+    validate: bool,
+    /// `--unified`, with which `diff` compares the printed lines of the documents instead of their values
+    unified: bool,
+    help: bool,
+    version: bool,
+    program: String,
+    paths: Vec<String>,
+    /// The variables given by `--arg`
+    named: Vec<(String, Json<'static>)>,
+    /// The words after the program given with `--args` or `--jsonargs`
+    positional: Vec<Json<'static>>,
+    /// The columns to fit the output in, which is that of the terminal by default
+    width: Option<i32>,
+    /// `--expand`, which breaks every container as if no columns were left
+    expand: bool,
+    /// `-c`, which prints without any whitespace
+    compact: bool,
+    /// `-r`, which prints strings without quotes or escapes
+    raw: bool,
+    /// `-S`, which prints object members in key order
+    sort_keys: bool,
+    /// `-a`, which escapes non-ASCII characters
+    ascii: bool,
+    /// `--precision N` and `--notation NOTATION`
+    number_format: NumberFormat,
+    /// `--depth N`, below which containers are collapsed
+    depth: Option<usize>,
+    indent: i32,
+    tabs: bool,
+    /// `-C` or `-M`, which override whether to color the output
+    color: Option<bool>,
+    /// Reads and writes `application/json-seq` (RFC 7464)
+    seq: bool,
+    /// Flushes the outputs of every input value at once
+    unbuffered: bool,
+    /// `--error-format json`, which reports errors as JSON
+    json_errors: bool,
+    /// What the input may have besides JSON, such as comments and trailing commas with `--jsonc`
+    parser: ParserOptions
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            diff: false,
+            validate: false,
+            unified: false,
+            help: false,
+            version: false,
+            // Like jq, the input is printed as it is without a program.
+            program: ".".to_string(),
+            paths: vec![],
+            named: vec![],
+            positional: vec![],
+            width: None,
+            expand: false,
+            compact: false,
+            raw: false,
+            sort_keys: false,
+            ascii: false,
+            number_format: NumberFormat::default(),
+            depth: None,
+            indent: PrinterOptions::default().style.indent_width,
+            tabs: false,
+            color: None,
+            seq: false,
+            unbuffered: false,
+            json_errors: false,
+            parser: ParserOptions::default()
+        }
+    }
+}
+
+impl Options {
+    /// Reads `args`, the command line without the name of the command.
+    fn parse<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut args = args.peekable();
+        let mut options = Options {diff: args.next_if(|arg| arg == "diff").is_some(), ..Options::default()};
+        let mut program = None;
+        // What the words after the program are: files, or `$ARGS.positional` after `--args` or `--jsonargs`.
+        let mut rest = Rest::Files;
+        let mut only_arguments = false;
+        while let Some(arg) = args.next() {
+            if only_arguments || !arg.starts_with('-') || arg == "-" {
+                if options.diff || options.validate {
+                    options.paths.push(arg)
+                } else if program.is_none() {
+                    program = Some(arg)
+                } else {
+                    options.push_argument(arg, &rest)?
+                }
+                continue;
+            }
+            match arg.as_str() {
+                "--" => only_arguments = true,
+                "--indent" => {
+                    let n = args.next().ok_or("--indent takes a number")?;
+                    options.indent = match n.parse() {
+                        Ok(n) if (0..=7).contains(&n) => n,
+                        _ => return Err(format!("Cannot indent more than 7 characters: {}", n))
+                    };
+                },
+                "--tab" => options.tabs = true,
+                "--width" => {
+                    let n = args.next().ok_or("--width takes a number")?;
+                    options.width = match n.parse() {
+                        _ if n == "inf" => Some(UNLIMITED_WIDTH),
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("--width takes a positive number or inf: {}", n))
+                    };
+                },
+                "--depth" => {
+                    let n = args.next().ok_or("--depth takes a number")?;
+                    options.depth = Some(n.parse().map_err(|_| format!("--depth takes a number: {}", n))?);
+                },
+                "--expand" => options.expand = true,
+                "--compact-output" => options.compact = true,
+                "--raw-output" => options.raw = true,
+                "--sort-keys" => options.sort_keys = true,
+                "--ascii-output" => options.ascii = true,
+                "--precision" => {
+                    let n = args.next().ok_or("--precision takes a number")?;
+                    options.number_format.precision = Some(n.parse().map_err(|_| format!("--precision takes a number: {}", n))?);
+                },
+                "--notation" => options.number_format.notation = match args.next().as_deref() {
+                    Some("plain") => NumberNotation::Plain,
+                    Some("exponent") => NumberNotation::Exponent,
+                    Some("auto") => NumberNotation::Auto,
+                    _ => return Err("--notation takes plain, exponent or auto".to_string())
+                },
+                "--color-output" => options.color = Some(true),
+                "--monochrome-output" => options.color = Some(false),
+                "--seq" => options.seq = true,
+                "--unbuffered" => options.unbuffered = true,
+                "--validate" => options.validate = true,
+                "--unified" => options.unified = true,
+                "--jsonc" => options.parser = options.parser.comments(true).trailing_commas(true),
+                "--preserve-numbers" => options.parser = options.parser.number_text(true),
+                "--error-format" => options.json_errors = match args.next().as_deref() {
+                    Some("text") => false,
+                    Some("json") => true,
+                    _ => return Err("--error-format takes text or json".to_string())
+                },
+                "--arg" => match (args.next(), args.next()) {
+                    (Some(name), Some(value)) => options.named.push((name, Json::JString(Cow::Owned(value)))),
+                    _ => return Err("--arg takes two parameters (e.g. --arg varname value)".to_string())
+                },
+                "--args" => rest = Rest::Strings,
+                "--jsonargs" => rest = Rest::Jsons,
+                "--help" => options.help = true,
+                "--version" => options.version = true,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                // Short options, which may be combined as `-CV`
+                _ => for c in arg.chars().skip(1) {
+                    match c {
+                        'c' => options.compact = true,
+                        'r' => options.raw = true,
+                        'S' => options.sort_keys = true,
+                        'a' => options.ascii = true,
+                        'C' => options.color = Some(true),
+                        'M' => options.color = Some(false),
+                        'h' => options.help = true,
+                        'V' => options.version = true,
+                        _ => return Err(format!("Unknown option: -{}", c))
+                    }
+                }
+            }
+        }
+        // Every word is a file without a program.
+        if options.validate {
+            options.paths.splice(0..0, program.take());
+        }
+        if let Some(program) = program {
+            options.program = program;
+        }
+        Ok(options)
+    }
+
+    /// Takes `arg`, a word after the program, as `rest` tells.
+    fn push_argument(&mut self, arg: String, rest: &Rest) -> Result<(), String> {
+        match *rest {
+            Rest::Files => self.paths.push(arg),
+            Rest::Strings => self.positional.push(Json::JString(Cow::Owned(arg))),
+            Rest::Jsons => match Json::from_str_many(&arg).as_ref().map(Vec::as_slice) {
+                Ok([json]) => self.positional.push(json.clone().into_owned()),
+                _ => return Err(format!("Invalid JSON text passed to --jsonargs: {}", arg))
+            }
+        }
+        Ok(())
+    }
+
+    /// The variables which the program may refer to, including `$ARGS`.
+    fn vars(&self) -> Vec<(&str, Json<'static>)> {
+        let named = self.named.iter().map(|(name, value)| (Cow::Owned(name.clone()), value.clone())).collect();
+        let args = Json::JObject(vec![
+            ("positional".into(), Json::JArray(self.positional.clone())),
+            ("named".into(), Json::JObject(named))
+        ]);
+        let mut vars = vec![("ARGS", args)];
+        vars.extend(self.named.iter().map(|(name, value)| (name.as_str(), value.clone())));
+        vars
+    }
+}
+
+enum Rest {
+    Files,
+    Strings,
+    Jsons
+}
+
+/// The kinds of errors, each of which has its own exit status.
+/// The others are 0 for success, 1 for the differences of `diff` and the status of `halt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorKind {
+    /// A command line which toyjq does not understand, with the status 2
+    Usage,
+    /// A file which cannot be read or written, with the status 2
+    Io,
+    /// A program which does not compile, with the status 3
+    Compile,
+    /// An input which is not JSON, with the status 4
+    Input,
+    /// An error raised by the program which no `try` caught, with the status 5
+    Runtime
+}
+
+impl ErrorKind {
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Usage => "usage",
+            ErrorKind::Io => "io",
+            ErrorKind::Compile => "compile",
+            ErrorKind::Input => "input",
+            ErrorKind::Runtime => "runtime"
+        }
+    }
+
+    fn exit_status(self) -> i32 {
+        match self {
+            ErrorKind::Usage | ErrorKind::Io => 2,
+            ErrorKind::Compile => 3,
+            ErrorKind::Input => 4,
+            ErrorKind::Runtime => 5
+        }
+    }
+}
+
+/// An error told to the user, at a line and a column of the program or the input if any.
+#[derive(Debug)]
+struct ErrorReport {
+    kind: ErrorKind,
+    message: String,
+    position: Option<(usize, usize)>,
+    /// The part of the program which raised a runtime error
+    source: Option<String>,
+    /// The line where a program or an input failed to parse, with a caret under the column
+    snippet: Option<String>
+}
+
+impl ErrorReport {
+    fn new(kind: ErrorKind, message: String) -> ErrorReport {
+        ErrorReport {kind, message, position: None, source: None, snippet: None}
+    }
+
+    fn at(self, line: usize, column: usize) -> ErrorReport {
+        ErrorReport {position: Some((line, column)), ..self}
+    }
+
+    fn snippet(self, snippet: String) -> ErrorReport {
+        ErrorReport {snippet: Some(snippet), ..self}
+    }
+
+    /// Moves the position down by `lines`, for a text which follows as many lines.
+    fn below(self, lines: usize) -> ErrorReport {
+        ErrorReport {position: self.position.map(|(line, column)| (lines + line, column)), ..self}
+    }
+
+    /// Prints the report to the standard error, as a line of JSON with `json` such as
+    /// `{"kind":"input","message":"...","line":1,"column":5}`, and exits.
+    fn exit(self, json: bool) -> ! {
+        if json {
+            let number = |n: Option<usize>| n.map_or(Json::JNull, |n| Json::JNumber((n as f64).into()));
+            let mut fields = vec![
+                ("kind".into(), Json::JString(self.kind.name().into())),
+                ("message".into(), Json::JString(self.message.as_str().into())),
+                ("line".into(), number(self.position.map(|p| p.0))),
+                ("column".into(), number(self.position.map(|p| p.1)))
+            ];
+            fields.extend(self.source.as_ref().map(|source| ("source".into(), Json::JString(source.as_str().into()))));
+            eprintln!("{}", print_json_compact(&Json::JObject(fields)));
+        } else {
+            eprintln!("toyjq: error: {}", self.message);
+            match (self.position, self.source) {
+                (Some((line, column)), Some(source)) => eprintln!("    at line {}, column {}: {}", line, column, source),
+                (Some((line, column)), None) => eprintln!("    at line {}, column {}", line, column),
+                _ => ()
+            }
+            for line in self.snippet.iter().flat_map(|s| s.lines()) {
+                eprintln!("    {}", line);
+            }
+            if self.kind == ErrorKind::Usage {
+                eprintln!("Use toyjq --help for help with the command-line options.");
+            }
+        }
+        process::exit(self.kind.exit_status())
+    }
+}
 
 fn main() {
-    interact(|s| {
-        let json = Json::from_str(s).map_err(ToyjqError::ParseError)?;
-        Ok(json.pretty_print(80))
-    }).unwrap_or_else(|e| {
-        println!("ERROR");
-        println!("{:?}", e);
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        // The options are not known, but the format of the errors may be.
+        let json = env::args().collect::<Vec<_>>().windows(2).any(|w| w[0] == "--error-format" && w[1] == "json");
+        ErrorReport::new(ErrorKind::Usage, e).exit(json)
+    });
+    if options.help {
+        print!("{}", USAGE);
+        return
+    }
+    if options.version {
+        println!("toyjq {}", env!("CARGO_PKG_VERSION"));
+        return
+    }
+    // Like jq, the output is colored only for terminals unless told otherwise.
+    let color = options.color.unwrap_or_else(|| io::stdout().is_terminal());
+    let width = options.width
+        .or_else(|| if io::stdout().is_terminal() { toyjq::terminal::width() } else { None })
+        .unwrap_or(80);
+    let theme = if color { Some(theme()) } else { None };
+    let width = if options.expand { 0 } else { width };
+    let opts = PrinterOptions::new().width(width).indent(options.indent).tabs(options.tabs)
+        .color(theme)
+        .compact(options.compact)
+        .sort_keys(options.sort_keys)
+        .ascii(options.ascii)
+        .number_format(options.number_format)
+        .max_depth(options.depth)
+        .trailing_newline(true);
+    if options.diff {
+        match print_diff(&options.paths, &options.parser, &opts, options.unified) {
+            Ok(same) => process::exit(if same { 0 } else { 1 }),
+            Err(report) => report.exit(options.json_errors)
+        }
+    }
+    if options.validate {
+        if let Err(report) = validate(&options.paths, &options.parser) {
+            report.exit(options.json_errors)
+        }
+        return
+    }
+    let program = &options.program;
+    let filter = Filter::parse_with_spans(program).unwrap_or_else(|e| {
+        let snippet = e.snippet(program);
+        ErrorReport::new(ErrorKind::Compile, e.message).at(e.line, e.column).snippet(snippet).exit(options.json_errors)
+    });
+    let vars = options.vars();
+    // `toyjq .` prints the input as it is read without making values, which `StreamPrinter`
+    // does for what it supports, so that an input of any size takes little memory.
+    let stream = program.trim() == "." && !options.raw && !options.seq && streamprinter::supports(&opts.style);
+    // Like jq, the values of every file make a single stream.
+    let result = if stream {
+        print_stream(&options.paths, options.unbuffered, &options.parser, &opts)
+    } else {
+        interact(&options.paths, options.unbuffered, &options.parser, options.seq, |s, mut lines, out| {
+            let texts: Vec<&str> = if options.seq { s.split(RS).collect() } else { vec![s] };
+            for text in texts {
+                let jsons = match Json::parse_many_with(text, &options.parser) {
+                    Ok(jsons) => jsons,
+                    // A text cut off by the next RS is dropped, which is the point of the format.
+                    Err(e) if options.seq => {
+                        eprintln!("toyjq: warning: ignoring a truncated text: {} at line {}, column {}", e.message, e.line, e.column);
+                        lines += text.matches('\n').count();
+                        continue
+                    },
+                    Err(e) => {
+                        let start = text.as_ptr() as usize - s.as_ptr() as usize;
+                        return Err(parse_error(s, lines - s[..start].matches('\n').count(), ParseError {pos: start + e.pos, ..e}))
+                    }
+                };
+                let last_newline = text.rfind('\n');
+                for (json, span) in jsons {
+                    let results = filter.apply_with_vars(&json, lines + lines_read(&span.end, last_newline), &vars).map_err(ToyjqError::FilterError)?;
+                    for j in results {
+                        if options.seq {
+                            write!(out, "{}", RS).map_err(ToyjqError::IoError)?;
+                        }
+                        match j {
+                            // Like jq, a string is printed as it is on a line.
+                            Json::JString(ref s) if options.raw => write!(out, "{}{}", s, opts.style.line_ending.as_str()),
+                            _ => out.write_all(j.pretty_print_with(&opts).as_bytes())
+                        }.map_err(ToyjqError::IoError)?;
+                    }
+                }
+                lines += text.matches('\n').count();
+            }
+            Ok(())
+        })
+    };
+    result.unwrap_or_else(|e| match e {
+        // The reader has gone (e.g. `toyjq | head`), so there is nobody to tell.
+        ToyjqError::IoError(ref e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+        // Like jq, a string is printed as it is, and any other value as JSON on a line.
+        ToyjqError::FilterError(FilterError::Halt(code, ref value)) => {
+            match *value {
+                Some(Json::JString(ref s)) => eprint!("{}", s),
+                Some(ref j) => eprintln!("{}", print_json_compact(j)),
+                None => ()
+            }
+            process::exit(code)
+        },
+        // Same as the exit status of jq for errors which no `try` caught.
+        ToyjqError::FilterError(e) => {
+            let report = ErrorReport::new(ErrorKind::Runtime, e.to_string());
+            match e.span() {
+                Some(span) => ErrorReport {source: Some(span.fragment(program).to_string()), ..report.at(span.start.line, span.start.column)},
+                None => report
+            }.exit(options.json_errors)
+        },
+        ToyjqError::ParseError(e, snippet) => ErrorReport::new(ErrorKind::Input, e.message).at(e.line, e.column).snippet(snippet).exit(options.json_errors),
+        ToyjqError::IoError(e) => ErrorReport::new(ErrorKind::Io, e.to_string()).exit(options.json_errors),
+        ToyjqError::Report(report) => report.exit(options.json_errors)
     })
 }
 
+/// Prints the differences between the documents in the two files at `paths` and tells whether
+/// they are the same, which `toyjq diff` gives as the exit status 0, or 1 if not, as `diff` does.
+/// With `unified`, both are printed with `opts` and their lines are compared, so that a key
+/// moved in an object counts as well.
+fn print_diff(paths: &[String], parser: &ParserOptions, opts: &PrinterOptions, unified: bool) -> Result<bool, ErrorReport> {
+    let (old_path, new_path) = match *paths {
+        [ref old, ref new] => (old, new),
+        _ => return Err(ErrorReport::new(ErrorKind::Usage, "diff takes two files (e.g. toyjq diff a.json b.json)".to_string()))
+    };
+    let (old_bytes, new_bytes) = (read_file(old_path)?, read_file(new_path)?);
+    let (old, new) = (parse_document(old_path, &old_bytes, parser)?, parse_document(new_path, &new_bytes, parser)?);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let (written, same) = if unified {
+        (write!(out, "{}", render_json_diff(&old, &new, opts.width, &opts.style, opts.theme.is_some())), old == new)
+    } else {
+        let changes = diff_json(&old, &new);
+        (write!(out, "{}", render_changes(&changes, &opts.clone().compact(true))), changes.is_empty())
+    };
+    match written.and_then(|()| out.flush()) {
+        // The reader has gone (e.g. `toyjq diff a.json b.json | head`), as for the outputs of programs.
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(same),
+        Err(e) => Err(ErrorReport::new(ErrorKind::Io, e.to_string())),
+        Ok(()) => Ok(same)
+    }
+}
+
+fn read_file(path: &str) -> Result<toyjq::mmap::FileBytes, ErrorReport> {
+    toyjq::mmap::read_file(path).map_err(|e| ErrorReport::new(ErrorKind::Io, format!("{}: {}", path, e)))
+}
+
+/// Parses `bytes`, the contents of the file at `path`, which must be a single document.
+fn parse_document<'a>(path: &str, bytes: &'a [u8], parser: &ParserOptions) -> Result<Json<'a>, ErrorReport> {
+    let text = utf8(path, bytes)?;
+    match Json::from_str_many_with(text, parser) {
+        Ok(mut jsons) if jsons.len() == 1 => Ok(jsons.remove(0)),
+        Ok(_) => Err(ErrorReport::new(ErrorKind::Input, format!("{}: Expected a single document", path))),
+        Err(e) => Err(input_error(path, text, e))
+    }
+}
+
+/// The text of `bytes`, the contents of the file at `path`, which must be UTF-8.
+fn utf8<'a>(path: &str, bytes: &'a [u8]) -> Result<&'a str, ErrorReport> {
+    str::from_utf8(bytes).map_err(|e| {
+        let valid = str::from_utf8(&bytes[..e.valid_up_to()]).unwrap();
+        let Position {line, column, ..} = Position::of(valid, valid.len());
+        ErrorReport::new(ErrorKind::Input, format!("{}: Invalid UTF-8", path)).at(line, column)
+    })
+}
+
+/// The report of `e`, which is where `text` of the file at `path` is not JSON.
+fn input_error(path: &str, text: &str, e: ParseError) -> ErrorReport {
+    let snippet = e.snippet(text);
+    ErrorReport::new(ErrorKind::Input, format!("{}: {}", path, e.message)).at(e.line, e.column).snippet(snippet)
+}
+
+/// Checks that the files at `paths`, or the standard input if there are none, are JSON.
+/// Nothing is printed if they are, and otherwise the first error is reported with its line and
+/// column, with the exit status 4, or 2 for a file which cannot be read.
+fn validate(paths: &[String], parser: &ParserOptions) -> Result<(), ErrorReport> {
+    let check = |name: &str, text: &str| Json::from_str_many_with(text, parser).map(|_| ()).map_err(|e| input_error(name, text, e));
+    if paths.is_empty() {
+        let mut input = vec![];
+        io::stdin().read_to_end(&mut input).map_err(|e| ErrorReport::new(ErrorKind::Io, format!("<stdin>: {}", e)))?;
+        return check("<stdin>", utf8("<stdin>", &input)?)
+    }
+    for path in paths {
+        let bytes = read_file(path)?;
+        check(path, utf8(path, &bytes)?)?;
+    }
+    Ok(())
+}
+
+/// The colors given by `$JQ_COLORS`, which are the defaults if it is not set or not valid.
+fn theme() -> Theme {
+    match env::var("JQ_COLORS") {
+        Ok(spec) => Theme::from_spec(&spec).unwrap_or_else(|e| {
+            eprintln!("toyjq: warning: ignoring $JQ_COLORS: {}", e);
+            Theme::default()
+        }),
+        Err(_) => Theme::default()
+    }
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum ToyjqError {
     IoError(io::Error),
-    ParseError(toyjq::parsercombinator::ParseError)
+    /// An input which is not JSON, at a line and a column of the whole input, with the snippet of
+    /// the line
+    ParseError(ParseError, String),
+    FilterError(FilterError),
+    /// An error which has been made into a report, such as an input which is not UTF-8
+    Report(ErrorReport)
+}
+
+/// The error of `e` at `e.pos` of `s`, a text after `lines` lines, which is told with the
+/// position in the whole input.
+fn parse_error(s: &str, lines: usize, e: ParseError) -> ToyjqError {
+    let Position {line, column, ..} = Position::of(s, e.pos);
+    let e = ParseError {line: lines + line, column, ..e};
+    let snippet = e.snippet(s);
+    ToyjqError::ParseError(e, snippet)
+}
+
+/// Prints the values of the files at `paths`, or of the standard input if there are none, with
+/// `opts` as `StreamPrinter` does while they are read. A value which turns out not to be JSON
+/// may have been printed in part.
+fn print_stream(paths: &[String], unbuffered: bool, parser: &ParserOptions, opts: &PrinterOptions) -> ToyjqResult<()> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut printer = StreamPrinter::with_options(&mut out, opts);
+    if paths.is_empty() {
+        let stdin = io::stdin();
+        print_events(&mut EventReader::new(stdin.lock(), parser), 0, unbuffered, &mut printer)?;
+    } else {
+        let mut lines = 0;
+        for path in paths {
+            let file = File::open(path).map_err(|e| {
+                ToyjqError::IoError(io::Error::new(e.kind(), format!("{}: {}", path, e)))
+            })?;
+            let mut reader = EventReader::new(file, parser);
+            print_events(&mut reader, lines, unbuffered, &mut printer)?;
+            lines += reader.text_end().line - 1;
+        }
+    }
+    printer.finish().and_then(|out| out.flush()).map_err(ToyjqError::IoError)
+}
+
+/// Prints the events of `reader`, a text after `lines` lines, flushing the output after every
+/// value with `unbuffered`.
+fn print_events<R: Read, W: Write>(reader: &mut EventReader<R>, lines: usize, unbuffered: bool, printer: &mut StreamPrinter<W>) -> ToyjqResult<()> {
+    let mut depth = 0;
+    while let Some(event) = reader.next() {
+        let event = match event {
+            Ok(event) => event,
+            Err(ReadError::Io(e)) => return Err(ToyjqError::IoError(e)),
+            Err(ReadError::Parse(e)) => {
+                let snippet = reader.snippet(&e);
+                return Err(ToyjqError::ParseError(ParseError {line: lines + e.line, ..e}, snippet))
+            }
+        };
+        match event {
+            Event::StartArray | Event::StartObject => depth += 1,
+            Event::EndArray | Event::EndObject => depth -= 1,
+            _ => ()
+        }
+        printer.event(event).map_err(ToyjqError::IoError)?;
+        if unbuffered && depth == 0 {
+            printer.flush().map_err(ToyjqError::IoError)?;
+        }
+    }
+    Ok(())
+}
+
+/// The number of the lines of a text which jq, reading a line at a time, has read by `end` of a
+/// value, where `last_newline` is the offset of the last newline of the text. The rest of the line
+/// is read with the value, and a last line without a newline is not counted.
+fn lines_read(end: &Position, last_newline: Option<usize>) -> usize {
+    end.line - 1 + last_newline.map_or(0, |i| (end.offset <= i) as usize)
 }
 
 type ToyjqResult<T> = std::result::Result<T, ToyjqError>;
 
-fn interact<F>(f: F) -> ToyjqResult<()>
-    where F: FnOnce(&str) -> ToyjqResult<String>
+/// Runs `f` on the contents of the files at `paths` one by one, or of the standard input if
+/// there are none, with the number of the lines before each and the standard output.
+/// The standard input is given in pieces as soon as every value in them is complete, so that
+/// toyjq can sit in a live pipeline such as `tail -f log | toyjq .a`. With `seq`, a piece also
+/// ends at RS, so that a truncated text does not hold back the ones after it.
+fn interact<F>(paths: &[String], unbuffered: bool, parser: &ParserOptions, seq: bool, mut f: F) -> ToyjqResult<()>
+    where F: FnMut(&str, usize, &mut dyn Write) -> ToyjqResult<()>
 {
-    let mut input = String::new();
-    io::stdin().read_to_string(&mut input).map_err(ToyjqError::IoError)?;
-    let s = f(input.as_ref())?;
-    println!("{}", s);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut lines = 0;
+    if paths.is_empty() {
+        let stdin = io::stdin();
+        let mut reader = stdin.lock();
+        let mut chunk = String::new();
+        let mut line = vec![];
+        let mut nesting = Nesting::new(parser.comments, seq);
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).map_err(ToyjqError::IoError)? == 0 {
+                break;
+            }
+            let text = utf8("<stdin>", &line).map_err(|report| ToyjqError::Report(report.below(lines + chunk.matches('\n').count())))?;
+            chunk.push_str(text);
+            nesting.feed(text);
+            if nesting.is_closed() {
+                f(&chunk, lines, &mut out)?;
+                lines += chunk.matches('\n').count();
+                chunk.clear();
+                nesting = Nesting::new(parser.comments, seq);
+                if unbuffered {
+                    out.flush().map_err(ToyjqError::IoError)?;
+                }
+            }
+        }
+        // Anything left is not closed, which `f` tells as an error.
+        if !chunk.is_empty() {
+            f(&chunk, lines, &mut out)?;
+        }
+    } else {
+        for path in paths {
+            let bytes = toyjq::mmap::read_file(path).map_err(|e| {
+                ToyjqError::IoError(io::Error::new(e.kind(), format!("{}: {}", path, e)))
+            })?;
+            let input = utf8(path, &bytes).map_err(ToyjqError::Report)?;
+            f(input, lines, &mut out)?;
+            lines += input.bytes().filter(|&b| b == b'\n').count();
+            if unbuffered {
+                out.flush().map_err(ToyjqError::IoError)?;
+            }
+        }
+    }
+    out.flush().map_err(ToyjqError::IoError)
+}
 
-    Ok(())
+/// How deep a text is in arrays, objects and strings, to tell where values may end without
+/// parsing them. With `comments`, brackets and quotes in comments do not count, and with `seq`,
+/// RS starts over as a new text does.
+#[derive(Default)]
+struct Nesting {
+    depth: i64,
+    seq: bool,
+    in_string: bool,
+    escaped: bool,
+    comments: bool,
+    in_line_comment: bool,
+    in_block_comment: bool,
+    /// The last character was `/` out of strings and comments, or `*` in a block comment.
+    after_mark: bool
+}
+
+impl Nesting {
+    fn new(comments: bool, seq: bool) -> Nesting {
+        Nesting {comments, seq, ..Nesting::default()}
+    }
+
+    /// Follows `s`, which continues the text fed so far.
+    fn feed(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.seq && c == RS {
+                *self = Nesting::new(self.comments, true);
+            } else if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => ()
+                }
+            } else if self.in_line_comment {
+                self.in_line_comment = c != '\n';
+            } else if self.in_block_comment {
+                self.in_block_comment = !(self.after_mark && c == '/');
+                self.after_mark = c == '*';
+            } else {
+                let after_slash = self.after_mark;
+                self.after_mark = false;
+                match c {
+                    '/' if self.comments && after_slash => self.in_line_comment = true,
+                    '*' if self.comments && after_slash => self.in_block_comment = true,
+                    '/' => self.after_mark = true,
+                    '"' => self.in_string = true,
+                    '[' | '{' => self.depth += 1,
+                    ']' | '}' => self.depth -= 1,
+                    _ => ()
+                }
+            }
+        }
+    }
+
+    /// Whether every array, object, string and block comment is closed, or closed too many
+    /// times, which the parser should tell at once.
+    fn is_closed(&self) -> bool {
+        self.depth <= 0 && !self.in_string && !self.in_block_comment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse(&["--indent", "4", ".a", "a.json", "-CM", "b.json"]).unwrap();
+        assert_eq!((options.program.as_str(), options.paths.clone()), (".a", vec!["a.json".to_string(), "b.json".to_string()]));
+        assert_eq!((options.indent, options.color), (4, Some(false)));
+        let options = parse(&[".", "--args", "x", "--", "-C", "--tab"]).unwrap();
+        assert_eq!(options.positional, vec![Json::JString("x".into()), Json::JString("-C".into()), Json::JString("--tab".into())]);
+        assert_eq!((options.color, options.tabs), (None, false));
+        assert_eq!(parse(&["--jsonargs", ".", "[1]"]).unwrap().positional, vec![Json::JArray(vec![Json::JNumber(1f64.into())])]);
+        assert_eq!(parse(&[]).unwrap().program, ".");
+        assert!(parse(&["-hV"]).map(|options| options.help && options.version).unwrap());
+        assert_eq!(parse(&["diff", "--width", "40", "a", "b"]).map(|options| (options.diff, options.paths)), Ok((true, vec!["a".to_string(), "b".to_string()])));
+        assert_eq!(parse(&["a", "--validate"]).map(|options| options.paths), Ok(vec!["a".to_string()]));
+        assert!(parse(&["diff", "--unified", "a", "b"]).unwrap().unified);
+        assert_eq!(parse(&["--expand", "."]).map(|options| (options.expand, options.program)), Ok((true, ".".to_string())));
+        assert!(parse(&["-cC"]).unwrap().compact);
+        assert!(parse(&["-cS"]).map(|options| options.compact && options.sort_keys).unwrap());
+        assert!(parse(&["--sort-keys"]).unwrap().sort_keys);
+        assert!(parse(&["-rc"]).map(|options| options.raw && options.compact).unwrap());
+        assert!(parse(&["--raw-output"]).unwrap().raw);
+        assert!(parse(&["-aM"]).unwrap().ascii);
+        assert!(parse(&["--ascii-output"]).unwrap().ascii);
+        let options = parse(&["--precision", "3", "--notation", "exponent"]).unwrap();
+        assert_eq!(options.number_format, NumberFormat {precision: Some(3), notation: NumberNotation::Exponent, ..NumberFormat::default()});
+        assert!(parse(&["--notation", "hex"]).is_err());
+        assert_eq!(parse(&["--width", "inf"]).unwrap().width, Some(UNLIMITED_WIDTH));
+        assert!(parse(&["--width", "0"]).is_err());
+        assert_eq!(parse(&["--depth", "2", "."]).unwrap().depth, Some(2));
+        assert_eq!(parse(&["--depth", "-1"]).err(), Some("--depth takes a number: -1".to_string()));
+        assert_eq!(parse(&["-Cx"]).err(), Some("Unknown option: -x".to_string()));
+        assert_eq!(parse(&["--indent", "8"]).err(), Some("Cannot indent more than 7 characters: 8".to_string()));
+        assert!(parse(&["--arg", "x"]).is_err());
+        assert!(parse(&["--jsonargs", ".", "{"]).is_err());
+        assert_eq!(parse(&["--jsonc", "."]).unwrap().parser, ParserOptions::new().comments(true).trailing_commas(true));
+        assert_eq!(parse(&["--preserve-numbers", "."]).unwrap().parser, ParserOptions::new().number_text(true));
+    }
+
+    #[test]
+    fn test_lines_read() {
+        let text = "1 2\n[\n3]\n4";
+        let lines: Vec<usize> = Json::parse_many(text).unwrap().iter().map(|doc| lines_read(&doc.1.end, text.rfind('\n'))).collect();
+        assert_eq!(lines, vec![1, 1, 3, 3]);
+        let text = "1\n2\n3\n";
+        let lines: Vec<usize> = Json::parse_many(text).unwrap().iter().map(|doc| lines_read(&doc.1.end, text.rfind('\n'))).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_nesting() {
+        let closed = |comments: bool, lines: &[&str]| {
+            let mut nesting = Nesting::new(comments, true);
+            lines.iter().map(|line| {
+                nesting.feed(line);
+                nesting.is_closed()
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(closed(false, &["{\"a\": \"]\\\"\",\n", "\"b\": [1]}\n"]), vec![false, true]);
+        assert_eq!(closed(true, &["/* [\n", "*/ [1, // ]\n", "2] // \"\n"]), vec![false, false, true]);
+        assert_eq!(closed(false, &["// [\n"]), vec![false]);
+        assert_eq!(closed(false, &["\u{1e}{\"a\": [\"b\n", "\u{1e}[1]\n"]), vec![false, true]);
+        let mut nesting = Nesting::new(false, false);
+        nesting.feed("[\u{1e}");
+        assert!(!nesting.is_closed());
+    }
 }
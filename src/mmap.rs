@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::ops::Deref;
+use std::path::Path;
+
+/// The contents of a file, memory-mapped where the platform allows it.
+pub enum FileBytes {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    Mapped(sys::Mmap),
+    Read(Vec<u8>)
+}
+
+impl Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            #[cfg(all(unix, target_pointer_width = "64"))]
+            FileBytes::Mapped(ref m) => m,
+            FileBytes::Read(ref v) => v
+        }
+    }
+}
+
+/// Maps the file at `path` into memory instead of copying it, falling back to reading it
+/// when it cannot be mapped (e.g. it is empty or a pipe).
+pub fn read_file<P: AsRef<Path>>(path: P) -> io::Result<FileBytes> {
+    let mut file = File::open(path)?;
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    {
+        if let Some(m) = sys::Mmap::map(&file)? {
+            return Ok(FileBytes::Mapped(m))
+        }
+    }
+    let mut v = vec![];
+    file.read_to_end(&mut v)?;
+    Ok(FileBytes::Read(v))
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::fs::File;
+    use std::io;
+    use std::ops::Deref;
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+    use std::slice;
+
+    const PROT_READ: c_int = 1;
+    const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    /// A read-only private mapping of a whole file.
+    pub struct Mmap {
+        ptr: *mut c_void,
+        len: usize
+    }
+
+    impl Mmap {
+        /// Returns `None` for files which are not regular or are empty.
+        pub fn map(file: &File) -> io::Result<Option<Mmap>> {
+            let meta = file.metadata()?;
+            if !meta.is_file() || meta.len() == 0 {
+                return Ok(None)
+            }
+            let len = meta.len() as usize;
+            let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+            if ptr as isize == -1 {
+                return Err(io::Error::last_os_error())
+            }
+            Ok(Some(Mmap {ptr, len}))
+        }
+    }
+
+    impl Deref for Mmap {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for Mmap {
+        fn drop(&mut self) {
+            unsafe { munmap(self.ptr, self.len); }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_read_file() {
+        let path = env::temp_dir().join(format!("toyjq-mmap-test-{}", std::process::id()));
+        fs::write(&path, b"[1, 2]").unwrap();
+        assert_eq!(&*read_file(&path).unwrap(), b"[1, 2]");
+        fs::write(&path, b"").unwrap();
+        assert_eq!(&*read_file(&path).unwrap(), b"");
+        fs::remove_file(&path).unwrap();
+        assert!(read_file(&path).is_err());
+    }
+}
@@ -1,8 +1,50 @@
+use std::prelude::v1::*;
+
+/// A failure of a parser at the byte offset `pos` of the input, which is at `line` and `column`,
+/// both counting from 1. `line` and `column` are 0 until `Parser::parse` gives them.
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseError {
     pub retry: bool,
     pub message: String,
-    pub pos: usize
+    pub pos: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl ParseError {
+    /// Sets `line` and `column` from `pos` in `input`.
+    pub fn locate(self, input: &str) -> ParseError {
+        let Position {line, column, ..} = Position::of(input, self.pos);
+        ParseError {line, column, ..self}
+    }
+
+    /// The line of `input` where the error is, and below it a `^` under the character at `pos`.
+    /// A long line is cut to the 40 characters on each side of the one at `pos`, marked by `...`.
+    ///
+    /// ```
+    /// # use toyjq::parsercombinator::*;
+    /// let e = chr('a').then(chr('\n')).then(chr('b')).parse("a\nxyz").unwrap_err();
+    /// assert_eq!(e.snippet("a\nxyz"), "xyz\n^");
+    /// let long = format!("{}x{}", "[".repeat(100), "]".repeat(100));
+    /// let e = chr('[').many().then(chr(']')).parse(&long).unwrap_err();
+    /// assert_eq!(e.snippet(&long), format!("...{}x{}...\n{}^", "[".repeat(40), "]".repeat(40), " ".repeat(43)));
+    /// ```
+    pub fn snippet(&self, input: &str) -> String {
+        const CONTEXT: usize = 40;
+        let pos = char_boundary(input, self.pos);
+        let start = input[..pos].rfind('\n').map_or(0, |i| i + 1);
+        let end = input[pos..].find('\n').map_or(input.len(), |i| pos + i);
+        let before: Vec<char> = input[start..pos].chars().collect();
+        let (cut, before) = if before.len() > CONTEXT { ("...", &before[before.len() - CONTEXT..]) } else { ("", &before[..]) };
+        // The character at `pos` and those after it
+        let mut after: String = input[pos..end].trim_end_matches('\r').chars().take(CONTEXT + 2).collect();
+        if after.chars().count() > CONTEXT + 1 {
+            after = after.chars().take(CONTEXT + 1).chain("...".chars()).collect();
+        }
+        // Tabs are kept so that the caret lines up in terminals.
+        let indent: String = cut.chars().chain(before.iter().cloned()).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        format!("{}{}{}\n{}^", cut, before.iter().collect::<String>(), after, indent)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -24,21 +66,36 @@ impl <'a> StrStream<'a> {
         &self.body[self.pos..self.body.len()]
     }
 
+    /// At most `n` bytes ahead, cut short at a character boundary.
     fn take(&'a self, n: usize) -> &'a str {
-        use std::cmp::min;
         let cr = self.current();
-        &cr[0..min(cr.len(), n)]
+        &cr[..char_boundary(cr, n)]
     }
 
+    /// Moves `n` bytes ahead, which must end at a character boundary.
     fn advance(mut self, n: usize) -> StrStream<'a> {
+        debug_assert!(self.body.is_char_boundary(self.pos + n), "advances into a character");
         self.pos += n;
         self
     }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {retry: true, message, pos: self.pos, line: 0, column: 0}
+    }
+}
+
+/// `pos` moved back to the start of the character it is in, or to the end of `s`.
+fn char_boundary(s: &str, pos: usize) -> usize {
+    let mut pos = pos.min(s.len());
+    while !s.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
 }
 
 type ParseResult<'a, T> = Result<(StrStream<'a>, T), ParseError>;
 
-pub struct Parser<'a, T>(Box<Fn(StrStream<'a>) -> ParseResult<'a, T> + 'a>);
+pub struct Parser<'a, T>(Box<dyn Fn(StrStream<'a>) -> ParseResult<'a, T> + 'a>);
 
 
 /// Creates a new Parser which returns the specified value.
@@ -48,10 +105,10 @@ pub struct Parser<'a, T>(Box<Fn(StrStream<'a>) -> ParseResult<'a, T> + 'a>);
 /// assert_eq!(unit(42).parse("").unwrap(), 42);
 /// ```
 pub fn unit<'a, T>(x: T) -> Parser<'a, T>
-    where T: Copy + 'a
+    where T: Clone + 'a
 {
     Parser(Box::new(move |i| {
-        Ok((i, x))
+        Ok((i, x.clone()))
     }))
 }
 
@@ -69,18 +126,10 @@ pub fn string<'a>(s: &'static str) -> Parser<'a, &'static str> {
             if s == heads {
                 Ok((input.advance(len), s))
             } else {
-                Err(ParseError {
-                    retry: true,
-                    message: format!("Expected `{}` but actual is `{}`.", s, heads),
-                    pos: input.pos
-                })
+                Err(input.error(format!("Expected `{}` but actual is `{}`.", s, heads)))
             }
         } else {
-            Err(ParseError {
-                retry: true,
-                message: "Reaches end.".to_string(),
-                pos: input.pos
-            })
+            Err(input.error("Reaches end.".to_string()))
         }
     }))
 }
@@ -94,22 +143,32 @@ pub fn string<'a>(s: &'static str) -> Parser<'a, &'static str> {
 pub fn chr<'a>(c: char) -> Parser<'a, char> {
     Parser(Box::new(move |input| {
         if input.can_advance() {
-            let head = input.take(1).chars().next().unwrap();
+            let head = input.current().chars().next().unwrap();
             if c == head {
-                Ok((input.advance(1), c))
+                Ok((input.advance(c.len_utf8()), c))
             } else {
-                Err(ParseError {
-                    retry: true,
-                    message: format!("Expected `{}` but actual is `{}`.", c, head),
-                    pos: input.pos
-                })
+                Err(input.error(format!("Expected `{}` but actual is `{}`.", c, head)))
             }
         } else {
-            Err(ParseError {
-                retry: true,
-                message: "Reaches end.".to_string(),
-                pos: input.pos
-            })
+            Err(input.error("Reaches end.".to_string()))
+        }
+    }))
+}
+
+/// Parses a character for which `pred` holds.
+///
+/// ```
+/// # use toyjq::parsercombinator::*;
+/// assert_eq!(satisfy(|c| c != '"').many().parse("aあ\"").unwrap(), vec!['a', 'あ']);
+/// ```
+pub fn satisfy<'a, F>(pred: F) -> Parser<'a, char>
+    where F: Fn(char) -> bool + 'a
+{
+    Parser(Box::new(move |input| {
+        match input.current().chars().next() {
+            Some(c) if pred(c) => Ok((input.advance(c.len_utf8()), c)),
+            Some(c) => Err(input.error(format!("Unexpected `{}`.", c))),
+            None => Err(input.error("Reaches end.".to_string()))
         }
     }))
 }
@@ -120,14 +179,131 @@ pub fn chr<'a>(c: char) -> Parser<'a, char> {
 /// ```
 pub fn failure<'a>(message: String) -> Parser<'a, ()> {
     Parser(Box::new(move |input| {
-        Err(ParseError {
-            retry: true,
-            message: message.clone(),
-            pos: input.pos
+        Err(input.error(message.clone()))
+    }))
+}
+
+/// Succeeds only at the end of input.
+///
+/// ```
+/// # use toyjq::parsercombinator::*;
+/// assert!(string("foo").skip(eof()).parse("foo").is_ok());
+/// assert!(string("foo").skip(eof()).parse("foo!").is_err());
+/// ```
+pub fn eof<'a>() -> Parser<'a, ()> {
+    Parser(Box::new(move |input| {
+        if input.can_advance() {
+            Err(input.error(format!("Expected end of input but actual is `{}`.", input.current().chars().next().unwrap())))
+        } else {
+            Ok((input, ()))
+        }
+    }))
+}
+
+
+/// A position in the input, where `line` and `column` count from 1 and `column` counts characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Position {
+    /// The position of the byte `offset` in `text`.
+    ///
+    /// ```
+    /// # use toyjq::parsercombinator::*;
+    /// assert_eq!(Position::of("ab\nあc", 6), Position {offset: 6, line: 2, column: 2});
+    /// ```
+    pub fn of(text: &str, offset: usize) -> Position {
+        let before = &text[..char_boundary(text, offset)];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Position {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1
+        }
+    }
+
+    /// The position after `text`, which starts at this position.
+    ///
+    /// ```
+    /// # use toyjq::parsercombinator::*;
+    /// assert_eq!(Position::of("ab\nあc", 1).advance("b\nあ"), Position::of("ab\nあc", 6));
+    /// ```
+    pub fn advance(self, text: &str) -> Position {
+        text.chars().fold(Position {offset: self.offset + text.len(), ..self}, |p, c| match c {
+            '\n' => Position {line: p.line + 1, column: 1, ..p},
+            _ => Position {column: p.column + 1, ..p}
         })
+    }
+}
+
+/// A part of an input from `start` to `end`, which does not include the latter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position
+}
+
+impl Span {
+    /// The text of the span in `program`, without the whitespace after it.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// # use toyjq::filter::*;
+    /// let program = "{a: 1} | .a.b ";
+    /// let e = Filter::parse_with_spans(program).unwrap().apply(&Json::JNull).unwrap_err();
+    /// let span = e.span().unwrap();
+    /// assert_eq!((span.start.line, span.start.column, span.fragment(program)), (1, 12, ".b"));
+    /// ```
+    pub fn fragment<'p>(&self, program: &'p str) -> &'p str {
+        program[self.start.offset..self.end.offset].trim_end()
+    }
+}
+
+/// Gives the current position without consuming anything.
+///
+/// ```
+/// # use toyjq::parsercombinator::*;
+/// let pos = string("a\nb").then(position()).parse("a\nbc").unwrap();
+/// assert_eq!(pos, Position {offset: 3, line: 2, column: 2});
+/// ```
+pub fn position<'a>() -> Parser<'a, Position> {
+    Parser(Box::new(move |input| {
+        Ok((input, Position::of(input.body, input.pos)))
     }))
 }
 
+/// Gives the current byte offset without consuming anything, which unlike `position` takes no time.
+///
+/// ```
+/// # use toyjq::parsercombinator::*;
+/// assert_eq!(string("ab").then(offset()).parse("abc").unwrap(), 2);
+/// ```
+pub fn offset<'a>() -> Parser<'a, usize> {
+    Parser(Box::new(move |input| {
+        Ok((input, input.pos))
+    }))
+}
+
+/// Makes a parser of `f`, which takes the whole input and the byte offset to start at, and
+/// gives the offset where it stops as `Parser::parse_at` does.
+///
+/// ```
+/// # use toyjq::parsercombinator::*;
+/// let p = from_fn(|input: &str, pos| Ok((input.len(), input[pos..].len())));
+/// assert_eq!(chr('a').then(p).parse("abcd").unwrap(), 3);
+/// ```
+pub fn from_fn<'a, T, F>(f: F) -> Parser<'a, T>
+    where F: Fn(&'a str, usize) -> Result<(usize, T), ParseError> + 'a
+{
+    Parser(Box::new(move |input| {
+        let (pos, v) = f(input.body, input.pos)?;
+        Ok((StrStream {pos, ..input}, v))
+    }))
+}
 
 /// Parses any string till the specified string appears.
 ///
@@ -137,24 +313,82 @@ pub fn failure<'a>(message: String) -> Parser<'a, ()> {
 /// ```
 pub fn until<'a>(s: &'a str) -> Parser<'a, &'a str> {
     Parser(Box::new(move |input| {
-        let initpos = input.pos;
-        let mut i = input;
-        while i.can_advance() {
-            let len = s.len();
-            if s == i.take(len) {
-                return Ok((i, &i.body[initpos..i.pos]))
-            } else {
-                i = i.advance(1);
+        let rest = input.current().as_bytes();
+        let needle = s.as_bytes();
+        if needle.is_empty() {
+            return Ok((input, ""))
+        }
+        let mut from = 0;
+        while let Some(i) = memchr(needle[0], &rest[from..]) {
+            let at = from + i;
+            if rest[at..].starts_with(needle) {
+                return Ok((input.advance(at), &input.body[input.pos..input.pos + at]))
             }
+            from = at + 1;
         }
-        Err(ParseError {
-            retry: true,
-            message: "Reaches end.".to_string(),
-            pos: input.pos
-        })
+        Err(input.error("Reaches end.".to_string()))
+    }))
+}
+
+/// Parses the longest run of characters, possibly empty, for which `pred` holds, without copying it.
+///
+/// ```
+/// # use toyjq::parsercombinator::*;
+/// assert_eq!(take_while(|c| c != '"').skip(chr('"')).parse("aあ\"").unwrap(), "aあ");
+/// assert_eq!(take_while(|c| c.is_ascii_digit()).parse("x").unwrap(), "");
+/// ```
+pub fn take_while<'a, F>(pred: F) -> Parser<'a, &'a str>
+    where F: Fn(char) -> bool + 'a
+{
+    Parser(Box::new(move |input| {
+        let rest = &input.body[input.pos..];
+        let len = rest.char_indices().find(|&(_, c)| !pred(c)).map_or(rest.len(), |(i, _)| i);
+        Ok((input.advance(len), &rest[..len]))
     }))
 }
 
+/// Finds the first `needle` in `haystack`.
+/// Skips a whole word at a time while no byte of the word can be `needle`.
+fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = LO * needle as usize;
+    find_byte(haystack, |word| has_zero(word ^ repeated), |b| b == needle)
+}
+
+/// Finds the first `"`, `\\` or control character below U+0020 in `haystack`, which ends the plain
+/// run of a JSON string, a word at a time as `memchr` does.
+pub(crate) fn find_string_end(haystack: &[u8]) -> Option<usize> {
+    const QUOTE: usize = LO * b'"' as usize;
+    const BACKSLASH: usize = LO * b'\\' as usize;
+    find_byte(haystack,
+        |word| has_zero(word ^ QUOTE) || has_zero(word ^ BACKSLASH) || word.wrapping_sub(LO * 0x20) & !word & HI != 0,
+        |b| b == b'"' || b == b'\\' || b < 0x20)
+}
+
+const LO: usize = usize::MAX / 255; // 0x0101..01
+const HI: usize = LO << 7;          // 0x8080..80
+
+/// Whether some byte of `x` is zero.
+fn has_zero(x: usize) -> bool {
+    x.wrapping_sub(LO) & !x & HI != 0
+}
+
+/// Finds the first byte for which `is` holds, skipping each word for which `may_have` does not.
+fn find_byte<W, B>(haystack: &[u8], may_have: W, is: B) -> Option<usize>
+    where W: Fn(usize) -> bool,
+          B: Fn(u8) -> bool
+{
+    use std::convert::TryInto;
+    use std::mem::size_of;
+    let mut i = 0;
+    while i + size_of::<usize>() <= haystack.len() {
+        let word = usize::from_ne_bytes(haystack[i..i + size_of::<usize>()].try_into().unwrap());
+        if may_have(word) {
+            break;
+        }
+        i += size_of::<usize>();
+    }
+    haystack[i..].iter().position(|&b| is(b)).map(|p| i + p)
+}
 
 /// Chains `or` opeartion
 ///
@@ -186,10 +420,22 @@ impl <'a, T> Parser<'a, T>
     /// input type will be &str or &String. (these implement Into<StrStream>)
     pub fn parse(&self, input: &'a str) -> Result<T, ParseError>
     {
-        let (_, v) = self.run(StrStream::new(input))?;
+        let (_, v) = self.run(StrStream::new(input)).map_err(|e| e.locate(input))?;
         Ok(v)
     }
 
+    /// Runs parser from the byte offset `pos` of `input`, giving the offset where it stops.
+    /// Unlike `parse`, errors are not given their line and column.
+    ///
+    /// ```
+    /// # use toyjq::parsercombinator::*;
+    /// assert_eq!(string("bc").parse_at("abcd", 1).unwrap(), (3, "bc"));
+    /// ```
+    pub fn parse_at(&self, input: &'a str, pos: usize) -> Result<(usize, T), ParseError> {
+        let (rest, v) = self.run(StrStream {body: input, pos})?;
+        Ok((rest.pos, v))
+    }
+
     /// ```
     /// # use toyjq::parsercombinator::*;
     /// assert_eq!(unit(42).map(|x|x+1).parse("").unwrap(), 43);
@@ -219,6 +465,29 @@ impl <'a, T> Parser<'a, T>
         }))
     }
 
+    /// Like `map`, but `f` may fail with the byte offset in the input where the error is and
+    /// a message.
+    ///
+    /// ```
+    /// # use toyjq::parsercombinator::*;
+    /// let p = chr('#').then(take_while(|c| c.is_ascii_digit())).try_map(|s| s.parse::<u8>().map_err(|e| (1, e.to_string())));
+    /// assert_eq!(p.parse("#42").unwrap(), 42);
+    /// let e = p.parse("#420").unwrap_err();
+    /// assert_eq!((e.pos, e.message.as_str()), (1, "number too large to fit in target type"));
+    /// ```
+    pub fn try_map<F, U>(self, f: F) -> Parser<'a, U>
+        where F: Fn(T) -> Result<U, (usize, String)> + 'a,
+              U: 'a
+    {
+        Parser(Box::new(move |input| {
+            let (input2, x) = self.run(input)?;
+            match f(x) {
+                Ok(y) => Ok((input2, y)),
+                Err((pos, message)) => Err(ParseError {retry: input.pos == input2.pos, message, pos, line: 0, column: 0})
+            }
+        }))
+    }
+
     /// ```
     /// # use toyjq::parsercombinator::*;
     /// assert_eq!(unit('f').flat_map(chr).parse("foo").unwrap(), 'f');
@@ -230,9 +499,7 @@ impl <'a, T> Parser<'a, T>
         Parser(Box::new(move |input| {
             let (input2, o) = self.run(input)?;
             let retry = input.pos == input2.pos;
-            f(o).run(input2).map_err(|ParseError {retry: _, message, pos}| {
-                ParseError {retry, message, pos}
-            })
+            f(o).run(input2).map_err(|e| ParseError {retry, ..e})
         }))
     }
 
@@ -249,9 +516,7 @@ impl <'a, T> Parser<'a, T>
         Parser(Box::new(move |input| {
             let (input2, _) = self.run(input)?;
             let retry = input.pos == input2.pos;
-            p.run(input2).map_err(|ParseError {retry: _, message, pos}| {
-                ParseError {retry, message, pos}
-            })
+            p.run(input2).map_err(|e| ParseError {retry, ..e})
         }))
     }
 
@@ -280,9 +545,7 @@ impl <'a, T> Parser<'a, T>
                 Ok((input2, v)) => {
                     let retry = input.pos == input2.pos;
                     p.run(input2).map(|(input3, _)| (input3, v))
-                        .map_err(|ParseError{retry: _, message, pos}| {
-                            ParseError {retry, message, pos}
-                        })
+                        .map_err(|e| ParseError {retry, ..e})
                 },
                 Err(e) => Err(e)
             }
@@ -302,9 +565,7 @@ impl <'a, T> Parser<'a, T>
         Parser(Box::new(move |input| {
             let (input2, o) = self.run(input)?;
             let retry = input.pos == input2.pos;
-            let (input3, o2) = p.run(input2).map_err(|ParseError{retry: _, message, pos}| {
-                ParseError {retry, message, pos}
-            })?;
+            let (input3, o2) = p.run(input2).map_err(|e| ParseError {retry, ..e})?;
             Ok((input3, (o, o2)))
         }))
     }
@@ -323,9 +584,7 @@ impl <'a, T> Parser<'a, T>
         Parser(Box::new(move |input| {
             let (input2, o) = self.run(input)?;
             let retry = input.pos == input2.pos;
-            let (input3, o2) = f().run(input2).map_err(|ParseError{retry: _, message, pos}| {
-                ParseError {retry, message, pos}
-            })?;
+            let (input3, o2) = f().run(input2).map_err(|e| ParseError {retry, ..e})?;
             Ok((input3, (o, o2)))
         }))
     }
@@ -391,9 +650,7 @@ impl <'a, T> Parser<'a, T>
     /// ```
     pub fn try(self) -> Parser<'a, T> {
         Parser(Box::new(move |input| {
-            self.run(input).map_err(|ParseError {message, ..}| {
-                ParseError {retry: true, message, pos: input.pos}
-            })
+            self.run(input).map_err(|e| ParseError {retry: true, pos: input.pos, ..e})
         }))
     }
 
@@ -461,6 +718,38 @@ impl <'a, T> Parser<'a, T>
         }))
     }
 
+    /// Like `sep_by`, but the last phrase may be followed by a delimiter.
+    ///
+    /// ```
+    /// # use toyjq::parsercombinator::*;
+    /// assert_eq!(string("foo").sep_end_by(chr(',')).parse("foo,foo,").unwrap(), vec!["foo", "foo"]);
+    /// assert_eq!(string("foo").sep_end_by(chr(',')).skip(eof()).parse(",").unwrap_err().pos, 0);
+    /// ```
+    pub fn sep_end_by<O2>(self, delim: Parser<'a, O2>) -> Parser<'a, Vec<T>>
+        where O2: 'a
+    {
+        Parser(Box::new(move |input| {
+            let mut v = vec![];
+            let mut i = input;
+            loop {
+                match self.run(i) {
+                    Ok((input2, o)) => {
+                        v.push(o);
+                        i = input2;
+                    },
+                    Err(ParseError {retry: true, ..}) => break,
+                    Err(e) => return Err(e)
+                }
+                match delim.run(i) {
+                    Ok((input3, _)) => i = input3,
+                    Err(ParseError {retry: true, ..}) => break,
+                    Err(e) => return Err(e)
+                }
+            }
+            Ok((i, v))
+        }))
+    }
+
     pub fn with_spaces(self) -> Self {
         let ws = or_from(" \n\t".chars().map(chr));
         let ws2 = or_from(" \n\t".chars().map(chr));
@@ -492,7 +781,7 @@ mod tests {
                 for c in tail {
                     st.push(c)
                 }
-                i32::from_str_radix(st.as_str(), 10).unwrap()
+                st.as_str().parse::<i32>().unwrap()
             })
         )
     }
@@ -513,7 +802,47 @@ mod tests {
     }
 
     fn parse_expr<'a>() -> Parser<'a, Expr> {
-        parse_add().try().or_lazy(||parse_num())
+        parse_add().try().or_lazy(parse_num)
+    }
+
+    #[test]
+    fn test_memchr() {
+        let haystack = "0123456789abcdefghijklmnopqrstuvwxyz".as_bytes();
+        for (i, &b) in haystack.iter().enumerate() {
+            assert_eq!(memchr(b, haystack), Some(i));
+        }
+        assert_eq!(memchr(b'!', haystack), None);
+        assert_eq!(memchr(0x80, &[0x7f, 0, 0xff, 1, 0x80]), Some(4));
+    }
+
+    #[test]
+    fn test_find_string_end() {
+        assert_eq!(find_string_end(b"abcdefghijklmnop\"q"), Some(16));
+        assert_eq!(find_string_end(b"abcdefgh\\"), Some(8));
+        assert_eq!(find_string_end("\u{3042}\u{3044}\u{3046}\n".as_bytes()), Some(9));
+        assert_eq!(find_string_end(&[0x7f, 0x20, 0xff, 0x80, 0x21, 0x5b, 0x5d, 0x23, 0x1f]), Some(8));
+        assert_eq!(find_string_end(b"plain text"), None);
+    }
+
+    #[test]
+    fn test_until() {
+        let long = format!("{}\u{3042}\"", "x".repeat(100));
+        assert_eq!(until("\"").parse(long.as_str()).unwrap(), &long[..long.len() - 1]);
+        assert_eq!(until("ab").parse("aaaaaaaaaaaab").unwrap(), "aaaaaaaaaaa");
+        assert_eq!(until("!").parse("no bang here").unwrap_err().pos, 0);
+        assert_eq!(chr('x').then(until("y")).parse("xabcy").unwrap(), "abc");
+    }
+
+    #[test]
+    fn test_multibyte() {
+        assert_eq!(string("ab").parse("a\u{e9}").unwrap_err().message, "Expected `ab` but actual is `a`.");
+        assert_eq!(string("abc").parse("a\u{e9}").unwrap_err().message, "Expected `abc` but actual is `a\u{e9}`.");
+        assert_eq!(chr('a').parse("\u{3042}").unwrap_err().message, "Expected `a` but actual is `\u{3042}`.");
+        assert_eq!(satisfy(|c| c != 'x').many().parse("\u{1F600}\u{e9}x").unwrap(), vec!['\u{1F600}', '\u{e9}']);
+        // An offset inside a character stands for the character.
+        let e = ParseError {retry: false, message: String::new(), pos: 2, line: 0, column: 0};
+        assert_eq!(e.snippet("a\u{e9}b"), "a\u{e9}b\n ^");
+        assert_eq!(e.locate("a\u{e9}b").column, 2);
     }
 
     #[test]
@@ -1,14 +1,290 @@
+use std::prelude::v1::*;
+use std::slice;
+use super::color::*;
+
 pub enum DocElem {
     Literal(&'static str),
     Text(String),
     Newline(i32),
-    Flatable(Vec<DocElem>)
+    /// Like `Newline` but vanishes instead of becoming a space when flattened.
+    Softline(i32),
+    /// Spaces which are emitted only when the enclosing `Flatable` is broken into lines.
+    PadWhenBroken(i32),
+    Flatable(Vec<DocElem>),
+    /// Like `Flatable` but when it does not fit, each `Newline` directly inside breaks only if
+    /// the elements up to the next one do not fit in the rest of the line.
+    Fill(Vec<DocElem>),
+    /// Zero-width text such as terminal escape sequences. It is emitted as is and never
+    /// counted against the width.
+    Markup(String),
+    /// Marks what the inner element stands for, so renderers can style it.
+    Annotated(Tag, Box<DocElem>)
+}
+
+/// The meaning of an annotated region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    NullValue,
+    FalseValue,
+    TrueValue,
+    NumberValue,
+    StringValue,
+    ObjectKey,
+    /// Brackets and commas of an array.
+    ArrayPunctuation,
+    /// Braces, colons and commas of an object.
+    ObjectPunctuation
+}
+
+impl Tag {
+    /// The CSS class used by `Doc::render_html`.
+    pub fn html_class(self) -> &'static str {
+        match self {
+            Tag::NullValue => "toyjq-null",
+            Tag::FalseValue => "toyjq-false",
+            Tag::TrueValue => "toyjq-true",
+            Tag::NumberValue => "toyjq-number",
+            Tag::StringValue => "toyjq-string",
+            Tag::ObjectKey => "toyjq-key",
+            Tag::ArrayPunctuation => "toyjq-array",
+            Tag::ObjectPunctuation => "toyjq-object"
+        }
+    }
 }
 
 pub fn literal(s: &'static str) -> DocElem {DocElem::Literal(s)}
 pub fn text(s: String) -> DocElem {DocElem::Text(s)}
 pub fn newline(indent: i32) -> DocElem{DocElem::Newline(indent)}
+pub fn softline(indent: i32) -> DocElem{DocElem::Softline(indent)}
+pub fn pad_when_broken(n: i32) -> DocElem{DocElem::PadWhenBroken(n)}
 pub fn flatable(ds: Vec<DocElem>) -> DocElem{DocElem::Flatable(ds)}
+pub fn fill(ds: Vec<DocElem>) -> DocElem{DocElem::Fill(ds)}
+pub fn markup(s: String) -> DocElem{DocElem::Markup(s)}
+pub fn annotated(tag: Tag, d: DocElem) -> DocElem{DocElem::Annotated(tag, Box::new(d))}
+
+/// A width with which every `Flatable` is flattened.
+pub const UNLIMITED_WIDTH: i32 = i32::MAX;
+
+
+/// How a document is laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintStyle {
+    /// Columns per level of indentation. `Newline` deltas are counted in columns too.
+    pub indent_width: i32,
+    /// Every `indent_width` columns of indentation are emitted as a single tab.
+    pub use_tabs: bool,
+    /// Packs as many items of a long array of scalars as fit on each line.
+    pub fill_arrays: bool,
+    /// Containers nested in this many or more containers are collapsed into `{...}` or
+    /// `[... N items]`.
+    pub max_depth: Option<usize>,
+    /// Emits object members in key order instead of the input order.
+    pub sort_keys: bool,
+    /// Which characters in strings are escaped.
+    pub escape: EscapeOptions,
+    /// How numbers are written.
+    pub number_format: NumberFormat,
+    /// How line breaks are written.
+    pub line_ending: LineEnding,
+    /// Ends the output with a line break.
+    pub trailing_newline: bool,
+    /// Pads the inside of flattened brackets like `[ 1, 2 ]` instead of `[1, 2]`.
+    pub bracket_spacing: bool,
+    /// Lines up the values of an object broken into lines.
+    pub align_values: bool
+}
+
+impl Default for PrintStyle {
+    fn default() -> PrintStyle {
+        PrintStyle {
+            indent_width: 2,
+            use_tabs: false,
+            fill_arrays: false,
+            max_depth: None,
+            sort_keys: false,
+            escape: EscapeOptions::default(),
+            number_format: NumberFormat::default(),
+            line_ending: LineEnding::Lf,
+            trailing_newline: false,
+            bracket_spacing: true,
+            align_values: false
+        }
+    }
+}
+
+impl PrintStyle {
+    /// Pushes `indent` columns of indentation.
+    pub fn push_indent(&self, indent: i32, ret: &mut String) {
+        if self.use_tabs && self.indent_width > 0 {
+            for _ in 0..indent / self.indent_width {ret.push('\t')}
+            for _ in 0..indent % self.indent_width {ret.push(' ')}
+        } else {
+            for _ in 0..indent {ret.push(' ')}
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberNotation {
+    /// `30000`, `0.0001`
+    Plain,
+    /// `3e4`, `1e-4`
+    Exponent,
+    /// Plain unless the magnitude is at least 1e17 or less than 1e-5 (as jq does).
+    Auto
+}
+
+/// How NaN and the infinities are written, which JSON has no numbers for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonFinite {
+    /// NaN as `null` and the infinities as the largest finite numbers, as jq does.
+    #[default]
+    Clamp,
+    /// `null` for all of them.
+    Null,
+    /// `NaN`, `Infinity` and `-Infinity`, as Python and JavaScript write them, which is not JSON.
+    Literal
+}
+
+/// How numbers are written.
+/// `precision` is the maximum number of fractional digits (of the mantissa in exponent notation).
+/// Trailing zeros are always dropped, so integral values never end with `.0`.
+/// `non_finite` tells how NaN and the infinities are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub precision: Option<usize>,
+    pub notation: NumberNotation,
+    pub non_finite: NonFinite
+}
+
+impl Default for NumberFormat {
+    fn default() -> NumberFormat {
+        NumberFormat {precision: None, notation: NumberNotation::Plain, non_finite: NonFinite::Clamp}
+    }
+}
+
+impl NumberFormat {
+    /// ```
+    /// # use toyjq::prettyprinter::*;
+    /// let exp = NumberFormat {precision: Some(2), notation: NumberNotation::Exponent, ..NumberFormat::default()};
+    /// assert_eq!(exp.format(30000.0), "3e4");
+    /// assert_eq!(exp.format(-0.0123456), "-1.23e-2");
+    /// let plain = NumberFormat {precision: Some(3), notation: NumberNotation::Plain, ..NumberFormat::default()};
+    /// assert_eq!(plain.format(3.0), "3");
+    /// assert_eq!(plain.format(3.14159), "3.142");
+    /// ```
+    ///
+    /// By default, like jq, NaN is written as `null` and the infinities as the largest finite numbers.
+    ///
+    /// ```
+    /// # use toyjq::prettyprinter::*;
+    /// assert_eq!(NumberFormat::default().format(f64::NAN), "null");
+    /// assert_eq!(NumberFormat::default().format(f64::NEG_INFINITY), "-1.7976931348623157e308");
+    /// let literal = NumberFormat {non_finite: NonFinite::Literal, ..NumberFormat::default()};
+    /// assert_eq!(literal.format(f64::NEG_INFINITY), "-Infinity");
+    /// ```
+    pub fn format(&self, v: f64) -> String {
+        if !v.is_finite() {
+            return match (self.non_finite, v.is_nan()) {
+                (NonFinite::Null, _) | (NonFinite::Clamp, true) => "null".to_string(),
+                (NonFinite::Clamp, false) => format!("{:e}", if v > 0.0 { f64::MAX } else { f64::MIN }),
+                (NonFinite::Literal, true) => "NaN".to_string(),
+                (NonFinite::Literal, false) => if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+            }
+        }
+        let exponent = match self.notation {
+            NumberNotation::Plain => false,
+            NumberNotation::Exponent => true,
+            NumberNotation::Auto => v != 0.0 && (v.abs() >= 1e17 || v.abs() < 1e-5)
+        };
+        match (exponent, self.precision) {
+            (false, None) => format!("{}", v),
+            (false, Some(p)) => trim_fraction(&format!("{:.*}", p, v)).to_string(),
+            (true, None) => format!("{:e}", v),
+            (true, Some(p)) => {
+                let s = format!("{:.*e}", p, v);
+                let epos = s.find('e').unwrap();
+                format!("{}{}", trim_fraction(&s[..epos]), &s[epos..])
+            }
+        }
+    }
+}
+
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+/// Which characters are escaped when a string is quoted.
+/// `"`, `\\` and control characters are always escaped.
+/// `ascii` escapes every non-ASCII character as `\uXXXX` (as a surrogate pair if needed),
+/// `slash` escapes `/` as `\/` and `html` escapes `<`, `>`, `&` and `'`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EscapeOptions {
+    pub ascii: bool,
+    pub slash: bool,
+    pub html: bool
+}
+
+impl EscapeOptions {
+    /// Makes a JSON string literal.
+    ///
+    /// ```
+    /// # use toyjq::prettyprinter::*;
+    /// let opts = EscapeOptions::default();
+    /// assert_eq!(opts.quote("say \"hi\"\n"), r#""say \"hi\"\n""#);
+    /// let opts = EscapeOptions {ascii: true, slash: true, html: true};
+    /// assert_eq!(opts.quote("</\u{e9}>"), r#""\u003c\/\u00e9\u003e""#);
+    /// ```
+    pub fn quote(&self, s: &str) -> String {
+        let mut ret = String::with_capacity(s.len() + 2);
+        ret.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => ret.push_str("\\\""),
+                '\\' => ret.push_str("\\\\"),
+                '\n' => ret.push_str("\\n"),
+                '\r' => ret.push_str("\\r"),
+                '\t' => ret.push_str("\\t"),
+                '\u{8}' => ret.push_str("\\b"),
+                '\u{c}' => ret.push_str("\\f"),
+                '/' if self.slash => ret.push_str("\\/"),
+                '<' | '>' | '&' | '\'' if self.html => push_unicode_escape(c, &mut ret),
+                _ if c.is_ascii_control() => push_unicode_escape(c, &mut ret),
+                _ if self.ascii && !c.is_ascii() => push_unicode_escape(c, &mut ret),
+                _ => ret.push(c)
+            }
+        }
+        ret.push('"');
+        ret
+    }
+}
+
+fn push_unicode_escape(c: char, ret: &mut String) {
+    let mut units = [0; 2];
+    for u in c.encode_utf16(&mut units) {
+        ret.push_str(&format!("\\u{:04x}", u));
+    }
+}
 
 
 pub struct Doc(Vec<DocElem>);
@@ -16,71 +292,208 @@ pub struct Doc(Vec<DocElem>);
 impl Doc {
     pub fn new(x: Vec<DocElem>) -> Doc {Doc(x)}
 
+    /// The width of the document when it is printed in a single line.
+    pub fn flat_width(&self) -> i32 {
+        flat_doc_width(&self.0)
+    }
+
+    /// Whether the whole document fits in a single line of `width` columns.
+    pub fn fits(&self, width: i32) -> bool {
+        self.flat_width() <= width
+    }
+
+    /// The number of lines of the document printed with `width`.
+    pub fn height(&self, width: i32) -> usize {
+        self.pretty(width).matches('\n').count() + 1
+    }
+
     pub fn pretty(&self, width: i32) -> String {
-        fn pretty_walk(ds: &Vec<DocElem>, width: i32, rest_width: &mut i32, indent: &mut i32, ret: &mut String) {
+        self.pretty_with_style(width, &PrintStyle::default())
+    }
+
+    /// Renders the document, ignoring annotations.
+    pub fn pretty_with_style(&self, width: i32, style: &PrintStyle) -> String {
+        self.render(width, &Renderer {style, highlight: Highlight::Plain})
+    }
+
+    /// Like `pretty_with_style` but colors each annotated region with ANSI escape sequences.
+    pub fn render_ansi(&self, width: i32, style: &PrintStyle, theme: &Theme) -> String {
+        self.render(width, &Renderer {style, highlight: Highlight::Ansi(theme)})
+    }
+
+    /// Like `pretty_with_style` but HTML-escapes every `Literal` and `Text` and wraps each
+    /// annotated region by a `<span>` whose class is `Tag::html_class`.
+    /// `Markup` is emitted as is.
+    pub fn render_html(&self, width: i32, style: &PrintStyle) -> String {
+        self.render(width, &Renderer {style, highlight: Highlight::Html})
+    }
+
+    fn render(&self, width: i32, r: &Renderer) -> String {
+        fn pretty_walk(ds: &[DocElem], width: i32, r: &Renderer, rest_width: &mut i32, indent: &mut i32, ret: &mut String) {
             for d in ds {
                 match *d {
-                    DocElem::Literal(ref s) => {
+                    DocElem::Literal(s) => {
                         // println!("literal {} (rest_width: {}", s, rest_width);
                         *rest_width -= s.len() as i32;
-                        ret.push_str(s);
+                        r.push_text(s, ret);
                     }
                     DocElem::Text(ref s) => {
                         // println!("text {} (rest_width: {}", s, rest_width);
                         *rest_width -= s.len() as i32;
-                        ret.push_str(s.as_str());
+                        r.push_text(s.as_str(), ret);
+                    },
+                    DocElem::PadWhenBroken(n) => {
+                        *rest_width -= n;
+                        for _ in 0..n {ret.push(' ')}
                     },
-                    DocElem::Newline(i) => {
+                    DocElem::Newline(i) | DocElem::Softline(i) => {
                         // println!("newline {} (rest_width: {}", i, rest_width);
                         *indent += i;
                         *rest_width = width - *indent;
-                        ret.push('\n');
-                        for _ in 0..*indent {ret.push(' ')}
+                        ret.push_str(r.style.line_ending.as_str());
+                        r.style.push_indent(*indent, ret);
                     },
                     DocElem::Flatable(ref ds2) => {
                         // println!("flat: ({} <= {}) `{}`", flat_doc_width(&ds2), rest_width, flatten_print(&ds2));
-                        if flat_doc_width(&ds2) <= *rest_width {
-                            let fstr = flatten_print(&ds2);
+                        let flat_width = flat_doc_width(ds2);
+                        if flat_width <= *rest_width {
+                            let fstr = flatten_print(ds2, r);
                             ret.push_str(fstr.as_str());
-                            *rest_width -= ret.len() as i32;
+                            *rest_width -= flat_width;
                         } else {
-                            pretty_walk(&ds2, width, rest_width, indent, ret)
+                            pretty_walk(ds2, width, r, rest_width, indent, ret)
                         }
+                    },
+                    DocElem::Fill(ref ds2) => {
+                        let flat_width = flat_doc_width(ds2);
+                        if flat_width <= *rest_width {
+                            let fstr = flatten_print(ds2, r);
+                            ret.push_str(fstr.as_str());
+                            *rest_width -= flat_width;
+                        } else {
+                            for (n, d2) in ds2.iter().enumerate() {
+                                match *d2 {
+                                    DocElem::Newline(i) => {
+                                        let rest = &ds2[n + 1..];
+                                        let chunk_len = rest.iter().position(|d| matches!(*d, DocElem::Newline(_))).unwrap_or(rest.len());
+                                        if i == 0 && flat_doc_width(&rest[..chunk_len]) < *rest_width {
+                                            ret.push(' ');
+                                            *rest_width -= 1;
+                                        } else {
+                                            pretty_walk(slice::from_ref(d2), width, r, rest_width, indent, ret)
+                                        }
+                                    },
+                                    _ => pretty_walk(slice::from_ref(d2), width, r, rest_width, indent, ret)
+                                }
+                            }
+                        }
+                    },
+                    DocElem::Markup(ref s) => ret.push_str(s),
+                    DocElem::Annotated(tag, ref d2) => {
+                        r.open(tag, ret);
+                        pretty_walk(slice::from_ref(&**d2), width, r, rest_width, indent, ret);
+                        r.close(ret);
                     }
                 }
             }
         }
         let mut ret = String::new();
-        pretty_walk(&self.0, width, &mut width.clone(), &mut 0, &mut ret);
+        pretty_walk(&self.0, width, r, &mut width.clone(), &mut 0, &mut ret);
+        if r.style.trailing_newline {
+            ret.push_str(r.style.line_ending.as_str());
+        }
         ret
     }
 }
 
-fn flatten_print(vdocs: &Vec<DocElem>) -> String {
-    fn flatten_walk(ds: &Vec<DocElem>, ret: &mut String) {
+enum Highlight<'t> {
+    Plain,
+    Ansi(&'t Theme),
+    Html
+}
+
+struct Renderer<'r> {
+    style: &'r PrintStyle,
+    highlight: Highlight<'r>
+}
+
+impl <'r> Renderer<'r> {
+    fn push_text(&self, s: &str, ret: &mut String) {
+        match self.highlight {
+            Highlight::Html => push_html_escaped(s, ret),
+            _ => ret.push_str(s)
+        }
+    }
+
+    fn open(&self, tag: Tag, ret: &mut String) {
+        match self.highlight {
+            Highlight::Plain => (),
+            Highlight::Ansi(theme) => ret.push_str(&theme.color(tag).escape()),
+            Highlight::Html => {
+                ret.push_str("<span class=\"");
+                ret.push_str(tag.html_class());
+                ret.push_str("\">");
+            }
+        }
+    }
+
+    fn close(&self, ret: &mut String) {
+        match self.highlight {
+            Highlight::Plain => (),
+            Highlight::Ansi(_) => ret.push_str(RESET),
+            Highlight::Html => ret.push_str("</span>")
+        }
+    }
+}
+
+fn push_html_escaped(s: &str, ret: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            _ => ret.push(c)
+        }
+    }
+}
+
+fn flatten_print(vdocs: &[DocElem], r: &Renderer) -> String {
+    fn flatten_walk(ds: &[DocElem], r: &Renderer, ret: &mut String) {
         for d in ds {
             match *d {
-                DocElem::Literal(ref s) => ret.push_str(s),
-                DocElem::Text(ref s) => ret.push_str(s.as_ref()),
+                DocElem::Literal(s) => r.push_text(s, ret),
+                DocElem::Text(ref s) => r.push_text(s.as_ref(), ret),
                 DocElem::Newline(_) => ret.push(' '),
-                DocElem::Flatable(ref ds2) => flatten_walk(&ds2, ret)
+                DocElem::Softline(_) | DocElem::PadWhenBroken(_) => (),
+                DocElem::Flatable(ref ds2) | DocElem::Fill(ref ds2) => flatten_walk(ds2, r, ret),
+                DocElem::Markup(ref s) => ret.push_str(s),
+                DocElem::Annotated(tag, ref d2) => {
+                    r.open(tag, ret);
+                    flatten_walk(slice::from_ref(&**d2), r, ret);
+                    r.close(ret);
+                }
             }
         }
     }
     let mut ret = String::new();
-    flatten_walk(vdocs, &mut ret);
+    flatten_walk(vdocs, r, &mut ret);
     ret
 }
 
-fn flat_doc_width(vdocs: &Vec<DocElem>) -> i32 {
-    fn flat_doc_width_walk(vdocs: &Vec<DocElem>) -> i32{
+fn flat_doc_width(vdocs: &[DocElem]) -> i32 {
+    fn flat_doc_width_walk(vdocs: &[DocElem]) -> i32{
         let mut sum = 0;
         for d in vdocs.iter() {
             match *d {
-                DocElem::Literal(ref s) => sum += s.len() as i32,
+                DocElem::Literal(s) => sum += s.len() as i32,
                 DocElem::Text(ref s) => sum += s.len() as i32,
                 DocElem::Newline(_) => sum += 1,
-                DocElem::Flatable(ref ds) => sum += flat_doc_width_walk(&ds)
+                DocElem::Softline(_) | DocElem::PadWhenBroken(_) => (),
+                DocElem::Flatable(ref ds) | DocElem::Fill(ref ds) => sum += flat_doc_width_walk(ds),
+                DocElem::Markup(_) => (),
+                DocElem::Annotated(_, ref d) => sum += flat_doc_width_walk(slice::from_ref(&**d))
             }
         }
         sum
@@ -98,6 +511,41 @@ mod tests {
         assert_eq!(doc.pretty(0), "\n \n  \n   ")
     }
 
+    #[test]
+    fn test_tab_indent() {
+        let doc = Doc::new(vec![newline(4), newline(2), newline(-6)]);
+        let style = PrintStyle {indent_width: 4, use_tabs: true, ..PrintStyle::default()};
+        assert_eq!(doc.pretty_with_style(0, &style), "\n\t\n\t  \n")
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let doc = Doc::new(vec![literal("a"), newline(2), literal("b")]);
+        let style = PrintStyle {line_ending: LineEnding::CrLf, trailing_newline: true, ..PrintStyle::default()};
+        assert_eq!(doc.pretty_with_style(0, &style), "a\r\n  b\r\n");
+        let style = PrintStyle {trailing_newline: true, ..PrintStyle::default()};
+        assert_eq!(doc.pretty_with_style(0, &style), "a\n  b\n");
+    }
+
+    #[test]
+    fn test_softline() {
+        let doc = Doc::new(vec![flatable(vec![literal("("), softline(2), literal("a"), newline(0), literal("b"), softline(-2), literal(")")])]);
+        assert_eq!(doc.pretty(5), "(a b)");
+        assert_eq!(doc.pretty(4), "(\n  a\n  b\n)");
+    }
+
+    #[test]
+    fn test_measurement() {
+        let doc = Doc::new(vec![flatable(vec![
+            literal("["), newline(2), literal("1,"), newline(0), markup("\x1b[1m".to_string()), literal("2"), newline(-2), literal("]")
+        ])]);
+        assert_eq!(doc.flat_width(), 8);
+        assert!(doc.fits(8));
+        assert!(!doc.fits(7));
+        assert_eq!(doc.height(8), 1);
+        assert_eq!(doc.height(7), 4);
+    }
+
     #[test]
     fn test_pretty() {
         let doc = Doc::new(vec![flatable(vec![
@@ -142,4 +590,37 @@ END"#.to_string()
         //     "foo bar,\n  1 2 3 4".to_string()
         // }
     }
+
+    #[test]
+    fn test_fill() {
+        let doc = Doc::new(vec![flatable(vec![
+            literal("["), newline(2),
+            fill(vec![
+                literal("1,"), newline(0), literal("22,"), newline(0), literal("333,"), newline(0), literal("4")
+            ]),
+            newline(-2), literal("]")
+        ])]);
+        assert_eq!(doc.pretty(18), "[ 1, 22, 333, 4 ]");
+        assert_eq!(doc.pretty(9), "[\n  1, 22,\n  333, 4\n]");
+        assert_eq!(doc.pretty(0), "[\n  1,\n  22,\n  333,\n  4\n]");
+    }
+
+    #[test]
+    fn test_render_html() {
+        let doc = Doc::new(vec![flatable(vec![
+            markup("<b>".to_string()), literal("a < b"), markup("</b>".to_string()), newline(2),
+            annotated(Tag::StringValue, text("\"&\"".to_string()))
+        ])]);
+        assert_eq!(doc.render_html(9, &PrintStyle::default()), "<b>a &lt; b</b> <span class=\"toyjq-string\">&quot;&amp;&quot;</span>");
+        assert_eq!(doc.render_html(8, &PrintStyle::default()), "<b>a &lt; b</b>\n  <span class=\"toyjq-string\">&quot;&amp;&quot;</span>");
+        assert_eq!(doc.pretty(9), "<b>a < b</b> \"&\"");
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let doc = Doc::new(vec![annotated(Tag::ObjectKey, flatable(vec![literal("a"), newline(0), literal("b")]))]);
+        let theme = Theme {key: Color::new("34"), ..Theme::default()};
+        assert_eq!(doc.render_ansi(3, &PrintStyle::default(), &theme), "\x1b[34ma b\x1b[0m");
+        assert_eq!(doc.render_ansi(2, &PrintStyle::default(), &theme), "\x1b[34ma\nb\x1b[0m");
+    }
 }
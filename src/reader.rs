@@ -0,0 +1,688 @@
+use std::prelude::v1::*;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::str;
+
+use super::event::{Event, Machine};
+use super::json::*;
+use super::parsercombinator::*;
+
+/// A failure to read JSON, either of the reader or of the text it gives.
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Parse(ParseError)
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "{}", e),
+            ReadError::Parse(ref e) => write!(f, "{} at line {}, column {}", e.message, e.line, e.column)
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> ReadError {
+        ReadError::Io(e)
+    }
+}
+
+impl Json<'static> {
+    /// Parses the value which `reader` gives, with whitespace around it if any.
+    /// Only the text of the value is held in memory, not what comes before or after it.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// let json = Json::from_reader(" [1, \"a\"]\n".as_bytes()).unwrap();
+    /// assert_eq!(json, Json::JArray(vec![Json::JNumber(1.0.into()), Json::JString("a".into())]));
+    /// assert!(Json::from_reader("[1] 2".as_bytes()).is_err());
+    /// ```
+    pub fn from_reader<R: Read>(reader: R) -> Result<Json<'static>, ReadError> {
+        Json::from_reader_with(reader, &ParserOptions::default())
+    }
+
+    /// Like `from_reader`, but parses as `opts` tells.
+    ///
+    /// ```
+    /// # use toyjq::*;
+    /// let json = Json::from_reader_with("[1, /* ] */ 2,] // end".as_bytes(), &ParserOptions::lenient()).unwrap();
+    /// assert_eq!(json, Json::from_str("[1, 2]").unwrap());
+    /// ```
+    pub fn from_reader_with<R: Read>(reader: R, opts: &ParserOptions) -> Result<Json<'static>, ReadError> {
+        let mut values = JsonReader::with_options(reader, opts);
+        let json = match values.next() {
+            Some(result) => result?,
+            None => return Err(values.error("Reaches end.".to_string(), values.position))
+        };
+        match values.next() {
+            None => Ok(json),
+            Some(Err(e)) => Err(e),
+            Some(Ok(_)) => Err(values.error("Expected end of input.".to_string(), values.start))
+        }
+    }
+}
+
+/// Parses the values of a reader one by one, separated by whitespace if any, holding only the
+/// text of the value being parsed. It gives the first error and then ends.
+///
+/// ```
+/// # use toyjq::*;
+/// # use toyjq::reader::*;
+/// let values: Vec<_> = JsonReader::new("1 {\"a\": [true]}\n\"b\"".as_bytes()).map(Result::unwrap).collect();
+/// assert_eq!(values.len(), 3);
+/// assert_eq!(values[2], Json::JString("b".into()));
+/// ```
+pub struct JsonReader<R: Read> {
+    reader: BufReader<R>,
+    opts: ParserOptions,
+    /// Where the value being read starts
+    start: Position,
+    /// Where the text read so far ends
+    position: Position,
+    done: bool
+}
+
+impl <R: Read> JsonReader<R> {
+    pub fn new(reader: R) -> JsonReader<R> {
+        JsonReader::with_options(reader, &ParserOptions::default())
+    }
+
+    /// Like `new`, but parses as `opts` tells.
+    pub fn with_options(reader: R, opts: &ParserOptions) -> JsonReader<R> {
+        let origin = Position {offset: 0, line: 1, column: 1};
+        JsonReader {reader: BufReader::new(reader), opts: *opts, start: origin, position: origin, done: false}
+    }
+
+    fn error(&self, message: String, at: Position) -> ReadError {
+        ReadError::Parse(ParseError {retry: false, message, pos: at.offset, line: at.line, column: at.column})
+    }
+
+    fn skip_spaces(&mut self) -> io::Result<()> {
+        loop {
+            let (n, end) = {
+                let buf = self.reader.fill_buf()?;
+                let n = buf.iter().take_while(|&&b| b == b' ' || b == b'\t' || b == b'\n' || b == b'\r').count();
+                self.position = self.position.advance(str::from_utf8(&buf[..n]).unwrap());
+                (n, buf.is_empty() || n < buf.len())
+            };
+            self.reader.consume(n);
+            if end {
+                return Ok(())
+            }
+        }
+    }
+
+    /// The text of the next value, which is cut at the end of an array, an object or a string,
+    /// or before whitespace or punctuation for the others. Empty at the end of the reader.
+    fn read_text(&mut self) -> io::Result<Vec<u8>> {
+        let mut text = vec![];
+        // Only the first value may follow a byte order mark.
+        let mut nesting = Nesting {mark: self.opts.bom && self.start.offset == 0, comments: self.opts.comments, ..Nesting::default()};
+        loop {
+            let (used, complete) = {
+                let buf = self.reader.fill_buf()?;
+                if buf.is_empty() {
+                    return Ok(text)
+                }
+                let mut used = 0;
+                let mut complete = false;
+                for &b in buf {
+                    match nesting.feed(b) {
+                        Step::Take => used += 1,
+                        Step::TakeLast => {
+                            used += 1;
+                            complete = true;
+                            break
+                        },
+                        Step::Stop => {
+                            complete = true;
+                            break
+                        }
+                    }
+                }
+                text.extend_from_slice(&buf[..used]);
+                (used, complete)
+            };
+            self.reader.consume(used);
+            if complete {
+                return Ok(text)
+            }
+        }
+    }
+
+    fn read_value(&mut self) -> Result<Option<Json<'static>>, ReadError> {
+        self.skip_spaces()?;
+        self.start = self.position;
+        let bytes = self.read_text()?;
+        if bytes.is_empty() {
+            return Ok(None)
+        }
+        let text = match str::from_utf8(&bytes) {
+            Ok(text) => text,
+            Err(e) => {
+                let at = self.start.advance(str::from_utf8(&bytes[..e.valid_up_to()]).unwrap());
+                return Err(self.error("Invalid UTF-8".to_string(), at))
+            }
+        };
+        if self.start.offset > 0 && byte_order_mark_len(text) > 0 {
+            return Err(self.error("Byte order mark is not allowed".to_string(), self.start))
+        }
+        self.position = self.start.advance(text);
+        match Json::parse_many_with(text, &self.opts) {
+            Ok(ref values) if values.len() > 1 => {
+                let at = values[1].1.start;
+                let c = text[at.offset..].chars().next().unwrap();
+                Err(self.error(format!("Unexpected `{}`.", c), self.start.advance(&text[..at.offset])))
+            },
+            Ok(mut values) => Ok(values.pop().map(|(json, _)| json.into_owned())),
+            Err(e) => Err(self.error(e.message, self.start.advance(&text[..e.pos.min(text.len())])))
+        }
+    }
+}
+
+impl <R: Read> Iterator for JsonReader<R> {
+    type Item = Result<Json<'static>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        match self.read_value() {
+            Ok(Some(json)) => Some(Ok(json)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// The bytes to read at a time
+const CHUNK: usize = 64 * 1024;
+
+/// The bytes of the text before the position of parsing which are kept for the snippets of
+/// errors, which are enough for the 40 characters before the position.
+const CONTEXT: usize = 256;
+
+/// Parses the values of a reader into events one by one as `EventParser::many_with_options`
+/// does. Only the text of the events being parsed is held in memory, and a little before it,
+/// so that a value as large as the reader gives takes no more memory than one of its strings.
+/// An event is given as soon as its text is read, which is as early as the reader gives it.
+///
+/// ```
+/// # use toyjq::ParserOptions;
+/// # use toyjq::event::*;
+/// # use toyjq::reader::*;
+/// let events: Vec<Event> = EventReader::new("[1] \"a\"".as_bytes(), &ParserOptions::default()).map(Result::unwrap).collect();
+/// assert_eq!(events, vec![Event::StartArray, Event::Number(1.0.into()), Event::EndArray, Event::String("a".into())]);
+/// ```
+pub struct EventReader<R: Read> {
+    reader: R,
+    /// The text read, from a little before where parsing is
+    text: String,
+    /// Where `text` starts in the whole text
+    base: Position,
+    /// The bytes read after `text` which do not make a character yet
+    partial: Vec<u8>,
+    eof: bool,
+    /// The bytes after `text` are not UTF-8.
+    invalid: bool,
+    machine: Machine,
+    comments: bool,
+    /// How far `text` has been looked at for the tokens of the next step
+    ahead: Option<Lookahead>,
+    done: bool
+}
+
+impl <R: Read> EventReader<R> {
+    pub fn new(reader: R, opts: &ParserOptions) -> EventReader<R> {
+        EventReader {
+            reader,
+            text: String::new(),
+            base: Position {offset: 0, line: 1, column: 1},
+            partial: vec![],
+            eof: false,
+            invalid: false,
+            machine: Machine::new(opts, true),
+            comments: opts.comments,
+            ahead: None,
+            done: false
+        }
+    }
+
+    /// Where the text read so far ends, which is the end of the whole text once every event
+    /// has been given.
+    pub fn text_end(&self) -> Position {
+        self.base.advance(&self.text)
+    }
+
+    /// The line of the text where `e`, an error of this reader, is, as `ParseError::snippet`
+    /// gives it. Only the last few hundred bytes of the line before it are kept to show.
+    pub fn snippet(&self, e: &ParseError) -> String {
+        let pos = e.pos.saturating_sub(self.base.offset);
+        ParseError {retry: false, message: String::new(), pos, line: 0, column: 0}.snippet(&self.text)
+    }
+
+    /// Sets the position of `e`, which is at `e.pos` of `text`, in the whole text.
+    fn locate(&self, e: ParseError) -> ReadError {
+        let Position {line, column, ..} = Position::of(&self.text, e.pos);
+        let column = if line == 1 { self.base.column + column - 1 } else { column };
+        ReadError::Parse(ParseError {pos: self.base.offset + e.pos, line: self.base.line + line - 1, column, ..e})
+    }
+
+    /// Reads what the reader gives at once, which is kept as text as far as it is UTF-8.
+    fn read_more(&mut self) -> io::Result<()> {
+        let start = self.partial.len();
+        self.partial.resize(start + CHUNK, 0);
+        let n = loop {
+            match self.reader.read(&mut self.partial[start..]) {
+                Ok(n) => break n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.partial.truncate(start);
+                    return Err(e)
+                }
+            }
+        };
+        self.partial.truncate(start + n);
+        if n == 0 {
+            self.eof = true;
+            // A character is cut off at the end.
+            self.invalid = !self.partial.is_empty();
+            return Ok(())
+        }
+        let valid = match str::from_utf8(&self.partial) {
+            Ok(_) => self.partial.len(),
+            Err(e) => {
+                // Bytes which no more bytes make a character
+                self.invalid = e.error_len().is_some();
+                e.valid_up_to()
+            }
+        };
+        self.text.push_str(str::from_utf8(&self.partial[..valid]).unwrap());
+        self.partial.drain(..valid);
+        Ok(())
+    }
+
+    /// Whether `text` has every token of the next step, and the whitespace and comments after
+    /// them, so that the step parses as it would with the whole text. Whitespace at the end is
+    /// enough, so that a value is given without waiting for what follows, and a comment which
+    /// is not closed after it fails the step after.
+    fn is_ready(&mut self) -> bool {
+        let pos = self.machine.pos;
+        // The first step takes a byte order mark if any.
+        let start = if self.machine.is_started() { pos } else { pos + byte_order_mark_len(&self.text[pos..]) };
+        let tokens = self.machine.tokens_ahead();
+        if self.ahead.as_ref().is_none_or(|ahead| ahead.start != start) {
+            self.ahead = Some(Lookahead::new(start, tokens, self.comments));
+        }
+        self.ahead.as_mut().unwrap().scan(self.text.as_bytes())
+    }
+
+    /// Drops the text which has been parsed but the end of its last line, which is kept for the
+    /// snippets of errors.
+    fn compact(&mut self) {
+        let pos = self.machine.pos;
+        if pos < CHUNK {
+            return
+        }
+        let mut from = pos - CONTEXT;
+        while !self.text.is_char_boundary(from) {
+            from += 1;
+        }
+        let cut = self.text[from..pos].rfind('\n').map_or(from, |i| from + i + 1);
+        self.base = self.base.advance(&self.text[..cut]);
+        self.text.drain(..cut);
+        self.machine.pos -= cut;
+        self.ahead = None;
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event<'static>>, ReadError> {
+        loop {
+            while !self.eof && !self.invalid && !self.is_ready() {
+                self.read_more()?;
+            }
+            if self.invalid && !self.is_ready() {
+                let at = self.text_end();
+                return Err(ReadError::Parse(ParseError {retry: false, message: "Invalid UTF-8".to_string(), pos: at.offset, line: at.line, column: at.column}))
+            }
+            self.ahead = None;
+            let event = match self.machine.step(&self.text) {
+                Ok(Some((_, true))) => None,
+                Ok(Some((event, false))) => Some(event.into_owned()),
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(self.locate(e))
+            };
+            self.compact();
+            if event.is_some() {
+                return Ok(event)
+            }
+        }
+    }
+}
+
+impl <R: Read> Iterator for EventReader<R> {
+    type Item = Result<Event<'static>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        match self.read_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scan {
+    Space,
+    /// A `/` which may start a comment
+    Slash,
+    LineComment,
+    BlockComment,
+    /// A `*` in a block comment
+    Star,
+    String,
+    Escape,
+    Scalar
+}
+
+/// Follows the bytes from where a step starts to tell whether they have the tokens it takes,
+/// going on from where it stopped as more bytes are read.
+struct Lookahead {
+    start: usize,
+    /// The offset looked at next
+    at: usize,
+    /// The tokens left to see
+    tokens: usize,
+    comments: bool,
+    scan: Scan
+}
+
+impl Lookahead {
+    fn new(start: usize, tokens: usize, comments: bool) -> Lookahead {
+        Lookahead {start, at: start, tokens, comments, scan: Scan::Space}
+    }
+
+    fn scan(&mut self, text: &[u8]) -> bool {
+        while self.at < text.len() {
+            let b = text[self.at];
+            match self.scan {
+                Scan::Space => match b {
+                    b' ' | b'\t' | b'\n' | b'\r' => (),
+                    b'/' if self.comments => self.scan = Scan::Slash,
+                    // Whatever comes after the tokens is not taken.
+                    _ if self.tokens == 0 => return true,
+                    // The step ends at the end of a container.
+                    b']' | b'}' => self.tokens = 0,
+                    b'[' | b'{' | b',' | b':' => self.tokens -= 1,
+                    b'"' => self.scan = Scan::String,
+                    _ => self.scan = Scan::Scalar
+                },
+                Scan::Slash => match b {
+                    b'/' => self.scan = Scan::LineComment,
+                    b'*' => self.scan = Scan::BlockComment,
+                    // Not a comment, which the step tells as an error.
+                    _ => return true
+                },
+                Scan::LineComment => if b == b'\n' {
+                    self.scan = Scan::Space;
+                },
+                Scan::BlockComment => if b == b'*' {
+                    self.scan = Scan::Star;
+                },
+                Scan::Star => match b {
+                    b'/' => self.scan = Scan::Space,
+                    b'*' => (),
+                    _ => self.scan = Scan::BlockComment
+                },
+                Scan::String => match b {
+                    b'\\' => self.scan = Scan::Escape,
+                    b'"' => {
+                        self.tokens -= 1;
+                        self.scan = Scan::Space;
+                    },
+                    _ => ()
+                },
+                Scan::Escape => self.scan = Scan::String,
+                Scan::Scalar => match b {
+                    b' ' | b'\t' | b'\n' | b'\r' | b'[' | b']' | b'{' | b'}' | b',' | b':' | b'"' => {
+                        self.tokens -= 1;
+                        self.scan = Scan::Space;
+                        // The byte is looked at again as the one after the scalar.
+                        continue
+                    },
+                    b'/' if self.comments => {
+                        self.tokens -= 1;
+                        self.scan = Scan::Space;
+                        continue
+                    },
+                    _ => ()
+                }
+            }
+            self.at += 1;
+        }
+        // Only whitespace is left, which the next step skips again if more follows.
+        self.tokens == 0 && self.scan == Scan::Space
+    }
+}
+
+enum Step {
+    Take,
+    /// Takes the byte, which ends the value
+    TakeLast,
+    /// Ends the value before the byte
+    Stop
+}
+
+/// Follows the bytes of a value to tell where it ends. With `comments`, the comments before it
+/// are taken with it, and brackets and quotes in comments do not count.
+#[derive(Default)]
+struct Nesting {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    comments: bool,
+    in_line_comment: bool,
+    in_block_comment: bool,
+    /// The last byte was `/` out of strings and comments, or `*` in a block comment.
+    after_mark: bool,
+    /// A byte order mark may come first, which is taken with the value.
+    mark: bool,
+    /// The bytes of the byte order mark left to take
+    in_mark: usize,
+    started: bool
+}
+
+impl Nesting {
+    fn feed(&mut self, b: u8) -> Step {
+        if self.in_mark > 0 {
+            self.in_mark -= 1;
+            return Step::Take
+        }
+        if self.mark && !self.started && b == 0xEF {
+            // The rest of the mark is not checked here but by the decoding as UTF-8.
+            self.in_mark = 2;
+            self.mark = false;
+            return Step::Take
+        }
+        if self.in_line_comment {
+            self.in_line_comment = b != b'\n';
+            return Step::Take
+        }
+        if self.in_block_comment {
+            self.in_block_comment = !(self.after_mark && b == b'/');
+            self.after_mark = b == b'*';
+            return Step::Take
+        }
+        if self.comments && !self.in_string && (!self.started || self.depth > 0) {
+            let after_slash = self.after_mark;
+            self.after_mark = false;
+            match b {
+                b'/' if after_slash => self.in_line_comment = true,
+                b'*' if after_slash => self.in_block_comment = true,
+                b'/' => self.after_mark = true,
+                _ => ()
+            }
+            if b == b'/' || self.in_block_comment {
+                return Step::Take
+            }
+        }
+        if !self.started && (b == b' ' || b == b'\t' || b == b'\n' || b == b'\r') {
+            // Whitespace after the mark
+            return Step::Take
+        }
+        let first = !self.started;
+        self.started = true;
+        if self.in_string {
+            match b {
+                _ if self.escaped => self.escaped = false,
+                b'\\' => self.escaped = true,
+                b'"' => {
+                    self.in_string = false;
+                    if self.depth == 0 {
+                        return Step::TakeLast
+                    }
+                },
+                _ => ()
+            }
+            return Step::Take
+        }
+        match b {
+            b'"' if first || self.depth > 0 => self.in_string = true,
+            b'[' | b'{' if first || self.depth > 0 => self.depth += 1,
+            b']' | b'}' if self.depth > 0 => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    return Step::TakeLast
+                }
+            },
+            // Anything else starts a scalar, which the parser tells if it is not valid.
+            _ if first || self.depth > 0 => (),
+            b' ' | b'\t' | b'\n' | b'\r' | b'[' | b']' | b'{' | b'}' | b',' | b':' | b'"' => return Step::Stop,
+            b'/' if self.comments => return Step::Stop,
+            _ => ()
+        }
+        Step::Take
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gives a few bytes at a time, so that values are split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl <'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_json_reader() {
+        let text = "{\"a\": [1, \"]\\\"\"]}\n12 true\"é\"\r\n[{}]";
+        let values: Vec<Json> = JsonReader::new(Trickle(text.as_bytes())).map(Result::unwrap).collect();
+        assert_eq!(values, Json::from_str_many(text).unwrap().into_iter().map(Json::into_owned).collect::<Vec<_>>());
+        assert_eq!(JsonReader::new(Trickle(b" \n ")).count(), 0);
+        let values: Vec<Json> = JsonReader::new(Trickle(b"\xEF\xBB\xBF [1] 2")).map(Result::unwrap).collect();
+        assert_eq!(values, Json::from_str_many("[1] 2").unwrap());
+        assert_eq!(JsonReader::new(Trickle(b"\xEF\xBB\xBF\n")).count(), 0);
+        assert!(JsonReader::new(Trickle(b"1 \xEF\xBB\xBF2")).any(|r| r.is_err()));
+        let errors: Vec<String> = [&b"1\n [2,\n 3 4]"[..], b"1 ]", b"nul", b"[1", b"\"\\u12", b"truefalse", b"[\"\xff\"]"].iter()
+            .map(|s| match JsonReader::new(Trickle(s)).find_map(Result::err) {
+                Some(ReadError::Parse(e)) => format!("{}:{}:{}", e.line, e.column, e.message),
+                e => panic!("{:?}", e)
+            })
+            .collect();
+        assert_eq!(errors, vec![
+            "3:4:Expected `]` but actual is `4`.",
+            "1:3:Expected a digit",
+            "1:1:Expected a digit",
+            "1:3:Reaches end.",
+            "1:4:Expected 4 hexadecimal digits after \\u",
+            "1:5:Unexpected `f`.",
+            "1:3:Invalid UTF-8"
+        ]);
+        assert_eq!(Json::from_reader(Trickle(b"\"x\" ")).unwrap(), Json::JString("x".into()));
+        let text = "// a [\n[1, /* ] \" */ 2,] 3// b\n{\"a\": NaN /* } */}/**/4 // c";
+        let values: Vec<String> = JsonReader::with_options(Trickle(text.as_bytes()), &ParserOptions::lenient()).map(|r| print_json_compact(&r.unwrap())).collect();
+        assert_eq!(values, vec!["[1,2]", "3", "{\"a\":null}", "4"]);
+        assert!(JsonReader::new(Trickle(b"[1, /* ] */ 2]")).any(|r| r.is_err()));
+        let opts = ParserOptions::new().number_text(true).duplicate_keys(DuplicateKeys::Error);
+        assert_eq!(print_json_compact(&Json::from_reader_with(Trickle(b"[1.10]"), &opts).unwrap()), "[1.10]");
+        match Json::from_reader_with(Trickle(b"{\"a\": 1,\n \"a\": 2}"), &opts) {
+            Err(ReadError::Parse(e)) => assert_eq!((e.line, e.column, e.message.as_str()), (2, 2, "Duplicate key \"a\"")),
+            e => panic!("{:?}", e)
+        }
+        assert!(Json::from_reader_with(Trickle(b"[[1]]"), &ParserOptions::new().max_depth(1)).is_err());
+        assert!(Json::from_reader_with(Trickle(b"\xEF\xBB\xBF1"), &ParserOptions::strict()).is_err());
+        match Json::from_reader(Trickle(b" \n")) {
+            Err(ReadError::Parse(e)) => assert_eq!((e.line, e.column, e.message.as_str()), (2, 1, "Reaches end.")),
+            e => panic!("{:?}", e)
+        }
+    }
+
+    #[test]
+    fn test_event_reader() {
+        use event::*;
+        let same = |text: &str, opts: &ParserOptions| {
+            let events: Vec<_> = EventReader::new(Trickle(text.as_bytes()), opts).map(|r| r.map_err(|e| e.to_string())).collect();
+            let expected: Vec<_> = EventParser::many_with_options(text, opts).map(|r| r.map(Event::into_owned).map_err(|e| ReadError::Parse(e).to_string())).collect();
+            // As NaN is not equal to itself
+            assert_eq!(format!("{:?}", events), format!("{:?}", expected), "{}", text);
+        };
+        let lenient = ParserOptions::lenient();
+        for text in &["", " ", "1 2", "{\"a\": [1, \"]\\\"\"]}\n12 true\"é\"\r\n[{}]", "\u{FEFF} [1]", "[1, 2", "[1 2]", "nul", "1 \u{FEFF}2", "{\"a\": 1, \"a\" 2}"] {
+            same(text, &ParserOptions::default());
+        }
+        for text in &["// a [\n[1, /* ] \" */ 2,] 3// b\n{\"a\": NaN /* } */}/**/4 // c", "[1 / 2]", "{\"a\": 1,}"] {
+            same(text, &lenient);
+        }
+        same("{\"a\": 1, \"b\": {\"c\": 2}, \"a\": [3, {\"a\": 4, \"a\": 5}], \"b\": {}}", &ParserOptions::new().duplicate_keys(DuplicateKeys::FirstWins));
+        same("\u{FEFF}1", &ParserOptions::strict());
+        let events: Vec<_> = EventReader::new(Trickle(b"[1 /* "), &lenient).map(|r| r.map_err(|e| e.to_string())).collect();
+        assert_eq!(events, vec![Ok(Event::StartArray), Ok(Event::Number(1.0.into())), Err("Unterminated comment at line 1, column 6".to_string())]);
+        let errors: Vec<String> = [&b"[\"\xff\"]"[..], b"1\n\"\xE3\x81", b"[1, 2\xff]"].iter()
+            .map(|s| match EventReader::new(Trickle(s), &ParserOptions::default()).find_map(Result::err) {
+                Some(ReadError::Parse(e)) => format!("{}:{}:{}", e.line, e.column, e.message),
+                e => panic!("{:?}", e)
+            })
+            .collect();
+        assert_eq!(errors, vec!["1:3:Invalid UTF-8", "2:2:Invalid UTF-8", "1:6:Invalid UTF-8"]);
+        // Values much longer than what is kept of the text
+        let line = format!("[{}]\n", vec!["\"abc\""; 1000].join(", "));
+        let text = format!("{}{}", line.repeat(100), "[1, x]");
+        let mut events = EventReader::new(text.as_bytes(), &ParserOptions::default());
+        assert_eq!(events.by_ref().take_while(Result::is_ok).count(), 100 * 1002 + 2);
+        let mut events = EventReader::new(text.as_bytes(), &ParserOptions::default());
+        match events.find_map(Result::err) {
+            Some(ReadError::Parse(e)) => {
+                assert_eq!((e.pos, e.line, e.column), (line.len() * 100 + 4, 101, 5));
+                assert_eq!(events.snippet(&e), "[1, x]\n    ^");
+            },
+            e => panic!("{:?}", e)
+        }
+        assert_eq!(events.text_end(), Position::of(&text, text.len()));
+    }
+}
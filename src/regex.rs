@@ -0,0 +1,764 @@
+//! A small backtracking regular expression engine for the regex builtins of the filters,
+//! such as `test` and `sub`.
+//!
+//! The syntax is a subset of that of Oniguruma, which jq uses: `.`, `[a-z]`, `[^0-9]`,
+//! `\d`, `\w`, `\s` and their negations, `^`, `$`, `\A`, `\z`, `\Z`, `\b`, `\B`,
+//! groups `(...)`, `(?:...)`, `(?<name>...)`, lookaheads `(?=...)`, `(?!...)`,
+//! back references `\1` and `\k<name>`, alternation `|` and the greedy and lazy quantifiers
+//! `*`, `+`, `?`, `{n}`, `{n,}`, `{,m}` and `{n,m}`.
+//! Offsets and lengths are counted in code points.
+//! A search which backtracks more than [`RETRY_LIMIT`] times from a position fails with
+//! [`RetryLimitExceeded`] instead of running for an exponential time.
+//!
+//! ```
+//! # use toyjq::regex::*;
+//! let re = Regex::new("(?<x>a+)b", "g").unwrap();
+//! let text: Vec<char> = "ab aab".chars().collect();
+//! let matches = re.matches(&text).unwrap();
+//! assert_eq!(matches.iter().map(|m| (m.start, m.end)).collect::<Vec<_>>(), vec![(0, 2), (3, 6)]);
+//! assert_eq!(matches[1].groups, vec![Some((3, 5))]);
+//! assert_eq!(re.names(), &[Some("x".to_string())]);
+//! ```
+
+use std::prelude::v1::*;
+use super::parsercombinator::ParseError;
+
+/// The modifiers of a regex, given as a string of letters such as `"gi"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags {
+    /// `g`: finds every match instead of the first one
+    pub global: bool,
+    /// `i`: ignores the case of letters
+    pub ignore_case: bool,
+    /// `x`: ignores whitespace and `#` comments in the pattern
+    pub extended: bool,
+    /// `n`: ignores empty matches
+    pub skip_empty: bool,
+    /// `p`: lets `.` match a newline
+    pub dot_all: bool,
+    /// `l`: finds the longest match at a position instead of the first one
+    pub longest: bool
+}
+
+impl Flags {
+    /// Parses modifiers like jq. `s`, which makes `^` and `$` match only at the ends of the text,
+    /// is accepted for compatibility, as they always do.
+    pub fn parse(flags: &str) -> Option<Flags> {
+        let mut ret = Flags::default();
+        for c in flags.chars() {
+            match c {
+                'g' => ret.global = true,
+                'i' => ret.ignore_case = true,
+                'x' => ret.extended = true,
+                'n' => ret.skip_empty = true,
+                'p' => ret.dot_all = true,
+                'l' => ret.longest = true,
+                's' => (),
+                _ => return None
+            }
+        }
+        Some(ret)
+    }
+}
+
+/// A compiled regex.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regex {
+    node: Node,
+    names: Vec<Option<String>>,
+    flags: Flags
+}
+
+/// The range of each capture group in order, or `None` for those which take no part in a match.
+pub type Groups = Vec<Option<(usize, usize)>>;
+
+/// How many steps a search may take from each position, as `retry-limit-in-match` of Oniguruma.
+pub const RETRY_LIMIT: usize = 1_000_000;
+
+/// The error of a search which took more than [`RETRY_LIMIT`] steps from a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryLimitExceeded;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub groups: Groups
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assertion {
+    Start,
+    End,
+    /// `\Z`, the end or before a newline at the end
+    EndOrNewline,
+    WordBoundary,
+    NotWordBoundary
+}
+
+/// `\d`, `\w` and `\s`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Perl {
+    Digit,
+    Word,
+    Space
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word(c),
+            Perl::Space => c.is_whitespace()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    /// The items and whether it is negated
+    Class(Vec<ClassItem>, bool),
+    Perl(Perl, bool),
+    Assert(Assertion),
+    /// A capture group and its index from 0
+    Group(Box<Node>, usize),
+    /// A lookahead and whether it is negated
+    Look(Box<Node>, bool),
+    Backref(usize),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {node: Box<Node>, min: usize, max: Option<usize>, greedy: bool}
+}
+
+impl Regex {
+    /// Compiles `pattern` with the modifiers `flags`.
+    pub fn new(pattern: &str, flags: &str) -> Result<Regex, ParseError> {
+        let flags = Flags::parse(flags).ok_or_else(|| ParseError {retry: false, message: format!("{} is not a valid modifier string", flags), pos: 0, line: 1, column: 1})?;
+        let mut compiler = Compiler {chars: pattern.chars().collect(), pos: 0, extended: flags.extended, names: vec![]};
+        let node = compiler.alternation()?;
+        if compiler.pos < compiler.chars.len() {
+            return Err(compiler.error("unmatched close parenthesis"));
+        }
+        Ok(Regex {node, names: compiler.names, flags})
+    }
+
+    /// The names of the capture groups in order, or `None` for those without one.
+    pub fn names(&self) -> &[Option<String>] {
+        &self.names
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Finds the first match which starts at `from` or after it.
+    pub fn find_at(&self, text: &[char], from: usize) -> Result<Option<Match>, RetryLimitExceeded> {
+        let mut matcher = Matcher {text, flags: self.flags, groups: vec![], steps: 0};
+        for start in from..=text.len() {
+            matcher.groups = vec![None; self.names.len()];
+            matcher.steps = RETRY_LIMIT;
+            let mut found: Option<(usize, Groups)> = None;
+            let longest = self.flags.longest;
+            matcher.run(&self.node, start, &mut |m, end| {
+                if found.as_ref().is_none_or(|&(e, _)| end > e) {
+                    found = Some((end, m.groups.clone()));
+                }
+                // The longest match needs to try every way.
+                !longest
+            });
+            if matcher.steps == 0 {
+                return Err(RetryLimitExceeded);
+            }
+            if let Some((end, groups)) = found {
+                return Ok(Some(Match {start, end, groups}));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds every match one after another if the regex is global, or the first one otherwise.
+    /// Empty matches are left out with the modifier `n`.
+    pub fn matches(&self, text: &[char]) -> Result<Vec<Match>, RetryLimitExceeded> {
+        let mut ret = vec![];
+        let mut from = 0;
+        while let Some(m) = self.find_at(text, from)? {
+            // An empty match moves on by one to avoid finding it again.
+            from = if m.end == m.start { m.end + 1 } else { m.end };
+            if !(self.flags.skip_empty && m.end == m.start) {
+                ret.push(m);
+                if !self.flags.global {
+                    break;
+                }
+            }
+            if from > text.len() {
+                break;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn fold_case(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// A recursive descent parser of patterns.
+struct Compiler {
+    chars: Vec<char>,
+    pos: usize,
+    extended: bool,
+    names: Vec<Option<String>>
+}
+
+impl Compiler {
+    fn error(&self, message: &str) -> ParseError {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line_start = before.iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+        ParseError {
+            retry: false,
+            message: message.to_string(),
+            pos: before.iter().map(|c| c.len_utf8()).sum(),
+            line: before.iter().filter(|&&c| c == '\n').count() + 1,
+            column: before.len() - line_start + 1
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        if self.extended {
+            self.skip_extended();
+        }
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn skip_extended(&mut self) {
+        while let Some(&c) = self.chars.get(self.pos) {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else if c == '#' {
+                while self.chars.get(self.pos).is_some_and(|&c| c != '\n') {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).cloned();
+        self.pos += 1;
+        c
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let end = self.pos + s.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().cloned().eq(s.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, ParseError> {
+        let mut alts = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alts.push(self.sequence()?);
+        }
+        Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Node::Alt(alts) })
+    }
+
+    fn sequence(&mut self) -> Result<Node, ParseError> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes)
+        })
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, ParseError> {
+        let (min, max) = match self.peek() {
+            Some('{') => match self.interval() {
+                Some(range) => range,
+                None => return Ok(atom)
+            },
+            Some(c @ '*') | Some(c @ '+') | Some(c @ '?') => {
+                self.pos += 1;
+                match c {
+                    '*' => (0, None),
+                    '+' => (1, None),
+                    _ => (0, Some(1))
+                }
+            },
+            _ => return Ok(atom)
+        };
+        if let Node::Assert(_) | Node::Look(_, _) = atom {
+            return Err(self.error("target of repeat operator is invalid"));
+        }
+        let greedy = !self.eat("?");
+        if let Some('*') | Some('+') | Some('?') = self.peek() {
+            return Err(self.error("nested repeat operator"));
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("upper bound must be greater than lower bound"));
+        }
+        Ok(Node::Repeat {node: Box::new(atom), min, max, greedy})
+    }
+
+    /// `{n}`, `{n,}`, `{,m}` or `{n,m}`, or `None` to read `{` as a character like Oniguruma.
+    fn interval(&mut self) -> Option<(usize, Option<usize>)> {
+        let start = self.pos;
+        let number = |c: &mut Compiler| {
+            let from = c.pos;
+            while c.chars.get(c.pos).is_some_and(|c| c.is_ascii_digit()) {
+                c.pos += 1;
+            }
+            c.chars[from..c.pos].iter().collect::<String>().parse::<usize>().ok()
+        };
+        self.pos += 1;
+        let min = number(self);
+        let max = if self.eat(",") { number(self) } else { min };
+        if (min.is_none() && max.is_none()) || !self.eat("}") {
+            self.pos = start;
+            return None;
+        }
+        Some((min.unwrap_or(0), max))
+    }
+
+    fn atom(&mut self) -> Result<Node, ParseError> {
+        match self.next() {
+            Some('(') => self.group(),
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Assert(Assertion::Start)),
+            Some('$') => Ok(Node::Assert(Assertion::End)),
+            Some('\\') => self.escape(),
+            Some('*') | Some('+') | Some('?') => {
+                self.pos -= 1;
+                Err(self.error("target of repeat operator is not specified"))
+            },
+            Some(c) => Ok(Node::Char(c)),
+            None => Err(self.error("end pattern"))
+        }
+    }
+
+    fn group(&mut self) -> Result<Node, ParseError> {
+        let node = if self.eat("?:") {
+            self.alternation()?
+        } else if self.eat("?=") {
+            Node::Look(Box::new(self.alternation()?), false)
+        } else if self.eat("?!") {
+            Node::Look(Box::new(self.alternation()?), true)
+        } else if self.chars.get(self.pos) == Some(&'?') {
+            if !(self.eat("?<") || self.eat("?P<")) || self.chars.get(self.pos).is_some_and(|&c| c == '=' || c == '!') {
+                return Err(self.error("undefined group option"));
+            }
+            let name = self.name()?;
+            if self.names.contains(&Some(name.clone())) {
+                return Err(self.error(&format!("multiplex defined name <{}>", name)));
+            }
+            self.names.push(Some(name));
+            let index = self.names.len() - 1;
+            Node::Group(Box::new(self.alternation()?), index)
+        } else {
+            self.names.push(None);
+            let index = self.names.len() - 1;
+            Node::Group(Box::new(self.alternation()?), index)
+        };
+        if self.next() != Some(')') {
+            self.pos -= 1;
+            return Err(self.error("end pattern with unmatched parenthesis"));
+        }
+        Ok(node)
+    }
+
+    /// The name of a group up to `>`
+    fn name(&mut self) -> Result<String, ParseError> {
+        let mut name = String::new();
+        loop {
+            match self.next() {
+                Some('>') if !name.is_empty() => return Ok(name),
+                Some(c) if is_word(c) => name.push(c),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("invalid group name"));
+                }
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, ParseError> {
+        let c = match self.next() {
+            Some(c) => c,
+            None => {
+                self.pos -= 1;
+                return Err(self.error("end pattern at escape"));
+            }
+        };
+        Ok(match c {
+            'A' => Node::Assert(Assertion::Start),
+            'z' => Node::Assert(Assertion::End),
+            'Z' => Node::Assert(Assertion::EndOrNewline),
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            '1'..='9' => {
+                let mut n = c.to_digit(10).unwrap() as usize;
+                while let Some(d) = self.chars.get(self.pos).and_then(|c| c.to_digit(10)) {
+                    n = n * 10 + d as usize;
+                    self.pos += 1;
+                }
+                if n > self.names.len() {
+                    return Err(self.error("invalid backref number/name"));
+                }
+                Node::Backref(n - 1)
+            },
+            'k' if self.eat("<") => {
+                let name = self.name()?;
+                match self.names.iter().position(|n| n.as_ref() == Some(&name)) {
+                    Some(i) => Node::Backref(i),
+                    None => return Err(self.error("undefined name reference"))
+                }
+            },
+            c => match self.class_escape(c)? {
+                ClassItem::Perl(perl, negated) => Node::Perl(perl, negated),
+                ClassItem::Range(c, _) => Node::Char(c)
+            }
+        })
+    }
+
+    /// An escape which can be in a class as well, following `\`.
+    fn class_escape(&mut self, c: char) -> Result<ClassItem, ParseError> {
+        let c = match c {
+            'd' => return Ok(ClassItem::Perl(Perl::Digit, false)),
+            'D' => return Ok(ClassItem::Perl(Perl::Digit, true)),
+            'w' => return Ok(ClassItem::Perl(Perl::Word, false)),
+            'W' => return Ok(ClassItem::Perl(Perl::Word, true)),
+            's' => return Ok(ClassItem::Perl(Perl::Space, false)),
+            'S' => return Ok(ClassItem::Perl(Perl::Space, true)),
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            'e' => '\x1b',
+            'a' => '\x07',
+            'x' => self.hex(2)?,
+            'u' => self.hex(4)?,
+            c if c.is_alphanumeric() => {
+                self.pos -= 1;
+                return Err(self.error("undefined escape"));
+            },
+            c => c
+        };
+        Ok(ClassItem::Range(c, c))
+    }
+
+    fn hex(&mut self, digits: usize) -> Result<char, ParseError> {
+        let end = self.pos + digits;
+        let code = if end <= self.chars.len() { self.chars[self.pos..end].iter().collect::<String>() } else { String::new() };
+        match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+            Some(c) if code.len() == digits => {
+                self.pos = end;
+                Ok(c)
+            },
+            _ => Err(self.error("invalid code point value"))
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, ParseError> {
+        let negated = self.eat("^");
+        let mut items = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                Some(']') if !first => break,
+                Some('\\') => match self.next() {
+                    Some(c) => self.class_escape(c)?,
+                    None => {
+                        self.pos -= 1;
+                        return Err(self.error("premature end of char-class"));
+                    }
+                },
+                Some(c) => ClassItem::Range(c, c),
+                None => {
+                    self.pos -= 1;
+                    return Err(self.error("premature end of char-class"));
+                }
+            };
+            first = false;
+            match c {
+                ClassItem::Range(from, _) if self.chars.get(self.pos) == Some(&'-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') => {
+                    self.pos += 1;
+                    let to = match self.next() {
+                        Some('\\') => match self.next().map(|c| self.class_escape(c)) {
+                            Some(Ok(ClassItem::Range(to, _))) => to,
+                            _ => return Err(self.error("invalid range in char-class"))
+                        },
+                        Some(to) => to,
+                        None => unreachable!()
+                    };
+                    if to < from {
+                        return Err(self.error("empty range in char class"));
+                    }
+                    items.push(ClassItem::Range(from, to));
+                },
+                c => items.push(c)
+            }
+        }
+        Ok(Node::Class(items, negated))
+    }
+}
+
+/// Runs a regex on a text. The continuation given to `run` receives the end of a match of the node,
+/// and tells whether the whole match succeeds from there, to stop the search.
+/// Each node run takes a step, and the search fails once none are left.
+struct Matcher<'t> {
+    text: &'t [char],
+    flags: Flags,
+    groups: Groups,
+    steps: usize
+}
+
+impl <'t> Matcher<'t> {
+    fn char_eq(&self, a: char, b: char) -> bool {
+        a == b || (self.flags.ignore_case && fold_case(a) == fold_case(b))
+    }
+
+    /// Whether the node of a single character matches `c`
+    fn matches_char(&self, node: &Node, c: char) -> bool {
+        match *node {
+            Node::Char(d) => self.char_eq(c, d),
+            Node::Any => self.flags.dot_all || c != '\n',
+            Node::Perl(perl, negated) => perl.matches(c) != negated,
+            Node::Class(ref items, negated) => {
+                let cases = if self.flags.ignore_case {
+                    vec![c, fold_case(c), c.to_uppercase().next().unwrap_or(c)]
+                } else {
+                    vec![c]
+                };
+                let found = items.iter().any(|item| match *item {
+                    ClassItem::Range(from, to) => cases.iter().any(|&c| from <= c && c <= to),
+                    ClassItem::Perl(perl, negated) => perl.matches(c) != negated
+                });
+                found != negated
+            },
+            _ => false
+        }
+    }
+
+    fn is_single_char(node: &Node) -> bool {
+        matches!(*node, Node::Char(_) | Node::Any | Node::Perl(_, _) | Node::Class(_, _))
+    }
+
+    fn assert(&self, assertion: Assertion, pos: usize) -> bool {
+        let len = self.text.len();
+        let word_before = pos > 0 && is_word(self.text[pos - 1]);
+        let word_after = pos < len && is_word(self.text[pos]);
+        match assertion {
+            Assertion::Start => pos == 0,
+            Assertion::End => pos == len,
+            Assertion::EndOrNewline => pos == len || (pos + 1 == len && self.text[pos] == '\n'),
+            Assertion::WordBoundary => word_before != word_after,
+            Assertion::NotWordBoundary => word_before == word_after
+        }
+    }
+
+    fn run(&mut self, node: &Node, pos: usize, k: &mut dyn FnMut(&mut Matcher<'t>, usize) -> bool) -> bool {
+        if self.steps == 0 {
+            return false;
+        }
+        self.steps -= 1;
+        match *node {
+            Node::Empty => k(self, pos),
+            Node::Char(_) | Node::Any | Node::Perl(_, _) | Node::Class(_, _) => {
+                pos < self.text.len() && self.matches_char(node, self.text[pos]) && k(self, pos + 1)
+            },
+            Node::Assert(assertion) => self.assert(assertion, pos) && k(self, pos),
+            Node::Group(ref node, index) => self.run(node, pos, &mut |m, end| {
+                let old = m.groups[index];
+                m.groups[index] = Some((pos, end));
+                if k(m, end) {
+                    return true;
+                }
+                m.groups[index] = old;
+                false
+            }),
+            Node::Look(ref node, negated) => {
+                let saved = self.groups.clone();
+                let found = self.run(node, pos, &mut |_, _| true);
+                if found == negated {
+                    self.groups = saved;
+                    return false;
+                }
+                if negated {
+                    self.groups = saved.clone();
+                }
+                if k(self, pos) {
+                    return true;
+                }
+                self.groups = saved;
+                false
+            },
+            Node::Backref(index) => match self.groups[index] {
+                Some((start, end)) => {
+                    let len = end - start;
+                    pos + len <= self.text.len()
+                        && (0..len).all(|i| self.char_eq(self.text[start + i], self.text[pos + i]))
+                        && k(self, pos + len)
+                },
+                None => false
+            },
+            Node::Concat(ref nodes) => self.run_sequence(nodes, pos, k),
+            Node::Alt(ref nodes) => nodes.iter().any(|node| {
+                let saved = self.groups.clone();
+                if self.run(node, pos, k) {
+                    return true;
+                }
+                self.groups = saved;
+                false
+            }),
+            Node::Repeat {ref node, min, max, greedy} => {
+                if Matcher::is_single_char(node) {
+                    self.repeat_char(node, min, max, greedy, pos, k)
+                } else {
+                    self.repeat(node, min, max, greedy, 0, pos, k)
+                }
+            }
+        }
+    }
+
+    fn run_sequence(&mut self, nodes: &[Node], pos: usize, k: &mut dyn FnMut(&mut Matcher<'t>, usize) -> bool) -> bool {
+        match nodes.split_first() {
+            None => k(self, pos),
+            Some((node, rest)) => self.run(node, pos, &mut |m, end| m.run_sequence(rest, end, k))
+        }
+    }
+
+    /// Repeats a node of a single character without recursion for each of them.
+    fn repeat_char(&mut self, node: &Node, min: usize, max: Option<usize>, greedy: bool, pos: usize, k: &mut dyn FnMut(&mut Matcher<'t>, usize) -> bool) -> bool {
+        let limit = max.map_or(self.text.len() - pos, |max| max.min(self.text.len() - pos));
+        let count = (0..limit).take_while(|&i| self.matches_char(node, self.text[pos + i])).count();
+        if count < min {
+            return false;
+        }
+        if greedy {
+            (min..=count).rev().any(|n| k(self, pos + n))
+        } else {
+            (min..=count).any(|n| k(self, pos + n))
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn repeat(&mut self, node: &Node, min: usize, max: Option<usize>, greedy: bool, count: usize, pos: usize, k: &mut dyn FnMut(&mut Matcher<'t>, usize) -> bool) -> bool {
+        let more = max.is_none_or(|max| count < max);
+        if count < min {
+            return self.run(node, pos, &mut |m, end| m.repeat(node, min, max, greedy, count + 1, end, k));
+        }
+        // An empty iteration after the minimum cannot make progress, so it is not tried.
+        let again = |m: &mut Matcher<'t>, k: &mut dyn FnMut(&mut Matcher<'t>, usize) -> bool| {
+            more && m.run(node, pos, &mut |m, end| end != pos && m.repeat(node, min, max, greedy, count + 1, end, k))
+        };
+        if !greedy && k(self, pos) {
+            return true;
+        }
+        again(self, k) || (greedy && k(self, pos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, flags: &str, text: &str) -> Vec<(usize, usize)> {
+        let text: Vec<char> = text.chars().collect();
+        Regex::new(pattern, flags).unwrap().matches(&text).unwrap().into_iter().map(|m| (m.start, m.end)).collect()
+    }
+
+    fn groups(pattern: &str, text: &str) -> Vec<Option<String>> {
+        let text: Vec<char> = text.chars().collect();
+        let m = Regex::new(pattern, "").unwrap().find_at(&text, 0).unwrap().unwrap();
+        m.groups.into_iter().map(|g| g.map(|(s, e)| text[s..e].iter().collect())).collect()
+    }
+
+    #[test]
+    fn test_match() {
+        assert_eq!(find("b+", "", "abbcb"), vec![(1, 3)]);
+        assert_eq!(find("b+", "g", "abbcb"), vec![(1, 3), (4, 5)]);
+        assert_eq!(find("b+?", "g", "abb"), vec![(1, 2), (2, 3)]);
+        assert_eq!(find("^a|c$", "g", "abc"), vec![(0, 1), (2, 3)]);
+        assert_eq!(find("a.c", "g", "abc a\nc"), vec![(0, 3)]);
+        assert_eq!(find("a.c", "gp", "abc a\nc"), vec![(0, 3), (4, 7)]);
+        assert_eq!(find("[a-c]{2}", "g", "abcd"), vec![(0, 2)]);
+        assert_eq!(find("[^a-c]", "g", "abcd"), vec![(3, 4)]);
+        assert_eq!(find(r"\d{2,}|\s", "g", "1 23 456"), vec![(1, 2), (2, 4), (4, 5), (5, 8)]);
+        assert_eq!(find(r"\bx", "g", "xx x"), vec![(0, 1), (3, 4)]);
+        assert_eq!(find("AB", "gi", "abAb"), vec![(0, 2), (2, 4)]);
+        assert_eq!(find("[A-B]+", "i", "cab"), vec![(1, 3)]);
+        assert_eq!(find("a b # comment", "x", "ab"), vec![(0, 2)]);
+        assert_eq!(find("", "g", "ab"), vec![(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(find("x*", "gn", "axxb"), vec![(1, 3)]);
+        assert_eq!(find("a|ab", "", "ab"), vec![(0, 1)]);
+        assert_eq!(find("a|ab", "l", "ab"), vec![(0, 2)]);
+        assert_eq!(find("a(?=b)", "g", "acab"), vec![(2, 3)]);
+        assert_eq!(find("a(?!b)", "g", "acab"), vec![(0, 1)]);
+        assert_eq!(find(r"(.)\1", "g", "abbcdd"), vec![(1, 3), (4, 6)]);
+        assert_eq!(find("(a*)*b", "", "aab"), vec![(0, 3)]);
+        assert_eq!(find("é+", "", "caféé"), vec![(3, 5)]);
+        assert_eq!(find(r"é\x41", "", "éA"), vec![(0, 2)]);
+        assert_eq!(find("a{,2}", "", "aaa"), vec![(0, 2)]);
+        assert_eq!(find("a{x}", "", "a{x}"), vec![(0, 4)]);
+    }
+
+    #[test]
+    fn test_retry_limit() {
+        let text: Vec<char> = "a".repeat(30).chars().chain(Some('b')).collect();
+        assert_eq!(Regex::new("^(a+)+$", "").unwrap().find_at(&text, 0), Err(RetryLimitExceeded));
+        assert_eq!(Regex::new("^(a+)+b$", "").unwrap().find_at(&text, 0).map(|m| m.map(|m| m.end)), Ok(Some(31)));
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(groups("(a)(x)?(?<n>b)", "ab"), vec![Some("a".to_string()), None, Some("b".to_string())]);
+        assert_eq!(groups("(?:(a)|b)+", "ab"), vec![Some("a".to_string())]);
+        assert_eq!(groups(r"(?<q>.)\k<q>", "xyy"), vec![Some("y".to_string())]);
+        assert_eq!(Regex::new("(a)(?<b>b)", "").unwrap().names(), &[None, Some("b".to_string())]);
+    }
+
+    #[test]
+    fn test_error() {
+        let message = |pattern, flags| Regex::new(pattern, flags).unwrap_err().message;
+        assert_eq!(message("(a", ""), "end pattern with unmatched parenthesis");
+        assert_eq!(message("a)", ""), "unmatched close parenthesis");
+        assert_eq!(message("[a", ""), "premature end of char-class");
+        assert_eq!(message("*", ""), "target of repeat operator is not specified");
+        assert_eq!(message("a**", ""), "nested repeat operator");
+        assert_eq!(message(r"\1", ""), "invalid backref number/name");
+        assert_eq!(message("(?<a>x)(?<a>y)", ""), "multiplex defined name <a>");
+        assert_eq!(message("a", "q"), "q is not a valid modifier string");
+        let e = Regex::new("\u{e9}\u{e9})", "").unwrap_err();
+        assert_eq!((e.pos, e.column), (4, 3));
+        assert_eq!(e.snippet("\u{e9}\u{e9})"), "\u{e9}\u{e9})\n  ^");
+    }
+}
@@ -0,0 +1,406 @@
+use std::io;
+use std::io::Write;
+use std::mem;
+
+use super::color::{Theme, RESET};
+use super::event::Event;
+use super::json::PrinterOptions;
+use super::prettyprinter::{PrintStyle, Tag, UNLIMITED_WIDTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Array,
+    Object
+}
+
+impl Container {
+    fn brackets(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Container::Array => ("[", "]", "[]"),
+            Container::Object => ("{", "}", "{}")
+        }
+    }
+
+    fn tag(self) -> Tag {
+        match self {
+            Container::Array => Tag::ArrayPunctuation,
+            Container::Object => Tag::ObjectPunctuation
+        }
+    }
+}
+
+/// An event with the text to print for it, which is held while it is not known whether the
+/// array or the object it is in fits in the line.
+enum Token {
+    Open(Container),
+    Close,
+    Key(String),
+    Scalar(Tag, String)
+}
+
+/// Prints a stream of events as it arrives, holding only the current nesting state.
+/// An array or an object is printed in a line if it fits in the width, as `print_json` does,
+/// so its events are held until it turns out to fit or not, which is by the end of the line.
+/// Consecutive top-level values are separated by line breaks.
+pub struct StreamPrinter<'s, W: Write> {
+    out: W,
+    style: &'s PrintStyle,
+    theme: Option<&'s Theme>,
+    width: i32,
+    compact: bool,
+    /// The arrays and objects which are open
+    stack: Vec<Container>,
+    /// How many of the outermost of `stack` are broken into lines
+    broken: usize,
+    /// The columns left in the current line
+    rest: i32,
+    /// A container has been opened but nothing is written in it yet.
+    just_opened: bool,
+    /// A key has been written and its value is expected.
+    after_key: bool,
+    values: usize,
+    /// The tokens from the outermost container which may be printed in a line, which is not
+    /// known to fit in `budget` columns yet
+    pending: Vec<Token>,
+    /// `pending` printed in a line, and its width
+    flat: String,
+    flat_width: i32,
+    budget: i32
+}
+
+impl <'s, W: Write> StreamPrinter<'s, W> {
+    /// A printer which breaks every non-empty array and object into lines, as `print_json`
+    /// does with the width 0.
+    pub fn new(out: W, style: &'s PrintStyle) -> StreamPrinter<'s, W> {
+        StreamPrinter {
+            out,
+            style,
+            theme: None,
+            width: 0,
+            compact: false,
+            stack: vec![],
+            broken: 0,
+            rest: 0,
+            just_opened: false,
+            after_key: false,
+            values: 0,
+            pending: vec![],
+            flat: String::new(),
+            flat_width: 0,
+            budget: 0
+        }
+    }
+
+    /// A printer which prints as `Json::pretty_print_with` does with `opts`.
+    pub fn with_options(out: W, opts: &'s PrinterOptions) -> StreamPrinter<'s, W> {
+        let width = if opts.compact { UNLIMITED_WIDTH } else { opts.width };
+        StreamPrinter {theme: opts.theme.as_ref(), width, compact: opts.compact, rest: width, ..StreamPrinter::new(out, &opts.style)}
+    }
+
+    pub fn event(&mut self, e: Event) -> io::Result<()> {
+        let token = match e {
+            Event::StartArray => Token::Open(Container::Array),
+            Event::StartObject => Token::Open(Container::Object),
+            Event::EndArray | Event::EndObject => Token::Close,
+            Event::Key(k) => Token::Key(self.style.escape.quote(&k)),
+            Event::Number(v) => Token::Scalar(Tag::NumberValue, v.format(&self.style.number_format)),
+            Event::String(s) => Token::Scalar(Tag::StringValue, self.style.escape.quote(&s)),
+            Event::Bool(true) => Token::Scalar(Tag::TrueValue, "true".to_string()),
+            Event::Bool(false) => Token::Scalar(Tag::FalseValue, "false".to_string()),
+            Event::Null => Token::Scalar(Tag::NullValue, "null".to_string())
+        };
+        self.token(token)
+    }
+
+    /// Flushes the writer, which has every value given so far but the one in progress.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Writes the trailing newline if the style asks for it and returns the writer, which is
+    /// left to flush.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.style.trailing_newline && self.values > 0 {
+            self.out.write_all(self.style.line_ending.as_str().as_bytes())?;
+        }
+        Ok(self.out)
+    }
+
+    fn token(&mut self, token: Token) -> io::Result<()> {
+        if self.stack.len() > self.broken {
+            return self.flat_token(token)
+        }
+        match token {
+            Token::Open(container) => {
+                self.begin_item()?;
+                self.stack.push(container);
+                self.just_opened = true;
+                // A line never ends in compact output, so there is nothing to wait for.
+                if !self.compact && self.width != UNLIMITED_WIDTH {
+                    self.pending.push(token);
+                    self.budget = self.rest;
+                }
+            },
+            Token::Close => {
+                let container = self.stack.pop().unwrap();
+                self.broken -= 1;
+                self.write_newline()?;
+                let mut s = String::new();
+                self.rest -= self.paint(container.tag(), container.brackets().1, &mut s);
+                self.out.write_all(s.as_bytes())?;
+                self.end_value();
+            },
+            Token::Key(ref key) => {
+                self.begin_item()?;
+                let mut s = String::new();
+                self.rest -= self.paint(Tag::ObjectKey, key, &mut s);
+                self.rest -= self.paint(Tag::ObjectPunctuation, self.colon(), &mut s);
+                self.out.write_all(s.as_bytes())?;
+                self.after_key = true;
+            },
+            Token::Scalar(tag, ref v) => {
+                self.begin_item()?;
+                let mut s = String::new();
+                self.rest -= self.paint(tag, v, &mut s);
+                self.out.write_all(s.as_bytes())?;
+                self.end_value();
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints `token` in a container printed in a line, or holds it while the container is not
+    /// known to fit.
+    fn flat_token(&mut self, token: Token) -> io::Result<()> {
+        let mut s = String::new();
+        let width = match token {
+            Token::Open(container) => {
+                let width = self.flat_separator(&mut s);
+                self.stack.push(container);
+                self.just_opened = true;
+                width
+            },
+            Token::Close => {
+                let container = self.stack.pop().unwrap();
+                if self.just_opened {
+                    self.just_opened = false;
+                    self.paint(container.tag(), container.brackets().2, &mut s)
+                } else {
+                    let space = self.bracket_space();
+                    s.push_str(space);
+                    space.len() as i32 + self.paint(container.tag(), container.brackets().1, &mut s)
+                }
+            },
+            Token::Key(ref key) => {
+                let width = self.flat_separator(&mut s) + self.paint(Tag::ObjectKey, key, &mut s);
+                self.after_key = true;
+                width + self.paint(Tag::ObjectPunctuation, self.colon(), &mut s)
+            },
+            Token::Scalar(tag, ref v) => self.flat_separator(&mut s) + self.paint(tag, v, &mut s)
+        };
+        let closed = self.stack.len() == self.broken;
+        if self.pending.is_empty() {
+            self.out.write_all(s.as_bytes())?;
+            if closed {
+                self.end_value();
+            }
+            return Ok(())
+        }
+        let empty = self.pending.len() == 1 && closed;
+        self.pending.push(token);
+        self.flat.push_str(&s);
+        self.flat_width += width;
+        if closed && (empty || self.flat_width <= self.budget) {
+            self.out.write_all(self.flat.as_bytes())?;
+            self.rest -= self.flat_width;
+            self.pending.clear();
+            self.flat.clear();
+            self.flat_width = 0;
+            self.end_value();
+        } else if self.flat_width > self.budget {
+            self.break_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Breaks the container of `pending`, which does not fit in the line, into lines and
+    /// prints the tokens in it again.
+    fn break_pending(&mut self) -> io::Result<()> {
+        let mut tokens = mem::take(&mut self.pending).into_iter();
+        self.flat.clear();
+        self.flat_width = 0;
+        self.stack.truncate(self.broken);
+        if let Some(Token::Open(container)) = tokens.next() {
+            let mut s = String::new();
+            self.rest -= self.paint(container.tag(), container.brackets().0, &mut s);
+            self.out.write_all(s.as_bytes())?;
+            self.stack.push(container);
+            self.broken += 1;
+            self.just_opened = true;
+            self.after_key = false;
+        }
+        for token in tokens {
+            self.token(token)?;
+        }
+        Ok(())
+    }
+
+    /// Pushes what precedes a value or a key in a container printed in a line, which is the
+    /// opening bracket for the first, and returns its width.
+    fn flat_separator(&mut self, s: &mut String) -> i32 {
+        if self.after_key {
+            self.after_key = false;
+            return 0
+        }
+        let container = *self.stack.last().unwrap();
+        if self.just_opened {
+            self.just_opened = false;
+            let space = self.bracket_space();
+            let width = self.paint(container.tag(), container.brackets().0, s);
+            s.push_str(space);
+            width + space.len() as i32
+        } else {
+            let width = self.paint(container.tag(), ",", s);
+            if self.compact {
+                width
+            } else {
+                s.push(' ');
+                width + 1
+            }
+        }
+    }
+
+    /// The space just inside the brackets of a container printed in a line.
+    fn bracket_space(&self) -> &'static str {
+        if self.style.bracket_spacing && !self.compact { " " } else { "" }
+    }
+
+    fn colon(&self) -> &'static str {
+        if self.compact { ":" } else { ": " }
+    }
+
+    /// Pushes `text` colored as `tag` if there is a theme, and returns its width.
+    fn paint(&self, tag: Tag, text: &str, s: &mut String) -> i32 {
+        match self.theme {
+            Some(theme) => {
+                s.push_str(&theme.color(tag).escape());
+                s.push_str(text);
+                s.push_str(RESET);
+            },
+            None => s.push_str(text)
+        }
+        text.len() as i32
+    }
+
+    /// Writes what precedes a value or a key: a separator and a line break.
+    fn begin_item(&mut self) -> io::Result<()> {
+        if self.after_key {
+            self.after_key = false;
+        } else if self.stack.is_empty() {
+            if self.values > 0 {
+                self.out.write_all(self.style.line_ending.as_str().as_bytes())?;
+            }
+            self.rest = self.width;
+        } else {
+            if self.just_opened {
+                self.just_opened = false;
+            } else {
+                let mut s = String::new();
+                self.paint(self.stack.last().unwrap().tag(), ",", &mut s);
+                self.out.write_all(s.as_bytes())?;
+            }
+            self.write_newline()?;
+        }
+        Ok(())
+    }
+
+    fn end_value(&mut self) {
+        if self.stack.is_empty() {
+            self.values += 1;
+        }
+    }
+
+    fn write_newline(&mut self) -> io::Result<()> {
+        let indent = self.broken as i32 * self.style.indent_width;
+        let mut s = self.style.line_ending.as_str().to_string();
+        self.style.push_indent(indent, &mut s);
+        self.rest = self.width - indent;
+        self.out.write_all(s.as_bytes())
+    }
+}
+
+/// Whether a `StreamPrinter` prints as `print_json` does with `style`.
+/// It ignores the options which need a whole container, `sort_keys`, `align_values`,
+/// `fill_arrays` and `max_depth`, so they must be the defaults.
+pub fn supports(style: &PrintStyle) -> bool {
+    !style.sort_keys && !style.align_values && !style.fill_arrays && style.max_depth.is_none()
+}
+
+/// Prints every event of `events` into `out`.
+pub fn print_events<'a, I, W>(events: I, out: W, style: &PrintStyle) -> io::Result<W>
+    where I: IntoIterator<Item = Event<'a>>,
+          W: Write
+{
+    let mut printer = StreamPrinter::new(out, style);
+    for e in events {
+        printer.event(e)?;
+    }
+    printer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::*;
+    use event::*;
+    use color::*;
+    use prettyprinter::*;
+
+    #[test]
+    fn test_print_events() {
+        use json::Json::*;
+        let json = JObject(vec![
+            ("a".into(), JArray(vec![JNumber(1f64.into()), JArray(vec![]), JObject(vec![("b\n".into(), JNull)])])),
+            ("c".into(), JObject(vec![])),
+            ("d".into(), JString("e".into()))
+        ]);
+        let style = PrintStyle {use_tabs: true, ..PrintStyle::default()};
+        assert!(supports(&style));
+        assert!(!supports(&PrintStyle {sort_keys: true, ..PrintStyle::default()}));
+        let out = print_events(json_events(&json), vec![], &style).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), print_json(&json, 0, &style));
+
+        let style = PrintStyle {trailing_newline: true, ..PrintStyle::default()};
+        let (first, second) = (JArray(vec![JBool(true)]), JNumber(2f64.into()));
+        let mut events = json_events(&first);
+        events.append(&mut json_events(&second));
+        let out = print_events(events, vec![], &style).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "[\n  true\n]\n2\n");
+    }
+
+    #[test]
+    fn test_stream_printer_width() {
+        let texts = [
+            "[1, [2, 3], {\"a\": [], \"bc\": {\"d\": [null, true]}}, \"e\"]",
+            "{\"a\": [[[1]], [[2, 3]]], \"b\": {}, \"c\": [{\"d\": false}]}",
+            "[[], {}] \"f\" [[\"g\", 4.5]]"
+        ];
+        let base = PrinterOptions::new().indent(3).trailing_newline(true);
+        let all = (0..40).map(|width| base.clone().width(width))
+            .chain((0..40).map(|width| base.clone().width(width).style(PrintStyle {bracket_spacing: false, use_tabs: true, ..base.style.clone()})))
+            .chain((0..40).map(|width| base.clone().width(width).color(Some(Theme::default()))))
+            .chain(vec![base.clone().compact(true), base.clone().width(UNLIMITED_WIDTH)]);
+        for opts in all {
+            for text in &texts {
+                let jsons = Json::from_str_many(text).unwrap();
+                let expected: String = jsons.iter().map(|json| json.pretty_print_with(&opts)).collect();
+                let mut printer = StreamPrinter::with_options(vec![], &opts);
+                for event in jsons.iter().flat_map(json_events) {
+                    printer.event(event).unwrap();
+                }
+                let out = printer.finish().unwrap();
+                assert_eq!(String::from_utf8(out).unwrap(), expected, "{} with {:?}", text, opts);
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::env;
+
+/// The number of columns of the terminal at the standard output, or else of `$COLUMNS`.
+/// Gives `None` when neither tells, e.g. the output is a pipe.
+pub fn width() -> Option<i32> {
+    sys::width().or_else(|| width_from(env::var("COLUMNS").ok().as_deref()))
+}
+
+/// The width given by `columns`, the value of `$COLUMNS` if any, which must be a positive number.
+fn width_from(columns: Option<&str>) -> Option<i32> {
+    columns?.parse().ok().filter(|&width| width > 0)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::os::raw::{c_int, c_ulong, c_ushort};
+
+    #[cfg(target_os = "linux")]
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    #[cfg(target_os = "macos")]
+    const TIOCGWINSZ: c_ulong = 0x4008_7468;
+
+    const STDOUT: c_int = 1;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Winsize {
+        ws_row: c_ushort,
+        ws_col: c_ushort,
+        ws_xpixel: c_ushort,
+        ws_ypixel: c_ushort
+    }
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    pub fn width() -> Option<i32> {
+        let mut size = Winsize::default();
+        // Fails unless the standard output is a terminal.
+        let result = unsafe { ioctl(STDOUT, TIOCGWINSZ, &mut size as *mut Winsize) };
+        if result == 0 && size.ws_col > 0 {
+            Some(i32::from(size.ws_col))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod sys {
+    pub fn width() -> Option<i32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width_from() {
+        assert_eq!(width_from(Some("120")), Some(120));
+        assert_eq!(width_from(Some("0")), None);
+        assert_eq!(width_from(Some("wide")), None);
+        assert_eq!(width_from(None), None);
+    }
+}
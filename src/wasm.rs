@@ -0,0 +1,100 @@
+//! Bindings for JavaScript, enabled by the `wasm` feature.
+//!
+//! Build the library as a `cdylib` for `wasm32-unknown-unknown` and run `wasm-bindgen` on it:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! wasm-bindgen target/wasm32-unknown-unknown/release/toyjq.wasm --out-dir pkg
+//! ```
+//!
+//! Every function takes and returns JSON as text and throws a string on errors.
+
+use wasm_bindgen::prelude::*;
+
+use super::json::*;
+use super::filter::{Filter, FilterError};
+use super::parsercombinator::ParseError;
+
+/// Layout options of `format`. `new FormatOptions()` gives the defaults of `PrinterOptions`.
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub indent: i32,
+    pub tabs: bool,
+    pub sort_keys: bool,
+    pub ascii: bool,
+    pub compact: bool
+}
+
+#[wasm_bindgen]
+impl FormatOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> FormatOptions {
+        FormatOptions::default()
+    }
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        let style = PrinterOptions::default().style;
+        FormatOptions {indent: style.indent_width, tabs: style.use_tabs, sort_keys: style.sort_keys, ascii: style.escape.ascii, compact: false}
+    }
+}
+
+impl FormatOptions {
+    fn to_printer_options(self, width: i32) -> PrinterOptions {
+        PrinterOptions::new()
+            .width(width)
+            .indent(self.indent)
+            .tabs(self.tabs)
+            .sort_keys(self.sort_keys)
+            .ascii(self.ascii)
+            .compact(self.compact)
+    }
+}
+
+fn to_js_error(e: ParseError) -> JsValue {
+    JsValue::from_str(&format!("parse error at line {}, column {}: {}", e.line, e.column, e.message))
+}
+
+fn filter_error_to_js(e: FilterError) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// Checks that `input` is JSON and returns it without whitespace.
+#[wasm_bindgen]
+pub fn parse(input: &str) -> Result<String, JsValue> {
+    let json = Json::from_str(input).map_err(to_js_error)?;
+    Ok(print_json_compact(&json))
+}
+
+/// Pretty prints `input` to fit in `width` columns.
+#[wasm_bindgen]
+pub fn format(input: &str, width: i32, options: &FormatOptions) -> Result<String, JsValue> {
+    let json = Json::from_str(input).map_err(to_js_error)?;
+    Ok(json.pretty_print_with(&options.to_printer_options(width)))
+}
+
+/// Runs the jq program `program` on `input` and returns its outputs without whitespace,
+/// one per line.
+#[wasm_bindgen]
+pub fn run_filter(program: &str, input: &str) -> Result<String, JsValue> {
+    let filter = Filter::parse(program).map_err(to_js_error)?;
+    let json = Json::from_str(input).map_err(to_js_error)?;
+    let outputs: Vec<String> = filter.apply(&json).map_err(filter_error_to_js)?.iter().map(print_json_compact).collect();
+    Ok(outputs.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(parse(r#"{ "a": [1, 2] }"#).unwrap(), r#"{"a":[1,2]}"#);
+        let opts = FormatOptions {indent: 4, sort_keys: true, ..FormatOptions::new()};
+        assert_eq!(format(r#"{"b": 1, "a": 2}"#, 0, &opts).unwrap(), "{\n    \"a\": 2,\n    \"b\": 1\n}");
+        assert_eq!(run_filter(".a", r#"{"a": [ 1 ]}"#).unwrap(), "[1]");
+        assert_eq!(run_filter(".a[]", r#"{"a": [1, [2]]}"#).unwrap(), "1\n[2]");
+    }
+}